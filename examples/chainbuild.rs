@@ -21,7 +21,11 @@ lazy_static!(
 
 fn build_ops() -> ChainedOps
 {
-    let build_ops = ChainedOps::new("build myapp");
+    let mut build_ops = ChainedOps::new("build myapp");
+
+    // The compilations are independent of each other, so allow them to be run
+    // concurrently; the remaining operations declare their dependencies.
+    build_ops.set_mode(&ChainMode::Dag(2));
 
     // A plain operation can be modified after adding it to the chain
     let mut compile_foo = build_ops.push_op(&SubProcOperation::new(&C_COMPILER));
@@ -31,26 +35,35 @@ fn build_ops() -> ChainedOps
         .push_arg("-DDEBUG=1");
 
     // Or the operation can be fully-configured and then added to the chain
-    build_ops.push_op(SubProcOperation::new(&C_COMPILER)
-                      .set_dir("src/")
-                      .set_input_file(&FileArg::loc("bar.c"))
-                      .set_output_file(&FileArg::loc("../build/bar.o")));
-    build_ops.push_op(SubProcOperation::new(&LINKER)
-                      .set_dir("build/")
-                      .set_input_file(&FileArg::loc("foo.o"))
-                      .add_input_file(&FileArg::loc("bar.o"))
-                      .set_output_file(&FileArg::loc("myapp.exe")));
-    build_ops.push_op(SubProcOperation::new(&Executable::new("bash",
-                                                             ExeFileSpec::Append,
-                                                             ExeFileSpec::NoFileUsed))
-                      .set_dir("build/")
-                      .set_input_file(&FileArg::loc("myapp.exe"))
-                      .set_output_file(&FileArg::temp("test_out")));
+    let compile_bar =
+        build_ops.push_op(SubProcOperation::new(&C_COMPILER)
+                          .set_dir("src/")
+                          .set_input_file(&FileArg::loc("bar.c"))
+                          .set_output_file(&FileArg::loc("../build/bar.o")));
+    let link = build_ops.push_op(SubProcOperation::new(&LINKER)
+                                 .set_dir("build/")
+                                 .set_input_file(&FileArg::loc("foo.o"))
+                                 .add_input_file(&FileArg::loc("bar.o"))
+                                 .set_output_file(&FileArg::loc("myapp.exe")))
+        .depends_on(&compile_foo)
+        .depends_on(&compile_bar)
+        .clone();
+    let test = build_ops.push_op(SubProcOperation::new(&Executable::new("bash",
+                                                                        ExeFileSpec::Append,
+                                                                        ExeFileSpec::NoFileUsed))
+                                 .set_dir("build/")
+                                 .set_input_file(&FileArg::loc("myapp.exe"))
+                                 .set_output_file(&FileArg::temp("test_out")))
+        .depends_on(&link)
+        .clone();
+    // grep exits with 1 if there are no matches, which is not a failure here
     build_ops.push_op(SubProcOperation::new(&Executable::new("grep",
                                                              ExeFileSpec::Append,
                                                               ExeFileSpec::NoFileUsed))
                       .push_arg("Passed")
-                      .set_input_file(&FileArg::glob_in("build/", "*.test_out")));
+                      .set_input_file(&FileArg::glob_in("build/", "*.test_out")))
+        .accept_exit_code(1)
+        .depends_on(&test);
     build_ops
 }
 
@@ -0,0 +1,419 @@
+// Content-addressed caching of the output files of SubProcOperations.
+//
+// An OutputCache is specified for a SubProcOperation (or for a chain, in which
+// case it is inherited by the operations in the chain).  Before the command is
+// run, a key is computed from everything that determines its output; if the
+// cache directory has an entry for that key, the output file is restored from
+// that entry instead of running the command, otherwise the output file is
+// stored in the cache after a successful run.
+
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use sha2::{Digest, Sha256};
+
+use crate::execution::{EnvSpec, OsRun};
+use crate::filehandling::ActualFile;
+
+
+/// Specifies a local directory in which the output files of
+/// [crate::SubProcOperation] executions are cached (see
+/// [crate::SubProcOperation::set_cache] and [crate::ChainedOps::set_cache]).
+///
+/// The cache key for an execution is a hash of the executable path, the final
+/// argument list, the directory in which the command is run, the [EnvSpec] of
+/// the operation, the values of any parent environment variables identified by
+/// [OutputCache::key_env], and the contents of the input files.  The names of
+/// the input and output files are not part of the key (only their positions in
+/// the argument list), so operations using temporary files can still be
+/// cached.  Only operations producing a single output file are cached.
+///
+/// Only the input files specified via [crate::FileArg] are tracked: the
+/// contents of any other files that the command reads (e.g. a file named by an
+/// argument such as "-I include" or "@rspfile", or an included header) are
+/// not part of the key, so a change to those files does not invalidate the
+/// cached output.
+///
+/// When the cache exceeds the size or entry limits, the least recently used
+/// entries are removed.
+///
+/// ```
+/// # use chainsop::*;
+/// let mut cache = OutputCache::new("/tmp/build-cache");
+/// cache.set_max_size(512 * 1024 * 1024).key_env("CC");
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputCache {
+    dir : PathBuf,
+    max_size : Option<u64>,
+    max_entries : Option<usize>,
+    key_env : Vec<String>,
+}
+
+impl OutputCache {
+
+    /// Creates a cache that stores its entries in the specified directory.  The
+    /// directory is created when the first entry is stored.  By default, the
+    /// cache is unlimited.
+    pub fn new<T>(dir: T) -> OutputCache
+    where T: Into<PathBuf>
+    {
+        OutputCache { dir: dir.into(),
+                      max_size: None,
+                      max_entries: None,
+                      key_env: Vec::new(),
+        }
+    }
+
+    /// Returns the directory holding the cache entries.
+    pub fn dir(&self) -> &Path
+    {
+        &self.dir
+    }
+
+    /// Limits the total size (in bytes) of the cached files.
+    pub fn set_max_size(&mut self, max_size: u64) -> &mut Self
+    {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Limits the number of cached files.
+    pub fn set_max_entries(&mut self, max_entries: usize) -> &mut Self
+    {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Adds the value of the named environment variable (as inherited from the
+    /// parent process) to the cache key.  This should be used for any variable
+    /// that affects the output of the cached commands but is not explicitly set
+    /// via the [EnvSpec] of the operation.
+    pub fn key_env<T>(&mut self, var_name: T) -> &mut Self
+    where T: Into<String>
+    {
+        self.key_env.push(var_name.into());
+        self
+    }
+
+    /// Returns the cache entry for an execution of the specified command, or
+    /// None if the execution cannot be cached (it does not produce a single
+    /// output file or the input files cannot be read).  The input and output
+    /// files are relative to fromdir, as they are for the command itself.
+    pub(crate) fn entry(&self,
+                        exe_file: &Path,
+                        args: &[OsString],
+                        exe_env: &EnvSpec,
+                        inpfiles: &ActualFile,
+                        outfile: &ActualFile,
+                        fromdir: &Option<PathBuf>) -> Option<CacheEntry>
+    {
+        let ActualFile::SingleFile(_) = outfile else { return None; };
+        let outpath = outfile.to_path(fromdir).ok()?;
+        let inppaths = match inpfiles {
+            ActualFile::NoActualFile => Vec::new(),
+            _ => inpfiles.to_paths(fromdir).ok()?,
+        };
+
+        // File names in the arguments are replaced by their role so that the
+        // key does not depend on the (possibly temporary) names of the files.
+        let mut names = vec![(outfile.to_path(&None::<PathBuf>).ok()?,
+                              "<output>".to_string())];
+        if let ActualFile::NoActualFile = inpfiles {} else {
+            for (n, inp) in inpfiles.to_paths(&None::<PathBuf>).ok()?
+                .into_iter().enumerate()
+            {
+                names.push((inp, format!("<input{}>", n)));
+            }
+        }
+
+        // Relative arguments are resolved from the directory in which the
+        // command is run, so the same arguments in another directory may refer
+        // to different files.
+        let rundir = std::path::absolute(fromdir.as_deref().unwrap_or(Path::new(".")))
+            .ok()?;
+
+        let mut hasher = Sha256::new();
+        key_field(&mut hasher, exe_file.as_os_str().as_encoded_bytes());
+        for arg in args {
+            key_field(&mut hasher, &anonymize(arg, &names));
+        }
+        key_field(&mut hasher, rundir.as_os_str().as_encoded_bytes());
+        key_field(&mut hasher, format!("{:?}", exe_env).as_bytes());
+        for var in &self.key_env {
+            key_field(&mut hasher, var.as_bytes());
+            key_field(&mut hasher,
+                      std::env::var_os(var).unwrap_or_default().as_encoded_bytes());
+        }
+        for inp in &inppaths {
+            key_field(&mut hasher, &fs::read(inp).ok()?);
+        }
+        let key: String = hasher.finalize().iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+
+        Some(CacheEntry { cache: self.clone(),
+                          path: self.dir.join(key),
+                          outfile: outpath,
+        })
+    }
+
+    /// Removes the least recently used entries until the cache is within its
+    /// limits.
+    fn evict(&self)
+    {
+        if self.max_size.is_none() && self.max_entries.is_none() {
+            return;
+        }
+        let Ok(dirents) = fs::read_dir(&self.dir) else { return; };
+        let mut entries: Vec<(SystemTime, u64, PathBuf)> = dirents
+            .filter_map(|e| e.ok())
+            .filter(|e| ! is_partial(&e.path()))
+            .filter_map(|e| {
+                let md = e.metadata().ok()?;
+                Some((md.modified().ok()?, md.len(), e.path()))
+            })
+            .collect();
+        entries.sort();
+        let mut size: u64 = entries.iter().map(|e| e.1).sum();
+        let mut count = entries.len();
+        for (_, len, path) in entries {
+            if self.max_size.is_none_or(|m| size <= m) &&
+                self.max_entries.is_none_or(|m| count <= m) {
+                    break;
+                }
+            if fs::remove_file(path).is_ok() {
+                size -= len;
+                count -= 1;
+            }
+        }
+    }
+}
+
+/// The cache entry for a specific execution of a command, as determined by
+/// [OutputCache::entry].
+#[derive(Clone, Debug)]
+pub(crate) struct CacheEntry {
+    cache : OutputCache,
+    path : PathBuf,
+    outfile : PathBuf,
+}
+
+impl CacheEntry {
+
+    /// Restores the output file from this cache entry, returning true if the
+    /// file was restored (and therefore the command does not need to be run).
+    pub(crate) fn restore<Exec>(&self, executor: &Exec, label: &str) -> bool
+    where Exec: OsRun
+    {
+        if ! self.path.is_file() ||
+            executor.restore_cached(label, &self.path, &self.outfile).is_err() {
+                return false;
+            }
+        touch(&self.path);
+        true
+    }
+
+    /// Stores the output file (written by a successful run of the command) in
+    /// this cache entry.  The cache is an optimization, so any failure to store
+    /// the entry is ignored.
+    pub(crate) fn store<Exec>(&self, executor: &Exec)
+    where Exec: OsRun
+    {
+        if fs::create_dir_all(&self.cache.dir).is_err() {
+            return;
+        }
+        // Write to a partial file and then rename it so that a concurrent
+        // lookup never sees an incomplete entry.
+        let mut partial = self.path.clone().into_os_string();
+        partial.push(PARTIAL_SUFFIX);
+        let partial = PathBuf::from(partial);
+        if executor.store_cached(&self.outfile, &partial).is_ok() && partial.is_file() {
+            touch(&partial);
+            if fs::rename(&partial, &self.path).is_ok() {
+                self.cache.evict();
+                return;
+            }
+        }
+        let _ = fs::remove_file(&partial);
+    }
+}
+
+const PARTIAL_SUFFIX: &str = ".partial";
+
+fn is_partial(path: &Path) -> bool
+{
+    path.as_os_str().as_encoded_bytes().ends_with(PARTIAL_SUFFIX.as_bytes())
+}
+
+/// Adds a (length-delimited) field to the cache key.
+fn key_field(hasher: &mut Sha256, value: &[u8])
+{
+    hasher.update((value.len() as u64).to_le_bytes());
+    hasher.update(value);
+}
+
+/// Returns the argument with any of the named file paths replaced by the
+/// corresponding role.  A file name is only replaced where it is the entire
+/// argument or the entire value of an "option=" argument, where the value may be
+/// a comma-separated list of file names (the default joining of multiple files
+/// for [crate::ExeFileSpec::Option]).  Files joined with another separator via
+/// [crate::ExeFileSpec::OptionJoin] are not replaced, so such an argument only
+/// matches the key of an execution using the same file names.  Other
+/// occurrences of the name within an argument are not replaced, so a short file
+/// name (e.g. "c") does not alter an option such as "-c".
+fn anonymize(arg: &OsStr, names: &[(PathBuf, String)]) -> Vec<u8>
+{
+    let role_of = |part: &[u8]| names.iter()
+        .find(|(path, _)| path.as_os_str().as_encoded_bytes() == part)
+        .map(|(_, role)| role.as_bytes().to_vec());
+    let arg = arg.as_encoded_bytes();
+    if let Some(role) = role_of(arg) {
+        return role;
+    }
+    let (opt, value) = match arg.iter().position(|b| *b == b'=') {
+        Some(pos) => arg.split_at(pos + 1),
+        None => (&arg[..0], arg),
+    };
+    let mut out = opt.to_vec();
+    match role_of(value) {
+        Some(role) => out.extend(role),
+        None => for (n, part) in value.split(|b| *b == b',').enumerate() {
+            if n > 0 {
+                out.push(b',');
+            }
+            out.extend(role_of(part).unwrap_or_else(|| part.to_vec()));
+        },
+    }
+    out
+}
+
+/// Updates the modification time of a cache entry to record its use.
+fn touch(path: &Path)
+{
+    if let Ok(f) = fs::File::options().append(true).open(path) {
+        let _ = f.set_modified(SystemTime::now());
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::execution::Executor;
+    use crate::filehandling::defs::FileRef;
+    use std::time::Duration;
+
+    fn entry_for(cache: &OutputCache, dir: &Path, args: &[&str],
+                 inp: &str, out: &str) -> Option<CacheEntry>
+    {
+        cache.entry(Path::new("cc"),
+                    &args.iter().map(OsString::from).collect::<Vec<_>>(),
+                    &EnvSpec::StdEnv,
+                    &ActualFile::SingleFile(FileRef::StaticFile(inp.into())),
+                    &ActualFile::SingleFile(FileRef::StaticFile(out.into())),
+                    &Some(dir.to_path_buf()))
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cache = OutputCache::new(tmp.path().join("cache"));
+        fs::write(tmp.path().join("a.c"), "int a;").unwrap();
+        fs::write(tmp.path().join("b.c"), "int a;").unwrap();
+
+        let a = entry_for(&cache, tmp.path(), &["-o", "a.o", "a.c"], "a.c", "a.o")
+            .unwrap();
+        assert_eq!(a.outfile, tmp.path().join("a.o"));
+
+        // Same command and input contents but different file names: same key
+        let b = entry_for(&cache, tmp.path(), &["-o", "b.o", "b.c"], "b.c", "b.o")
+            .unwrap();
+        assert_eq!(a.path, b.path);
+
+        // Different arguments or input contents: different key
+        let c = entry_for(&cache, tmp.path(), &["-O2", "-o", "a.o", "a.c"],
+                          "a.c", "a.o").unwrap();
+        assert_ne!(a.path, c.path);
+        fs::write(tmp.path().join("b.c"), "int b;").unwrap();
+        let b = entry_for(&cache, tmp.path(), &["-o", "b.o", "b.c"], "b.c", "b.o")
+            .unwrap();
+        assert_ne!(a.path, b.path);
+
+        // Unreadable inputs cannot be cached
+        assert!(entry_for(&cache, tmp.path(), &["x.c"], "x.c", "x.o").is_none());
+
+        // The same command and inputs run in another directory: different key
+        let sub = tmp.path().join("sub");
+        fs::create_dir(&sub).unwrap();
+        fs::write(sub.join("a.c"), "int a;").unwrap();
+        let s = entry_for(&cache, &sub, &["-o", "a.o", "a.c"], "a.c", "a.o")
+            .unwrap();
+        assert_eq!(s.outfile, sub.join("a.o"));
+        assert_ne!(a.path, s.path);
+    }
+
+    #[test]
+    fn test_cache_key_file_names() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cache = OutputCache::new(tmp.path().join("cache"));
+        fs::write(tmp.path().join("a.c"), "int a;").unwrap();
+        fs::write(tmp.path().join("c"), "int a;").unwrap();
+        let key = |args: &[&str], inp: &str, out: &str|
+            entry_for(&cache, tmp.path(), args, inp, out).unwrap().path;
+
+        // Short file names do not alter the options that contain them.
+        assert_eq!(key(&["-c", "-O2", "-o", "a.o", "a.c"], "a.c", "a.o"),
+                   key(&["-c", "-O2", "-o", "O2", "c"], "c", "O2"));
+        assert_ne!(key(&["-c", "-o", "a.o", "a.c"], "a.c", "a.o"),
+                   key(&["-x", "-o", "a.o", "a.c"], "a.c", "a.o"));
+        assert_ne!(key(&["-c", "-o", "o", "c"], "c", "o"),
+                   key(&["-<input0>", "-o", "o", "c"], "c", "o"));
+
+        // File names are replaced in option values and comma-separated lists.
+        assert_eq!(key(&["--out=a.o", "-I", "a.c,x"], "a.c", "a.o"),
+                   key(&["--out=o", "-I", "c,x"], "c", "o"));
+        assert_ne!(key(&["--out=a.o", "-I", "a.c,x"], "a.c", "a.o"),
+                   key(&["--out=o", "-I", "c,y"], "c", "o"));
+    }
+
+    #[test]
+    fn test_cache_store_restore_evict() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cache = OutputCache::new(tmp.path().join("cache"));
+        cache.set_max_entries(2);
+        let executor = Executor::NormalRun;
+        let outfile = tmp.path().join("x.o");
+        let entry = |n: usize| {
+            let src = format!("{}.c", n);
+            fs::write(tmp.path().join(&src), format!("int x{};", n)).unwrap();
+            entry_for(&cache, tmp.path(), &[&src], &src, "x.o").unwrap()
+        };
+        let mut entries = Vec::new();
+        for n in 0..2 {
+            let e = entry(n);
+            assert!(! e.restore(&executor, "cc"));
+            fs::write(&outfile, format!("obj {}", n)).unwrap();
+            e.store(&executor);
+            entries.push(e);
+            // Ensures distinct modification times for the eviction order
+            std::thread::sleep(Duration::from_millis(20));
+        }
+
+        // Using the first entry makes the second the least recently used
+        assert!(entries[0].restore(&executor, "cc"));
+        assert_eq!(fs::read_to_string(&outfile).unwrap(), "obj 0");
+        std::thread::sleep(Duration::from_millis(20));
+
+        let e = entry(2);
+        fs::write(&outfile, "obj 2").unwrap();
+        e.store(&executor);
+        assert!(entries[0].path.is_file());
+        assert!(! entries[1].path.is_file());
+        assert!(e.path.is_file());
+        assert_eq!(fs::read_dir(&cache.dir).unwrap().count(), 2);
+    }
+}
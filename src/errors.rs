@@ -1,5 +1,6 @@
 use std::ffi::{OsString};
 use std::path::{PathBuf};
+use std::time::Duration;
 use anyhow;
 use thiserror;
 
@@ -12,12 +13,18 @@ pub enum ChainsopError {
     #[error("Missing file for operation")]
     ErrorMissingFile,
 
+    #[error("Output of operation {0:?} is not available: it has not been performed")]
+    ErrorMissingOutput(String),
+
     #[error("Target directory {1:?} error running command {0:?}: {2:?}")]
     ErrorBadDirectory(String, PathBuf, std::io::Error),
 
     #[error("Error {2:?} running command {0:?} {1:?} in dir {3:?}\n{4:}")]
     ErrorRunningCmd(String, Vec<OsString>, Option<i32>, Option<PathBuf>, String),
 
+    #[error("Command {0:?} {1:?} in dir {3:?} timed out after {2:?}\n{4:}")]
+    ErrorTimedOut(String, Vec<OsString>, Duration, Option<PathBuf>, String),
+
     #[error("Error {2:?} setting up running command {0:?} {1:?} in dir {3:?}")]
     ErrorCmdSetup(String, Vec<OsString>, std::io::Error, Option<PathBuf>),
 
@@ -30,6 +37,75 @@ pub enum ChainsopError {
     #[error("Invalid operation actual file specification: {0:?}")]
     ErrorUnsupportedActualFile(String),
 
+    #[error("Invalid {1} file specification for command {0:?}")]
+    ErrorInvalidFileSpec(String, String),
+
     #[error("No valid operation specified")]
     ErrorInvalidOperation,
+
+    #[error("Operation {1} in chain {0:?} cannot depend on operation {2} which is not an earlier operation in the chain")]
+    ErrorInvalidDependency(String, usize, usize),
+
+    #[error("Operation {1} in chain {0:?} cannot depend on an operation in another chain")]
+    ErrorForeignDependency(String, usize),
+
+    #[error("Reference to an operation in another chain used with chain {0:?}")]
+    ErrorForeignOperation(String),
+
+    #[error("Reference to an operation that has been removed from chain {0:?}")]
+    ErrorRemovedOperation(String),
+
+    #[error("Operation {0:?} cannot be part of a pipeline: {1}")]
+    ErrorInvalidPipe(String, String),
+
+    #[error("Operation {0:?} failed after {} attempts:{}", .1.len(), attempt_errors(.1))]
+    ErrorAttemptsFailed(String, Vec<anyhow::Error>),
+
+    #[error("{} operation(s) failed in chain {0:?}:{}", .1.len(), op_failures(.1))]
+    ErrorOpsFailed(String, Vec<OpFailure>),
+
+    #[error("{} finally operation(s) failed in chain {0:?}{}:{}", .2.len(), chain_failure(.1), op_failures(.2))]
+    ErrorFinallyFailed(String, Option<anyhow::Error>, Vec<OpFailure>),
+
+    #[error("Cannot resume chain {0:?} from checkpoint {1:?}: {2}")]
+    ErrorCheckpoint(String, PathBuf, String),
+
+    #[error("Chain {1:?} cannot be nested in chain {0:?} because it contains that chain")]
+    ErrorChainCycle(String, String),
+}
+
+/// Describes an operation that failed during the execution of a chain that
+/// continues past failures (see [crate::ChainPolicy]) or a finally operation of
+/// a chain; these are reported together by [ChainsopError::ErrorOpsFailed] or
+/// [ChainsopError::ErrorFinallyFailed], respectively.
+#[derive(Debug)]
+pub struct OpFailure {
+    /// The index of the operation in the chain.
+    pub index: usize,
+
+    /// The label of the operation.
+    pub label: String,
+
+    /// The error returned by the operation.
+    pub error: anyhow::Error,
+}
+
+fn op_failures(failures: &[OpFailure]) -> String {
+    failures.iter()
+        .map(|f| format!("\n  [{}] {}: {:#}", f.index, f.label, f.error))
+        .collect()
+}
+
+fn chain_failure(error: &Option<anyhow::Error>) -> String {
+    match error {
+        Some(e) => format!(" after chain failure ({:#})", e),
+        None => String::new(),
+    }
+}
+
+fn attempt_errors(errors: &[anyhow::Error]) -> String {
+    errors.iter()
+        .enumerate()
+        .map(|(n, e)| format!("\n  attempt {}: {:#}", n + 1, e))
+        .collect()
 }
@@ -20,6 +20,7 @@ pub struct Executable {
     base_args : Vec<String>,
     inp_file : ExeFileSpec,
     out_file : ExeFileSpec,
+    ok_exit_codes : Vec<i32>,
 }
 
 // These get_xxx functions are accessors used _within_ this crate to access the
@@ -38,11 +39,23 @@ pub fn get_outfile(exe: &Executable) -> ExeFileSpec {
     exe.out_file.clone()
 }
 
+pub fn get_ok_exit_codes(exe: &Executable) -> &Vec<i32> {
+    &exe.ok_exit_codes
+}
+
+pub fn with_file_specs(exe: &Executable,
+                       inp_file: ExeFileSpec,
+                       out_file: ExeFileSpec) -> Executable {
+    Executable { inp_file, out_file, ..exe.clone() }
+}
+
 /// Specifies the manner in which a file is provided to an Executable command.
-/// Both input and output files are specified in this manner.  There is no
-/// provision for handling stdin, stdout, and stderr.  It is assumed that an
-/// executable consumes a file specified on the command line, and writes a file
-/// that is also specified on the command line.
+/// Both input and output files are specified in this manner.  It is usually
+/// assumed that an executable consumes a file specified on the command line, and
+/// writes a file that is also specified on the command line, but the input
+/// file(s) can also be provided as the executable's stdin and the output file
+/// can also be the executable's stdout.  There is no provision for handling
+/// stderr.
 #[derive(Clone,Default)]
 pub enum ExeFileSpec {
     /// No file provided or needed
@@ -64,8 +77,27 @@ pub enum ExeFileSpec {
     ///  * `Option("-f")` to specify "CMD -f FILE"
     ///
     ///  * `Option("-file=")` to specify "CMD --file=FILE"
+    ///
+    /// Multiple files are separated by commas (e.g. "CMD -f FILE1,FILE2"); see
+    /// [ExeFileSpec::OptionJoin] for other handling of multiple files.
     Option(String),
 
+    /// Specify the file using this option as for [ExeFileSpec::Option], with
+    /// multiple files combined as specified by the [FileJoin].  All of the
+    /// input files are combined, even if they are provided by different
+    /// [FileArg](crate::FileArg) specifications.
+    ///
+    /// Examples (see [ExeFileSpec::option_join]):
+    ///
+    ///  * `OptionJoin("-I", FileJoin::Repeat)` to specify "CMD -I FILE1 -I FILE2"
+    ///
+    ///  * `OptionJoin("--input=", FileJoin::Separator(":".into()))` to specify
+    ///    "CMD --input=FILE1:FILE2"
+    ///
+    ///  * `OptionJoin("-o", FileJoin::Single)` to specify "CMD -o FILE", where
+    ///    multiple files are an error.
+    OptionJoin(String, FileJoin),
+
     /// The file is added to the arguments list by a special function.  The
     /// function specified here is called with the argument list and the named
     /// file; it should add the named file to the arguments list in some manner
@@ -80,6 +112,47 @@ pub enum ExeFileSpec {
     ViaCall(fn(&mut Vec<OsString>,
                &Option<PathBuf>,
                &ActualFile) -> anyhow::Result<()>),
+
+    /// The stdout of the executable is written to the file; nothing is added to
+    /// the command-line arguments.  This is only valid for the output file.
+    Stdout,
+
+    /// The input file is provided as the stdin of the executable; nothing is
+    /// added to the command-line arguments.  If there are multiple input files,
+    /// their contents are concatenated.  This is only valid for the input file.
+    Stdin,
+
+    /// The file replaces each occurrence of the specified pattern within the
+    /// arguments, allowing the file to be embedded in an argument.  Multiple
+    /// files are combined as specified by the [FileJoin].  Only the arguments
+    /// provided for the command are substituted: a file name (e.g. one added by
+    /// the other file specification) that contains the pattern is not altered.
+    /// It is an error if the pattern is empty, if it does not occur in any
+    /// argument, or if it occurs in an argument that is not valid UTF-8.
+    ///
+    /// Examples (see [ExeFileSpec::pattern]):
+    ///
+    ///  * `pattern("{in}")` with the argument "--add-section=.data={in}"
+    ///
+    ///  * `pattern("{in}")` and `pattern("{out}")` for the input and output
+    ///    files with the arguments "-c" "tool < {in} > {out}" for "sh"
+    Pattern(String, FileJoin),
+}
+
+/// Specifies how multiple files are combined when they are substituted for an
+/// [ExeFileSpec::Pattern] or provided for an [ExeFileSpec::OptionJoin].
+#[derive(Clone, Debug, PartialEq)]
+pub enum FileJoin {
+    /// The files are joined into the argument, separated by the specified
+    /// string.
+    Separator(String),
+
+    /// The argument (or option) is repeated for each file, where each copy
+    /// contains one of the files.
+    Repeat,
+
+    /// Only a single file is allowed; multiple files are an error.
+    Single,
 }
 
 impl fmt::Debug for ExeFileSpec {
@@ -88,7 +161,11 @@ impl fmt::Debug for ExeFileSpec {
             ExeFileSpec::NoFileUsed => "<none>".fmt(f),
             ExeFileSpec::Append => "append".fmt(f),
             ExeFileSpec::Option(o) => format!("option({})", o).fmt(f),
+            ExeFileSpec::OptionJoin(o, j) => format!("option({}, {:?})", o, j).fmt(f),
             ExeFileSpec::ViaCall(_) => "via function call".fmt(f),
+            ExeFileSpec::Stdout => "stdout".fmt(f),
+            ExeFileSpec::Stdin => "stdin".fmt(f),
+            ExeFileSpec::Pattern(p, j) => format!("pattern({}, {:?})", p, j).fmt(f),
         }
     }
 }
@@ -101,6 +178,20 @@ impl ExeFileSpec {
     {
         ExeFileSpec::Option(optname.to_string())
     }
+
+    /// Constructs the OptionJoin ExeFileSpec with automatic argument conversion
+    pub fn option_join<T: ToString + ?Sized>(optname : &T, join : FileJoin)
+                                             -> ExeFileSpec
+    {
+        ExeFileSpec::OptionJoin(optname.to_string(), join)
+    }
+
+    /// Constructs the Pattern ExeFileSpec with automatic argument conversion;
+    /// multiple files are separated by a space.
+    pub fn pattern<T: ToString + ?Sized>(pattern : &T) -> ExeFileSpec
+    {
+        ExeFileSpec::Pattern(pattern.to_string(), FileJoin::Separator(" ".into()))
+    }
 }
 
 impl Executable {
@@ -119,6 +210,7 @@ impl Executable {
             base_args : Vec::new(),
             inp_file : inp_file.clone(),
             out_file : out_file.clone(),
+            ok_exit_codes : Vec::new(),
         }
     }
 
@@ -136,6 +228,21 @@ impl Executable {
         }
     }
 
+    /// Specifies a non-zero exit code that indicates success for this command
+    /// (e.g. `grep` exits with 1 if no lines match, or `diff` exits with 1 if
+    /// the files differ).  By default, any non-zero exit code is a failure.
+    #[inline]
+    pub fn accept_exit_code(&self, code: i32) -> Executable
+    {
+        Executable {
+            ok_exit_codes : { let mut tmp = self.ok_exit_codes.clone();
+                              tmp.push(code);
+                              tmp
+            },
+            ..self.clone()
+        }
+    }
+
     /// Specifies the name of the executable file
     #[inline]
     pub fn set_exe<T>(&self, exe: T) -> Executable
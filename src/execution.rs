@@ -19,11 +19,18 @@ use anyhow;
 use glob;
 use std::env::{current_dir, vars};
 use std::ffi::{OsString};
+use std::fs::File;
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::process;
-use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 use crate::filehandling::defs::*;
+use crate::shared::Shared;
 
 
 /// The OsRun trait is used to define the interface to implementation that will
@@ -43,19 +50,86 @@ use crate::filehandling::defs::*;
 pub trait OsRun {
 
     /// Run the specified executable with the specified arguments.  The default
-    /// (NormalRun) behaviour is to use Command to perform this execution.
+    /// (NormalRun) behaviour is to use Command to perform this execution.  The
+    /// exe_opts specify additional handling for the execution, such as the
+    /// redirection of the executable's stdin and stdout.
     fn run_executable(&self,
                       label: &str,
                       exe_file: &Path,
                       args: &Vec<OsString>,
                       exe_env: &EnvSpec,
+                      exe_opts: &ExecOptions,
                       fromdir: &Option<PathBuf>) -> OsRunResult;
 
+    /// Starts running the specified executable with the specified arguments,
+    /// but does not wait for it to complete; the returned [OsRunHandle] is used
+    /// to determine when the executable has completed and to obtain the
+    /// result.  This is used to run multiple independent operations
+    /// concurrently (e.g. a [crate::ChainedOps] in [crate::ChainMode::Dag]
+    /// mode).
+    ///
+    /// The default implementation simply calls [OsRun::run_executable] and
+    /// returns the already-completed result, so an OsRun implementation that
+    /// does not override this method will run all operations sequentially.
+    fn start_executable(&self,
+                        label: &str,
+                        exe_file: &Path,
+                        args: &Vec<OsString>,
+                        exe_env: &EnvSpec,
+                        exe_opts: &ExecOptions,
+                        fromdir: &Option<PathBuf>) -> OsRunHandle
+    {
+        OsRunHandle::Completed(
+            self.run_executable(label, exe_file, args, exe_env, exe_opts, fromdir))
+    }
+
+    /// Runs the specified executables as a pipeline: the stdout of each stage
+    /// is provided as the stdin of the next stage.  Only the stdin
+    /// specification of the first stage and the stdout specification of the
+    /// last stage are used.  The return is the result of each stage, in the
+    /// same order as the stages.
+    ///
+    /// The default implementation emulates the pipeline by running each stage
+    /// in turn via [OsRun::run_executable], using temporary files (see
+    /// [OsRun::mk_tempfile]) to pass the output of each stage to the next
+    /// stage.  The [Executor] overrides this to run all of the stages
+    /// concurrently, connected by OS pipes.
+    fn run_pipeline(&self, stages: &[PipeStage]) -> Vec<OsRunResult>
+    {
+        let mut results = Vec::new();
+        let mut prev_out: Option<tempfile::NamedTempFile> = None;
+        for (n, stage) in stages.iter().enumerate() {
+            let mut exe_opts = stage.exe_opts.clone();
+            if n > 0 {
+                exe_opts.stdin = prev_out.iter()
+                    .map(|tf| tf.path().to_path_buf())
+                    .collect();
+            }
+            prev_out = None;
+            if n + 1 < stages.len() {
+                match self.mk_tempfile(&".pipe".to_string()) {
+                    Ok(tf) => {
+                        exe_opts.stdout = Some(tf.path().to_path_buf());
+                        prev_out = Some(tf);
+                    }
+                    Err(e) => {
+                        results.push(OsRunResult::RunError(e));
+                        continue;
+                    }
+                }
+            }
+            results.push(self.run_executable(&stage.label, &stage.exe_file,
+                                             &stage.args, &stage.exe_env,
+                                             &exe_opts, &stage.fromdir));
+        }
+        results
+    }
+
     /// Call the specified function with the specified file arguments.  The
     /// default (NormalRun) behaviour is to actually perform the call.
     fn run_function(&self,
                     name : &str,
-                    call : &Rc<dyn Fn(&Path, &ActualFile, &ActualFile) -> anyhow::Result<()>>,
+                    call : &OpFunction,
                     inpfiles: &ActualFile,
                     outfile: &ActualFile,
                     fromdir: &Option<PathBuf>) -> OsRunResult;
@@ -76,6 +150,148 @@ pub trait OsRun {
     /// existence is generally non-impactful to the system, it is relatively safe
     /// to allow the normal behavior even in simulation or testing scenarios.
     fn mk_tempfile(&self, suffix: &String) -> anyhow::Result<tempfile::NamedTempFile>;
+
+    /// This function is called to wait for the specified amount of time before
+    /// retrying a failed operation (see [crate::RetryPolicy]).  The default
+    /// implementation simply sleeps for that amount of time.
+    fn delay(&self, duration: Duration)
+    {
+        thread::sleep(duration);
+    }
+
+    /// Returns the modification time of the specified file, or None if the file
+    /// does not exist (or its modification time is not available).  This is used
+    /// to determine whether an incremental operation is up to date (see
+    /// [crate::ChainedOpRef::set_incremental]).
+    fn modified(&self, path: &Path) -> Option<SystemTime>
+    {
+        std::fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    /// This function is called when an incremental operation is not performed
+    /// because its output file is up to date.  The default implementation does
+    /// nothing.
+    fn report_up_to_date(&self, _label: &str, _outfile: &Path) {}
+
+    /// This function is called to restore the output file of an operation from
+    /// its [crate::OutputCache] entry instead of running the operation.  The
+    /// default implementation copies the cached file to the output file.
+    fn restore_cached(&self, _label: &str, cached: &Path, outfile: &Path)
+                      -> anyhow::Result<()>
+    {
+        std::fs::copy(cached, outfile)?;
+        Ok(())
+    }
+
+    /// This function is called to store the output file of a successful
+    /// operation as an [crate::OutputCache] entry.  The default implementation
+    /// copies the output file to the cached file.
+    fn store_cached(&self, outfile: &Path, cached: &Path) -> anyhow::Result<()>
+    {
+        std::fs::copy(outfile, cached)?;
+        Ok(())
+    }
+}
+
+/// The local function called by a [crate::FunctionOperation], as passed to
+/// [OsRun::run_function].  The arguments are the reference directory, the
+/// input file(s) and the output file.
+#[cfg(not(feature = "sync"))]
+pub type OpFunction =
+    Shared<dyn Fn(&Path, &ActualFile, &ActualFile) -> anyhow::Result<()>>;
+
+/// The local function called by a [crate::FunctionOperation], as passed to
+/// [OsRun::run_function].  The arguments are the reference directory, the
+/// input file(s) and the output file.
+#[cfg(feature = "sync")]
+pub type OpFunction =
+    Shared<dyn Fn(&Path, &ActualFile, &ActualFile) -> anyhow::Result<()>
+           + Send + Sync>;
+
+/// The AsyncOsRun trait is the asynchronous counterpart of the [OsRun] trait,
+/// and is used by the [crate::AsyncOpInterface] to perform operations from
+/// within an async runtime.  Only the running of executables is asynchronous:
+/// the other operations (calling local functions, glob searches, and creating
+/// temporary files) are local and are still performed via the [OsRun]
+/// methods.
+///
+/// The [Executor] implementation of this trait uses `tokio::process` and must
+/// therefore be used from within a tokio runtime.
+#[cfg(feature = "async")]
+pub trait AsyncOsRun: OsRun {
+
+    /// Run the specified executable with the specified arguments, completing
+    /// when the executable has completed.
+    ///
+    /// The default implementation simply calls [OsRun::run_executable], which
+    /// blocks until the executable completes.  This is suitable for an OsRun
+    /// implementation that does not actually run executables (e.g. for testing).
+    fn run_executable_async(&self,
+                            label: &str,
+                            exe_file: &Path,
+                            args: &[OsString],
+                            exe_env: &EnvSpec,
+                            exe_opts: &ExecOptions,
+                            fromdir: &Option<PathBuf>)
+                            -> impl Future<Output = OsRunResult>
+    {
+        std::future::ready(self.run_executable(label, exe_file, &args.to_vec(),
+                                               exe_env, exe_opts, fromdir))
+    }
+
+    /// Runs the specified executables as a pipeline (see
+    /// [OsRun::run_pipeline]), completing when all of the stages have
+    /// completed.
+    ///
+    /// The default implementation simply calls [OsRun::run_pipeline].
+    fn run_pipeline_async(&self, stages: &[PipeStage])
+                          -> impl Future<Output = Vec<OsRunResult>>
+    {
+        std::future::ready(self.run_pipeline(stages))
+    }
+
+    /// Waits for the specified amount of time before retrying a failed
+    /// operation (see [OsRun::delay]).
+    ///
+    /// The default implementation simply calls [OsRun::delay].
+    fn delay_async(&self, duration: Duration) -> impl Future<Output = ()>
+    {
+        self.delay(duration);
+        std::future::ready(())
+    }
+}
+
+/// Specifies additional handling for running an executable via the
+/// [OsRun::run_executable] (and related) methods.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExecOptions {
+    /// If specified, the stdout of the executable is written to this file
+    /// (relative to the directory in which the executable is run); otherwise
+    /// the stdout is discarded.
+    pub stdout: Option<PathBuf>,
+
+    /// The contents of these files (relative to the directory in which the
+    /// executable is run) are provided in sequence as the stdin of the
+    /// executable.  If there are no files specified, the executable inherits
+    /// the stdin of the current process.
+    pub stdin: Vec<PathBuf>,
+
+    /// If specified, the executable is killed if it has not completed within
+    /// this amount of time, along with any other processes in its process
+    /// group, and the result is [OsRunResult::TimedOut].
+    pub timeout: Option<Duration>,
+}
+
+/// Describes a single stage of a pipeline run by [OsRun::run_pipeline].  The
+/// fields correspond to the arguments of [OsRun::run_executable].
+#[derive(Clone, Debug, PartialEq)]
+pub struct PipeStage {
+    pub label: String,
+    pub exe_file: PathBuf,
+    pub args: Vec<OsString>,
+    pub exe_env: EnvSpec,
+    pub exe_opts: ExecOptions,
+    pub fromdir: Option<PathBuf>,
 }
 
 /// The OsRunResult is the return value from the `run_executable` and
@@ -86,6 +302,48 @@ pub enum OsRunResult {
     ExecError(Option<i32>, String),
     RunError(anyhow::Error),
     BadDirectory(PathBuf, std::io::Error),
+    /// The executable was killed after running for the specified time (see
+    /// [ExecOptions::timeout]); also provides the stderr output up to that
+    /// point.
+    TimedOut(Duration, String),
+}
+
+/// The OsRunHandle is returned by [OsRun::start_executable] to track an
+/// executable that may still be running.
+pub enum OsRunHandle {
+    /// The executable has already completed with the specified result.
+    Completed(OsRunResult),
+
+    /// The executable is being run by the specified thread, which returns the
+    /// result when the executable completes.  The thread should unpark the
+    /// thread that started it upon completion so that the latter can promptly
+    /// respond to the completion.
+    Running(thread::JoinHandle<OsRunResult>),
+}
+
+impl OsRunHandle {
+    /// Returns true if the executable has completed and the [OsRunHandle::wait]
+    /// method will return the result without blocking.
+    pub fn is_complete(&self) -> bool
+    {
+        match self {
+            OsRunHandle::Completed(_) => true,
+            OsRunHandle::Running(h) => h.is_finished(),
+        }
+    }
+
+    /// Waits for the executable to complete (if it has not already) and returns
+    /// the result.
+    pub fn wait(self) -> OsRunResult
+    {
+        match self {
+            OsRunHandle::Completed(r) => r,
+            OsRunHandle::Running(h) =>
+                h.join().unwrap_or_else(
+                    |_| OsRunResult::RunError(
+                        anyhow::anyhow!("Thread running executable panicked"))),
+        }
+    }
 }
 
 
@@ -382,6 +640,316 @@ impl Executor {
     {
         fromdir.as_ref().map(|p| Ok(p.clone().into())).unwrap_or_else(current_dir)
     }
+
+    fn echo_executable(&self,
+                       label: &str,
+                       exe_file: &Path,
+                       args: &[OsString],
+                       exe_opts: &ExecOptions,
+                       tgtdir: &Path)
+    {
+        match &self {
+            Executor::NormalRun => {}
+            Executor::NormalWithLabel => eprintln!("#=> {}", label),
+            Executor::NormalWithEcho |
+            Executor::DryRun =>
+                eprintln!("#: {} {}{}{} [in {}]",
+                          exe_file.display(),
+                          args.iter().map(|x| x.to_str().unwrap())
+                          .collect::<Vec<_>>().join(" "),
+                          exe_opts.stdin.iter()
+                          .map(|i| format!(" < {}", i.display()))
+                          .collect::<String>(),
+                          exe_opts.stdout.as_ref()
+                          .map(|o| format!(" > {}", o.display()))
+                          .unwrap_or_default(),
+                          tgtdir.display())
+        }
+    }
+}
+
+// Creates the Command that will run the executable in a subprocess.  This
+// fails if the stdin files cannot be opened or the stdout file cannot be
+// created.
+fn mk_command(exe_file: &Path,
+              args: &[OsString],
+              exe_env: &EnvSpec,
+              exe_opts: &ExecOptions,
+              tgtdir: &Path) -> std::io::Result<process::Command>
+{
+    let stdin = stdin_source(&exe_opts.stdin, tgtdir)?;
+    let stdout = stdout_dest(&exe_opts.stdout, tgtdir)?;
+    Ok(mk_stdio_command(exe_file, args, exe_env, exe_opts, tgtdir, stdin, stdout))
+}
+
+// Creates the Command that will run the executable in a subprocess with the
+// specified stdin and stdout.  An executable with a timeout is run in its own
+// process group so that the entire group can be killed if the timeout expires.
+fn mk_stdio_command(exe_file: &Path,
+                    args: &[OsString],
+                    exe_env: &EnvSpec,
+                    exe_opts: &ExecOptions,
+                    tgtdir: &Path,
+                    stdin: process::Stdio,
+                    stdout: process::Stdio) -> process::Command
+{
+    let mut cmnd = process::Command::new(exe_file);
+    update_env(cmnd.args(args)
+               .current_dir(tgtdir)
+               .stdin(stdin)
+               .stdout(stdout)
+               .stderr(process::Stdio::piped()),
+               exe_env);
+    #[cfg(unix)]
+    if exe_opts.timeout.is_some() {
+        std::os::unix::process::CommandExt::process_group(&mut cmnd, 0);
+    }
+    cmnd
+}
+
+// Determines the stdout for the subprocess: either the specified file or
+// captured (and discarded).
+fn stdout_dest(outfile: &Option<PathBuf>, tgtdir: &Path) -> std::io::Result<process::Stdio>
+{
+    match outfile {
+        Some(outfile) => Ok(process::Stdio::from(File::create(tgtdir.join(outfile))?)),
+        None => Ok(process::Stdio::piped()),
+    }
+}
+
+// Creates the Command for each stage of a pipeline, connecting the stdout of
+// each stage to the stdin of the next stage via the spawn callback, which
+// starts the Command and returns the (pipe) stdout of the started process if
+// the stage's output is piped to a subsequent stage.
+// Stages that cannot be started have their result set here; the next stage then
+// receives an empty stdin.  This is common to the synchronous and asynchronous
+// forms of pipeline execution.
+fn spawn_pipeline<Spawn>(executor: &Executor,
+                         stages: &[PipeStage],
+                         results: &mut [Option<OsRunResult>],
+                         mut spawn: Spawn)
+where Spawn: FnMut(usize, process::Command, bool) -> std::io::Result<Option<process::Stdio>>
+{
+    let mut prev_stdout: Option<process::Stdio> = None;
+    for (n, stage) in stages.iter().enumerate() {
+        let pipe_in = prev_stdout.take();
+        let tgtdir = match Executor::get_dir(&stage.fromdir) {
+            Ok(tgtdir) => tgtdir,
+            Err(e) => {
+                results[n] = Some(OsRunResult::BadDirectory(".".into(), e));
+                continue;
+            }
+        };
+        executor.echo_executable(&stage.label, &stage.exe_file, &stage.args,
+                                 &stage.exe_opts, &tgtdir);
+        if let Executor::DryRun = executor {
+            results[n] = Some(OsRunResult::Good);
+            continue;
+        }
+        let stdin = match pipe_in {
+            Some(pipe) => Ok(pipe),
+            None if n > 0 => Ok(process::Stdio::null()),
+            None => stdin_source(&stage.exe_opts.stdin, &tgtdir),
+        };
+        let pipe_out = n + 1 < stages.len();
+        let stdout = if pipe_out {
+            Ok(process::Stdio::piped())
+        } else {
+            stdout_dest(&stage.exe_opts.stdout, &tgtdir)
+        };
+        match stdin.and_then(
+            |stdin| stdout.map(
+                |stdout| mk_stdio_command(&stage.exe_file, &stage.args,
+                                          &stage.exe_env, &stage.exe_opts,
+                                          &tgtdir, stdin, stdout)))
+            .and_then(|cmnd| spawn(n, cmnd, pipe_out))
+        {
+            Ok(stdout) => prev_stdout = stdout,
+            Err(e) => results[n] = Some(OsRunResult::ExecFailed(e)),
+        }
+    }
+}
+
+// Determines the stdin for the subprocess.  A single file is provided directly,
+// but multiple files must be concatenated: these are written to a pipe by a
+// separate thread (which simply stops if the subprocess closes its stdin).
+fn stdin_source(inpfiles: &[PathBuf], tgtdir: &Path) -> std::io::Result<process::Stdio>
+{
+    match inpfiles {
+        [] => Ok(process::Stdio::inherit()),
+        [inpfile] => Ok(process::Stdio::from(File::open(tgtdir.join(inpfile))?)),
+        _ => {
+            let mut files = inpfiles.iter()
+                .map(|f| File::open(tgtdir.join(f)))
+                .collect::<std::io::Result<Vec<_>>>()?;
+            let (reader, mut writer) = std::io::pipe()?;
+            thread::spawn(move || {
+                for f in files.iter_mut() {
+                    if std::io::copy(f, &mut writer).is_err() {
+                        break;
+                    }
+                }
+            });
+            Ok(process::Stdio::from(reader))
+        }
+    }
+}
+
+// Converts the output of a completed subprocess into the corresponding
+// OsRunResult.
+fn command_result(output: std::io::Result<process::Output>) -> OsRunResult
+{
+    match output {
+        Ok(out) => {
+            if !out.status.success() {
+                OsRunResult::ExecError(
+                    out.status.code(),
+                    String::from_utf8_lossy(&out.stderr).into_owned())
+            } else {
+                OsRunResult::Good
+            }
+        }
+        Err(e) => OsRunResult::ExecFailed(e)
+    }
+}
+
+// Actually runs the executable in a subprocess and waits for it to complete.
+fn run_command(exe_file: &Path,
+               args: &[OsString],
+               exe_env: &EnvSpec,
+               exe_opts: &ExecOptions,
+               tgtdir: &Path) -> OsRunResult
+{
+    match mk_command(exe_file, args, exe_env, exe_opts, tgtdir)
+        .and_then(|mut cmnd| cmnd.spawn())
+    {
+        Ok(child) => wait_child(child, exe_opts.timeout),
+        Err(e) => OsRunResult::ExecFailed(e),
+    }
+}
+
+// Waits for the subprocess to complete, killing it (and its process group) if
+// it does not complete within the timeout.  The stdout and stderr of the
+// subprocess are read by separate threads while waiting so that the subprocess
+// does not block on a full pipe.
+fn wait_child(mut child: process::Child, timeout: Option<Duration>) -> OsRunResult
+{
+    let Some(timeout) = timeout else {
+        return command_result(child.wait_with_output());
+    };
+    let started = Instant::now();
+    let errbuf = Arc::new(Mutex::new(Vec::new()));
+    let err_reader = child.stderr.take().map(|mut pipe| {
+        let errbuf = Arc::clone(&errbuf);
+        thread::spawn(move || {
+            let mut chunk = [0u8; 4096];
+            while let Ok(n) = pipe.read(&mut chunk) {
+                if n == 0 {
+                    break;
+                }
+                errbuf.lock().unwrap().extend_from_slice(&chunk[..n]);
+            }
+        })
+    });
+    let out_reader = child.stdout.take().map(|mut pipe| {
+        thread::spawn(move || std::io::copy(&mut pipe, &mut std::io::sink()))
+    });
+    let stderr = |errbuf: &Mutex<Vec<u8>>|
+        String::from_utf8_lossy(&errbuf.lock().unwrap()).into_owned();
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                if let Some(reader) = out_reader { let _ = reader.join(); }
+                if let Some(reader) = err_reader { let _ = reader.join(); }
+                return command_result(Ok(process::Output {
+                    status,
+                    stdout: Vec::new(),
+                    stderr: errbuf.lock().unwrap().clone(),
+                }));
+            }
+            Ok(None) if started.elapsed() >= timeout => {
+                kill_child_group(&mut child);
+                let _ = child.wait();
+                let elapsed = started.elapsed();
+                // Allow the reader to collect any remaining stderr output, but
+                // do not wait indefinitely: a process outside of the killed
+                // process group may still hold the pipe open.
+                let grace = Instant::now();
+                while err_reader.as_ref().is_some_and(|r| ! r.is_finished())
+                    && grace.elapsed() < Duration::from_millis(100)
+                {
+                    thread::sleep(Duration::from_millis(5));
+                }
+                return OsRunResult::TimedOut(elapsed, stderr(&errbuf));
+            }
+            Ok(None) =>
+                thread::sleep(Duration::from_millis(10)
+                              .min(timeout.saturating_sub(started.elapsed()))),
+            Err(e) => return OsRunResult::ExecFailed(e),
+        }
+    }
+}
+
+// Kills the subprocess along with any other processes in its process group
+// (see mk_stdio_command).
+fn kill_child_group(child: &mut process::Child)
+{
+    #[cfg(unix)]
+    // SAFETY: killpg has no memory safety requirements; the process group id
+    // is the subprocess pid because the subprocess is the group leader.
+    unsafe { libc::killpg(child.id() as libc::pid_t, libc::SIGKILL); }
+    #[cfg(not(unix))]
+    let _ = child.kill();
+}
+
+// Asynchronously waits for the subprocess to complete, killing it (and its
+// process group) if it does not complete within the timeout.
+#[cfg(feature = "async")]
+async fn wait_child_async(mut child: tokio::process::Child,
+                          timeout: Option<Duration>) -> OsRunResult
+{
+    use tokio::io::AsyncReadExt;
+    let Some(timeout) = timeout else {
+        return command_result(child.wait_with_output().await);
+    };
+    let started = Instant::now();
+    let mut stderr = child.stderr.take();
+    let mut stdout = child.stdout.take();
+    let mut errbuf = Vec::new();
+    let mut outbuf = Vec::new();
+    let waited = tokio::time::timeout(timeout, async {
+        let (status, _, _) = tokio::join!(
+            child.wait(),
+            async { match stderr.as_mut() {
+                Some(pipe) => pipe.read_to_end(&mut errbuf).await,
+                None => Ok(0),
+            } },
+            async { match stdout.as_mut() {
+                Some(pipe) => pipe.read_to_end(&mut outbuf).await,
+                None => Ok(0),
+            } });
+        status
+    }).await;
+    match waited {
+        Ok(status) => command_result(status.map(|status| process::Output {
+            status,
+            stdout: outbuf,
+            stderr: errbuf,
+        })),
+        Err(_) => {
+            #[cfg(unix)]
+            if let Some(pid) = child.id() {
+                // SAFETY: see kill_child_group
+                unsafe { libc::killpg(pid as libc::pid_t, libc::SIGKILL); }
+            }
+            #[cfg(not(unix))]
+            let _ = child.start_kill();
+            let elapsed = started.elapsed();
+            let _ = child.wait().await;
+            OsRunResult::TimedOut(elapsed,
+                                  String::from_utf8_lossy(&errbuf).into_owned())
+        }
+    }
 }
 
 // Modifications to Command environment settings.  Expects the EnvSpec to be
@@ -424,59 +992,97 @@ impl OsRun for Executor {
                       exe_file: &Path,
                       args: &Vec<OsString>,
                       exe_env: &EnvSpec,
+                      exe_opts: &ExecOptions,
                       fromdir: &Option<PathBuf>) -> OsRunResult
     {
         match Executor::get_dir(fromdir) {
             Ok(tgtdir) => {
+                self.echo_executable(label, exe_file, args, exe_opts, &tgtdir);
                 match &self {
-                    Executor::NormalRun => {}
-                    Executor::NormalWithLabel => eprintln!("#=> {}", label),
-                    Executor::NormalWithEcho |
-                    Executor::DryRun =>
-                        eprintln!("#: {} {} [in {}]",
-                                  exe_file.display(),
-                                  args.iter().map(|x| x.to_str().unwrap())
-                                  .collect::<Vec<_>>().join(" "),
-                                  tgtdir.display())
+                    Executor::NormalRun |
+                    Executor::NormalWithLabel |
+                    Executor::NormalWithEcho =>
+                        run_command(exe_file, args, exe_env, exe_opts, &tgtdir),
+                    Executor::DryRun => OsRunResult::Good
                 }
+            }
+            Err(e) => OsRunResult::BadDirectory(".".into(), e)
+        }
+    }
+
+    fn start_executable(&self,
+                        label: &str,
+                        exe_file: &Path,
+                        args: &Vec<OsString>,
+                        exe_env: &EnvSpec,
+                        exe_opts: &ExecOptions,
+                        fromdir: &Option<PathBuf>) -> OsRunHandle
+    {
+        match Executor::get_dir(fromdir) {
+            Ok(tgtdir) => {
+                self.echo_executable(label, exe_file, args, exe_opts, &tgtdir);
                 match &self {
                     Executor::NormalRun |
                     Executor::NormalWithLabel |
                     Executor::NormalWithEcho => {
-                        match update_env(process::Command::new(&exe_file)
-                                         .args(args)
-                                         .current_dir(&tgtdir)
-                                         .stdout(process::Stdio::piped())
-                                         .stderr(process::Stdio::piped()),
-                                         exe_env).spawn()
-                        {
-                            Ok(child) => {
-                                match child.wait_with_output() {
-                                    Ok(out) => {
-                                        if !out.status.success() {
-                                            OsRunResult::ExecError(
-                                                out.status.code(),
-                                                String::from_utf8_lossy(&out.stderr).into_owned())
-                                        } else {
-                                            OsRunResult::Good
-                                        }
-                                    }
-                                    Err(e) => OsRunResult::ExecFailed(e)
-                                }
-                            }
-                            Err(e) => OsRunResult::ExecFailed(e)
-                        }
+                        let exe_file = exe_file.to_path_buf();
+                        let args = args.clone();
+                        let exe_env = exe_env.clone();
+                        let exe_opts = exe_opts.clone();
+                        let waiter = thread::current();
+                        OsRunHandle::Running(thread::spawn(move || {
+                            let result = run_command(&exe_file, &args,
+                                                     &exe_env, &exe_opts,
+                                                     &tgtdir);
+                            waiter.unpark();
+                            result
+                        }))
                     }
-                    Executor::DryRun => OsRunResult::Good
+                    Executor::DryRun => OsRunHandle::Completed(OsRunResult::Good)
                 }
             }
-            Err(e) => OsRunResult::BadDirectory(".".into(), e)
+            Err(e) => OsRunHandle::Completed(
+                OsRunResult::BadDirectory(".".into(), e))
+        }
+    }
+
+    fn run_pipeline(&self, stages: &[PipeStage]) -> Vec<OsRunResult>
+    {
+        let mut results: Vec<Option<OsRunResult>> =
+            stages.iter().map(|_| None).collect();
+        let mut children = Vec::new();
+        spawn_pipeline(self, stages, &mut results, |n, mut cmnd, pipe_out| {
+            let mut child = cmnd.spawn()?;
+            let stdout = if pipe_out {
+                child.stdout.take().map(process::Stdio::from)
+            } else {
+                None
+            };
+            children.push((n, child));
+            Ok(stdout)
+        });
+        // Each stage is waited for by a separate thread, which also collects
+        // the stage's stderr: waiting for the stages in turn could deadlock
+        // if a later stage blocks writing to its (full) stderr pipe.
+        let waiters = children.into_iter()
+            .map(|(n, child)| {
+                let timeout = stages[n].exe_opts.timeout;
+                (n, thread::spawn(move || wait_child(child, timeout)))
+            })
+            .collect::<Vec<_>>();
+        for (n, waiter) in waiters {
+            results[n] = Some(
+                waiter.join()
+                    .unwrap_or_else(
+                        |_| OsRunResult::RunError(
+                            anyhow::anyhow!("Thread running executable panicked"))));
         }
+        results.into_iter().map(|r| r.unwrap_or(OsRunResult::Good)).collect()
     }
 
     fn run_function(&self,
                     name : &str,
-                    call : &Rc<dyn Fn(&Path, &ActualFile, &ActualFile) -> anyhow::Result<()>>,
+                    call : &OpFunction,
                     inpfiles: &ActualFile,
                     outfile: &ActualFile,
                     fromdir: &Option<PathBuf>) -> OsRunResult
@@ -536,6 +1142,140 @@ impl OsRun for Executor {
                 Ok(tempfile::Builder::new().suffix(suffix).tempfile()?),
         }
     }
+
+    fn delay(&self, duration: Duration)
+    {
+        match &self {
+            Executor::NormalRun |
+            Executor::NormalWithLabel |
+            Executor::NormalWithEcho => thread::sleep(duration),
+            Executor::DryRun => {}
+        }
+    }
+
+    fn report_up_to_date(&self, label: &str, outfile: &Path)
+    {
+        match &self {
+            Executor::NormalRun => {}
+            Executor::NormalWithLabel => eprintln!("#=> {} (up to date)", label),
+            Executor::NormalWithEcho |
+            Executor::DryRun =>
+                eprintln!("#: {} is up to date: {}", label, outfile.display()),
+        }
+    }
+
+    fn restore_cached(&self, label: &str, cached: &Path, outfile: &Path)
+                      -> anyhow::Result<()>
+    {
+        match &self {
+            Executor::NormalRun => {}
+            Executor::NormalWithLabel => eprintln!("#=> {} (cached)", label),
+            Executor::NormalWithEcho |
+            Executor::DryRun =>
+                eprintln!("#: {} restored from cache: {} <- {}", label,
+                          outfile.display(), cached.display()),
+        }
+        match &self {
+            Executor::DryRun => Ok(()),
+            _ => {
+                std::fs::copy(cached, outfile)?;
+                Ok(())
+            }
+        }
+    }
+
+    fn store_cached(&self, outfile: &Path, cached: &Path) -> anyhow::Result<()>
+    {
+        match &self {
+            Executor::DryRun => Ok(()),
+            _ => {
+                std::fs::copy(outfile, cached)?;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl AsyncOsRun for Executor {
+
+    async fn run_executable_async(&self,
+                                  label: &str,
+                                  exe_file: &Path,
+                                  args: &[OsString],
+                                  exe_env: &EnvSpec,
+                                  exe_opts: &ExecOptions,
+                                  fromdir: &Option<PathBuf>) -> OsRunResult
+    {
+        match Executor::get_dir(fromdir) {
+            Ok(tgtdir) => {
+                self.echo_executable(label, exe_file, args, exe_opts, &tgtdir);
+                match &self {
+                    Executor::NormalRun |
+                    Executor::NormalWithLabel |
+                    Executor::NormalWithEcho =>
+                        match mk_command(exe_file, args, exe_env, exe_opts,
+                                         &tgtdir)
+                            .and_then(|cmnd| tokio::process::Command::from(cmnd)
+                                      .spawn())
+                        {
+                            Ok(child) => wait_child_async(child,
+                                                          exe_opts.timeout).await,
+                            Err(e) => OsRunResult::ExecFailed(e),
+                        },
+                    Executor::DryRun => OsRunResult::Good
+                }
+            }
+            Err(e) => OsRunResult::BadDirectory(".".into(), e)
+        }
+    }
+
+    async fn delay_async(&self, duration: Duration)
+    {
+        match &self {
+            Executor::NormalRun |
+            Executor::NormalWithLabel |
+            Executor::NormalWithEcho => tokio::time::sleep(duration).await,
+            Executor::DryRun => {}
+        }
+    }
+
+    async fn run_pipeline_async(&self, stages: &[PipeStage]) -> Vec<OsRunResult>
+    {
+        let mut results: Vec<Option<OsRunResult>> =
+            stages.iter().map(|_| None).collect();
+        let mut children = Vec::new();
+        spawn_pipeline(self, stages, &mut results, |n, cmnd, pipe_out| {
+            let mut child = tokio::process::Command::from(cmnd).spawn()?;
+            let stdout = if pipe_out {
+                child.stdout.take().map(|o| o.try_into()).transpose()?
+            } else {
+                None
+            };
+            children.push((n, Box::pin(wait_child_async(child,
+                                                        stages[n].exe_opts.timeout))));
+            Ok(stdout)
+        });
+        // All of the stages are awaited together (see the note in the
+        // synchronous run_pipeline).
+        let mut outputs: Vec<_> = children.iter().map(|_| None).collect();
+        std::future::poll_fn(|cx| {
+            let mut pending = false;
+            for ((_, child), output) in children.iter_mut().zip(outputs.iter_mut()) {
+                if output.is_none() {
+                    match child.as_mut().poll(cx) {
+                        std::task::Poll::Ready(out) => *output = Some(out),
+                        std::task::Poll::Pending => pending = true,
+                    }
+                }
+            }
+            if pending { std::task::Poll::Pending } else { std::task::Poll::Ready(()) }
+        }).await;
+        for ((n, _), output) in children.iter().zip(outputs) {
+            results[*n] = output;
+        }
+        results.into_iter().map(|r| r.unwrap_or(OsRunResult::Good)).collect()
+    }
 }
 
 
@@ -674,4 +1414,80 @@ mod tests {
                 .prepend("quux", "capacitor", "**")
         )
     }
+
+    // The script writes some stderr output and starts a background process (in
+    // the same process group) that would create the marker file if it were not
+    // also killed when the timeout expires.
+    #[cfg(unix)]
+    const HANGING_SCRIPT: &str =
+        "echo started >&2; (sleep 1; touch marker) & sleep 30";
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_executable_timeout() -> anyhow::Result<()> {
+        let workdir = tempfile::tempdir()?;
+        let started = Instant::now();
+        let result = Executor::NormalRun.run_executable(
+            "sh", Path::new("sh"),
+            &vec!["-c".into(), HANGING_SCRIPT.into()],
+            &EnvSpec::StdEnv,
+            &ExecOptions { timeout: Some(Duration::from_millis(300)),
+                           ..ExecOptions::default() },
+            &Some(workdir.path().to_path_buf()));
+        match result {
+            OsRunResult::TimedOut(elapsed, stderr) => {
+                assert!(elapsed >= Duration::from_millis(300));
+                assert_eq!(stderr, "started\n");
+            }
+            _ => panic!("Expected the executable to time out"),
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(1500));
+        assert!(! workdir.path().join("marker").exists());
+        Ok(())
+    }
+
+    #[cfg(all(feature = "async", unix))]
+    #[tokio::test]
+    async fn test_run_executable_async_timeout() -> anyhow::Result<()> {
+        let workdir = tempfile::tempdir()?;
+        let result = Executor::NormalRun.run_executable_async(
+            "sh", Path::new("sh"),
+            &["-c".into(), HANGING_SCRIPT.into()],
+            &EnvSpec::StdEnv,
+            &ExecOptions { timeout: Some(Duration::from_millis(300)),
+                           ..ExecOptions::default() },
+            &Some(workdir.path().to_path_buf())).await;
+        match result {
+            OsRunResult::TimedOut(elapsed, stderr) => {
+                assert!(elapsed >= Duration::from_millis(300));
+                assert_eq!(stderr, "started\n");
+            }
+            _ => panic!("Expected the executable to time out"),
+        }
+        thread::sleep(Duration::from_millis(1500));
+        assert!(! workdir.path().join("marker").exists());
+        Ok(())
+    }
+
+    #[cfg(all(feature = "async", unix))]
+    #[tokio::test]
+    async fn test_run_executable_async() {
+        let run = |args: &[&str]| {
+            let args : Vec<OsString> = args.iter().map(Into::into).collect();
+            async move {
+                Executor::NormalRun.run_executable_async(
+                    "sh", Path::new("sh"), &args,
+                    &EnvSpec::StdEnv.add("CHAINSOP_TEST", "7"),
+                    &ExecOptions::default(),
+                    &Some(PathBuf::from("/"))).await
+            }
+        };
+        assert!(matches!(run(&["-c", "test \"$(pwd)\" = /"]).await,
+                         OsRunResult::Good));
+        match run(&["-c", "echo oops >&2; exit $CHAINSOP_TEST"]).await {
+            OsRunResult::ExecError(Some(7), stderr) => assert_eq!(stderr, "oops\n"),
+            _ => panic!("Expected exit code 7 with stderr output"),
+        }
+    }
 }
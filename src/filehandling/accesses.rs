@@ -1,14 +1,15 @@
-use std::cell::RefCell;
 use std::ffi::{OsString};
 use std::fs::File;
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
 use anyhow;
 
 use crate::errors::ChainsopError;
 use crate::filehandling::defs::*;
 use crate::execution::OsRun;
+use crate::shared::{Shared, SharedCell};
+#[cfg(feature = "sync")]
+use crate::shared::SharedBorrow;
 
 
 impl ActualFile {
@@ -37,6 +38,23 @@ impl ActualFile {
         }
     }
 
+    /// Returns another ActualFile that references the same file(s).  Any
+    /// temporary files are shared, and remain in existence until all of the
+    /// references to them have been dropped.
+    pub(crate) fn share(&self) -> ActualFile
+    {
+        let share_ref = |fref: &FileRef| match fref {
+            FileRef::StaticFile(p) => FileRef::StaticFile(p.clone()),
+            FileRef::TempFile(tf) => FileRef::TempFile(Shared::clone(tf)),
+        };
+        match self {
+            ActualFile::NoActualFile => ActualFile::NoActualFile,
+            ActualFile::SingleFile(fref) => ActualFile::SingleFile(share_ref(fref)),
+            ActualFile::MultiFile(frefs) =>
+                ActualFile::MultiFile(frefs.iter().map(share_ref).collect()),
+        }
+    }
+
     /// Gets the Path associated with a ActualFile or returns an error if there
     /// is no Path.  This expects there to be a single path and will generate an
     /// error if there is no file or there are multiple files.  The cwd is
@@ -105,7 +123,7 @@ impl ActualFile {
         match self {
             ActualFile::NoActualFile => Ok(CAFl::AD),
             ActualFile::SingleFile(FileRef::StaticFile(pb)) =>
-                Ok(CAFl::AF(Rc::new(RefCell::new(File::options()
+                Ok(CAFl::AF(Shared::new(SharedCell::new(File::options()
                                                  .create(true).append(true)
                                                  .open(pb)?)))),
             ActualFile::SingleFile(FileRef::TempFile(tf)) =>
@@ -116,7 +134,7 @@ impl ActualFile {
                 } else {
                     match &mf[0] {
                         FileRef::StaticFile(pb) =>
-                            Ok(CAFl::AF(Rc::new(RefCell::new(File::options()
+                            Ok(CAFl::AF(Shared::new(SharedCell::new(File::options()
                                                              .create(true)
                                                              .append(true)
                                                              .open(pb)?)))),
@@ -142,7 +160,7 @@ where E: Fn() -> anyhow::Result<ActualFile>,
         FileArg::TBD => on_missing(),
         FileArg::Temp(sfx) => {
             let tf = executor.mk_tempfile(sfx)?;
-            Ok(ActualFile::SingleFile(FileRef::TempFile(Rc::new(RefCell::new(tf)))))
+            Ok(ActualFile::SingleFile(FileRef::TempFile(Shared::new(SharedCell::new(tf)))))
         }
         FileArg::Loc(fpath) => {
             Ok(ActualFile::SingleFile(FileRef::StaticFile(fpath.clone())))
@@ -159,6 +177,16 @@ where E: Fn() -> anyhow::Result<ActualFile>,
                 })?;
             Ok(ActualFile::MultiFile(fpaths))
         }
+        FileArg::OutputOf(output) => match output.paths() {
+            Some(mut paths) if paths.len() == 1 =>
+                Ok(ActualFile::SingleFile(FileRef::StaticFile(paths.pop().unwrap()))),
+            Some(paths) if paths.is_empty() => Ok(ActualFile::NoActualFile),
+            Some(paths) =>
+                Ok(ActualFile::MultiFile(paths.into_iter().map(FileRef::StaticFile)
+                                         .collect())),
+            None => Err(anyhow::Error::new(
+                ChainsopError::ErrorMissingOutput(output.label().to_string()))),
+        }
     }
 }
 
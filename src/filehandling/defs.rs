@@ -1,9 +1,11 @@
-use std::cell::RefCell;
 use std::fs::File;
 use std::path::{Path,PathBuf};
-use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 use tempfile;
 
+use crate::operations::chained::ChainedOpRef;
+use crate::shared::{Shared, SharedCell};
+
 
 /// Designates a type of file that can be identified by name on the command line.
 #[derive(Clone, Debug, PartialEq)]
@@ -17,6 +19,10 @@ pub enum FileArg {
     /// Create a temporary file; str is suffix to give temporary filename.
     Temp(String),
 
+    /// The output file(s) of an earlier operation in a chain (see
+    /// [FileArg::output_of]).
+    OutputOf(OpOutput),
+
     /// Allowed on initial construction, but causes a runtime error on the call
     /// to execute an operation if it has not been converted to one of the other
     /// forms before the execute call.
@@ -47,6 +53,91 @@ impl FileArg {
     {
         FileArg::GlobIn(dpath.into(), glob.into())
     }
+
+    /// Generates a reference to the output file(s) of an operation in a chain,
+    /// which is resolved to the actual output of that operation when the chain
+    /// is executed.  This is normally used as an input file of a later
+    /// operation in the same chain (or in a chain nested within that chain),
+    /// allowing an operation to use the output of any earlier operation instead
+    /// of (or in addition to) the output of the immediately preceding operation.
+    /// Any temporary output file remains in existence until the execution of
+    /// the chain has completed.
+    ///
+    /// It is an error to use this when the referenced operation has not been
+    /// performed in the current execution of the chain (e.g. it is disabled,
+    /// it failed, or it was completed in an earlier execution resumed via a
+    /// checkpoint).  For a [crate::ChainMode::Dag] chain, the operation using
+    /// this should depend upon the referenced operation.
+    pub fn output_of(op: &ChainedOpRef) -> FileArg
+    {
+        FileArg::OutputOf(op.output_slot())
+    }
+}
+
+/// The output of an operation in a chain, as referenced by [FileArg::OutputOf].
+/// This is set when the operation is performed during the execution of the
+/// chain, and cleared when that execution completes.
+//
+// Only the paths of the output files are held here (the chain holds the output
+// itself, keeping any temporary files in existence), so that a FileArg remains
+// Send + Sync regardless of the "sync" feature.
+#[derive(Clone)]
+pub struct OpOutput {
+    label : String,
+    paths : Arc<Mutex<Option<Vec<PathBuf>>>>,
+}
+
+impl OpOutput {
+    pub(crate) fn new(label: String) -> OpOutput
+    {
+        OpOutput { label, paths : Arc::new(Mutex::new(None)) }
+    }
+
+    /// Returns the label of the operation (at the time the reference was
+    /// created).
+    pub fn label(&self) -> &str
+    {
+        &self.label
+    }
+
+    /// Returns the paths of the output file(s) of the operation, or None if it
+    /// has not been performed in the current execution of the chain.
+    pub fn paths(&self) -> Option<Vec<PathBuf>>
+    {
+        self.paths.lock().unwrap().clone()
+    }
+
+    pub(crate) fn set(&self, outfile: &ActualFile)
+    {
+        *self.paths.lock().unwrap() =
+            Some(outfile.to_paths::<PathBuf>(&None).unwrap_or_default());
+    }
+
+    pub(crate) fn clear(&self)
+    {
+        *self.paths.lock().unwrap() = None;
+    }
+
+    // Returns an identifier for the output, which is common to all clones of
+    // this OpOutput.
+    pub(crate) fn key(&self) -> *const Mutex<Option<Vec<PathBuf>>>
+    {
+        Arc::as_ptr(&self.paths)
+    }
+}
+
+impl PartialEq for OpOutput {
+    fn eq(&self, other: &Self) -> bool
+    {
+        Arc::ptr_eq(&self.paths, &other.paths)
+    }
+}
+
+impl std::fmt::Debug for OpOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+    {
+        write!(f, "OpOutput({:?})", self.label)
+    }
 }
 
 // ----------------------------------------------------------------------
@@ -177,7 +268,7 @@ pub enum FileRef {
 
     /// References a temporary file, which will cease to exist when this value is
     /// garbage collected.
-    TempFile(Rc<RefCell<tempfile::NamedTempFile>>)
+    TempFile(Shared<SharedCell<tempfile::NamedTempFile>>)
 }
 
 
@@ -189,6 +280,6 @@ pub enum FileRef {
 // except use them with their Read or Write trait methods.
 pub enum CAFl {
     AD,
-    AF(Rc<RefCell<File>>),
-    AT(Rc<RefCell<tempfile::NamedTempFile>>),
+    AF(Shared<SharedCell<File>>),
+    AT(Shared<SharedCell<tempfile::NamedTempFile>>),
 }
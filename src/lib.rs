@@ -198,11 +198,13 @@
 //!
 //! > ------
 //! >
-//! > The `chainsop` package does not provide explicit methods of capturing
-//! > `stdout` or `stderr`, nor for providing specific `stdin` to
-//! > [SubProcOperation] invocations.  Instead, `stdout` or `stderr` should be
-//! > redirected to (temporary) files which are then used as input files
-//! > (instead of `stdin`) for subsequent operations.
+//! > The `stdout` of an [Executable] can be written to its output file by
+//! > specifying [ExeFileSpec::Stdout] for the output file, and the input file(s)
+//! > can be provided as the `stdin` of an [Executable] by specifying
+//! > [ExeFileSpec::Stdin] for the input file.  The `chainsop` package does not
+//! > provide explicit methods of capturing `stderr`; instead, `stderr` should be
+//! > redirected to (temporary) files which are then used as input files for
+//! > subsequent operations.
 //! >
 //! > ------
 //!
@@ -297,6 +299,46 @@
 //! calling the [ChainedOpRef::active()] method on the [ChainedOpRef] handle for
 //! that operation in the chain.
 //!
+//! By default, the operations in a [ChainedOps] are performed sequentially.
+//! Many operations do not actually depend on each other, however: in the
+//! build example above, the two compilations are independent and only the link
+//! step requires both.  Setting the chain to [ChainMode::Dag] mode allows each
+//! operation to declare the earlier operations it depends upon (via
+//! [ChainedOpRef::depends_on()]); independent operations are then run
+//! concurrently (up to the specified number of jobs) and each dependent
+//! operation is started when its dependencies have completed.
+//!
+//! Passing large amounts of data from one operation to the next through an
+//! intermediate file can also be avoided: linking an operation to the previous
+//! operation in the chain with [ChainLink::Pipe] (via [ChainedOpRef::link()])
+//! connects the `stdout` of the previous operation to the `stdin` of the linked
+//! operation with an OS pipe, and the connected operations are run concurrently
+//! as a single pipeline (e.g. `zcat | grep | sort`).
+//!
+//! By default, the [ChainedOps], [FunctionOperation], and [FileRef] types use
+//! [std::rc::Rc] and [std::cell::RefCell] internally and therefore cannot be
+//! sent to another thread.  Enabling the "sync" feature of this crate switches
+//! these to [std::sync::Arc] and [std::sync::RwLock], making them `Send + Sync`
+//! so that independent chains can be built and then executed on a pool of
+//! worker threads.  When this feature is enabled, the functions provided to
+//! [FunctionOperation::calling] and [Activation::when] must also be
+//! `Send + Sync`.
+//!
+//! Note that a chain cannot be modified or inspected (e.g. via its
+//! [ChainedOpRef] accessors) by code that runs during the execution of that
+//! same chain, such as an [Activation::when] predicate or a
+//! [FunctionOperation] in the chain.  By default, this conflicting access
+//! panics (as a [std::cell::RefCell] borrow conflict), but when the "sync"
+//! feature is enabled it waits on the [std::sync::RwLock] that is held by the
+//! execution and therefore deadlocks.
+//!
+//! For use in an async application, the "async" feature of this crate adds the
+//! `AsyncOpInterface` trait, whose `execute_async` method performs an operation
+//! (or chain of operations) via the `AsyncOsRun` trait.  The [Executor]
+//! implementation of `AsyncOsRun` runs executables with `tokio::process`, so
+//! many chains can be executed concurrently within a single tokio runtime
+//! without dedicating a thread to each one.
+//!
 //!
 //! -----
 //! ## Structures, Traits, and their relationships:
@@ -322,7 +364,9 @@
 //!    mode is that the output file from a previous operation becomes the input
 //!    file to a subsequent operation.  Also in this mode, it is convenient to
 //!    specify that intermediate files in the chain should be temporary files
-//!    that are automatically removed upon completion of the execution.
+//!    that are automatically removed upon completion of the execution.  A
+//!    [ChainedOps] can itself be an element of another [ChainedOps] (see
+//!    [ChainedOps::push_chain]), allowing reusable sub-chains.
 //!
 //!  * ##### [FunctionOperation]
 //!
@@ -358,8 +402,8 @@
 //            in_dir                :       |  |     Append
 //                                  :       |  |     Option("-opt")
 //       [trait] FilesPrep: ........:....   |  |     ViaCall(fn)
-//                 set_input_file       :   |  |
-//                 set_output_file      :   |  |
+//                 set_input_file       :   |  |     Stdout
+//                 set_output_file      :   |  |     Stdin
 //                 set_dir              :   |  |  Specific Operations
 //                                      :   |  |  -------------------
 //                                      :   |  |
@@ -396,8 +440,8 @@
 //!
 //! * `subprocess` crate (<https://crates.io/crates/subprocess>)
 //!
-//!     The `subprocess` crate allows creation of pipelines connected via
-//!     stdin/stdout, but not sequences using shared input/output files.
+//!     Both crates allow creation of pipelines connected via stdin/stdout, but
+//!     only `chainsop` supports sequences using shared input/output files.
 //!
 //!     In addition, `chainsop` provides automatic creation and management of
 //!     temporary files used in the above.
@@ -440,22 +484,35 @@ pub mod errors;
 mod executable;
 mod operations;
 mod execution;
+mod retry;
+mod cache;
+mod shared;
 
 // Exports are setup here such that the user only needs to use the top level
 // "chainsop" module to access the public API.
 
 #[doc(inline)]
-pub use filehandling::defs::{FilesPrep,FileArg,ActualFile,FileRef};
+pub use filehandling::defs::{FilesPrep,FileArg,OpOutput,ActualFile,FileRef};
 pub use errors::*;
 #[doc(inline)]
-pub use executable::{Executable, ExeFileSpec};
+pub use executable::{Executable, ExeFileSpec, FileJoin};
 #[doc(inline)]
 pub use operations::generic::{OpInterface};
+#[cfg(feature = "async")]
+#[doc(inline)]
+pub use operations::generic::AsyncOpInterface;
 #[doc(inline)]
-pub use operations::subproc::SubProcOperation;
+pub use operations::subproc::{SubProcOperation, ArgPosition};
 #[doc(inline)]
 pub use operations::function::FunctionOperation;
 #[doc(inline)]
-pub use operations::chained::{ChainedOps, Activation, ChainedOpRef};
+pub use operations::chained::{ChainedOps, Activation, ActivationContext,
+                              ActivationPredicate, ChainMode, EachOutput, ChainLink,
+                              ChainedOpRef, ChainPolicy, ChainReport, OpReport,
+                              OpStatus, ChainStatus};
 #[doc(inline)]
 pub use execution::*;
+#[doc(inline)]
+pub use retry::{RetryPolicy, Backoff, RetryOn};
+#[doc(inline)]
+pub use cache::OutputCache;
@@ -2,6 +2,7 @@ pub mod generic;
 pub mod subproc;
 pub mod function;
 pub mod chained;
+mod checkpoint;
 pub use crate::operations::generic::*;
 pub use crate::operations::subproc::SubProcOperation;
 pub use crate::operations::function::FunctionOperation;
@@ -1,30 +1,45 @@
 use anyhow::Context;
-use std::cell::{RefCell, RefMut};
 use std::collections::HashMap;
 use std::ffi::{OsString};
 use std::fmt;
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
 use std::sync::RwLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use sha2::{Digest, Sha256};
 
 use crate::filehandling::*;
 use crate::errors::*;
 use crate::operations::generic::*;
 use crate::operations::subproc::*;
 use crate::operations::function::*;
-use crate::execution::{OsRun, EnvSpec};
+use crate::execution::{OsRun, OsRunResult, EnvSpec, PipeStage};
+use crate::retry::RetryPolicy;
+use crate::cache::OutputCache;
+use crate::operations::checkpoint::Checkpoint;
+#[cfg(feature = "async")]
+use crate::execution::AsyncOsRun;
+#[cfg(feature = "async")]
+use std::future::Future;
+use crate::shared::{Shared, SharedCell, SharedRefMut, Shareable};
+#[cfg(feature = "sync")]
+use crate::shared::SharedBorrow;
 
 
-/// Each entry in Chained operations can refer to either a sub-process operation
-/// or a function operation; the RunnableOp is a wrapper to allow Chained to
-/// homomorphically refer to these chained operations.  The impls for a
-/// RunnableOp simply pass the method through to the corresponding method in the
-/// underlying operation.
+/// Each entry in Chained operations can refer to either a sub-process operation,
+/// a function operation, or another (nested) chain of operations; the
+/// RunnableOp is a wrapper to allow Chained to homomorphically refer to these
+/// chained operations.  The impls for a RunnableOp simply pass the method
+/// through to the corresponding method in the underlying operation.
+// The elements are held in the chain's Vec and are rarely moved, so the size
+// difference between the variants is not significant.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 enum RunnableOp {
     Exec(SubProcOperation),
     Call(FunctionOperation),
-    // n.b. could be extended to allow nesting of ChainedOps as well if desired
+    Chain(ChainedOps),
 }
 
 macro_rules! runnable_passthru_call {
@@ -32,24 +47,40 @@ macro_rules! runnable_passthru_call {
         match $me {
             Self::Exec(sp) => sp.$method($($arg,)*),
             Self::Call(fp) => fp.$method($($arg,)*),
+            Self::Chain(cp) => cp.$method($($arg,)*),
         }
     };
     ($me:ident, exec-only $default:expr, or $method:ident with $($arg:ident),*) => {
+        match $me {
+            Self::Exec(sp) => sp.$method($($arg,)*),
+            Self::Call(_) | Self::Chain(_) => $default,
+        }
+    };
+    ($me:ident, with-env $default:expr, or $method:ident with $($arg:ident),*) => {
         match $me {
             Self::Exec(sp) => sp.$method($($arg,)*),
             Self::Call(_) => $default,
+            Self::Chain(cp) => cp.$method($($arg,)*),
         }
     };
     (mutable $me:ident, $method:ident with $($arg:ident),*) => {
         match *$me {
             Self::Exec(ref mut sp) => { sp.$method($($arg,)*); },
             Self::Call(ref mut fp) => { fp.$method($($arg,)*); },
+            Self::Chain(ref mut cp) => { cp.$method($($arg,)*); },
         }
     };
     (mutable $me:ident exec-only $method:ident with $($arg:ident),*) => {
+        match *$me {
+            Self::Exec(ref mut sp) => { sp.$method($($arg,)*); },
+            Self::Call(_) | Self::Chain(_) => {},
+        }
+    };
+    (mutable $me:ident with-env $method:ident with $($arg:ident),*) => {
         match *$me {
             Self::Exec(ref mut sp) => { sp.$method($($arg,)*); },
             Self::Call(_) => {},
+            Self::Chain(ref mut cp) => { cp.$method($($arg,)*); },
         }
     }
 }
@@ -62,31 +93,45 @@ macro_rules! runnable_op_passthru {
             self
         }
     };
-    ($method:ident exec-only with) => {
+    ($method:ident exec-only with $argty:ty) => {
+        fn $method(&mut self, arg: $argty) -> &mut Self
+        {
+            runnable_passthru_call!(mutable self exec-only $method with arg);
+            self
+        }
+    };
+    ($method:ident exec-only with $argty:ty, $argty2:ty) => {
+        fn $method(&mut self, arg: $argty, arg2: $argty2) -> &mut Self
+        {
+            runnable_passthru_call!(mutable self exec-only $method with arg, arg2);
+            self
+        }
+    };
+    ($method:ident with-env with) => {
         fn $method(&mut self) -> &mut Self
         {
-            runnable_passthru_call!(mutable self exec-only $method with);
+            runnable_passthru_call!(mutable self with-env $method with);
             self
         }
     };
-    ($method:ident exec-only with $argty:ty) => {
+    ($method:ident with-env with $argty:ty) => {
         fn $method(&mut self, arg: $argty) -> &mut Self
         {
-            runnable_passthru_call!(mutable self exec-only $method with arg);
+            runnable_passthru_call!(mutable self with-env $method with arg);
             self
         }
     };
-    ($method:ident exec-only with $argty:ty, $argty2:ty) => {
+    ($method:ident with-env with $argty:ty, $argty2:ty) => {
         fn $method(&mut self, arg: $argty, arg2: $argty2) -> &mut Self
         {
-            runnable_passthru_call!(mutable self exec-only $method with arg, arg2);
+            runnable_passthru_call!(mutable self with-env $method with arg, arg2);
             self
         }
     };
-    ($method:ident exec-only with $argty:ty, $argty2:ty, $argty3:ty) => {
+    ($method:ident with-env with $argty:ty, $argty2:ty, $argty3:ty) => {
         fn $method(&mut self, arg: $argty, arg2: $argty2, arg3: $argty3) -> &mut Self
         {
-            runnable_passthru_call!(mutable self exec-only $method with arg, arg2, arg3);
+            runnable_passthru_call!(mutable self with-env $method with arg, arg2, arg3);
             self
         }
     };
@@ -96,10 +141,10 @@ macro_rules! runnable_op_passthru {
             runnable_passthru_call!(self, $method with)
         }
     };
-    ($method:ident exec-only returning $rty:ty := $default:expr) => {
+    ($method:ident with-env returning $rty:ty := $default:expr) => {
         fn $method(&self) -> $rty
         {
-            runnable_passthru_call!(self, exec-only $default, or $method with)
+            runnable_passthru_call!(self, with-env $default, or $method with)
         }
     }
 }
@@ -130,17 +175,154 @@ impl OpInterface for RunnableOp {
     }
 }
 
+#[cfg(feature = "async")]
+impl RunnableOp {
+    async fn execute_async<Exec, P>(&mut self, executor: &Exec, cwd: &Option<P>)
+                                    -> anyhow::Result<ActualFile>
+    where P: AsRef<Path>, Exec: AsyncOsRun
+    {
+        match self {
+            Self::Exec(sp) => sp.execute_async(executor, cwd).await,
+            Self::Call(fp) => fp.execute_async(executor, cwd).await,
+            // The nested chain execution is boxed because it is recursive.
+            Self::Chain(cp) => Box::pin(cp.execute_async(executor, cwd)).await,
+        }
+    }
+
+    // Returns a copy of this element that can be executed without the chain
+    // being borrowed, after which it replaces this element (see
+    // perform_op_async).  A nested chain is shared rather than copied.
+    fn detached(&self) -> RunnableOp
+    {
+        match self {
+            Self::Exec(sp) => Self::Exec(sp.clone()),
+            Self::Call(fp) => Self::Call(fp.clone()),
+            Self::Chain(cp) => Self::Chain(cp.shared()),
+        }
+    }
+}
+
 impl RunnableOp {
     runnable_op_passthru!(set_executable exec-only with PathBuf);
     runnable_op_passthru!(push_arg exec-only with OsString);
-    runnable_op_passthru!(clear_env exec-only with);
-    runnable_op_passthru!(set_env exec-only with String, String);
-    runnable_op_passthru!(prepend_env exec-only with String, String, String);
-    runnable_op_passthru!(append_env exec-only with String, String, String);
-    runnable_op_passthru!(unset_env exec-only with String);
-    runnable_op_passthru!(set_base_env exec-only with &EnvSpec);
-    runnable_op_passthru!(set_full_env exec-only with &EnvSpec);
-    runnable_op_passthru!(get_full_env exec-only returning EnvSpec := EnvSpec::StdEnv);
+    runnable_op_passthru!(push_arg_at exec-only with ArgPosition, OsString);
+    runnable_op_passthru!(accept_exit_code exec-only with i32);
+    runnable_op_passthru!(clear_env with-env with);
+    runnable_op_passthru!(set_env with-env with String, String);
+    runnable_op_passthru!(prepend_env with-env with String, String, String);
+    runnable_op_passthru!(append_env with-env with String, String, String);
+    runnable_op_passthru!(unset_env with-env with String);
+    runnable_op_passthru!(set_base_env with-env with &EnvSpec);
+    runnable_op_passthru!(set_full_env with-env with &EnvSpec);
+    runnable_op_passthru!(get_full_env with-env returning EnvSpec := EnvSpec::StdEnv);
+    runnable_op_passthru!(set_timeout with-env with Duration);
+    runnable_op_passthru!(set_base_timeout with-env with &Option<Duration>);
+    runnable_op_passthru!(set_full_timeout with-env with &Option<Duration>);
+    runnable_op_passthru!(get_full_timeout with-env returning Option<Duration> := None);
+    runnable_op_passthru!(set_cache with-env with &OutputCache);
+    runnable_op_passthru!(set_base_cache with-env with &Option<OutputCache>);
+    runnable_op_passthru!(set_full_cache with-env with &Option<OutputCache>);
+    runnable_op_passthru!(get_full_cache with-env returning Option<OutputCache> := None);
+
+    fn exit_code(&self) -> Option<i32>
+    {
+        runnable_passthru_call!(self, exec-only None, or exit_code with)
+    }
+
+    fn command_line(&self) -> Vec<OsString>
+    {
+        runnable_passthru_call!(self, exec-only Vec::new(), or command_line with)
+    }
+
+    fn args(&self) -> Vec<OsString>
+    {
+        match self {
+            Self::Exec(sp) => sp.args(),
+            Self::Call(_) | Self::Chain(_) => Vec::new(),
+        }
+    }
+
+    fn files(&self) -> FileTransformation
+    {
+        match self {
+            Self::Exec(sp) => sp.files().clone(),
+            Self::Call(fp) => fp.files().clone(),
+            Self::Chain(cp) => cp.chops.borrow().files.clone(),
+        }
+    }
+
+    fn set_input_files(&mut self, fnames: &[FileArg])
+    {
+        match self {
+            Self::Exec(sp) => sp.set_input_files(fnames),
+            Self::Call(fp) => fp.set_input_files(fnames),
+            Self::Chain(cp) => cp.chops.borrow_mut().files.inp_filenames = fnames.to_vec(),
+        }
+    }
+
+    // Returns a description of the definition of this operation (excluding its
+    // files), for the fingerprint of a chain checkpoint.
+    fn definition(&self) -> String
+    {
+        match self {
+            Self::Exec(sp) => sp.definition(),
+            Self::Call(fp) => format!("call {:?}", fp.label()),
+            Self::Chain(cp) => format!("chain {}", chain_definition(&cp.chops.borrow())),
+        }
+    }
+
+    // Returns the report for the operations in a nested chain (from its most
+    // recent execution); empty for any other operation.
+    fn nested_report(&self) -> Vec<OpReport>
+    {
+        match self {
+            Self::Chain(cp) => cp.chops.borrow().report(),
+            Self::Exec(_) | Self::Call(_) => Vec::new(),
+        }
+    }
+
+    // A nested chain has no retry policy of its own: the policy applies to the
+    // individual operations within that chain.
+    fn set_retry(&mut self, policy: &RetryPolicy) -> &mut Self
+    {
+        match self {
+            Self::Exec(sp) => { sp.set_retry(policy); },
+            Self::Call(fp) => { fp.set_retry(policy); },
+            Self::Chain(_) => {},
+        }
+        self
+    }
+
+    // Applies the chain settings to this operation for its execution (the
+    // operation's own settings take precedence).  Returns the original settings
+    // of the operation, which should be restored via restore_settings after the
+    // execution.
+    fn inherit_settings(&mut self, chain: &ChainSettings) -> ChainSettings
+    {
+        let orig = ChainSettings { env : self.get_full_env(),
+                                   timeout : self.get_full_timeout(),
+                                   cache : self.get_full_cache() };
+        self.set_base_env(&chain.env);
+        self.set_base_timeout(&chain.timeout);
+        self.set_base_cache(&chain.cache);
+        orig
+    }
+
+    fn restore_settings(&mut self, orig: &ChainSettings)
+    {
+        self.set_full_env(&orig.env);
+        self.set_full_timeout(&orig.timeout);
+        self.set_full_cache(&orig.cache);
+    }
+}
+
+// The settings of a chain that are inherited by each operation in the chain
+// when it is executed.
+#[derive(Clone, Debug)]
+struct ChainSettings {
+    env : EnvSpec,
+    timeout : Option<Duration>,
+    cache : Option<OutputCache>,
 }
 
 // ----------------------------------------------------------------------
@@ -157,9 +339,10 @@ impl RunnableOp {
 //    To support this access and honor Rust's ownership rules, this means that
 //   the result references the underlying ChainedOpsInternals via a
 //   reference counted (Rc) cell (RefCell) to maintain a single copy via the Rc
-//   but allow updates of that object via the RefCell.
+//   but allow updates of that object via the RefCell (these are the Arc and
+//   RwLock equivalents when the "sync" feature is enabled; see shared.rs).
 //
-//   To hide the complexity of the Rc<RefCell<ChainedOpsInternals>> from the
+//   To hide the complexity of the Shared<SharedCell<ChainedOpsInternals>> from the
 //   user, this value is wrapped internally in the ChainedOps struct.
 //
 //   User API operations are therefore primarily defined for the ChainedOps
@@ -198,7 +381,7 @@ pub struct ChainedOps {
     // nest .borrow() calls, but not .borrow_mut(), and this is a *runtime*
     // error, so careful use of chops is needed since Rust's borrow checking
     // cannot validate the code.
-    chops : Rc<RefCell<ChainedOpsInternals>>,
+    chops : Shared<SharedCell<ChainedOpsInternals>>,
 
     // execlock is a lock that is used to ensure that this ChainedOps is *not*
     // being executed in parallel because that would cause conflicting
@@ -220,12 +403,30 @@ struct ChainedOpsInternals {
     // The chain of operations to execute.
     chain : Vec<RunnableOp>,
 
+    // The identifier of each element of the chain (in chain order), as
+    // referenced by a ChainedOpRef.  Unlike the chain index, the identifier of
+    // an element does not change when elements are inserted, removed or moved.
+    ids : Vec<usize>,
+
+    // The identifier for the next element added to the chain.
+    next_id : usize,
+
+    // Identifies this chain and any chains deep cloned from it (see
+    // ChainedOps::corresponding).
+    lineage : usize,
+
     // The input and output files, and directory for the entire chain.
     files : FileTransformation,
 
     // The env specification for the entire chain
     chain_env: EnvSpec,
 
+    // The default timeout for the operations in the chain.
+    timeout : Option<Duration>,
+
+    // The default output cache for the operations in the chain.
+    cache : Option<OutputCache>,
+
     // The activation state of entries in the chain (hash key == chain index).
     // If there is no hash entry for a specific chain entry, then that entry is
     // Active by default.
@@ -235,17 +436,276 @@ struct ChainedOpsInternals {
     // input should *not* be set to the output of the previous operation during
     // execution).
     preset_inputs : Vec<usize>,
+
+    // The manner in which the operations in the chain are executed.
+    mode : ChainMode,
+
+    // The earlier operations in the chain that each operation depends upon
+    // (hash key == chain index).  Only used for ChainMode::Dag execution.
+    depends : HashMap<usize, Vec<usize>>,
+
+    // The operations that were declared to depend upon an operation in another
+    // chain, which is reported as an error when the chain is executed in
+    // ChainMode::Dag mode.
+    foreign_depends : Vec<usize>,
+
+    // The manner in which each operation receives the output of the previous
+    // operation (hash key == chain index).  If there is no hash entry for a
+    // specific chain entry, then that entry is linked via ChainLink::File by
+    // default.  Only used for ChainMode::Sequential execution.
+    links : HashMap<usize, ChainLink>,
+
+    // The outcome of each operation that was performed (or attempted) during
+    // the most recent execution of the chain (hash key == chain index).  Used to
+    // generate the ChainReport.
+    records : HashMap<usize, OpReport>,
+
+    // The outputs of the operations referenced by a FileArg::OutputOf (hash key
+    // == chain index), which are set as the operations are performed during an
+    // execution of the chain.
+    outputs : HashMap<usize, HeldOutput>,
+
+    // The handling of operation failures during execution of the chain.
+    policy : ChainPolicy,
+
+    // Identifies which chain operations are optional (see
+    // ChainPolicy::ContinueOptional).
+    optional : Vec<usize>,
+
+    // Identifies which chain operations are incremental (see
+    // ChainedOpRef::set_incremental).
+    incremental : Vec<usize>,
+
+    // Identifies which chain operations are finally operations, which are
+    // performed after the main chain execution regardless of its outcome.
+    finally : Vec<usize>,
+
+    // Whether the main chain execution succeeded, which is shared with the
+    // handles returned by ChainedOps::status.
+    status : ChainStatus,
+
+    // The checkpoint file written after each successful operation (see
+    // ChainedOps::set_checkpoint).
+    checkpoint : Option<PathBuf>,
+}
+
+
+// The output of a chain operation that is referenced by a FileArg::OutputOf.  The
+// output is held during the execution of the chain so that any temporary file
+// remains in existence until all of the operations referencing it have been
+// performed.
+#[derive(Debug)]
+struct HeldOutput {
+    output : OpOutput,
+    file : Option<ActualFile>,
+}
+
+impl HeldOutput {
+    fn set(&mut self, outfile: &ActualFile)
+    {
+        self.output.set(outfile);
+        self.file = Some(outfile.share());
+    }
+
+    fn release(&mut self)
+    {
+        self.output.clear();
+        self.file = None;
+    }
+}
+
+// The lineage for the next new chain.
+static NEXT_LINEAGE : AtomicUsize = AtomicUsize::new(0);
+
+// The copies made during a deep clone of the nested chains and of the outputs
+// referenced by FileArg::OutputOf, keyed by the original.
+#[derive(Default)]
+struct ChainCopies {
+    chains : HashMap<*const SharedCell<ChainedOpsInternals>, ChainedOps>,
+    outputs : HashMap<*const std::sync::Mutex<Option<Vec<PathBuf>>>, OpOutput>,
+}
+
+impl ChainCopies {
+    // Returns the input files with any FileArg::OutputOf replaced by the copy of
+    // the referenced output, or None if there are no replacements.
+    fn inputs(&self, inputs: &[FileArg]) -> Option<Vec<FileArg>>
+    {
+        let copy = |f: &FileArg| match f {
+            FileArg::OutputOf(o) => self.outputs.get(&o.key()).cloned()
+                .map(FileArg::OutputOf),
+            _ => None,
+        };
+        if inputs.iter().all(|f| copy(f).is_none()) {
+            return None;
+        }
+        Some(inputs.iter().map(|f| copy(f).unwrap_or_else(|| f.clone())).collect())
+    }
+}
+
+impl ChainedOpsInternals {
+    // Returns a copy of the chain which shares nothing with the original.  A
+    // nested chain that appears more than once is copied only once, and that
+    // copy is shared in the same manner as the original.  The references to
+    // outputs (FileArg::OutputOf) within the copy refer to the copied
+    // operations.  The records of the most recent execution and the checkpoint
+    // file are not copied.
+    fn deep_clone(&self, copies: &mut ChainCopies) -> ChainedOpsInternals
+    {
+        let outputs = self.outputs.iter().map(|(i, held)| {
+            let output = OpOutput::new(held.output.label().to_string());
+            copies.outputs.insert(held.output.key(), output.clone());
+            (*i, HeldOutput { output, file : None })
+        }).collect();
+        let mut chain : Vec<RunnableOp> = self.chain.iter().map(|op| match op {
+            RunnableOp::Exec(op) => RunnableOp::Exec(op.clone()),
+            RunnableOp::Call(op) => RunnableOp::Call(op.clone()),
+            RunnableOp::Chain(sub) => {
+                let key = Shared::as_ptr(&sub.chops);
+                if ! copies.chains.contains_key(&key) {
+                    let copy = sub.chops.borrow().deep_clone(copies);
+                    copies.chains.insert(key, ChainedOps {
+                        chops : Shared::new(SharedCell::new(copy)),
+                        chlock : RwLock::new(0),
+                    });
+                }
+                RunnableOp::Chain(copies.chains[&key].shared())
+            }
+        }).collect();
+        for op in chain.iter_mut() {
+            if let RunnableOp::Chain(_) = op {
+                continue;  // copied along with the nested chain
+            }
+            if let Some(inputs) = copies.inputs(&op.files().inp_filenames) {
+                op.set_input_file(&inputs[0]);
+                for inp in &inputs[1..] {
+                    op.add_input_file(inp);
+                }
+            }
+        }
+        let mut files = self.files.clone();
+        if let Some(inputs) = copies.inputs(&files.inp_filenames) {
+            files.inp_filenames = inputs;
+        }
+        ChainedOpsInternals { name : self.name.clone(),
+                              chain,
+                              ids : self.ids.clone(),
+                              next_id : self.next_id,
+                              lineage : self.lineage,
+                              files,
+                              chain_env : self.chain_env.clone(),
+                              timeout : self.timeout,
+                              cache : self.cache.clone(),
+                              opstate : self.opstate.clone(),
+                              preset_inputs : self.preset_inputs.clone(),
+                              mode : self.mode.clone(),
+                              depends : self.depends.clone(),
+                              foreign_depends : self.foreign_depends.clone(),
+                              links : self.links.clone(),
+                              records : HashMap::new(),
+                              outputs,
+                              policy : self.policy.clone(),
+                              optional : self.optional.clone(),
+                              incremental : self.incremental.clone(),
+                              finally : self.finally.clone(),
+                              status : ChainStatus::default(),
+                              checkpoint : None,
+        }
+    }
+
+    // Returns the current index in the chain of the element with the specified
+    // identifier.  Panics if the element has been removed from the chain.
+    fn index(&self, opid: usize) -> usize
+    {
+        self.ids.iter().position(|i| *i == opid)
+            .unwrap_or_else(|| panic!("ChainedOpRef for an operation removed from chain {:?}",
+                                      self.name))
+    }
+
+    // Returns the element of the chain with the specified identifier.  Panics if
+    // the element has been removed from the chain.
+    fn op(&self, opid: usize) -> &RunnableOp
+    {
+        &self.chain[self.index(opid)]
+    }
+
+    // Rearranges the elements of the chain, where order specifies the current
+    // index of each element in its new position.  Any element whose index is
+    // not in order is removed from the chain.  All of the chain state that
+    // refers to elements by index is updated accordingly.
+    fn rearrange(&mut self, order: &[usize])
+    {
+        let newidx : HashMap<usize, usize> = order.iter().enumerate()
+            .map(|(new, old)| (*old, new))
+            .collect();
+        let remap = |old: &usize| newidx.get(old).copied();
+        let mut chain : Vec<Option<RunnableOp>> =
+            std::mem::take(&mut self.chain).into_iter().map(Some).collect();
+        self.chain = order.iter().map(|old| chain[*old].take().unwrap()).collect();
+        self.ids = order.iter().map(|old| self.ids[*old]).collect();
+        self.opstate = std::mem::take(&mut self.opstate).into_iter()
+            .filter_map(|(i, a)| remap(&i).map(|i| (i, a)))
+            .collect();
+        self.links = std::mem::take(&mut self.links).into_iter()
+            .filter_map(|(i, l)| remap(&i).map(|i| (i, l)))
+            .collect();
+        self.records = std::mem::take(&mut self.records).into_iter()
+            .filter_map(|(i, r)| remap(&i).map(|i| (i, r)))
+            .collect();
+        self.outputs = std::mem::take(&mut self.outputs).into_iter()
+            .filter_map(|(i, o)| remap(&i).map(|i| (i, o)))
+            .collect();
+        self.depends = std::mem::take(&mut self.depends).into_iter()
+            .filter_map(|(i, ds)| remap(&i).map(
+                |i| (i, ds.iter().filter_map(remap).collect())))
+            .collect();
+        for idxs in [&mut self.preset_inputs, &mut self.foreign_depends,
+                     &mut self.optional, &mut self.incremental,
+                     &mut self.finally] {
+            *idxs = idxs.iter().filter_map(remap).collect();
+        }
+    }
+    // Returns the settings of this chain that are inherited by the operations
+    // in the chain.
+    fn settings(&self) -> ChainSettings
+    {
+        ChainSettings { env : self.chain_env.clone(),
+                        timeout : self.timeout,
+                        cache : self.cache.clone() }
+    }
+
+    // Returns the report of the most recent execution of each element of the
+    // chain, in chain order.
+    fn report(&self) -> Vec<OpReport>
+    {
+        self.chain.iter().enumerate().map(|(i, op)| {
+            self.records.get(&i).cloned().unwrap_or_else(|| OpReport {
+                label : op.label(),
+                status : match self.opstate.get(&i) {
+                    Some(Activation::Disabled) => OpStatus::Disabled,
+                    _ => OpStatus::NotRun,
+                },
+                exit_code : None,
+                elapsed : None,
+                command : Vec::new(),
+                output_files : Vec::new(),
+                nested : op.nested_report(),
+            })
+        }).collect()
+    }
 }
 
 
 /// This is returned when an operation is added to the [ChainedOps] structure and
 /// serves as a proxy for that operation as it exists in the chain.  This
 /// supports additional customization actions on the contained operation via the
-/// [FilesPrep] trait.
+/// [FilesPrep] trait.  The reference follows the operation as other elements are
+/// inserted, removed or moved within the chain.
 #[derive(Clone,Debug)]
 pub struct ChainedOpRef {
-    opidx : usize,
-    chop : Rc<RefCell<ChainedOpsInternals>>  // cloned from ChainedOps.chops
+    // The identifier of the referenced element, which remains the same as the
+    // position of the element in the chain changes (see ChainedOps::move_to).
+    opid : usize,
+    chop : Shared<SharedCell<ChainedOpsInternals>>  // cloned from ChainedOps.chops
 }
 
 
@@ -255,8 +715,6 @@ impl fmt::Debug for ChainedOps {
     }
 }
 
-// TODO: Iterator impl on ChainedOps returning ChainedOpRef by: usize? label?
-
 
 impl ChainedOps {
     // The result is Rc'd so that the ChainedOpRef instances can have a
@@ -265,15 +723,31 @@ impl ChainedOps {
     {
         ChainedOps {
             chops :
-            Rc::new(
-                RefCell::new(
+            Shared::new(
+                SharedCell::new(
                     ChainedOpsInternals { name: label.clone().into(),
                                           chain : Vec::new(),
+                                          ids : Vec::new(),
+                                          next_id : 0,
+                                          lineage : NEXT_LINEAGE.fetch_add(1, Ordering::Relaxed),
                                           files : FileTransformation::new(),
                                           chain_env : EnvSpec::StdEnv,
+                                          timeout : None,
+                                          cache : None,
                                           opstate : HashMap::new(),
                                           preset_inputs : Vec::new(),
-
+                                          mode : ChainMode::Sequential,
+                                          depends : HashMap::new(),
+                                          foreign_depends : Vec::new(),
+                                          links : HashMap::new(),
+                                          records : HashMap::new(),
+                                          outputs : HashMap::new(),
+                                          policy : ChainPolicy::FailFast,
+                                          optional : Vec::new(),
+                                          incremental : Vec::new(),
+                                          finally : Vec::new(),
+                                          status : ChainStatus::default(),
+                                          checkpoint : None,
                     }
                 )
             ),
@@ -291,376 +765,499 @@ impl ChainedOps {
     /// [FilesPrep::set_input_file] trait method).
     pub fn push_op(self: &ChainedOps, op: &SubProcOperation) -> ChainedOpRef
     {
-        let opidx = {
-            let mut ops: RefMut<_> = self.chops.borrow_mut();
-            ops.chain.push(RunnableOp::Exec(op.clone()));
-            let opidx = ops.chain.len() - 1;
-            if op.has_input_file() {
-                ops.preset_inputs.push(opidx);
-            }
-            opidx
-        };
-        ChainedOpRef { opidx,
-                       chop : Rc::clone(&self.chops)
-        }
+        self.add_element(RunnableOp::Exec(op.clone()), op.has_input_file())
     }
 
     /// Adds a new FunctionOperation operation to the end of the chain.  Returns
     /// a reference for modifying that operation.
     pub fn push_call(self: &ChainedOps, op: &FunctionOperation) -> ChainedOpRef
     {
-        let opidx = {
-            let mut ops: RefMut<_> = self.chops.borrow_mut();
-            ops.chain.push(RunnableOp::Call(op.clone()));
-            let opidx = ops.chain.len() - 1;
-            if op.has_input_file() {
-                ops.preset_inputs.push(opidx);
-            }
-            opidx
-        };
-        ChainedOpRef { opidx,
-                       chop : Rc::clone(&self.chops)
-        }
+        self.add_element(RunnableOp::Call(op.clone()), op.has_input_file())
     }
 
-    /// Clears all environment variable settings for the environment in which the
-    /// entire chain executes.  Any previous environment variable settings are
-    /// discarded.  Any environment settings on an individual operation in the
-    /// chain are an additive override to the settings here.
+    /// Adds another ChainedOps as a single (nested) element at the end of this
+    /// chain.  Returns a reference for modifying that element.
     ///
-    /// By default, the current environment is inherited by the chain operations.
-    pub fn clear_env(&mut self) -> &mut Self
+    /// The nested chain is treated like any other operation in this chain: it
+    /// receives the output of the previous element as its input file(s) (unless
+    /// it has its own input file(s) specified), and its output file is used as
+    /// the input for the next element.  The nested chain inherits the
+    /// environment and directory of this chain in the same manner as a single
+    /// operation.
+    ///
+    /// Unlike [ChainedOps::push_op], the nested chain is not copied: it is
+    /// shared with the `ChainedOps` passed here, so subsequent modifications to
+    /// either one will be visible in both.  This allows a common sub-chain to be
+    /// defined once and re-used in multiple enclosing chains.  Because of this
+    /// sharing, whether the nested chain has its own input file(s) is
+    /// determined each time this chain is executed rather than when it is
+    /// added here.
+    ///
+    /// Unlike [ChainedOps::push_op] and [ChainedOps::push_call], this returns
+    /// a `Result`: a [ChainsopError::ErrorChainCycle] error is returned (and
+    /// nothing is added) if the nested chain is this chain or contains this
+    /// chain (directly or via further nesting).  Such a chain could never be
+    /// executed, and it is rejected here rather than at execution because the
+    /// cycle would also prevent reporting on, cloning, or looking up the
+    /// elements of the chain.
+    pub fn push_chain(self: &ChainedOps, subchain: &ChainedOps)
+                      -> anyhow::Result<ChainedOpRef>
     {
-        {
-            let mut ops: RefMut<_> = self.chops.borrow_mut();
-            ops.chain_env = EnvSpec::BlankEnv;
+        if subchain.contains_chain(self) {
+            return Err(anyhow::Error::new(
+                ChainsopError::ErrorChainCycle(self.label(), subchain.label())));
         }
-        self
+        Ok(self.add_element(RunnableOp::Chain(subchain.shared()), false))
     }
 
-    /// Specifies an environment variable value to be set in the environment for
-    /// executing this chain of operations.  This can be used multiple times to
-    /// set multiple environment variables; subsequent settings of the same
-    /// variable will override previous settings.  Any environment settings on an
-    /// individual operation in the chain are an additive override to the
-    /// settings here.
-    ///
-    /// The first argument is the environment variable name and the second
-    /// argument is the value to set for that variable.
-    pub fn set_env<N,V>(&mut self, var_name: N, var_value: V) -> &mut Self
-    where N: Into<String>,
-          V: Into<String>
+    // Returns true if this chain is the other chain or contains it as a nested
+    // chain (at any depth).
+    fn contains_chain(&self, other: &ChainedOps) -> bool
     {
-        {
-            let mut ops: RefMut<_> = self.chops.borrow_mut();
-            ops.chain_env = ops.chain_env.add(var_name, var_value);
-        }
-        self
+        Shared::ptr_eq(&self.chops, &other.chops)
+            || self.chops.borrow().chain.iter().any(|elem| match elem {
+                RunnableOp::Chain(sub) => sub.contains_chain(other),
+                _ => false,
+            })
     }
 
-    /// Extends the chained operations environment by prepending a value to an
-    /// environment variable.  If the environment variable was not previously
-    /// set, this becomes the new value for that variable.  This can be used
-    /// multiple times to extend the environment variable with multiple values.
-    /// Any environment settings on an individual operation in the
-    /// chain are an additive override to the settings here.
+    /// Adds a new SubProcOperation as a finally operation of the chain, and
+    /// returns a reference for modifying that operation.
     ///
-    /// The first argument is the environment variable name and the second
-    /// argument is the value to prepend to that variable, and the third value is
-    /// the separator between the prepended value and the existing value.
-    pub fn prepend_env<N,V,S>(&mut self, var: N, value: V, sep: S) -> &mut Self
-    where N: Into<String>,
-          V: Into<String>,
-          S: Into<String>
+    /// The finally operations are performed (in the order they were added)
+    /// after the main chain execution completes, whether that execution
+    /// succeeded or failed, and are typically used for teardown activities such
+    /// as stopping servers or collecting logs.  The finally operations do not
+    /// participate in the chaining of input and output files: they use only the
+    /// files explicitly specified for them.  They inherit the chain's
+    /// environment, with the `CHAINSOP_CHAIN_STATUS` environment variable set
+    /// to "success" or "failure" to indicate the outcome of the main chain (see
+    /// also [ChainedOps::status]).
+    ///
+    /// All the enabled finally operations are performed even if one of them
+    /// fails.  Any finally operation failures are returned as a
+    /// [ChainsopError::ErrorFinallyFailed], which also holds the main chain's
+    /// error (if it failed).
+    pub fn push_finally_op(self: &ChainedOps, op: &SubProcOperation) -> ChainedOpRef
     {
+        let opref = self.push_op(op);
         {
-            let mut ops: RefMut<_> = self.chops.borrow_mut();
-            ops.chain_env = ops.chain_env.prepend(var, value, sep);
+            let mut ops: SharedRefMut<_> = self.chops.borrow_mut();
+            let opidx = ops.index(opref.opid);
+            ops.finally.push(opidx);
         }
-        self
+        opref
     }
 
-    /// Extends the chained operations environment by appending a value to an
-    /// environment variable.  If the environment variable was not previously
-    /// set, this becomes the new value for that variable.  This can be used
-    /// multiple times to extend the environment variable with multiple values.
-    /// Any environment settings on an individual operation in the
-    /// chain are an additive override to the settings here.
-    ///
-    /// The first argument is the environment variable name and the second
-    /// argument is the value to append to that variable, and the third value is
-    /// the separator between the appended value and the existing value.
-    pub fn append_env<N,V,S>(&mut self, var: N, value: V, sep: S) -> &mut Self
-    where N: Into<String>,
-          V: Into<String>,
-          S: Into<String>
+    /// Adds a new FunctionOperation as a finally operation of the chain, and
+    /// returns a reference for modifying that operation (see
+    /// [ChainedOps::push_finally_op]).  The function can use a [ChainStatus]
+    /// obtained from [ChainedOps::status] to determine whether the main chain
+    /// succeeded.
+    pub fn push_finally_call(self: &ChainedOps, op: &FunctionOperation) -> ChainedOpRef
     {
+        let opref = self.push_call(op);
         {
-            let mut ops: RefMut<_> = self.chops.borrow_mut();
-            ops.chain_env = ops.chain_env.append(var, value, sep);
+            let mut ops: SharedRefMut<_> = self.chops.borrow_mut();
+            let opidx = ops.index(opref.opid);
+            ops.finally.push(opidx);
         }
-        self
+        opref
     }
 
-    /// Removes the specified environment variable from the environment in which
-    /// the chain of operations executes.  Has no effect but does not fail if the
-    /// environment variable does not exist.  Any environment settings on an
-    /// individual operation in the chain are an additive override to the
-    /// settings here.
-    pub fn unset_env<N>(&mut self, var_name: N) -> &mut Self
-    where N: Into<String>
+    // Adds the element to the end of the chain and returns the reference to it.
+    fn add_element(&self, op: RunnableOp, preset_inputs: bool) -> ChainedOpRef
     {
-        {
-            let mut ops: RefMut<_> = self.chops.borrow_mut();
-            ops.chain_env = ops.chain_env.rmv(var_name);
+        let mut ops: SharedRefMut<_> = self.chops.borrow_mut();
+        let opid = ops.next_id;
+        ops.next_id += 1;
+        ops.chain.push(op);
+        ops.ids.push(opid);
+        if preset_inputs {
+            let opidx = ops.chain.len() - 1;
+            ops.preset_inputs.push(opidx);
         }
-        self
+        ChainedOpRef { opid, chop : Shared::clone(&self.chops) }
     }
 
-}
-
-impl FilesPrep for ChainedOps
-{
-    /// Sets the current directory for the entire chain.  Individual operations
-    /// can specify a relative directory that will be make relative to this
-    /// location, or they can specify an absolute directory that will override
-    /// this location.  If this call is not made, the operations are executed in
-    /// the current process directory.
-    #[inline]
-    fn set_dir<T: AsRef<Path>>(&mut self, tgtdir: T) -> &mut Self
+    // Returns the current index of the referenced element in this chain, or an
+    // error if the reference is for another chain or for a removed element.
+    fn element_index(&self, op: &ChainedOpRef) -> anyhow::Result<usize>
     {
-        {
-            let mut ops: RefMut<_> = self.chops.borrow_mut();
-            ops.files.set_dir(tgtdir);
+        if ! Shared::ptr_eq(&op.chop, &self.chops) {
+            return Err(anyhow::Error::new(
+                ChainsopError::ErrorForeignOperation(self.label())));
         }
-        self
+        let ops = self.chops.borrow();
+        ops.ids.iter().position(|i| *i == op.opid).ok_or_else(
+            || anyhow::Error::new(ChainsopError::ErrorRemovedOperation(ops.name.clone())))
     }
 
-    /// Sets the input file(s) for the entire chain
-    #[inline]
-    fn set_input_file(&mut self, fname: &FileArg) -> &mut Self
+    // Adds the element (via the push function) and moves it to the position
+    // relative to the referenced element: immediately before it (offset 0) or
+    // immediately after it (offset 1).
+    fn insert_element<F>(&self, at: &ChainedOpRef, offset: usize, push: F)
+                         -> anyhow::Result<ChainedOpRef>
+    where F: FnOnce() -> anyhow::Result<ChainedOpRef>
     {
-        {
-            let mut ops: RefMut<_> = self.chops.borrow_mut();
-            ops.files.set_input_file(fname);
-        }
-        self
+        let index = self.element_index(at)? + offset;
+        let opref = push()?;
+        self.move_to(&opref, index)?;
+        Ok(opref)
     }
 
-    /// Adds an input file(s) to the entire chain
-    #[inline]
-    fn add_input_file(&mut self, fname: &FileArg) -> &mut Self
+    /// Inserts a new SubProcOperation into the chain immediately before the
+    /// referenced element, and returns a reference for modifying the new
+    /// operation.
+    ///
+    /// Returns a [ChainsopError::ErrorForeignOperation] error if the reference
+    /// is for an element of another chain, or a
+    /// [ChainsopError::ErrorRemovedOperation] error if it is for an element
+    /// that has been removed (see [ChainedOps::remove]); nothing is inserted
+    /// in either case.
+    pub fn insert_op_before(&self, before: &ChainedOpRef, op: &SubProcOperation)
+                            -> anyhow::Result<ChainedOpRef>
     {
-        {
-            let mut ops: RefMut<_> = self.chops.borrow_mut();
-            ops.files.add_input_file(fname);
-        }
-        self
+        self.insert_element(before, 0, || Ok(self.push_op(op)))
     }
 
-    fn has_input_file(&self) -> bool
+    /// Inserts a new SubProcOperation into the chain immediately after the
+    /// referenced element, and returns a reference for modifying the new
+    /// operation (see [ChainedOps::insert_op_before]).
+    pub fn insert_op_after(&self, after: &ChainedOpRef, op: &SubProcOperation)
+                           -> anyhow::Result<ChainedOpRef>
     {
-        self.chops.borrow().files.has_input_file()
+        self.insert_element(after, 1, || Ok(self.push_op(op)))
     }
 
-    #[inline]
-    fn set_output_file(&mut self, fname: &FileArg) -> &mut Self
+    /// Inserts a new FunctionOperation into the chain immediately before the
+    /// referenced element, and returns a reference for modifying the new
+    /// operation (see [ChainedOps::insert_op_before]).
+    pub fn insert_call_before(&self, before: &ChainedOpRef, op: &FunctionOperation)
+                              -> anyhow::Result<ChainedOpRef>
     {
-        {
-            let mut ops: RefMut<_> = self.chops.borrow_mut();
-            ops.files.set_output_file(fname);
-        }
-        self
+        self.insert_element(before, 0, || Ok(self.push_call(op)))
     }
 
-    fn has_explicit_output_file(&self) -> bool
+    /// Inserts a new FunctionOperation into the chain immediately after the
+    /// referenced element, and returns a reference for modifying the new
+    /// operation (see [ChainedOps::insert_op_before]).
+    pub fn insert_call_after(&self, after: &ChainedOpRef, op: &FunctionOperation)
+                             -> anyhow::Result<ChainedOpRef>
     {
-        self.chops.borrow().files.has_explicit_output_file()
+        self.insert_element(after, 1, || Ok(self.push_call(op)))
     }
 
-}
-
-impl OpInterface for ChainedOps
-{
-    fn label(&self) -> String
+    /// Inserts another ChainedOps as a single (nested) element of this chain
+    /// immediately before the referenced element, and returns a reference for
+    /// modifying that element (see [ChainedOps::push_chain] and
+    /// [ChainedOps::insert_op_before]).
+    pub fn insert_chain_before(&self, before: &ChainedOpRef, subchain: &ChainedOps)
+                               -> anyhow::Result<ChainedOpRef>
     {
-        self.chops.borrow().name.clone()
+        self.insert_element(before, 0, || self.push_chain(subchain))
     }
 
+    /// Inserts another ChainedOps as a single (nested) element of this chain
+    /// immediately after the referenced element, and returns a reference for
+    /// modifying that element (see [ChainedOps::push_chain] and
+    /// [ChainedOps::insert_op_before]).
+    pub fn insert_chain_after(&self, after: &ChainedOpRef, subchain: &ChainedOps)
+                              -> anyhow::Result<ChainedOpRef>
+    {
+        self.insert_element(after, 1, || self.push_chain(subchain))
+    }
 
-    fn set_label(&mut self, new_label: &str) -> &mut Self {
-        self.chops.borrow_mut().name = new_label.to_string();
-        self
+    /// Removes the referenced element from the chain, along with its settings
+    /// in the chain (activation, link, dependencies, etc.) and any dependencies
+    /// of other elements upon it.  The other [ChainedOpRef] references remain
+    /// valid, but the reference for the removed element (and any clones of it)
+    /// may no longer be used: see [ChainedOpRef::is_removed].
+    ///
+    /// Returns an error if the reference is for an element of another chain or
+    /// for an element that has already been removed (see
+    /// [ChainedOps::insert_op_before]).
+    pub fn remove(&self, op: &ChainedOpRef) -> anyhow::Result<()>
+    {
+        let index = self.element_index(op)?;
+        let mut ops: SharedRefMut<_> = self.chops.borrow_mut();
+        let order : Vec<usize> = (0..ops.chain.len()).filter(|i| *i != index).collect();
+        ops.rearrange(&order);
+        Ok(())
     }
 
-    /// Executes all the enabled operations in this chain sequentially, updating
-    /// the input file of each operation to be the output file from the previous
-    /// operation.  On success, returns the number of operations executed.
+    /// Moves the referenced element to the specified index in the chain,
+    /// shifting the elements between its current position and the new position
+    /// (as with [Vec::insert]).  The element retains its settings in the chain
+    /// and all [ChainedOpRef] references remain valid.  Note that a
+    /// [ChainedOpRef::depends_on] dependency upon an element that is no longer
+    /// earlier in the chain will cause an error when the chain is executed.
     ///
-    /// The directory parameter specifies the default directory from which the
-    /// chained operations will be performed.  Each chained operation might
-    /// operate from a separate directory if the [SubProcOperation::set_dir()] or
-    /// [ChainedOpRef::set_dir()] function has been called for this operation,
-    /// which overrides the default directory passed to this command.
-    fn execute<Exec, P>(&mut self, executor: &Exec, cwd: &Option<P>)
-                        -> anyhow::Result<ActualFile>
-    where P: AsRef<Path>, Exec: OsRun
+    /// Returns an error if the reference is for an element of another chain or
+    /// for an element that has been removed (see
+    /// [ChainedOps::insert_op_before]).  Panics if the index is not less than
+    /// the length of the chain.
+    pub fn move_to(&self, op: &ChainedOpRef, index: usize) -> anyhow::Result<()>
     {
-        // Lock this chain to ensure it is not run in parallel, which would
-        // create conflicts with the internal chain element input file updates.
-        // The lock is automatically released at the end of this method scope.
-        let mut locked = self.chlock.write().unwrap();
-        *locked += 1;
+        let current = self.element_index(op)?;
+        let mut ops: SharedRefMut<_> = self.chops.borrow_mut();
+        assert!(index < ops.chain.len(),
+                "move_to index {} is beyond the end of chain {:?}", index, ops.name);
+        let mut order : Vec<usize> = (0..ops.chain.len()).filter(|i| *i != current).collect();
+        order.insert(index, current);
+        ops.rearrange(&order);
+        Ok(())
+    }
 
-        let mut chops = self.chops.borrow_mut();
+    /// Returns the number of elements in the chain (including any disabled and
+    /// finally operations).
+    pub fn len(&self) -> usize
+    {
+        self.chops.borrow().chain.len()
+    }
 
-        // Some chain elements might be marked as disabled.  Rather than
-        // requiring a test of each chain element each time it is to be
-        // considered, we instead build a vec of the enabled element indices.
-        // Build it in reverse so the operations can simply .pop() the next index
-        // off the end.
-        let mut enabled_opidxs : Vec<usize> = chops.chain.iter()
-            .enumerate()
-            .filter(|(i,_op)|
-                    chops.opstate.get(i).unwrap_or(&Activation::Enabled) == &Activation::Enabled)
-            .map(|(i,_op)| i)
-            .rev()
-            .collect();
+    /// Returns true if the chain has no elements.
+    pub fn is_empty(&self) -> bool
+    {
+        self.chops.borrow().chain.is_empty()
+    }
 
-        if enabled_opidxs.is_empty() {
-            // This is a non-functional chain: it is either empty or every
-            // operation in the chain is disabled.  No output file was generated.
-            return Ok(ActualFile::NoActualFile);
+    /// Returns a reference to the element currently at the specified index in
+    /// the chain, or None if there is no such element.  The index is the current
+    /// position of the element, which changes as elements are inserted, removed
+    /// or moved (see [ChainedOps::move_to]).
+    pub fn get(&self, index: usize) -> Option<ChainedOpRef>
+    {
+        if index < self.len() {
+            Some(ChainedOpRef { opid : self.chops.borrow().ids[index],
+                                chop : Shared::clone(&self.chops) })
+        } else {
+            None
         }
+    }
 
-        let first_op = enabled_opidxs[enabled_opidxs.len()-1];
-        let last_op = enabled_opidxs[0];
-        let chain_inps = chops.files.inp_filenames.clone();
+    /// Returns a reference to the first element of the chain with the
+    /// specified label, or None if there is no such element.
+    pub fn find_by_label(&self, label: &str) -> Option<ChainedOpRef>
+    {
+        let index = self.chops.borrow().chain.iter().position(|op| op.label() == label);
+        index.and_then(|i| self.get(i))
+    }
 
-        if ! chain_inps.is_empty() {
-            chops.chain[first_op].set_input_file(&chain_inps[0]);
-            for f in &chain_inps[1..] {
-                chops.chain[first_op].add_input_file(f);
-            }
+    /// Returns an iterator over references to the elements of the chain, in
+    /// chain order.  The iterator covers the elements in the chain at the time
+    /// this is called.
+    ///
+    /// ```
+    /// # use chainsop::*;
+    /// let chain = ChainedOps::new("example");
+    /// let exe = Executable::new(&"gzip", ExeFileSpec::Stdin, ExeFileSpec::Stdout);
+    /// chain.push_op(SubProcOperation::new(&exe).set_label("compress"));
+    /// chain.push_op(SubProcOperation::new(&exe).set_label("again"));
+    /// let labels : Vec<String> = chain.iter().map(|op| op.label()).collect();
+    /// assert_eq!(labels, ["compress", "again"]);
+    /// ```
+    pub fn iter(&self) -> impl Iterator<Item = ChainedOpRef> + '_
+    {
+        (0..self.len()).filter_map(|i| self.get(i))
+    }
+
+    /// Returns a handle that reports whether the main chain (i.e. excluding the
+    /// finally operations) succeeded during the most recent execution of this
+    /// chain.  The handle remains valid (and is updated by each execution), so
+    /// it can be captured by a function passed to [ChainedOps::push_finally_call].
+    pub fn status(&self) -> ChainStatus
+    {
+        self.chops.borrow().status.clone()
+    }
+
+    /// Returns a copy of this chain that is fully independent of the original:
+    /// subsequent modifications to either chain (or to its elements via a
+    /// [ChainedOpRef]) are not visible in the other.  Nested chains (see
+    /// [ChainedOps::push_chain]) are copied as well.  This allows a template
+    /// chain to be defined once and then copied for variations that are
+    /// modified separately.
+    ///
+    /// The references to the elements of the original chain remain references
+    /// to the original; use [ChainedOps::corresponding] to obtain the references
+    /// to the copied elements.  The report and status of any previous execution
+    /// of the original are not copied.  The checkpoint file (see
+    /// [ChainedOps::set_checkpoint]) is not copied either, since the copies
+    /// would otherwise overwrite each other's checkpoints: a new checkpoint file
+    /// must be specified for the copy if one is needed.
+    pub fn deep_clone(&self) -> ChainedOps
+    {
+        let copy = self.chops.borrow().deep_clone(&mut ChainCopies::default());
+        ChainedOps {
+            chops : Shared::new(SharedCell::new(copy)),
+            chlock : RwLock::new(0),
         }
-        let tgtdir = match cwd {
-            None => chops.files.in_dir.clone(),
-            Some(d) =>
-                match &chops.files.in_dir {
-                    Some(od) => Some(d.as_ref().join(od)),
-                    None => Some(d.as_ref().into()),
-            }
-        };
-        if chops.files.has_explicit_output_file() {
-            let main_out_file = chops.files.out_filename.clone();
-            chops.chain[last_op].set_output_file(&main_out_file);
+    }
+
+    /// Returns the reference to the element of this chain that corresponds to
+    /// the referenced element of a chain this chain was deep cloned from (see
+    /// [ChainedOps::deep_clone]), or of a chain deep cloned from this one.  The
+    /// referenced element may also be an element of a nested chain, in which
+    /// case the returned reference is for the corresponding element of the
+    /// nested chain here (the first one, if that nested chain appears more than
+    /// once).  Returns None if there is no corresponding element, either
+    /// because the reference is for an unrelated chain or because the element
+    /// has been removed from this chain (or was added after the copy was made).
+    ///
+    /// ```
+    /// # use chainsop::*;
+    /// let release = ChainedOps::new("release");
+    /// let exe = Executable::new(&"cc", ExeFileSpec::Append, ExeFileSpec::option("-o"));
+    /// let compile = release.push_op(&SubProcOperation::new(&exe).set_label("compile"));
+    /// let debug = release.deep_clone();
+    /// debug.corresponding(&compile).unwrap().push_arg("-g");
+    /// assert!(compile.args().is_empty());
+    /// ```
+    pub fn corresponding(&self, op: &ChainedOpRef) -> Option<ChainedOpRef>
+    {
+        let lineage = op.chop.borrow().lineage;
+        let chops = self.chops.borrow();
+        if chops.lineage == lineage {
+            return chops.ids.contains(&op.opid).then(|| ChainedOpRef {
+                opid : op.opid,
+                chop : Shared::clone(&self.chops),
+            });
         }
+        chops.chain.iter().find_map(|elem| match elem {
+            RunnableOp::Chain(sub) => sub.corresponding(op),
+            _ => None,
+        })
+    }
 
-        let pinp = chops.preset_inputs.clone();
-        let chenv = chops.chain_env.clone();
-        execute_chain(executor, &mut chops.chain, &pinp, &tgtdir,
-                      chenv, &mut enabled_opidxs)
+    // Returns another ChainedOps handle that references the same chain
+    // internals as this one.  The execution lock is not shared: each handle
+    // protects its own executions, and the RefCell borrow of the internals will
+    // catch any attempt to execute both handles simultaneously.
+    fn shared(&self) -> ChainedOps
+    {
+        ChainedOps {
+            chops : Shared::clone(&self.chops),
+            chlock : RwLock::new(0),
+        }
     }
-}
 
-fn execute_chain(executor: &impl OsRun,
-                 chops: &mut Vec<RunnableOp>,
-                 preset_inputs: &Vec<usize>,
-                 cwd: &Option<PathBuf>,
-                 ch_env: EnvSpec,
-                 mut op_idxs: &mut Vec<usize>) -> anyhow::Result<ActualFile>
-{
-    let op_idx = op_idxs.pop().unwrap();
-    let spo = &mut chops[op_idx];
-    let orig_env = spo.get_full_env();
-    spo.set_base_env(&ch_env);
-    let outfile = spo.execute(executor, cwd)?;
-    spo.set_full_env(&orig_env);
-    if op_idxs.is_empty() {
-        // This was the last operation, execution of the chain is completed.
-        return Ok(outfile);
+    /// Returns the current environment settings for the entire chain.
+    pub(crate) fn get_full_env(&self) -> EnvSpec
+    {
+        self.chops.borrow().chain_env.clone()
     }
-    match outfile.to_paths::<PathBuf>(&None).with_context(
-        || format!("Output file for chained operation {}", spo.label()))
+
+    /// Sets the entirety of the environment settings for the entire chain.
+    pub(crate) fn set_full_env(&mut self, new_env: &EnvSpec) -> &mut Self
     {
-        Ok(mut ps) => {
-            // If no output files, just let next chained element's input be what
-            // it was originally set to.
-            if !ps.is_empty() {
-                // Otherwise, set the inputs of the next operation to the output
-                // of the just-completed operation (unless the inputs are already
-                // pre-set).
-                //
-                // n.b. OK to unwrap here for last_idx: size matches ps and
-                // therefore cannot be empty
-                let last_idx = op_idxs.last().unwrap();
-                if ! preset_inputs.contains(last_idx) {
-                    chops[*last_idx].set_input_file(&FileArg::Loc(ps.pop().unwrap()));
-                    for p in ps {
-                        chops[*last_idx].add_input_file(&FileArg::Loc(p.clone()));
-                    }
-                }
-            }
+        self.chops.borrow_mut().chain_env = new_env.clone();
+        self
+    }
+
+    /// Uses the specified environment as the base environment setting for the
+    /// entire chain.  This is used when this chain is nested within another
+    /// chain to inherit the enclosing chain's environment.
+    pub(crate) fn set_base_env(&mut self, base_env: &EnvSpec) -> &mut Self
+    {
+        {
+            let mut ops: SharedRefMut<_> = self.chops.borrow_mut();
+            ops.chain_env = ops.chain_env.set_base(base_env);
         }
-        Err(e) => match &e.root_cause().downcast_ref::<ChainsopError>() {
-            Some(ChainsopError::ErrorMissingFile) => {
-                // This is OK here because the following operation may be setup
-                // for not needing an input file specification; if it does need
-                // one then presumably some subsequent runtime check will
-                // validate its (lack of) existence and signal a useful failure.
-            }
-            _ => { return Err(e); }
-        },
-    };
-    execute_chain(executor, chops, preset_inputs, cwd, ch_env, &mut op_idxs)
-}
+        self
+    }
 
-/// This enumerates the possible active conditions for each operation in the
-/// chain.  This is used as the argumement to the [ChainedOpRef::active] method
-/// to determine how the associated operation should be treated during execution
-/// of the chain.
-#[derive(Clone, Debug, PartialEq)]
-pub enum Activation {
-    /// The associated operation is performed during execution of the chain.
-    Enabled,
-    /// The associated operation is skipped (not performed) during execution of
-    /// the chain.
-    Disabled,
-}
+    /// Returns the current default timeout for the entire chain.
+    pub(crate) fn get_full_timeout(&self) -> Option<Duration>
+    {
+        self.chops.borrow().timeout
+    }
 
-impl ChainedOpRef {
+    /// Sets (or clears) the default timeout for the entire chain.
+    pub(crate) fn set_full_timeout(&mut self, timeout: &Option<Duration>) -> &mut Self
+    {
+        self.chops.borrow_mut().timeout = *timeout;
+        self
+    }
 
-    /// Changes the name of the command to execute if this is a
-    /// [SubProcOperation]; does nothing if this is a [FunctionOperation].
-    pub fn set_executable<T>(&mut self, exe: T) -> &mut Self
-    where T: Into<PathBuf>
+    /// Uses the specified timeout as the default timeout for the entire chain if
+    /// this chain does not have its own default timeout.  This is used when
+    /// this chain is nested within another chain to inherit the enclosing
+    /// chain's default timeout.
+    pub(crate) fn set_base_timeout(&mut self, base_timeout: &Option<Duration>) -> &mut Self
     {
         {
-            let mut ops: RefMut<_> = self.chop.borrow_mut();
-            ops.chain[self.opidx].set_executable(exe.into());
+            let mut ops: SharedRefMut<_> = self.chops.borrow_mut();
+            ops.timeout = ops.timeout.or(*base_timeout);
         }
         self
     }
 
-    /// Clears all environment variable settings for the environment in which
-    /// this operation in the chain executes.  Any previous environment variable
-    /// settings are discarded.
+    /// Specifies the default timeout for each [SubProcOperation] in the chain
+    /// (including those in nested chains) that does not have its own timeout
+    /// (see [SubProcOperation::set_timeout]).  Note that this limits the time
+    /// of each operation individually, not the total time of the chain.
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self
+    {
+        self.chops.borrow_mut().timeout = Some(timeout);
+        self
+    }
+
+    /// Returns the current default output cache for the entire chain.
+    pub(crate) fn get_full_cache(&self) -> Option<OutputCache>
+    {
+        self.chops.borrow().cache.clone()
+    }
+
+    /// Sets (or clears) the default output cache for the entire chain.
+    pub(crate) fn set_full_cache(&mut self, cache: &Option<OutputCache>) -> &mut Self
+    {
+        self.chops.borrow_mut().cache = cache.clone();
+        self
+    }
+
+    /// Uses the specified cache as the default output cache for the entire
+    /// chain if this chain does not have its own default cache.  This is used
+    /// when this chain is nested within another chain to inherit the enclosing
+    /// chain's default cache.
+    pub(crate) fn set_base_cache(&mut self, base_cache: &Option<OutputCache>) -> &mut Self
+    {
+        {
+            let mut ops: SharedRefMut<_> = self.chops.borrow_mut();
+            ops.cache = ops.cache.clone().or(base_cache.clone());
+        }
+        self
+    }
+
+    /// Specifies the default output cache for each [SubProcOperation] in the
+    /// chain (including those in nested chains) that does not have its own
+    /// cache (see [SubProcOperation::set_cache]).
+    pub fn set_cache(&mut self, cache: &OutputCache) -> &mut Self
+    {
+        self.chops.borrow_mut().cache = Some(cache.clone());
+        self
+    }
+
+    /// Clears all environment variable settings for the environment in which the
+    /// entire chain executes.  Any previous environment variable settings are
+    /// discarded.  Any environment settings on an individual operation in the
+    /// chain are an additive override to the settings here.
     ///
-    /// By default, the chain's environment is inherited by the operation.
+    /// By default, the current environment is inherited by the chain operations.
     pub fn clear_env(&mut self) -> &mut Self
     {
         {
-            let mut ops: RefMut<_> = self.chop.borrow_mut();
-            ops.chain[self.opidx].clear_env();
+            let mut ops: SharedRefMut<_> = self.chops.borrow_mut();
+            ops.chain_env = EnvSpec::BlankEnv;
         }
         self
     }
 
     /// Specifies an environment variable value to be set in the environment for
-    /// executing this operation in the chain.  This can be used multiple times
-    /// to set multiple environment variables; subsequent settings of the same
-    /// variable will override previous settings, including those from the
-    /// overall chain.
+    /// executing this chain of operations.  This can be used multiple times to
+    /// set multiple environment variables; subsequent settings of the same
+    /// variable will override previous settings.  Any environment settings on an
+    /// individual operation in the chain are an additive override to the
+    /// settings here.
     ///
     /// The first argument is the environment variable name and the second
     /// argument is the value to set for that variable.
@@ -669,17 +1266,18 @@ impl ChainedOpRef {
           V: Into<String>
     {
         {
-            let mut ops: RefMut<_> = self.chop.borrow_mut();
-            ops.chain[self.opidx].set_env(var_name.into(), var_value.into());
+            let mut ops: SharedRefMut<_> = self.chops.borrow_mut();
+            ops.chain_env = ops.chain_env.add(var_name, var_value);
         }
         self
     }
 
-    /// Extends the operations environment for this chained operation by
-    /// prepending a value to an environment variable.  If the environment
-    /// variable was not previously set, this becomes the new value for that
-    /// variable.  This can be used multiple times to extend the environment
-    /// variable with multiple values, including those from the overall chain.
+    /// Extends the chained operations environment by prepending a value to an
+    /// environment variable.  If the environment variable was not previously
+    /// set, this becomes the new value for that variable.  This can be used
+    /// multiple times to extend the environment variable with multiple values.
+    /// Any environment settings on an individual operation in the
+    /// chain are an additive override to the settings here.
     ///
     /// The first argument is the environment variable name and the second
     /// argument is the value to prepend to that variable, and the third value is
@@ -690,18 +1288,18 @@ impl ChainedOpRef {
           S: Into<String>
     {
         {
-            let mut ops: RefMut<_> = self.chop.borrow_mut();
-            ops.chain[self.opidx].prepend_env(var.into(), value.into(),
-                                              sep.into());
+            let mut ops: SharedRefMut<_> = self.chops.borrow_mut();
+            ops.chain_env = ops.chain_env.prepend(var, value, sep);
         }
         self
     }
 
-    /// Extends the operations environment for this operation in the chain by
-    /// appending a value to an environment variable.  If the environment
-    /// variable was not previously set, this becomes the new value for that
-    /// variable.  This can be used multiple times to extend the environment
-    /// variable with multiple values, including those from the overall chain.
+    /// Extends the chained operations environment by appending a value to an
+    /// environment variable.  If the environment variable was not previously
+    /// set, this becomes the new value for that variable.  This can be used
+    /// multiple times to extend the environment variable with multiple values.
+    /// Any environment settings on an individual operation in the
+    /// chain are an additive override to the settings here.
     ///
     /// The first argument is the environment variable name and the second
     /// argument is the value to append to that variable, and the third value is
@@ -712,924 +1310,5438 @@ impl ChainedOpRef {
           S: Into<String>
     {
         {
-            let mut ops: RefMut<_> = self.chop.borrow_mut();
-            ops.chain[self.opidx].append_env(var.into(), value.into(),
-                                             sep.into());
+            let mut ops: SharedRefMut<_> = self.chops.borrow_mut();
+            ops.chain_env = ops.chain_env.append(var, value, sep);
         }
         self
     }
 
     /// Removes the specified environment variable from the environment in which
-    /// this chained operation executes.  Has no effect but does not fail if the
-    /// environment variable does not exist.
+    /// the chain of operations executes.  Has no effect but does not fail if the
+    /// environment variable does not exist.  Any environment settings on an
+    /// individual operation in the chain are an additive override to the
+    /// settings here.
     pub fn unset_env<N>(&mut self, var_name: N) -> &mut Self
     where N: Into<String>
     {
         {
-            let mut ops: RefMut<_> = self.chop.borrow_mut();
-            ops.chain[self.opidx].unset_env(var_name.into());
+            let mut ops: SharedRefMut<_> = self.chops.borrow_mut();
+            ops.chain_env = ops.chain_env.rmv(var_name);
         }
         self
     }
 
-
-    /// Add an argument to this operation in the chain
-    #[inline]
-    pub fn push_arg<T>(&mut self, arg: T) -> &mut ChainedOpRef
-    where T: Into<OsString>
+    /// Sets the manner in which the operations in this chain are executed.  By
+    /// default, a chain is executed in [ChainMode::Sequential] mode.
+    pub fn set_mode(&mut self, mode: &ChainMode) -> &mut Self
     {
-        {
-            let mut ops: RefMut<_> = self.chop.borrow_mut();
-            ops.chain[self.opidx].push_arg(arg.into());
-        }
+        self.chops.borrow_mut().mode = mode.clone();
         self
     }
 
-    /// Sets the "active" status of this operation in the chain.  An individual
-    /// operation in the chain can be skipped or executed normally based on the
-    /// [Activation] value set by this method.  When initially added to the
-    /// chain, all operations are set to [Activation::Enabled] by default.
-    #[inline]
-    pub fn active(&mut self, state: &Activation) -> &mut ChainedOpRef
+    /// Sets the manner in which the failure of an operation is handled during the
+    /// execution of this chain.  By default, a chain uses the
+    /// [ChainPolicy::FailFast] policy.
+    pub fn set_policy(&mut self, policy: &ChainPolicy) -> &mut Self
     {
-        {
-            let mut ops: RefMut<_> = self.chop.borrow_mut();
-            match state {
-                Activation::Enabled => ops.opstate.remove(&self.opidx),
-                Activation::Disabled => ops.opstate.insert(self.opidx, state.clone()),
-            };
-        }
+        self.chops.borrow_mut().policy = policy.clone();
         self
     }
 
-}
-
+    /// Executes this chain (as with [ChainedOps::execute]) and returns a
+    /// [ChainReport] that describes the outcome of each element of the chain
+    /// along with the result of the execution.
+    pub fn execute_with_report<Exec, P>(&mut self, executor: &Exec, cwd: &Option<P>)
+                                        -> ChainReport
+    where P: AsRef<Path>, Exec: OsRun
+    {
+        let result = self.execute(executor, cwd);
+        self.report(result)
+    }
 
-impl FilesPrep for ChainedOpRef {
+    /// Executes this chain asynchronously (as with [ChainedOps::execute_async])
+    /// and returns a [ChainReport] (see [ChainedOps::execute_with_report]).
+    #[cfg(feature = "async")]
+    pub async fn execute_with_report_async<Exec, P>(&mut self,
+                                                    executor: &Exec,
+                                                    cwd: &Option<P>)
+                                                    -> ChainReport
+    where P: AsRef<Path>, Exec: AsyncOsRun
+    {
+        let result = self.execute_async(executor, cwd).await;
+        self.report(result)
+    }
 
-    /// Sets the directory in which this operation is performed.  If this is a
-    /// relative directory, it will be relative to the directory of the entire
-    /// ChainedOps.  If not set, the ChainedOps directory is used, and if set to
-    /// an absolute directory, it will override the ChainedOps directory setting.
-    fn set_dir<T>(&mut self, tgtdir: T) -> &mut ChainedOpRef
-    where T: AsRef<Path>
+    /// Specifies a checkpoint file for this chain.  During execution of a
+    /// [ChainMode::Sequential] chain, the checkpoint file is written after each
+    /// successful operation (or pipeline), recording the index of the operation
+    /// and its output file(s); temporary output files are copied to a directory
+    /// alongside the checkpoint file (the checkpoint path with a `.files`
+    /// suffix) to preserve them.  If the chain fails, it can then be resumed
+    /// from the first incomplete operation via [ChainedOps::execute_resume].
+    ///
+    /// The checkpoint also records a fingerprint of the chain definition (the
+    /// operations, their commands, activation and links, the files of the
+    /// chain, and the input files set explicitly for operations); a chain can
+    /// only be resumed if its definition is unchanged.  The checkpoint is
+    /// removed when the main chain (i.e. excluding any finally operations)
+    /// succeeds, and an execution that is not resumed replaces any existing
+    /// checkpoint.  Checkpoints are written on a best-effort basis: a failure to
+    /// write a checkpoint does not fail the chain.
+    pub fn set_checkpoint<T>(&mut self, path: T) -> &mut Self
+    where T: Into<PathBuf>
     {
-        {
-            let mut ops: RefMut<_> = self.chop.borrow_mut();
-            ops.chain[self.opidx].set_dir(tgtdir);
-        }
+        self.chops.borrow_mut().checkpoint = Some(path.into());
         self
     }
 
-    /// Sets the input file for this operation in the chain.  This is normally
-    /// not used for chained operations, since the input file will default to the
-    /// output file of the previous operation in the chain (and the chain's input
-    /// file will become the input file for the first operation in the chain).
+    /// Executes this chain (as with [ChainedOps::execute]), resuming a previous
+    /// failed execution from its checkpoint (see [ChainedOps::set_checkpoint]):
+    /// the operations up to and including the operation recorded by the
+    /// checkpoint are not performed, and the recorded output of that operation
+    /// is provided to the next operation.  If there is no checkpoint file, the
+    /// entire chain is executed.
     ///
-    /// * If this is the first operation in the chain, this will be ignored
-    ///   unless the chain itself has no input file specified.
-    ///
-    /// * If this is not the first operation in the chain, this will override the
-    ///   default chain behaviour of setting the input file to the output of the
-    ///   previous element.
-    fn set_input_file(&mut self, inp_fname : &FileArg) -> &mut ChainedOpRef
+    /// Fails with [ChainsopError::ErrorCheckpoint] if the checkpoint cannot be
+    /// read, the chain definition has changed since the checkpoint was written,
+    /// or the chain is not a [ChainMode::Sequential] chain.
+    pub fn execute_resume<Exec, P>(&mut self, executor: &Exec, cwd: &Option<P>)
+                                   -> anyhow::Result<ActualFile>
+    where P: AsRef<Path>, Exec: OsRun
     {
-        {
-            let mut ops: RefMut<_> = self.chop.borrow_mut();
-            ops.chain[self.opidx].set_input_file(inp_fname);
-            if ! ops.preset_inputs.contains(&self.opidx) {
-                ops.preset_inputs.push(self.opidx);
-            }
-        }
-        self
+        self.run(executor, cwd, true)
     }
 
-    /// Appends an additional input file for this operation.  Has the same
-    /// behavior characteristics as the [ChainedOpRef::set_input_file] method.
-    fn add_input_file(&mut self, inp_fname : &FileArg) -> &mut ChainedOpRef
+    /// Executes this chain asynchronously, resuming a previous failed execution
+    /// from its checkpoint (see [ChainedOps::execute_resume]).
+    #[cfg(feature = "async")]
+    pub async fn execute_resume_async<Exec, P>(&mut self,
+                                               executor: &Exec,
+                                               cwd: &Option<P>)
+                                               -> anyhow::Result<ActualFile>
+    where P: AsRef<Path>, Exec: AsyncOsRun
+    {
+        self.run_async(executor, cwd, true).await
+    }
+
+    fn report(&self, result: anyhow::Result<ActualFile>) -> ChainReport
+    {
+        let chops = self.chops.borrow();
+        ChainReport { label : chops.name.clone(), ops : chops.report(), result }
+    }
+
+}
+
+impl FilesPrep for ChainedOps
+{
+    /// Sets the current directory for the entire chain.  Individual operations
+    /// can specify a relative directory that will be make relative to this
+    /// location, or they can specify an absolute directory that will override
+    /// this location.  If this call is not made, the operations are executed in
+    /// the current process directory.
+    #[inline]
+    fn set_dir<T: AsRef<Path>>(&mut self, tgtdir: T) -> &mut Self
     {
         {
-            let mut ops: RefMut<_> = self.chop.borrow_mut();
-            ops.chain[self.opidx].add_input_file(inp_fname);
-            if ! ops.preset_inputs.contains(&self.opidx) {
-                ops.preset_inputs.push(self.opidx);
-            }
+            let mut ops: SharedRefMut<_> = self.chops.borrow_mut();
+            ops.files.set_dir(tgtdir);
+        }
+        self
+    }
+
+    /// Sets the input file(s) for the entire chain
+    #[inline]
+    fn set_input_file(&mut self, fname: &FileArg) -> &mut Self
+    {
+        {
+            let mut ops: SharedRefMut<_> = self.chops.borrow_mut();
+            ops.files.set_input_file(fname);
+        }
+        self
+    }
+
+    /// Adds an input file(s) to the entire chain
+    #[inline]
+    fn add_input_file(&mut self, fname: &FileArg) -> &mut Self
+    {
+        {
+            let mut ops: SharedRefMut<_> = self.chops.borrow_mut();
+            ops.files.add_input_file(fname);
         }
         self
     }
 
     fn has_input_file(&self) -> bool
     {
-        self.chop.borrow().chain[self.opidx].has_input_file()
+        self.chops.borrow().files.has_input_file()
     }
 
-    /// Specifies the output file for this operation in the chain. This will also
-    /// inform the input file setting for the subsequent operation in the chain
-    /// unless that operation has an explicit input file setting.
-    ///
-    /// * If this is the last element of the chain, this is ignored if an output
-    ///   file for the chain itself is set.
-    fn set_output_file(&mut self, out_fname : &FileArg) -> &mut ChainedOpRef
+    #[inline]
+    fn set_output_file(&mut self, fname: &FileArg) -> &mut Self
     {
         {
-            let mut ops: RefMut<_> = self.chop.borrow_mut();
-            ops.chain[self.opidx].set_output_file(out_fname);
+            let mut ops: SharedRefMut<_> = self.chops.borrow_mut();
+            ops.files.set_output_file(fname);
         }
         self
     }
 
     fn has_explicit_output_file(&self) -> bool
     {
-        self.chop.borrow().chain[self.opidx].has_explicit_output_file()
+        self.chops.borrow().files.has_explicit_output_file()
     }
 
 }
 
+impl OpInterface for ChainedOps
+{
+    fn label(&self) -> String
+    {
+        self.chops.borrow().name.clone()
+    }
 
-// ----------------------------------------------------------------------
-// TESTS
-// ----------------------------------------------------------------------
 
-#[cfg(test)]
-mod tests {
+    fn set_label(&mut self, new_label: &str) -> &mut Self {
+        self.chops.borrow_mut().name = new_label.to_string();
+        self
+    }
 
-    // Tested functionality (summarized here for overview and completeness
-    // determination, then marked throughout where verified by a test):
-    //
-    // * [TC1] empty chain
-    // * [TC2] chain of one element
-    // * [TC3] multi-element chain (both SubProcOperation and FunctionOperation),
-    //         including setting input file of one op to the output file of the
-    //         previous op
-    // * [TC4] multi-element chain skips disabled individual ops
-    // * [TC5] multi-element chain does not override a chain op's explicit
-    //         input file
-    // * [TC6] Set chain output file *after* final operation output file
-    // * [TC7] Set chain output file *before* final operation output file
-    // * [TC8] Set chain input file *after* final operation output file
-    // * [TC9] Set chain input file *before* final operation output file
-    // * [TC10] first and last op set input and output, no chain-level spec.
-    // * [TC11] Default chain directory is current directory
-    // * [TC12] Chain operation without directory inherits chain directory
-    // * [TC13] Chain operation relative directory is relative to chain directory
-    // * [TC14] Chain operation absolute directory ignores chain directory
-    // * [TC15] Chain specification can be executed multiple times
-    // * [TC16] Verify Temp op output file
-    // * [TC17] Verify function ViaCall (function-specified) op output file
-    // * [TC18] Verify closure ViaCall (function-specified) op output file
-    // * [TC19] Verify Loc (explicitly specified) op output file
-    // * [TC20] Verify chained ops set_dir does not affect filenames
-    // * [TC21] Absolute chain directory overrides execute directory
-    // * [TC22] Absolute chain directory combines with relative op directory
-    // * [TC23] Specified env settings are applied
-    // * [TC24] Individual op env settings supplement chain env settings
+    /// Executes all the enabled operations in this chain sequentially, updating
+    /// the input file of each operation to be the output file from the previous
+    /// operation.  On success, returns the output file of the last operation
+    /// executed; use [ChainedOps::execute_with_report] to obtain information
+    /// about each of the operations in the chain.
+    ///
+    /// The directory parameter specifies the default directory from which the
+    /// chained operations will be performed.  Each chained operation might
+    /// operate from a separate directory if the [SubProcOperation::set_dir()] or
+    /// [ChainedOpRef::set_dir()] function has been called for this operation,
+    /// which overrides the default directory passed to this command.
+    fn execute<Exec, P>(&mut self, executor: &Exec, cwd: &Option<P>)
+                        -> anyhow::Result<ActualFile>
+    where P: AsRef<Path>, Exec: OsRun
+    {
+        self.run(executor, cwd, false)
+    }
+}
 
-    use super::*;
-    use std::cell::RefCell;
-    use std::path::PathBuf;
-    use crate::executable::*;
-    use crate::execution::*;
+impl ChainedOps {
+    // Performs the execution of the chain for ChainedOps::execute (or, if
+    // resume is true, ChainedOps::execute_resume).
+    fn run<Exec, P>(&mut self, executor: &Exec, cwd: &Option<P>, resume: bool)
+                    -> anyhow::Result<ActualFile>
+    where P: AsRef<Path>, Exec: OsRun
+    {
+        // Lock this chain to ensure it is not run in parallel, which would
+        // create conflicts with the internal chain element input file updates.
+        // The lock is automatically released at the end of this method scope.
+        let mut locked = self.chlock.write().unwrap();
+        *locked += 1;
 
-    #[derive(Clone, Debug, PartialEq)]
-    struct RunExec {
-        name: String,
-        exe: PathBuf,
-        args: Vec<OsString>,
-        env: EnvSpec,
-        dir: Option<PathBuf>
-    }
-    #[derive(Debug, PartialEq)]
-    struct RunFunc{
-        fname: String,
-        inpfiles: Vec<PathBuf>,
-        outfile: Option<PathBuf>,
-        dir: Option<PathBuf>
-    }
-    #[derive(Debug, PartialEq)]
-    enum TestOp {
-        SPO(RunExec),
-        FO(RunFunc)
-    }
-    struct TestCollector(RefCell<Vec<TestOp>>);
-    impl TestCollector {
-        pub fn new() -> TestCollector {
-            TestCollector(RefCell::new(vec![]))
-        }
+        let mut chops = self.chops.borrow_mut();
+        let chops = &mut *chops;
+        let (tgtdir, start) = begin_chain(chops, cwd, resume);
+        let result = match start {
+            ChainStart::Done(result) => result,
+            ChainStart::Run(enabled_opidxs, prev) => match chops.mode.clone() {
+                ChainMode::Sequential =>
+                    execute_chain(executor, chops, &tgtdir, enabled_opidxs, prev),
+                ChainMode::Dag(max_jobs) =>
+                    execute_dag(executor, chops, &tgtdir, enabled_opidxs,
+                                max_jobs),
+                ChainMode::ForEach(each) =>
+                    execute_each(executor, chops, &tgtdir, &enabled_opidxs,
+                                 &each),
+            },
+        };
+        completed_checkpoint(chops, &result);
+        let result = execute_finally(executor, chops, &tgtdir, result);
+        release_outputs(chops);
+        clear_nested_inputs(chops);
+        result
     }
+}
 
-    impl OsRun for TestCollector {
-        fn run_executable(&self,
-                          label: &str,
-                          exe_file: &Path,
-                          args: &Vec<OsString>,
-                          exe_env: &EnvSpec,
-                          fromdir: &Option<PathBuf>) -> OsRunResult
-        {
-            self.0.borrow_mut()
-                .push(TestOp::SPO(RunExec{ name: String::from(label),
-                                           exe: PathBuf::from(exe_file),
-                                           args: args.clone(),
-                                           env: exe_env.clone(),
-                                           dir: fromdir.clone()
-            }));
-            OsRunResult::Good
-        }
-        fn run_function(&self,
-                        name : &str,
-                        _call : &Rc<dyn Fn(&Path, &ActualFile, &ActualFile) -> anyhow::Result<()>>,
-                        inpfiles: &ActualFile,
-                        outfile: &ActualFile,
-                        fromdir: &Option<PathBuf>) -> OsRunResult
-        {
-            self.0.borrow_mut()
-                .push(TestOp::FO(RunFunc{ fname: name.to_string(),
-                                          inpfiles: inpfiles.to_paths::<PathBuf>(&None).unwrap(),
-                                          outfile: outfile.to_path::<PathBuf>(&None).ok(),
-                                          dir: fromdir.clone()
-            }));
-            OsRunResult::Good
-        }
-        fn glob_search(&self, _globpat: &String) -> anyhow::Result<Vec<PathBuf>>
-        {
-            Err(anyhow::anyhow!("glob_search not implemented for ArgCollector"))
-        }
-        fn mk_tempfile(&self, suffix: &String) -> anyhow::Result<tempfile::NamedTempFile>
-        {
-            Executor::DryRun.mk_tempfile(suffix)
-        }
-    }
+#[cfg(feature = "async")]
+impl AsyncOpInterface for ChainedOps {
 
-    fn test_callee(_indir: &Path,
-                   _inpfiles: &ActualFile,
-                   _outfile: &ActualFile) -> anyhow::Result<()>
+    /// Executes all the enabled operations in this chain in the same manner as
+    /// [ChainedOps::execute], except that each operation is awaited.  In
+    /// [ChainMode::Dag] mode, the [SubProcOperation] elements that are ready
+    /// are awaited concurrently (up to the maximum number of jobs); other
+    /// elements are awaited in turn when they become ready.
+    ///
+    /// The chain is only borrowed between the await points, so the returned
+    /// future can be moved between threads (e.g. via `tokio::spawn`) when the
+    /// "sync" feature is enabled and the executor is `Sync`.  The chain (or its
+    /// [ChainedOpRef] handles) must still not be modified while the execution
+    /// is in progress: the operation being awaited is a copy of the chain
+    /// element, which replaces that element when it completes.
+    async fn execute_async<Exec, P>(&mut self, executor: &Exec, cwd: &Option<P>)
+                                    -> anyhow::Result<ActualFile>
+    where P: AsRef<Path>, Exec: AsyncOsRun
     {
-        // n.b. the FunctionalOp entries in the chains below are disabled, so
-        // this is not actually needed.  If it is, a more complete body should be
-        // supplied.
-        todo!("test_callee not implemented")
+        self.run_async(executor, cwd, false).await
     }
+}
 
-    fn add_finish_outarg(args: &mut Vec<OsString>,
-                         _cwd: &Option<PathBuf>,
-                         outf: &ActualFile)
-                         -> anyhow::Result<()>
+#[cfg(feature = "async")]
+impl ChainedOps {
+    // Performs the execution of the chain for ChainedOps::execute_async (or, if
+    // resume is true, ChainedOps::execute_resume_async).  This is the same as
+    // ChainedOps::run, except that each operation is awaited.
+    //
+    // Neither the execution lock nor the chain internals are held across the
+    // await points (which would prevent the future from being Send); instead
+    // the internals are borrowed for each step of the execution.
+    async fn run_async<Exec, P>(&mut self, executor: &Exec, cwd: &Option<P>,
+                                resume: bool)
+                                -> anyhow::Result<ActualFile>
+    where P: AsRef<Path>, Exec: AsyncOsRun
     {
-        for pth in outf.to_paths::<PathBuf>(&None)? {
-            args.push(format!("out:{:?}", pth).into());
-        }
-        Ok(())
+        *self.chlock.write().unwrap() += 1;
+
+        let chops : &SharedCell<ChainedOpsInternals> = &self.chops;
+        let (tgtdir, start) = begin_chain(&mut chops.borrow_mut(), cwd, resume);
+        let mode = chops.borrow().mode.clone();
+        let result = match start {
+            ChainStart::Done(result) => result,
+            ChainStart::Run(enabled_opidxs, prev) => match mode {
+                ChainMode::Sequential =>
+                    execute_chain_async(executor, chops, &tgtdir, enabled_opidxs,
+                                        prev).await,
+                ChainMode::Dag(max_jobs) =>
+                    execute_dag_async(executor, chops, &tgtdir, enabled_opidxs,
+                                      max_jobs).await,
+                ChainMode::ForEach(each) =>
+                    execute_each_async(executor, chops, &tgtdir, &enabled_opidxs,
+                                       &each).await,
+            },
+        };
+        completed_checkpoint(&chops.borrow(), &result);
+        let result = execute_finally_async(executor, chops, &tgtdir, result).await;
+        let mut chops = chops.borrow_mut();
+        release_outputs(&mut chops);
+        clear_nested_inputs(&mut chops);
+        result
     }
+}
 
-    #[test]
-    // n.b. this pretty much tests all the things as far as chainedops is
-    // concerned; more focused tests to identify individual failure modes may be
-    // helpful when tracking down issues.
-    fn test_chain() -> anyhow::Result<()> {
-        let mut ops = ChainedOps::new("test chain");
-        ops.set_input_file(&FileArg::loc("orig.inp"));   // [TC9]
-        ops.set_output_file(&FileArg::loc("final.out"));  // [TC7]
-        ops.set_env("GONE", "Long").append_env("SUB", "CLUB", ",")
-            .set_env("ZINC", "ALLOY");
+// Where the execution of a chain starts, as determined by begin_chain.
+enum ChainStart {
+    // There are no (further) operations to perform in the main chain, which
+    // has therefore completed with the specified result.
+    Done(anyhow::Result<ActualFile>),
 
-        let exe = Executable::new(&"test-cmd",
-                                  ExeFileSpec::Append,
-                                  ExeFileSpec::Append);
-        let op = SubProcOperation::new(&exe)
-            // set the input file, but the chain will override this
-            .set_input_file(&FileArg::loc("inpfile.txt"))  // [TC9]
-            .set_output_file(&FileArg::temp(".out")) // [TC16]
-            .prepend_env("SUB", "HUB", ":")
-            .unset_env("GONE")
-            .push_arg("-a")
-            .push_arg("a-arg-value")
-            .push_arg("-b")
-            .append_env("SUB", "DUB", ";")
-            .clone();
+    // The enabled operations (in reverse order) are performed, where the
+    // previous output (if any) is the output of the last operation completed
+    // before resuming from a checkpoint.
+    Run(Vec<usize>, Option<ActualFile>),
+}
 
-        ops.push_op(&op);  // [TC12]
-        ops.add_input_file(&FileArg::loc("other_inpfile")); // [TC9]
+// Prepares the chain for execution (see prepare_chain and start_point),
+// returning the directory in which the chain should be executed along with
+// where the execution starts.
+fn begin_chain<P>(chops: &mut ChainedOpsInternals, cwd: &Option<P>, resume: bool)
+                  -> (Option<PathBuf>, ChainStart)
+where P: AsRef<Path>
+{
+    let (tgtdir, mut enabled_opidxs) = prepare_chain(chops, cwd);
+    let start = match start_point(chops, &mut enabled_opidxs, resume) {
+        // This is a non-functional chain: it is either empty or every
+        // operation in the chain is disabled (or has already been
+        // completed).  No (further) output file was generated.
+        Ok(prev) if enabled_opidxs.is_empty() =>
+            ChainStart::Done(Ok(prev.unwrap_or(ActualFile::NoActualFile))),
+        Ok(prev) => ChainStart::Run(enabled_opidxs, prev),
+        Err(e) => ChainStart::Done(Err(e)),
+    };
+    (tgtdir, start)
+}
 
-        let mut op2 = ops.push_op(SubProcOperation::new(
-            &Executable::new(&"cmd2",
-                             ExeFileSpec::Option("--input".into()),
-                             ExeFileSpec::Append))
-                              .set_output_file(&FileArg::temp(".o2")) // [TC16]
-                              .push_arg("-D")
-                              .push_arg("DVAL")
-        );
-        op2.push_arg("-a2");
-        op2.set_dir(".build");
+// Prepares the chain for execution by setting the chain's input and output
+// files on the first and last enabled operations (respectively).  Returns the
+// directory in which the chain should be executed along with the indices of the
+// enabled operations in reverse order (which is empty if there are no enabled
+// operations to execute).  The finally operations are not included: these are
+// performed separately by execute_finally.
+fn prepare_chain<P>(chops: &mut ChainedOpsInternals, cwd: &Option<P>)
+                    -> (Option<PathBuf>, Vec<usize>)
+where P: AsRef<Path>
+{
+    chops.records.clear();
+    release_outputs(chops);
+    preset_nested_inputs(chops);
+    *chops.status.0.borrow_mut() = None;
 
-        let mut op3 = ops.push_op(&op);
-        op3.active(&Activation::Disabled);
-        op3.active(&Activation::Enabled).active(&Activation::Disabled); // [TC4]
+    // Some chain elements might be marked as disabled.  Rather than
+    // requiring a test of each chain element each time it is to be
+    // considered, we instead build a vec of the enabled element indices.
+    // Build it in reverse so the operations can simply .pop() the next index
+    // off the end.
+    let enabled_opidxs : Vec<usize> = chops.chain.iter()
+        .enumerate()
+        .filter(|(i,_op)| ! chops.finally.contains(i) && ! is_disabled(chops, i))
+        .map(|(i,_op)| i)
+        .rev()
+        .collect();
 
-        ops.push_call(FunctionOperation::calling("a func", test_callee)
-                      .set_input_file(&FileArg::loc("not replaced")) // [TC5]
-                      .set_output_file(&FileArg::temp(".fnout"))); // [TC16]
+    let tgtdir = match cwd {
+        None => chops.files.in_dir.clone(),
+        Some(d) =>
+            match &chops.files.in_dir {
+                Some(od) => Some(d.as_ref().join(od)),
+                None => Some(d.as_ref().into()),
+        }
+    };
+    if enabled_opidxs.is_empty() {
+        return (tgtdir, enabled_opidxs);
+    }
 
-        // Verify ViaCall can take a closure
-        ops.push_op(SubProcOperation::new(
-            &Executable::new(&"finish",
-                             ExeFileSpec::option("-i"),
-                             ExeFileSpec::ViaCall(|args, cwd, outf| { // [TC18]
-                                 args.push(format!("+({:?}){:?}", cwd, outf).into());
-                                 Ok(())
-                             })))
-                    .set_output_file(&FileArg::TBD)
-                    .push_arg("--last")
-                    .set_dir(".other")
-        ).active(&Activation::Disabled); // [TC4]
+    let first_op = enabled_opidxs[enabled_opidxs.len()-1];
+    let last_op = enabled_opidxs[0];
 
-        let mut op5 = ops.push_op(SubProcOperation::new(
-            &Executable::new(&"finish",
-                             ExeFileSpec::option("-i"),
-                             ExeFileSpec::ViaCall(add_finish_outarg))) // [TC17]
-                                  // The output file setting here will be ignored
-                                  // because the output file for the chain itself
-                                  // is specified.
-                              .set_output_file(&FileArg::loc("ignored")) // [TC7]
-                              .push_arg("--last")
-                              .set_dir(".other")
-        );
-        op5.push_arg("op").set_dir(".build");  // [TC13]
+    if chops.mode == ChainMode::Sequential {
+        set_chain_inputs(chops, first_op);
+    }
+    if chops.files.has_explicit_output_file()
+        && ! matches!(chops.mode, ChainMode::ForEach(_))
+    {
+        let main_out_file = chops.files.out_filename.clone();
+        chops.chain[last_op].set_output_file(&main_out_file);
+    }
+    (tgtdir, enabled_opidxs)
+}
 
-        let xor = TestCollector::new();
-        let result = ops.execute(&xor, &Some("target/loc"));
-        assert!(match result {
-            Ok(ActualFile::SingleFile(FileRef::StaticFile(sf))) =>
-                sf == PathBuf::from("final.out"),
-            _ => false,
-        });
-        let mut collected = xor.0.into_inner();
-        assert_eq!(collected.len(), 4);
+// Determines where the execution of the (prepared) chain starts.  When resuming
+// from a checkpoint, the operations already completed are removed from the
+// enabled_opidxs and the recorded output of the last completed operation is
+// returned (and provided to the next operation).  Otherwise any existing
+// checkpoint is removed since it is replaced by this execution.
+fn start_point(chops: &mut ChainedOpsInternals,
+               enabled_opidxs: &mut Vec<usize>,
+               resume: bool) -> anyhow::Result<Option<ActualFile>>
+{
+    let Some(path) = chops.checkpoint.clone() else { return Ok(None); };
+    if ! resume {
+        Checkpoint::remove(&path);
+        return Ok(None);
+    }
+    let cannot_resume = |reason: String| anyhow::Error::new(
+        ChainsopError::ErrorCheckpoint(chops.name.clone(), path.clone(), reason));
+    let checkpoint = match Checkpoint::load(&path) {
+        Ok(Some(checkpoint)) => checkpoint,
+        Ok(None) => return Ok(None),
+        Err(e) => return Err(cannot_resume(format!("{:#}", e))),
+    };
+    if chops.mode != ChainMode::Sequential {
+        return Err(cannot_resume("only a sequential chain can be resumed".into()));
+    }
+    if checkpoint.fingerprint != fingerprint(chops) {
+        return Err(cannot_resume("the chain definition has changed".into()));
+    }
+    let prev = checkpoint.output();
+    enabled_opidxs.retain(|i| *i > checkpoint.completed);
+    if let Some(next_idx) = enabled_opidxs.last() {
+        chain_to_next(&mut chops.chain, &chops.preset_inputs,
+                      checkpoint.completed, *next_idx, &prev)?;
+    }
+    Ok(Some(prev))
+}
 
-        // The last arg of the first op is an assigned output tempfile
-        let output0_tmpfile = match &mut collected[0] {
-            TestOp::SPO(re) => {
-                let outf = PathBuf::from(&re.args.last().unwrap());
-                assert!(!outf.exists(), // [TC16]
-                        "intermediate temp file #0 {:?} did not get cleaned up!",
-                        outf);
-                assert_eq!(outf.extension(), Some(OsString::from("out")).as_deref()); // [TC16]
-                re.args.pop();
-                outf
+// Writes the chain's checkpoint (if any) after the successful completion of the
+// specified operation.  No checkpoint is written once an operation has failed
+// (i.e. the chain is continuing past a failure) because resuming from that
+// point would skip the failed operation.
+fn save_checkpoint(chops: &ChainedOpsInternals,
+                   failures: &[OpFailure],
+                   done_idx: usize,
+                   outfile: &ActualFile)
+{
+    if let Some(path) = &chops.checkpoint {
+        if failures.is_empty() && chops.mode == ChainMode::Sequential {
+            Checkpoint::save(path, &fingerprint(chops), done_idx, outfile);
+        }
+    }
+}
+
+// Removes the chain's checkpoint (if any) when the main chain has succeeded.
+fn completed_checkpoint(chops: &ChainedOpsInternals,
+                        result: &anyhow::Result<ActualFile>)
+{
+    if let (Some(path), Ok(_)) = (&chops.checkpoint, result) {
+        Checkpoint::remove(path);
+    }
+}
+
+// Returns the fingerprint of the chain definition that is recorded in a
+// checkpoint.
+fn fingerprint(chops: &ChainedOpsInternals) -> String
+{
+    let definition = format!("{:?}\n{}", chops.files.inp_filenames,
+                             chain_definition(chops));
+    Sha256::digest(definition.as_bytes()).iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// Returns a description of the chain definition.  The input files of the chain
+// and of each operation (other than preset inputs) and the output files of the
+// operations are not part of the definition: these are updated when the chain
+// is executed.
+fn chain_definition(chops: &ChainedOpsInternals) -> String
+{
+    let mut definition = format!("{:?} {:?} {:?} {:?} {:?}\n", chops.name,
+                                 chops.files.out_filename, chops.files.in_dir,
+                                 chops.chain_env, chops.mode);
+    for (i, op) in chops.chain.iter().enumerate() {
+        let files = op.files();
+        definition.push_str(&format!(
+            "{} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {}\n",
+            i, op.label(), chops.opstate.get(&i), chops.links.get(&i),
+            chops.depends.get(&i),
+            chops.preset_inputs.contains(&i).then_some(&files.inp_filenames),
+            files.in_dir, chops.optional.contains(&i),
+            chops.incremental.contains(&i), chops.finally.contains(&i),
+            op.definition()));
+    }
+    definition
+}
+
+// Sets the input file(s) of the specified operation to the chain's input files
+// (if any).
+fn set_chain_inputs(chops: &mut ChainedOpsInternals, op_idx: usize)
+{
+    let chain_inps = chops.files.inp_filenames.clone();
+    if let Some((inp1, inpr)) = chain_inps.split_first() {
+        chops.chain[op_idx].set_input_file(inp1);
+        for f in inpr {
+            chops.chain[op_idx].add_input_file(f);
+        }
+    }
+}
+
+fn is_disabled(chops: &ChainedOpsInternals, op_idx: &usize) -> bool
+{
+    matches!(chops.opstate.get(op_idx), Some(Activation::Disabled))
+}
+
+// Returns false if the operation has an Activation::When predicate that is not
+// satisfied in the current context, in which case the operation should be
+// skipped.
+fn activated(chops: &ChainedOpsInternals,
+             op_idx: usize,
+             executor: &dyn OsRun,
+             dir: &Option<PathBuf>,
+             previous: &ActualFile) -> bool
+{
+    match chops.opstate.get(&op_idx) {
+        Some(Activation::When(predicate)) =>
+            (predicate.0)(&ActivationContext { executor, dir, previous }),
+        _ => true,
+    }
+}
+
+// Records an operation that was skipped because its Activation::When predicate
+// was not satisfied, for the report of the chain execution.
+fn record_skipped(records: &mut HashMap<usize, OpReport>,
+                  op_idx: usize,
+                  op: &RunnableOp)
+{
+    records.insert(op_idx, OpReport {
+        label : op.label(),
+        status : OpStatus::Skipped,
+        exit_code : None,
+        elapsed : None,
+        command : Vec::new(),
+        output_files : Vec::new(),
+        nested : op.nested_report(),
+    });
+}
+
+// Determines whether an incremental operation is up to date: its explicit
+// (FileArg::Loc) output file exists and is newer than all of its input files
+// (of which there must be at least one).  If so, the operation is recorded as
+// up to date and its existing output file is returned, and the operation should
+// not be performed.  The previous is the output of the previous operation(s),
+// which provides the inputs of the operation unless they are pre-set.
+fn up_to_date(executor: &impl OsRun,
+              chops: &mut ChainedOpsInternals,
+              op_idx: usize,
+              cwd: &Option<PathBuf>,
+              previous: &ActualFile) -> Option<ActualFile>
+{
+    if ! chops.incremental.contains(&op_idx) {
+        return None;
+    }
+    // A temporary input file has just been generated, so the operation is
+    // never up to date.  This includes the temporary output of a previous
+    // operation, which is passed on as a FileArg::Loc input.
+    if ! chops.preset_inputs.contains(&op_idx) && has_temp_file(previous) {
+        return None;
+    }
+    let op = &chops.chain[op_idx];
+    let files = op.files();
+    let outpath = match &files.out_filename {
+        FileArg::Loc(p) => p.clone(),
+        _ => return None,
+    };
+    let rundir = match cwd {
+        Some(d) => Some(files.in_dir.as_ref().map_or_else(|| d.clone(),
+                                                          |sub| d.join(sub))),
+        None => files.in_dir.clone(),
+    };
+    let out_time = executor.modified(&rundir.as_ref().map_or_else(
+        || outpath.clone(), |d| d.join(&outpath)))?;
+    let mut inpaths = Vec::new();
+    for inp in &files.inp_filenames {
+        if let FileArg::Temp(_) = inp {
+            return None;
+        }
+        inpaths.extend(setup_file(executor, inp, || Ok(ActualFile::NoActualFile))
+                       .and_then(|f| f.to_paths(&rundir))
+                       .ok()?);
+    }
+    // Without any input files, the output cannot be known to be up to date.
+    if inpaths.is_empty()
+        || ! inpaths.iter().all(|p| executor.modified(p).is_some_and(|t| t < out_time))
+    {
+        return None;
+    }
+
+    executor.report_up_to_date(&op.label(), &outpath);
+    let outfile = ActualFile::SingleFile(FileRef::StaticFile(outpath.clone()));
+    if let Some(held) = chops.outputs.get_mut(&op_idx) {
+        held.set(&outfile);
+    }
+    chops.records.insert(op_idx, OpReport {
+        label : op.label(),
+        status : OpStatus::UpToDate,
+        exit_code : None,
+        elapsed : None,
+        command : Vec::new(),
+        output_files : vec![outpath.clone()],
+        nested : op.nested_report(),
+    });
+    Some(outfile)
+}
+
+// Returns true if any of the files is a temporary file.
+fn has_temp_file(files: &ActualFile) -> bool
+{
+    let is_temp = |fref: &FileRef| matches!(fref, FileRef::TempFile(_));
+    match files {
+        ActualFile::SingleFile(fref) => is_temp(fref),
+        ActualFile::MultiFile(frefs) => frefs.iter().any(is_temp),
+        ActualFile::NoActualFile => false,
+    }
+}
+
+// Passes the input of a skipped operation in a sequential chain on to the next
+// operation: this is the output of the previous operation or, if no operation
+// has been performed yet, the chain's input files.
+fn skip_to_next(chops: &mut ChainedOpsInternals,
+                failures: &[OpFailure],
+                skipped_idx: usize,
+                next_idx: usize,
+                prev: &Option<ActualFile>) -> anyhow::Result<()>
+{
+    match prev {
+        Some(prev) => chain_to_next(&mut chops.chain, &chops.preset_inputs,
+                                    skipped_idx, next_idx, prev),
+        None => {
+            // Without a previous output, either no operation has been performed
+            // or the previous operation failed (and the chain continues).
+            if failures.is_empty() && ! chops.preset_inputs.contains(&next_idx) {
+                set_chain_inputs(chops, next_idx);
             }
-            TestOp::FO(_) => {
-                panic!("Expected first op to be a SubProcOperation");
+            Ok(())
+        }
+    }
+}
+
+// Returns the combined outputs of the operations that a DAG operation depends
+// upon, which is the previous output for an Activation::When predicate (and is
+// passed on to the dependents of the operation if it is skipped).
+fn dag_previous(outputs: &HashMap<usize, ActualFile>, deps: &[usize]) -> ActualFile
+{
+    deps.iter().fold(ActualFile::NoActualFile,
+                     |prev, d| prev.extend(outputs[d].share()))
+}
+
+// The environment variable that is set for the finally operations of a chain to
+// indicate whether the main chain execution succeeded.
+const CHAIN_STATUS_VAR : &str = "CHAINSOP_CHAIN_STATUS";
+
+// Performs the enabled finally operations of the chain (in the order they were
+// added) after the main chain execution has completed with the specified result,
+// and returns the overall result of the chain execution.
+fn execute_finally(executor: &impl OsRun,
+                   chops: &mut ChainedOpsInternals,
+                   cwd: &Option<PathBuf>,
+                   result: anyhow::Result<ActualFile>) -> anyhow::Result<ActualFile>
+{
+    let (finally_idxs, settings) = prepare_finally(chops, &result);
+    let mut failures = Vec::new();
+    for op_idx in finally_idxs {
+        if finally_activated(executor, chops, op_idx, cwd, &result) {
+            if let Err(error) = perform_op(executor, chops, op_idx, cwd, &settings) {
+                failures.push(OpFailure { index : op_idx,
+                                          label : chops.chain[op_idx].label(),
+                                          error });
             }
-        };
+        }
+    }
+    finally_result(chops, result, failures)
+}
 
-        // The penultimate arg of the second op should be the input tempfile,
-        // which should be the output tempfile of the previous op
-        match &mut collected[1] {
-            TestOp::SPO(re) => {
-                let inpf = PathBuf::from(&re.args[re.args.len()-2]);
-                assert_eq!(inpf, output0_tmpfile);
+// Performs the enabled finally operations of the chain asynchronously (see
+// execute_finally).
+#[cfg(feature = "async")]
+async fn execute_finally_async<Exec>(executor: &Exec,
+                                     chops: &SharedCell<ChainedOpsInternals>,
+                                     cwd: &Option<PathBuf>,
+                                     result: anyhow::Result<ActualFile>)
+                                     -> anyhow::Result<ActualFile>
+where Exec: AsyncOsRun
+{
+    let (finally_idxs, settings) = prepare_finally(&chops.borrow(), &result);
+    let mut failures = Vec::new();
+    for op_idx in finally_idxs {
+        let active = finally_activated(executor, &mut chops.borrow_mut(), op_idx,
+                                       cwd, &result);
+        if active {
+            if let Err(error) = perform_op_async(executor, chops, op_idx, cwd,
+                                                 &settings).await {
+                let label = chops.borrow().chain[op_idx].label();
+                failures.push(OpFailure { index : op_idx, label, error });
             }
-            TestOp::FO(_) => {
-                panic!("Expected second op to be a SubProcOperation");
+        }
+    }
+    finally_result(&chops.borrow(), result, failures)
+}
+
+// Returns false (recording the operation as skipped) if the finally operation
+// is not activated, where the previous output for an Activation::When predicate
+// is the output of the main chain (if it succeeded).
+fn finally_activated(executor: &dyn OsRun,
+                     chops: &mut ChainedOpsInternals,
+                     op_idx: usize,
+                     cwd: &Option<PathBuf>,
+                     result: &anyhow::Result<ActualFile>) -> bool
+{
+    let no_output = ActualFile::NoActualFile;
+    if activated(chops, op_idx, executor, cwd, result.as_ref().unwrap_or(&no_output)) {
+        return true;
+    }
+    record_skipped(&mut chops.records, op_idx, &chops.chain[op_idx]);
+    false
+}
+
+// Performs the specified operation with the chain's settings, and records the
+// outcome for the report of the chain execution.
+fn perform_op(executor: &impl OsRun,
+              chops: &mut ChainedOpsInternals,
+              op_idx: usize,
+              cwd: &Option<PathBuf>,
+              settings: &ChainSettings) -> anyhow::Result<ActualFile>
+{
+    let orig = chops.chain[op_idx].inherit_settings(settings);
+    let started = Instant::now();
+    let result = chops.chain[op_idx].execute(executor, cwd);
+    performed_op(chops, op_idx, &orig, started, &result);
+    result
+}
+
+// Performs the specified operation asynchronously (see perform_op).  The chain
+// is not borrowed while the operation is awaited: a detached copy of the
+// operation is performed, which then replaces the chain element.
+#[cfg(feature = "async")]
+async fn perform_op_async<Exec>(executor: &Exec,
+                                chops: &SharedCell<ChainedOpsInternals>,
+                                op_idx: usize,
+                                cwd: &Option<PathBuf>,
+                                settings: &ChainSettings) -> anyhow::Result<ActualFile>
+where Exec: AsyncOsRun
+{
+    let (orig, mut op) = {
+        let mut chops = chops.borrow_mut();
+        let orig = chops.chain[op_idx].inherit_settings(settings);
+        (orig, chops.chain[op_idx].detached())
+    };
+    let started = Instant::now();
+    let result = op.execute_async(executor, cwd).await;
+    let mut chops = chops.borrow_mut();
+    chops.chain[op_idx] = op;
+    performed_op(&mut chops, op_idx, &orig, started, &result);
+    result
+}
+
+// Records the outcome of an operation performed by perform_op (or
+// perform_op_async) and restores the operation's own settings.
+fn performed_op(chops: &mut ChainedOpsInternals,
+                op_idx: usize,
+                orig: &ChainSettings,
+                started: Instant,
+                result: &anyhow::Result<ActualFile>)
+{
+    let spo = &mut chops.chain[op_idx];
+    record_op(&mut chops.records, &mut chops.outputs, op_idx, spo, started.elapsed(),
+              result);
+    spo.restore_settings(orig);
+}
+
+// Releases the outputs of the operations referenced by FileArg::OutputOf, which
+// removes any temporary files that are no longer otherwise referenced.
+fn release_outputs(chops: &mut ChainedOpsInternals)
+{
+    for held in chops.outputs.values_mut() {
+        held.release();
+    }
+}
+
+// Determines which nested chain elements have preset input files for this
+// execution.  A nested chain is shared (see ChainedOps::push_chain), so its own
+// input files may have been changed since it was added to this chain.
+fn preset_nested_inputs(chops: &mut ChainedOpsInternals)
+{
+    for (i, op) in chops.chain.iter().enumerate() {
+        if let RunnableOp::Chain(_) = op {
+            chops.preset_inputs.retain(|p| *p != i);
+            if op.has_input_file() {
+                chops.preset_inputs.push(i);
             }
-        };
-        // The last arg of the second op is another assigned output tempfile
-        let output1_tmpfile = match &mut collected[1] {
-            TestOp::SPO(re) => {
-                let outf = PathBuf::from(&re.args.last().unwrap());
-                assert!(!outf.exists(), // [TC16]
-                        "intermediate temp file #1 {:?} did not get cleaned up!",
-                        outf);
-                assert_eq!(outf.extension(), Some(OsString::from("o2")).as_deref()); // [TC16]
-                re.args.pop();  // remove output file
-                re.args.pop();  // remove input file
-                outf
+        }
+    }
+}
+
+// Removes the input files provided to the nested chain elements (that did not
+// have preset input files) during the execution, so that the next execution
+// can again determine which of them have their own input files.
+fn clear_nested_inputs(chops: &mut ChainedOpsInternals)
+{
+    for (i, op) in chops.chain.iter_mut().enumerate() {
+        if let RunnableOp::Chain(_) = op {
+            if ! chops.preset_inputs.contains(&i) {
+                op.set_input_files(&[]);
             }
-            TestOp::FO(_) => {
-                panic!("Expected second op to be a SubProcOperation");
+        }
+    }
+}
+
+// Records the outcome of the main chain execution in the chain status and
+// returns the enabled finally operations along with the settings they inherit
+// (which include the CHAIN_STATUS_VAR environment variable).
+fn prepare_finally(chops: &ChainedOpsInternals,
+                   result: &anyhow::Result<ActualFile>)
+                   -> (Vec<usize>, ChainSettings)
+{
+    *chops.status.0.borrow_mut() = Some(result.is_ok());
+    let mut settings = chops.settings();
+    settings.env = settings.env.add(CHAIN_STATUS_VAR,
+                                    if result.is_ok() { "success" } else { "failure" });
+    let finally_idxs = chops.finally.iter()
+        .filter(|i| ! is_disabled(chops, i))
+        .cloned()
+        .collect();
+    (finally_idxs, settings)
+}
+
+// Returns the overall result of the chain execution given the result of the
+// main chain and the failures of any finally operations.  The latter are
+// reported along with (rather than replacing) any main chain failure.
+fn finally_result(chops: &ChainedOpsInternals,
+                  result: anyhow::Result<ActualFile>,
+                  failures: Vec<OpFailure>) -> anyhow::Result<ActualFile>
+{
+    if failures.is_empty() {
+        return result;
+    }
+    Err(anyhow::Error::new(ChainsopError::ErrorFinallyFailed(chops.name.clone(),
+                                                             result.err(),
+                                                             failures)))
+}
+
+// The state of the execution of a sequential chain.  This is common to
+// execute_chain and execute_chain_async, which perform each step returned by
+// SeqRun::next_step and pass the result to SeqRun::completed.
+struct SeqRun {
+    // The enabled operations that have not yet been performed, in reverse
+    // order.
+    op_idxs : Vec<usize>,
+    settings : ChainSettings,
+    failures : Vec<OpFailure>,
+    // The output of the previous operation (which is the input of the current
+    // operation) must remain in scope until the current operation completes:
+    // it may be a temporary file that is removed when dropped.
+    prev : Option<ActualFile>,
+}
+
+// The next step of a sequential chain execution, as returned by
+// SeqRun::next_step.
+enum SeqStep {
+    // Perform the operation (see perform_op).
+    Op(usize),
+
+    // Run the stages of the pipeline formed by the operations.
+    Pipe(Vec<usize>, Vec<(PipeStage, ActualFile)>),
+
+    // The chain execution has completed with the specified result.
+    Done(anyhow::Result<ActualFile>),
+}
+
+impl SeqRun {
+    // The op_idxs are in reverse order (as returned by prepare_chain) and the
+    // prev is the output of the last operation completed before resuming from a
+    // checkpoint (if any).
+    fn new(chops: &ChainedOpsInternals, op_idxs: Vec<usize>, prev: Option<ActualFile>)
+           -> SeqRun
+    {
+        SeqRun { op_idxs, settings : chops.settings(), failures : Vec::new(), prev }
+    }
+
+    // Returns the next step of the chain execution.  Operations that are not
+    // activated or that are up to date are handled here and do not need a step.
+    fn next_step(&mut self,
+                 executor: &impl OsRun,
+                 chops: &mut ChainedOpsInternals,
+                 cwd: &Option<PathBuf>) -> SeqStep
+    {
+        while let Some(op_idx) = self.op_idxs.pop() {
+            let group = pipe_group(&chops.links, op_idx, &mut self.op_idxs);
+            if self.prev.is_none() && ! self.failures.is_empty()
+                && ! chops.preset_inputs.contains(&op_idx)
+            {
+                // The input of this operation would be the output of a failed
+                // operation (or of an operation that was not performed because
+                // of a failure), so it is not performed either and is reported
+                // as OpStatus::NotRun.  Operations with pre-set inputs are
+                // still performed.
+                for i in &group {
+                    chops.records.remove(i);
+                }
+                continue;
+            }
+            if group.len() == 1
+                && ! activated(chops, op_idx, executor, cwd,
+                               self.prev.as_ref().unwrap_or(&ActualFile::NoActualFile))
+            {
+                // The skipped operation's input is passed on to the next operation.
+                record_skipped(&mut chops.records, op_idx, &chops.chain[op_idx]);
+                if let Some(next_idx) = self.op_idxs.last() {
+                    if let Err(e) = skip_to_next(chops, &self.failures, op_idx,
+                                                 *next_idx, &self.prev) {
+                        return SeqStep::Done(Err(e));
+                    }
+                }
+                continue;
+            }
+            let result = if group.len() > 1 {
+                match prepare_pipeline(executor, &mut chops.chain, &chops.opstate,
+                                       cwd, &self.settings, &group) {
+                    Ok(stages) => return SeqStep::Pipe(group, stages),
+                    Err(e) => Err(e),
+                }
+            } else if let Some(outfile) = up_to_date(
+                executor, chops, op_idx, cwd,
+                self.prev.as_ref().unwrap_or(&ActualFile::NoActualFile))
+            {
+                Ok(outfile)
+            } else {
+                return SeqStep::Op(op_idx);
+            };
+            if let Err(e) = self.completed(chops, &group, result) {
+                return SeqStep::Done(Err(e));
+            }
+        }
+        // There are no more operations, execution of the chain is completed.
+        let outfile = self.prev.take().unwrap_or(ActualFile::NoActualFile);
+        SeqStep::Done(chain_result(chops, &mut self.failures, Some(outfile)))
+    }
+
+    // Handles the result of the operation (or pipeline) performed for a step.
+    // A failure is handled according to the chain's policy, returning the error
+    // for the chain execution if it should not continue.  The output of a
+    // successful operation becomes the input of the next operation.
+    fn completed(&mut self,
+                 chops: &mut ChainedOpsInternals,
+                 group: &[usize],
+                 result: anyhow::Result<ActualFile>) -> anyhow::Result<()>
+    {
+        let outfile = match result {
+            Ok(outfile) => Some(outfile),
+            Err(e) => {
+                let failed_idx = failed_op(chops, group);
+                op_failure(chops, &mut self.failures, failed_idx, e)?;
+                None
             }
         };
+        // If the operation failed (and the chain continues), the input of the
+        // next operation is not updated.
+        let done_idx = *group.last().unwrap();
+        if let (Some(next_idx), Some(outfile)) = (self.op_idxs.last(), &outfile) {
+            save_checkpoint(chops, &self.failures, done_idx, outfile);
+            chain_to_next(&mut chops.chain, &chops.preset_inputs, done_idx,
+                          *next_idx, outfile)?;
+        }
+        self.prev = outfile;
+        Ok(())
+    }
+}
 
-        // The input file of the third op should be the tempfile output of the
-        // second op, and the output file of the third op should be an assigned
-        // tempfile.
-        let output2_tmpfile = match &mut collected[2] {
-            TestOp::SPO(_) => {
-                panic!("Expected third op to be a FunctionOperation");
+// Executes the enabled operations of a sequential chain (see SeqRun::new).
+fn execute_chain(executor: &impl OsRun,
+                 chops: &mut ChainedOpsInternals,
+                 cwd: &Option<PathBuf>,
+                 op_idxs: Vec<usize>,
+                 prev: Option<ActualFile>) -> anyhow::Result<ActualFile>
+{
+    let mut seq = SeqRun::new(chops, op_idxs, prev);
+    loop {
+        let (group, result) = match seq.next_step(executor, chops, cwd) {
+            SeqStep::Done(result) => return result,
+            SeqStep::Op(op_idx) =>
+                (vec![op_idx], perform_op(executor, chops, op_idx, cwd, &seq.settings)),
+            SeqStep::Pipe(group, stages) => {
+                let started = Instant::now();
+                let results = executor.run_pipeline(
+                    &stages.iter().map(|(stage, _)| stage.clone())
+                        .collect::<Vec<_>>());
+                let result = pipeline_result(&mut chops.chain, &group, stages,
+                                             results, started.elapsed(),
+                                             &mut chops.records, &mut chops.outputs);
+                (group, result)
             }
-            TestOp::FO(rf) => {
-                // There was an explicit input file set for this operation, so it
-                // should *not* have been overwritten by the output of the
-                // previous operation.
-                assert_ne!(rf.inpfiles, vec![output1_tmpfile.clone()]);
-                assert_eq!(rf.inpfiles, vec![PathBuf::from("not replaced")]); // [TC5]
-                let outf = rf.outfile.clone().unwrap();
-                assert!(!outf.exists(), // [TC16]
-                        "intermediate temp file #2 {:?} did not get cleaned up!",
-                        outf);
-                assert_eq!(outf.extension(), Some(OsString::from("fnout")).as_deref()); // [TC16]
-                rf.inpfiles = vec![];
-                rf.outfile = None;
-                outf
+        };
+        seq.completed(chops, &group, result)?;
+    }
+}
+
+// Executes the enabled operations of a sequential chain asynchronously (see
+// execute_chain).
+#[cfg(feature = "async")]
+async fn execute_chain_async<Exec>(executor: &Exec,
+                                   chops: &SharedCell<ChainedOpsInternals>,
+                                   cwd: &Option<PathBuf>,
+                                   op_idxs: Vec<usize>,
+                                   prev: Option<ActualFile>)
+                                   -> anyhow::Result<ActualFile>
+where Exec: AsyncOsRun
+{
+    let mut seq = SeqRun::new(&chops.borrow(), op_idxs, prev);
+    loop {
+        let step = seq.next_step(executor, &mut chops.borrow_mut(), cwd);
+        let (group, result) = match step {
+            SeqStep::Done(result) => return result,
+            SeqStep::Op(op_idx) =>
+                (vec![op_idx], perform_op_async(executor, chops, op_idx, cwd,
+                                                &seq.settings).await),
+            SeqStep::Pipe(group, stages) => {
+                let started = Instant::now();
+                let results = executor.run_pipeline_async(
+                    &stages.iter().map(|(stage, _)| stage.clone())
+                        .collect::<Vec<_>>()).await;
+                let chops = &mut *chops.borrow_mut();
+                let result = pipeline_result(&mut chops.chain, &group, stages,
+                                             results, started.elapsed(),
+                                             &mut chops.records, &mut chops.outputs);
+                (group, result)
+            }
+        };
+        seq.completed(&mut chops.borrow_mut(), &group, result)?;
+    }
+}
+
+// The state of the execution of a ChainMode::ForEach chain.  This is common to
+// execute_each and execute_each_async, which perform the operations for each
+// input returned by EachRun::next_input and pass the result to
+// EachRun::completed.
+struct EachRun {
+    inputs : std::vec::IntoIter<PathBuf>,
+    // The input files of the first operation and the output file of the last
+    // operation, which are restored once all of the instances have been
+    // performed.
+    first_inps : Vec<FileArg>,
+    last_out : FileArg,
+    outputs : ActualFile,
+    // The failures of the instances that the chain's policy continued past.
+    failures : Vec<OpFailure>,
+}
+
+impl EachRun {
+    // The op_idxs are the enabled operations in reverse order (as returned by
+    // prepare_chain).
+    fn new(executor: &impl OsRun,
+           chops: &ChainedOpsInternals,
+           cwd: &Option<PathBuf>,
+           op_idxs: &[usize]) -> anyhow::Result<EachRun>
+    {
+        let first_op = &chops.chain[op_idxs[op_idxs.len()-1]];
+        Ok(EachRun { inputs : each_inputs(executor, chops, cwd)?.into_iter(),
+                     first_inps : first_op.files().inp_filenames,
+                     last_out : chops.chain[op_idxs[0]].files().out_filename,
+                     outputs : ActualFile::NoActualFile,
+                     failures : vec![] })
+    }
+
+    // Prepares the operations for the next instance, returning the instance's
+    // input file (or None if all of the instances have been performed).
+    fn next_input(&mut self,
+                  chops: &mut ChainedOpsInternals,
+                  op_idxs: &[usize],
+                  each: &EachOutput) -> Option<PathBuf>
+    {
+        let inp = self.inputs.next()?;
+        prepare_each(chops, op_idxs, &inp, each);
+        Some(inp)
+    }
+
+    // Collects the output of an instance, returning the error for the chain
+    // execution if the instance failed and the chain's policy does not continue
+    // past that failure.  The instance is performed with the chain's policy, so
+    // any failure it continued past is reported as ErrorOpsFailed, whose
+    // failures are collected for the chain (identifying the instance's input
+    // file).
+    fn completed(&mut self,
+                 chops: &ChainedOpsInternals,
+                 inp: &Path,
+                 result: anyhow::Result<ActualFile>) -> anyhow::Result<()>
+    {
+        let error = match result {
+            Ok(outfile) => {
+                let outputs = std::mem::replace(&mut self.outputs,
+                                                ActualFile::NoActualFile);
+                self.outputs = outputs.extend(outfile);
+                return Ok(());
             }
+            Err(error) => error,
         };
+        let continued = chops.policy != ChainPolicy::FailFast
+            && matches!(error.downcast_ref::<ChainsopError>(),
+                        Some(ChainsopError::ErrorOpsFailed(..)));
+        if !continued {
+            return Err(error.context(format!("For input file {:?}", inp)));
+        }
+        let Ok(ChainsopError::ErrorOpsFailed(_, failures)) = error.downcast() else {
+            unreachable!("checked above")
+        };
+        let mut stop = false;
+        for failure in failures {
+            stop |= chops.policy == ChainPolicy::ContinueOptional
+                && !chops.optional.contains(&failure.index);
+            let error = failure.error.context(format!("For input file {:?}", inp));
+            self.failures.push(OpFailure { error, ..failure });
+        }
+        if stop {
+            Err(failed_ops(chops, &mut self.failures))
+        } else {
+            Ok(())
+        }
+    }
+
+    // Restores the operations after the instances have been performed and
+    // returns the result of the chain execution: the collected outputs of the
+    // instances, or the failures of any instances if the chain's policy
+    // continued past them.
+    fn finish(mut self,
+              chops: &mut ChainedOpsInternals,
+              op_idxs: &[usize],
+              result: anyhow::Result<()>) -> anyhow::Result<ActualFile>
+    {
+        chops.chain[op_idxs[op_idxs.len()-1]].set_input_files(&self.first_inps);
+        chops.chain[op_idxs[0]].set_output_file(&self.last_out);
+        result?;
+        if self.failures.is_empty() {
+            Ok(self.outputs)
+        } else {
+            Err(failed_ops(chops, &mut self.failures))
+        }
+    }
+}
+
+// Executes the enabled operations of a ChainMode::ForEach chain (in reverse
+// order, as returned by prepare_chain) once for each of the chain's input files,
+// returning the collected outputs of the instances.
+fn execute_each(executor: &impl OsRun,
+                chops: &mut ChainedOpsInternals,
+                cwd: &Option<PathBuf>,
+                op_idxs: &[usize],
+                each: &EachOutput) -> anyhow::Result<ActualFile>
+{
+    let mut run = EachRun::new(executor, chops, cwd, op_idxs)?;
+    let mut result = Ok(());
+    while let Some(inp) = run.next_input(chops, op_idxs, each) {
+        let outfile = execute_chain(executor, chops, cwd, op_idxs.to_vec(), None);
+        result = run.completed(chops, &inp, outfile);
+        if result.is_err() {
+            break;
+        }
+    }
+    run.finish(chops, op_idxs, result)
+}
+
+// Performs the operations of a ChainMode::ForEach chain asynchronously (see
+// execute_each).
+#[cfg(feature = "async")]
+async fn execute_each_async<Exec>(executor: &Exec,
+                                  chops: &SharedCell<ChainedOpsInternals>,
+                                  cwd: &Option<PathBuf>,
+                                  op_idxs: &[usize],
+                                  each: &EachOutput) -> anyhow::Result<ActualFile>
+where Exec: AsyncOsRun
+{
+    let mut run = EachRun::new(executor, &chops.borrow(), cwd, op_idxs)?;
+    let mut result = Ok(());
+    loop {
+        let Some(inp) = run.next_input(&mut chops.borrow_mut(), op_idxs, each)
+        else { break; };
+        let outfile = execute_chain_async(executor, chops, cwd, op_idxs.to_vec(),
+                                          None).await;
+        result = run.completed(&chops.borrow(), &inp, outfile);
+        if result.is_err() {
+            break;
+        }
+    }
+    run.finish(&mut chops.borrow_mut(), op_idxs, result)
+}
+
+// Returns the input files of a ChainMode::ForEach chain (resolved against the
+// directory in which the chain is executed), each of which is the input of one
+// instance of the chain's operations.  An input specification that does not
+// provide any file is an error.
+fn each_inputs(executor: &impl OsRun,
+               chops: &ChainedOpsInternals,
+               cwd: &Option<PathBuf>) -> anyhow::Result<Vec<PathBuf>>
+{
+    let mut inputs = Vec::new();
+    for inp in &chops.files.inp_filenames {
+        let files = setup_file(executor, inp, || Ok(ActualFile::NoActualFile))?;
+        inputs.extend(files.to_paths(cwd)?);
+    }
+    Ok(inputs)
+}
+
+// Prepares the operations of a ChainMode::ForEach chain for the instance with
+// the specified input file.
+fn prepare_each(chops: &mut ChainedOpsInternals,
+                op_idxs: &[usize],
+                inp: &Path,
+                each: &EachOutput)
+{
+    chops.chain[op_idxs[op_idxs.len()-1]].set_input_file(&FileArg::Loc(inp.into()));
+    if let Some(outfile) = each.output_for(inp) {
+        chops.chain[op_idxs[0]].set_output_file(&outfile);
+    }
+}
+
+// Returns the index of the operation in the group (a single operation or a
+// pipeline) that is responsible for the failure of the group.
+fn failed_op(chops: &ChainedOpsInternals, group: &[usize]) -> usize
+{
+    *group.iter()
+        .find(|i| matches!(chops.records.get(i),
+                           Some(OpReport { status: OpStatus::Failed(_), .. })))
+        .unwrap_or(&group[0])
+}
+
+// Handles the failure of an operation according to the chain's policy.  Returns
+// Ok if the chain execution should continue, in which case the failure is added
+// to the failures; otherwise returns the error for the chain execution.
+fn op_failure(chops: &ChainedOpsInternals,
+              failures: &mut Vec<OpFailure>,
+              op_idx: usize,
+              error: anyhow::Error) -> anyhow::Result<()>
+{
+    if chops.policy == ChainPolicy::FailFast {
+        return Err(error);
+    }
+    failures.push(OpFailure { index : op_idx,
+                              label : chops.chain[op_idx].label(),
+                              error });
+    if chops.policy == ChainPolicy::ContinueOnError
+        || chops.optional.contains(&op_idx)
+    {
+        Ok(())
+    } else {
+        Err(failed_ops(chops, failures))
+    }
+}
+
+// Returns the error reporting all of the (accumulated) operation failures.
+fn failed_ops(chops: &ChainedOpsInternals, failures: &mut Vec<OpFailure>)
+              -> anyhow::Error
+{
+    anyhow::Error::new(ChainsopError::ErrorOpsFailed(chops.name.clone(),
+                                                     std::mem::take(failures)))
+}
+
+// Returns the result of a completed chain execution, given the output of the
+// last operation (None if it failed).
+fn chain_result(chops: &ChainedOpsInternals,
+                failures: &mut Vec<OpFailure>,
+                outfile: Option<ActualFile>) -> anyhow::Result<ActualFile>
+{
+    match outfile {
+        Some(outfile) if failures.is_empty() => Ok(outfile),
+        _ => Err(failed_ops(chops, failures)),
+    }
+}
+
+// Returns the operations that are run together as a pipeline starting with the
+// specified operation: this operation and each subsequent enabled operation
+// that is linked to its predecessor via ChainLink::Pipe.  The latter are removed
+// from op_idxs.  A single-element result indicates no pipeline.
+fn pipe_group(links: &HashMap<usize, ChainLink>,
+              op_idx: usize,
+              op_idxs: &mut Vec<usize>) -> Vec<usize>
+{
+    let mut group = vec![op_idx];
+    while let Some(next_idx) = op_idxs.last() {
+        if links.get(next_idx) != Some(&ChainLink::Pipe) {
+            break;
+        }
+        group.push(*next_idx);
+        op_idxs.pop();
+    }
+    group
+}
+
+// Prepares each of the operations in the pipeline group to be run as a stage of
+// the pipeline.  Each stage is returned along with the output file of that
+// stage.
+fn prepare_pipeline(executor: &impl OsRun,
+                    chops: &mut [RunnableOp],
+                    opstate: &HashMap<usize, Activation>,
+                    cwd: &Option<PathBuf>,
+                    settings: &ChainSettings,
+                    group: &[usize]) -> anyhow::Result<Vec<(PipeStage, ActualFile)>>
+{
+    group.iter().enumerate().map(|(n, op_idx)| {
+        let op = &mut chops[*op_idx];
+        if let Some(Activation::When(_)) = opstate.get(op_idx) {
+            return Err(anyhow::Error::new(
+                ChainsopError::ErrorInvalidPipe(
+                    op.label(),
+                    "a conditionally activated operation cannot be piped".into())));
+        }
+        let orig = op.inherit_settings(settings);
+        let stage = match &mut *op {
+            RunnableOp::Exec(sp) =>
+                sp.pipe_stage(executor, cwd, n > 0, n + 1 < group.len()),
+            other => Err(anyhow::Error::new(
+                ChainsopError::ErrorInvalidPipe(
+                    other.label(), "only a SubProcOperation can be piped".into()))),
+        };
+        op.restore_settings(&orig);
+        stage
+    }).collect()
+}
+
+// Converts the results of running the pipeline stages into the output file of
+// the last stage.  If any stage failed, the error for the first failed stage is
+// returned, with context that reports the result of every stage.  Each stage is
+// recorded with the elapsed time of the entire pipeline.
+fn pipeline_result(chops: &mut [RunnableOp],
+                   group: &[usize],
+                   stages: Vec<(PipeStage, ActualFile)>,
+                   results: Vec<OsRunResult>,
+                   elapsed: Duration,
+                   records: &mut HashMap<usize, OpReport>,
+                   outputs: &mut HashMap<usize, HeldOutput>)
+                   -> anyhow::Result<ActualFile>
+{
+    let mut statuses = Vec::new();
+    let mut failure : Option<anyhow::Error> = None;
+    let mut outfile = ActualFile::NoActualFile;
+    for ((op_idx, (stage, stage_out)), result) in group.iter().zip(stages).zip(results) {
+        statuses.push(format!("{}: {}", stage.label, match &result {
+            OsRunResult::Good => "ok".to_string(),
+            OsRunResult::ExecError(Some(code), _) => format!("exit code {}", code),
+            OsRunResult::ExecError(None, _) => "killed by signal".to_string(),
+            OsRunResult::ExecFailed(_) => "not started".to_string(),
+            OsRunResult::RunError(_) => "run error".to_string(),
+            OsRunResult::BadDirectory(_, _) => "bad directory".to_string(),
+            OsRunResult::TimedOut(t, _) => format!("timed out after {:?}", t),
+        }));
+        let op = &mut chops[*op_idx];
+        if let RunnableOp::Exec(sp) = &mut *op {
+            let stage_result = sp.pipe_result(result, stage, stage_out);
+            record_op(records, outputs, *op_idx, op, elapsed, &stage_result);
+            match stage_result {
+                Ok(out) => outfile = out,
+                Err(e) => if failure.is_none() { failure = Some(e); },
+            }
+        }
+    }
+    match failure {
+        Some(e) => Err(e.context(format!("Pipeline failed: {}", statuses.join(", ")))),
+        None => Ok(outfile),
+    }
+}
+
+// Records the outcome of performing an operation in the chain, for the report
+// of the chain execution.  The output of a successful operation is also
+// provided to any FileArg::OutputOf references to it.
+fn record_op(records: &mut HashMap<usize, OpReport>,
+             outputs: &mut HashMap<usize, HeldOutput>,
+             op_idx: usize,
+             op: &RunnableOp,
+             elapsed: Duration,
+             result: &anyhow::Result<ActualFile>)
+{
+    if let (Some(held), Ok(outfile)) = (outputs.get_mut(&op_idx), result) {
+        held.set(outfile);
+    }
+    records.insert(op_idx, OpReport {
+        label : op.label(),
+        status : match result {
+            Ok(_) => OpStatus::Succeeded,
+            Err(e) => OpStatus::Failed(format!("{:#}", e)),
+        },
+        exit_code : op.exit_code(),
+        elapsed : Some(elapsed),
+        command : op.command_line(),
+        output_files : match result {
+            Ok(outfile) => outfile.to_paths(&None::<PathBuf>).unwrap_or_default(),
+            Err(_) => Vec::new(),
+        },
+        nested : op.nested_report(),
+    });
+}
+
+// Sets the inputs of the next operation in a sequential chain to the output of
+// the just-completed operation.
+fn chain_to_next(chops: &mut [RunnableOp],
+                 preset_inputs: &[usize],
+                 done_idx: usize,
+                 next_idx: usize,
+                 outfile: &ActualFile) -> anyhow::Result<()>
+{
+    match outfile.to_paths::<PathBuf>(&None).with_context(
+        || format!("Output file for chained operation {}", chops[done_idx].label()))
+    {
+        Ok(mut ps) => {
+            // If no output files, just let next chained element's input be what
+            // it was originally set to.
+            if !ps.is_empty() {
+                // Otherwise, set the inputs of the next operation to the output
+                // of the just-completed operation (unless the inputs are already
+                // pre-set).
+                //
+                // n.b. OK to remove the first of ps here: it cannot be empty
+                if ! preset_inputs.contains(&next_idx) {
+                    chops[next_idx].set_input_file(&FileArg::Loc(ps.remove(0)));
+                    for p in ps {
+                        chops[next_idx].add_input_file(&FileArg::Loc(p.clone()));
+                    }
+                }
+            }
+        }
+        Err(e) => match &e.root_cause().downcast_ref::<ChainsopError>() {
+            Some(ChainsopError::ErrorMissingFile) => {
+                // This is OK here because the following operation may be setup
+                // for not needing an input file specification; if it does need
+                // one then presumably some subsequent runtime check will
+                // validate its (lack of) existence and signal a useful failure.
+            }
+            _ => { return Err(e); }
+        },
+    };
+    Ok(())
+}
+
+// The state of the execution of a ChainMode::Dag chain.  This is common to
+// execute_dag and execute_dag_async, which perform (or start) each operation
+// returned by DagRun::next_ready and pass its result to DagRun::completed.
+struct DagRun {
+    // The enabled operations, in chain order.
+    op_idxs : Vec<usize>,
+    // The enabled operations that have not yet been performed (or started).
+    waiting : Vec<usize>,
+    chain_inps : Vec<FileArg>,
+    settings : ChainSettings,
+    outputs : HashMap<usize, ActualFile>,
+    // The error for the chain execution once an operation has failed and the
+    // chain does not continue, after which no further operations are started.
+    failure : Option<anyhow::Error>,
+    failures : Vec<OpFailure>,
+}
+
+impl DagRun {
+    // The op_idxs are in reverse order (as returned by prepare_chain).  Returns
+    // an error if the dependencies are not acyclic.
+    fn new(chops: &ChainedOpsInternals, mut op_idxs: Vec<usize>)
+           -> anyhow::Result<DagRun>
+    {
+        op_idxs.reverse();
+        check_dependencies(chops, &op_idxs)?;
+        Ok(DagRun { waiting : op_idxs.clone(),
+                    op_idxs,
+                    chain_inps : chops.files.inp_filenames.clone(),
+                    settings : chops.settings(),
+                    outputs : HashMap::new(),
+                    failure : None,
+                    failures : Vec::new() })
+    }
+
+    // Returns the next operation whose dependencies have all completed (with its
+    // inputs set from their outputs), or None if no operation is currently ready
+    // to be performed.  Dependencies on disabled operations are ignored.
+    // Operations that are not activated or that are up to date are handled here
+    // and are not returned.
+    fn next_ready(&mut self,
+                  executor: &impl OsRun,
+                  chops: &mut ChainedOpsInternals,
+                  cwd: &Option<PathBuf>) -> Option<usize>
+    {
+        while self.failure.is_none() {
+            let next = self.waiting.iter().position(
+                |op_idx| dag_dependencies(chops, *op_idx, &self.op_idxs)
+                    .iter().all(|d| self.outputs.contains_key(d)))?;
+            let op_idx = self.waiting.remove(next);
+            let deps = dag_dependencies(chops, op_idx, &self.op_idxs);
+            let previous = dag_previous(&self.outputs, &deps);
+            if ! activated(chops, op_idx, executor, cwd, &previous) {
+                // The dependents of a skipped operation receive its inputs.
+                record_skipped(&mut chops.records, op_idx, &chops.chain[op_idx]);
+                self.outputs.insert(op_idx, previous);
+                continue;
+            }
+            set_dag_inputs(chops, op_idx, &deps, &self.outputs, &self.chain_inps);
+            match up_to_date(executor, chops, op_idx, cwd, &previous) {
+                Some(outfile) => { self.outputs.insert(op_idx, outfile); }
+                None => return Some(op_idx),
+            }
+        }
+        None
+    }
+
+    // Handles the result of performing an operation.  A failure is handled
+    // according to the chain's policy; only the first failure that stops the
+    // chain execution is reported.
+    fn completed(&mut self,
+                 chops: &ChainedOpsInternals,
+                 op_idx: usize,
+                 result: anyhow::Result<ActualFile>)
+    {
+        match result {
+            Ok(outfile) => { self.outputs.insert(op_idx, outfile); }
+            Err(e) if self.failure.is_none() =>
+                if let Err(e) = op_failure(chops, &mut self.failures, op_idx, e) {
+                    self.failure = Some(e);
+                },
+            Err(_) => {}
+        }
+    }
+
+    // Records the outcome of a SubProcOperation started by start_exec (which
+    // is not recorded by perform_op) and handles its result.
+    fn finished(&mut self,
+                chops: &mut ChainedOpsInternals,
+                op_idx: usize,
+                started: Instant,
+                result: anyhow::Result<ActualFile>)
+    {
+        record_op(&mut chops.records, &mut chops.outputs, op_idx,
+                  &chops.chain[op_idx], started.elapsed(), &result);
+        self.completed(chops, op_idx, result);
+    }
+
+    // Returns the result of the chain execution once no operations are running:
+    // the output of the last operation in the chain.
+    fn result(mut self, chops: &ChainedOpsInternals) -> anyhow::Result<ActualFile>
+    {
+        match self.failure {
+            Some(e) => Err(e),
+            None if ! self.failures.is_empty() => Err(failed_ops(chops, &mut self.failures)),
+            None => Ok(self.op_idxs.last()
+                       .and_then(|last| self.outputs.remove(last))
+                       .unwrap_or(ActualFile::NoActualFile)),
+        }
+    }
+}
+
+// Starts the operation with the chain's settings via the start function if it is
+// a SubProcOperation; returns None for any other operation, which should be
+// performed via perform_op instead.
+fn start_exec<T>(chops: &mut ChainedOpsInternals,
+                 op_idx: usize,
+                 settings: &ChainSettings,
+                 start: impl FnOnce(&mut SubProcOperation) -> anyhow::Result<T>)
+                 -> Option<anyhow::Result<T>>
+{
+    let op = &mut chops.chain[op_idx];
+    let orig = op.inherit_settings(settings);
+    let started = match &mut *op {
+        RunnableOp::Exec(sp) => Some(start(sp)),
+        _ => None,
+    };
+    op.restore_settings(&orig);
+    started
+}
+
+// Executes the enabled operations in the chain (in reverse order, as returned by
+// prepare_chain) as a directed acyclic graph, where each operation can start as
+// soon as all of the operations it depends upon have completed.  Up to max_jobs
+// operations are run concurrently.  Only SubProcOperation elements actually run
+// concurrently; FunctionOperation and nested chain elements are performed
+// synchronously when they become ready.
+//
+// Upon failure of an operation, no further operations are started, but any
+// operations that are already running are allowed to complete before the
+// failure is returned.
+fn execute_dag(executor: &impl OsRun,
+               chops: &mut ChainedOpsInternals,
+               cwd: &Option<PathBuf>,
+               op_idxs: Vec<usize>,
+               max_jobs: usize) -> anyhow::Result<ActualFile>
+{
+    let mut dag = DagRun::new(chops, op_idxs)?;
+    let mut running : Vec<(usize, PendingExec, Instant)> = Vec::new();
+    loop {
+        // Start as many of the ready operations as the job limit allows.
+        while running.len() < max_jobs.max(1) {
+            let Some(op_idx) = dag.next_ready(executor, chops, cwd) else { break; };
+            let started = Instant::now();
+            match start_exec(chops, op_idx, &dag.settings,
+                             |sp| sp.start(executor, cwd)) {
+                Some(Ok(pending)) => running.push((op_idx, pending, started)),
+                Some(Err(e)) => dag.finished(chops, op_idx, started, Err(e)),
+                None => {
+                    let result = perform_op(executor, chops, op_idx, cwd,
+                                            &dag.settings);
+                    dag.completed(chops, op_idx, result);
+                }
+            }
+        }
+
+        if running.is_empty() {
+            break;
+        }
+
+        // Wait for any running operation to complete.  The thread running the
+        // operation will usually unpark this thread on completion, but the
+        // timeout ensures progress regardless.
+        match running.iter().position(|(_, pending, _)| pending.is_complete()) {
+            None => thread::park_timeout(Duration::from_millis(50)),
+            Some(n) => {
+                let (op_idx, pending, started) = running.remove(n);
+                let RunnableOp::Exec(sp) = &mut chops.chain[op_idx] else { continue; };
+                match sp.finish(executor, pending) {
+                    FinishedExec::Complete(result) =>
+                        dag.finished(chops, op_idx, started, result),
+                    FinishedExec::Retrying(pending) =>
+                        running.push((op_idx, pending, started)),
+                }
+            }
+        }
+    }
+    dag.result(chops)
+}
+
+// Executes the enabled operations in the chain as a directed acyclic graph
+// asynchronously (see execute_dag).  The running SubProcOperation elements are
+// awaited concurrently; FunctionOperation and nested chain elements are awaited
+// in turn when they become ready.
+#[cfg(feature = "async")]
+async fn execute_dag_async<Exec>(executor: &Exec,
+                                 chops: &SharedCell<ChainedOpsInternals>,
+                                 cwd: &Option<PathBuf>,
+                                 op_idxs: Vec<usize>,
+                                 max_jobs: usize) -> anyhow::Result<ActualFile>
+where Exec: AsyncOsRun
+{
+    let mut dag = DagRun::new(&chops.borrow(), op_idxs)?;
+    let mut running = Vec::new();
+    loop {
+        // Start as many of the ready operations as the job limit allows.
+        while running.len() < max_jobs.max(1) {
+            let Some(op_idx) = dag.next_ready(executor, &mut chops.borrow_mut(), cwd)
+            else { break; };
+            let started = Instant::now();
+            let start = start_exec(&mut chops.borrow_mut(), op_idx, &dag.settings,
+                                   |sp| sp.start_async(executor, cwd));
+            match start {
+                Some(Ok(exec)) =>
+                    running.push((op_idx, Box::pin(exec.attempt(executor)), started)),
+                Some(Err(e)) =>
+                    dag.finished(&mut chops.borrow_mut(), op_idx, started, Err(e)),
+                None => {
+                    let result = perform_op_async(executor, chops, op_idx, cwd,
+                                                  &dag.settings).await;
+                    dag.completed(&chops.borrow(), op_idx, result);
+                }
+            }
+        }
+
+        if running.is_empty() {
+            break;
+        }
+
+        // Wait for any running operation to complete; the others continue to
+        // run concurrently.
+        let (n, attempt) = std::future::poll_fn(|cx| {
+            running.iter_mut().enumerate()
+                .find_map(|(n, (_, attempt, _))| match attempt.as_mut().poll(cx) {
+                    std::task::Poll::Ready(done) => Some((n, done)),
+                    std::task::Poll::Pending => None,
+                })
+                .map_or(std::task::Poll::Pending, std::task::Poll::Ready)
+        }).await;
+        let (op_idx, _, started) = running.remove(n);
+        let finished = match &mut chops.borrow_mut().chain[op_idx] {
+            RunnableOp::Exec(sp) => sp.finish_async(executor, attempt),
+            _ => continue,
+        };
+        match finished {
+            Ok(result) => dag.finished(&mut chops.borrow_mut(), op_idx, started, result),
+            Err(retry) =>
+                running.push((op_idx, Box::pin(retry.attempt(executor)), started)),
+        }
+    }
+    dag.result(&chops.borrow())
+}
+
+// Verifies that each of the operations only depends upon earlier operations in
+// the chain, which ensures that the dependencies are acyclic.
+fn check_dependencies(chops: &ChainedOpsInternals,
+                      op_idxs: &[usize]) -> anyhow::Result<()>
+{
+    for op_idx in op_idxs {
+        if chops.foreign_depends.contains(op_idx) {
+            return Err(anyhow::Error::new(
+                ChainsopError::ErrorForeignDependency(chops.name.clone(), *op_idx)));
+        }
+        for dep in chops.depends.get(op_idx).unwrap_or(&Vec::new()) {
+            if dep >= op_idx {
+                return Err(anyhow::Error::new(
+                    ChainsopError::ErrorInvalidDependency(chops.name.clone(),
+                                                          *op_idx, *dep)));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Returns the operations that the specified operation depends upon.
+// Dependencies on disabled operations (those not in op_idxs) are ignored.
+fn dag_dependencies(chops: &ChainedOpsInternals,
+                    op_idx: usize,
+                    op_idxs: &[usize]) -> Vec<usize>
+{
+    chops.depends.get(&op_idx)
+        .unwrap_or(&Vec::new())
+        .iter()
+        .filter(|d| op_idxs.contains(d))
+        .cloned()
+        .collect()
+}
+
+// Sets the inputs of the operation to the outputs of the operations it depends
+// upon (or the chain inputs if it has no dependencies), unless the inputs are
+// already pre-set.
+fn set_dag_inputs(chops: &mut ChainedOpsInternals,
+                  op_idx: usize,
+                  deps: &[usize],
+                  outputs: &HashMap<usize, ActualFile>,
+                  chain_inps: &[FileArg])
+{
+    if ! chops.preset_inputs.contains(&op_idx) {
+        let inps : Vec<FileArg> =
+            if deps.is_empty() {
+                chain_inps.to_vec()
+            } else {
+                deps.iter()
+                    .flat_map(|d| outputs[d].to_paths::<PathBuf>(&None)
+                              .unwrap_or_default())
+                    .map(FileArg::Loc)
+                    .collect()
+            };
+        if let Some((inp1, inpr)) = inps.split_first() {
+            chops.chain[op_idx].set_input_file(inp1);
+            for f in inpr {
+                chops.chain[op_idx].add_input_file(f);
+            }
+        }
+    }
+}
+
+/// Specifies the manner in which the operations in a [ChainedOps] are executed.
+/// This is the argument to the [ChainedOps::set_mode] method.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChainMode {
+    /// The operations are executed one at a time in the order they were added
+    /// to the chain, and the output of each operation is the input to the next
+    /// operation.  This is the default.
+    Sequential,
+
+    /// The operations are executed as a directed acyclic graph: each operation
+    /// is started as soon as all of the earlier operations it depends upon (as
+    /// declared via [ChainedOpRef::depends_on]) have completed, and
+    /// independent operations are run concurrently, up to the specified
+    /// maximum number of concurrent jobs.
+    ///
+    /// The input of an operation that does not have explicitly specified input
+    /// files is the collected outputs of the operations it depends upon; an
+    /// operation with no dependencies receives the chain's input files instead.
+    /// The output file of the chain is the output of the last operation in the
+    /// chain.
+    ///
+    /// Only [SubProcOperation] elements are run concurrently, and only when the
+    /// executor supports it (see [OsRun::start_executable]); other elements are
+    /// performed synchronously when they are ready.
+    Dag(usize),
+
+    /// The operations are executed sequentially (as with [ChainMode::Sequential])
+    /// once for each of the chain's input files, with that file as the input of
+    /// the first operation.  The chain's input files may be a glob (see
+    /// [FileArg::GlobIn]) or a list of files, or they may be the outputs of the
+    /// previous element when this chain is nested in another chain.  The
+    /// output file of each instance is determined by the [EachOutput], and the
+    /// output of the chain is the collected outputs of all of the instances.
+    ///
+    /// The instances are performed in the order of the input files, and the
+    /// chain's [ChainPolicy] applies to each instance: by default the execution
+    /// stops at the first instance that fails, but it may continue with the
+    /// remaining input files, in which case the failures of all of the
+    /// instances are reported together.  The chain's own
+    /// output file specification is not used, and the [ChainReport] for the
+    /// chain describes only the last instance.
+    ForEach(EachOutput),
+}
+
+/// Specifies the output file of each instance of the operations in a
+/// [ChainMode::ForEach] chain, which is derived from the input file of that
+/// instance.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EachOutput {
+    /// The output file is determined by the output file specification of the
+    /// last operation in the chain.  This is normally a [FileArg::Temp], which
+    /// provides a separate temporary file for each instance.
+    AsSpecified,
+
+    /// The output file is the input file with its extension replaced by the
+    /// specified extension (e.g. "o" for the output of "src/foo.c" to be
+    /// "src/foo.o").
+    Extension(String),
+
+    /// The output file is in the specified directory, and is named as the input
+    /// file with its extension replaced by the specified extension.
+    ExtensionIn(PathBuf, String),
+}
+
+impl EachOutput {
+    // Returns the output file for the instance with the specified input file,
+    // or None if the output file is not changed.
+    fn output_for(&self, inp: &Path) -> Option<FileArg>
+    {
+        match self {
+            EachOutput::AsSpecified => None,
+            EachOutput::Extension(ext) => Some(FileArg::Loc(inp.with_extension(ext))),
+            EachOutput::ExtensionIn(dir, ext) =>
+                Some(FileArg::Loc(dir.join(inp.file_name().unwrap_or_default())
+                                  .with_extension(ext))),
+        }
+    }
+}
+
+/// Specifies the manner in which an operation in a [ChainMode::Sequential]
+/// chain receives the output of the previous (enabled) operation.  This is the
+/// argument to the [ChainedOpRef::link] method.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChainLink {
+    /// The output file of the previous operation is the input file of this
+    /// operation.  This is the default.
+    File,
+
+    /// The stdout of the previous operation is connected directly to the stdin
+    /// of this operation via an OS pipe (see [OsRun::run_pipeline]) and both
+    /// operations are run concurrently; no intermediate file is created.  A
+    /// sequence of piped operations is run as a single pipeline.
+    ///
+    /// Both operations must be [SubProcOperation] elements, with the previous
+    /// operation's output specified as [crate::ExeFileSpec::Stdout] and this
+    /// operation's input specified as [crate::ExeFileSpec::Stdin]; any output
+    /// file of the former or input file of the latter is ignored.  As with the
+    /// shell's "pipefail" option, the pipeline fails if any stage fails (which
+    /// includes an earlier stage terminated because a later stage exited
+    /// without reading all of its input); the resulting error reports the
+    /// status of each stage.
+    ///
+    /// This has no effect for the first enabled operation in the chain or when
+    /// the chain is executed in [ChainMode::Dag] mode.
+    Pipe,
+}
+
+/// Specifies the manner in which the failure of an operation is handled during
+/// the execution of a [ChainedOps].  This is the argument to the
+/// [ChainedOps::set_policy] method.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChainPolicy {
+    /// The execution of the chain stops at the first failed operation, and the
+    /// chain returns the error from that operation.  This is the default.
+    FailFast,
+
+    /// The execution of the chain continues after a failed operation, and the
+    /// chain returns a [ChainsopError::ErrorOpsFailed] error that reports all
+    /// of the failed operations.
+    ///
+    /// In [ChainMode::Sequential] mode, the operations that would receive the
+    /// output of a failed operation (directly or via other such operations) are
+    /// not performed, until an operation with explicitly specified input files
+    /// is reached.  In [ChainMode::Dag] mode, the operations depending on a
+    /// failed operation are not performed.  In [ChainMode::ForEach] mode, the
+    /// instances for the remaining input files are performed.  The operations
+    /// not performed are reported as [OpStatus::NotRun].
+    ContinueOnError,
+
+    /// The same as [ChainPolicy::ContinueOnError] for operations that have been
+    /// marked as optional (see [ChainedOpRef::set_optional]), but the same as
+    /// [ChainPolicy::FailFast] for any other operation (although the returned
+    /// [ChainsopError::ErrorOpsFailed] error also reports the failures of any
+    /// earlier optional operations).
+    ContinueOptional,
+}
+
+/// This enumerates the possible active conditions for each operation in the
+/// chain.  This is used as the argumement to the [ChainedOpRef::active] method
+/// to determine how the associated operation should be treated during execution
+/// of the chain.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Activation {
+    /// The associated operation is performed during execution of the chain.
+    Enabled,
+    /// The associated operation is skipped (not performed) during execution of
+    /// the chain.
+    Disabled,
+    /// The associated operation is performed only if the predicate returns
+    /// true when it is evaluated (see [ActivationContext]) at the point the
+    /// operation would be performed; otherwise the operation is skipped and its
+    /// input is passed on to the next operation in its place (or becomes the
+    /// result of the chain if this is the last operation).  Use
+    /// [Activation::when] to construct this.
+    When(ActivationPredicate),
+}
+
+impl Activation {
+
+    /// Constructs the When Activation for the specified predicate.
+    ///
+    /// The predicate must not access the chain being executed (e.g. via a
+    /// [ChainedOpRef]): this panics, or deadlocks when the "sync" feature is
+    /// enabled.  The [ActivationContext] provides the information about the
+    /// execution that is available to the predicate.
+    ///
+    /// ```
+    /// # use chainsop::*;
+    /// let chain = ChainedOps::new("example");
+    /// chain.push_op(&SubProcOperation::new(
+    ///         &Executable::new(&"make", ExeFileSpec::NoFileUsed,
+    ///                          ExeFileSpec::NoFileUsed)))
+    ///     .active(&Activation::when(|ctx| ctx.path("Makefile").exists()));
+    /// ```
+    pub fn when<T>(predicate: T) -> Activation
+    where T: Fn(&ActivationContext) -> bool + Shareable + 'static
+    {
+        Activation::When(ActivationPredicate(Shared::new(predicate)))
+    }
+}
+
+#[cfg(not(feature = "sync"))]
+type PredicateFn = dyn Fn(&ActivationContext) -> bool;
+#[cfg(feature = "sync")]
+type PredicateFn = dyn Fn(&ActivationContext) -> bool + Send + Sync;
+
+/// The predicate function of an [Activation::When].  Two predicates are only
+/// equal if they are the same (shared) function.
+#[derive(Clone)]
+pub struct ActivationPredicate(Shared<PredicateFn>);
+
+impl fmt::Debug for ActivationPredicate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        "<predicate>".fmt(f)
+    }
+}
+
+impl PartialEq for ActivationPredicate {
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// The information available to an [Activation::When] predicate when it is
+/// evaluated during the execution of the chain.
+pub struct ActivationContext<'a> {
+    /// The executor that is performing the chain.
+    pub executor : &'a dyn OsRun,
+
+    /// The directory in which the chain is executed (None for the current
+    /// directory).
+    pub dir : &'a Option<PathBuf>,
+
+    /// The output of the previous operation in the chain; this is
+    /// [ActualFile::NoActualFile] for the first operation performed (or if the
+    /// previous operation failed).  In [ChainMode::Dag] mode, this is the
+    /// combined output of the operations depended upon, and for a finally
+    /// operation (see [ChainedOps::push_finally_op]) this is the output of the
+    /// main chain.
+    pub previous : &'a ActualFile,
+}
+
+impl ActivationContext<'_> {
+    /// Returns the location of the specified path relative to the directory in
+    /// which the chain is executed.
+    pub fn path<P: AsRef<Path>>(&self, path: P) -> PathBuf
+    {
+        match self.dir {
+            Some(d) => d.join(path),
+            None => path.as_ref().to_path_buf(),
+        }
+    }
+}
+
+/// The report of an execution of a [ChainedOps], as returned by
+/// [ChainedOps::execute_with_report].  The report is available whether the
+/// execution succeeded or failed.
+#[derive(Debug)]
+pub struct ChainReport {
+    /// The label of the chain.
+    pub label : String,
+
+    /// The report for each element of the chain, in chain order (including the
+    /// disabled elements).
+    pub ops : Vec<OpReport>,
+
+    /// The result of the chain execution, as would have been returned by
+    /// [ChainedOps::execute].
+    pub result : anyhow::Result<ActualFile>,
+}
+
+impl ChainReport {
+    /// Returns true if the chain execution succeeded.
+    pub fn succeeded(&self) -> bool
+    {
+        self.result.is_ok()
+    }
+}
+
+/// Reports whether the main portion of a [ChainedOps] execution succeeded; this
+/// is obtained from [ChainedOps::status] and is primarily useful for the finally
+/// operations of the chain (see [ChainedOps::push_finally_call]).
+#[derive(Clone, Debug)]
+pub struct ChainStatus(Shared<SharedCell<Option<bool>>>);
+
+impl Default for ChainStatus {
+    fn default() -> ChainStatus
+    {
+        ChainStatus(Shared::new(SharedCell::new(None)))
+    }
+}
+
+impl ChainStatus {
+    /// Returns Some(true) if the main chain operations succeeded, Some(false) if
+    /// they failed, or None if the chain has not been executed or the main
+    /// chain operations are still being executed.
+    pub fn succeeded(&self) -> Option<bool>
+    {
+        *self.0.borrow()
+    }
+}
+
+/// The report of a single element of a [ChainedOps] execution (see
+/// [ChainReport]).
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpReport {
+    /// The label of the operation.
+    pub label : String,
+
+    /// The outcome of the operation.
+    pub status : OpStatus,
+
+    /// The exit code of the executable (see [SubProcOperation::exit_code]).
+    /// Always None for a [FunctionOperation] or a nested [ChainedOps].
+    pub exit_code : Option<i32>,
+
+    /// The wall-clock time taken to perform the operation (including any retry
+    /// attempts), or None if the operation was not performed.  Each stage of a
+    /// pipeline (see [ChainLink::Pipe]) reports the time taken by the entire
+    /// pipeline.
+    pub elapsed : Option<Duration>,
+
+    /// The resolved command line (the executable followed by its arguments).
+    /// Empty for a [FunctionOperation] or a nested [ChainedOps], or if the
+    /// operation was not performed.
+    pub command : Vec<OsString>,
+
+    /// The output file(s) generated by the operation, if it succeeded.  Note
+    /// that a temporary file may no longer exist once the chain execution has
+    /// completed.
+    pub output_files : Vec<PathBuf>,
+
+    /// The reports for the elements of a nested [ChainedOps]; empty for any
+    /// other operation.
+    pub nested : Vec<OpReport>,
+}
+
+/// The outcome of an element of a [ChainedOps] execution (see [OpReport]).
+#[derive(Clone, Debug, PartialEq)]
+pub enum OpStatus {
+    /// The operation was performed successfully.
+    Succeeded,
+
+    /// The operation failed, with the specified error message.
+    Failed(String),
+
+    /// The operation was skipped because it is [Activation::Disabled].
+    Disabled,
+
+    /// The operation was skipped because its [Activation::When] predicate was
+    /// not satisfied.
+    Skipped,
+
+    /// The operation was not performed because it is incremental and its output
+    /// file is up to date (see [ChainedOpRef::set_incremental]).
+    UpToDate,
+
+    /// The operation was not performed because the chain execution failed
+    /// before reaching it.
+    NotRun,
+}
+
+impl ChainedOpRef {
+
+    /// Changes the name of the command to execute if this is a
+    /// [SubProcOperation]; does nothing if this is a [FunctionOperation] or a
+    /// nested [ChainedOps].
+    pub fn set_executable<T>(&mut self, exe: T) -> &mut Self
+    where T: Into<PathBuf>
+    {
+        {
+            let mut ops: SharedRefMut<_> = self.chop.borrow_mut();
+            let opidx = ops.index(self.opid);
+            ops.chain[opidx].set_executable(exe.into());
+        }
+        self
+    }
+
+    /// Clears all environment variable settings for the environment in which
+    /// this operation in the chain executes.  Any previous environment variable
+    /// settings are discarded.
+    ///
+    /// By default, the chain's environment is inherited by the operation.
+    pub fn clear_env(&mut self) -> &mut Self
+    {
+        {
+            let mut ops: SharedRefMut<_> = self.chop.borrow_mut();
+            let opidx = ops.index(self.opid);
+            ops.chain[opidx].clear_env();
+        }
+        self
+    }
+
+    /// Specifies an environment variable value to be set in the environment for
+    /// executing this operation in the chain.  This can be used multiple times
+    /// to set multiple environment variables; subsequent settings of the same
+    /// variable will override previous settings, including those from the
+    /// overall chain.
+    ///
+    /// The first argument is the environment variable name and the second
+    /// argument is the value to set for that variable.
+    pub fn set_env<N,V>(&mut self, var_name: N, var_value: V) -> &mut Self
+    where N: Into<String>,
+          V: Into<String>
+    {
+        {
+            let mut ops: SharedRefMut<_> = self.chop.borrow_mut();
+            let opidx = ops.index(self.opid);
+            ops.chain[opidx].set_env(var_name.into(), var_value.into());
+        }
+        self
+    }
+
+    /// Extends the operations environment for this chained operation by
+    /// prepending a value to an environment variable.  If the environment
+    /// variable was not previously set, this becomes the new value for that
+    /// variable.  This can be used multiple times to extend the environment
+    /// variable with multiple values, including those from the overall chain.
+    ///
+    /// The first argument is the environment variable name and the second
+    /// argument is the value to prepend to that variable, and the third value is
+    /// the separator between the prepended value and the existing value.
+    pub fn prepend_env<N,V,S>(&mut self, var: N, value: V, sep: S) -> &mut Self
+    where N: Into<String>,
+          V: Into<String>,
+          S: Into<String>
+    {
+        {
+            let mut ops: SharedRefMut<_> = self.chop.borrow_mut();
+            let opidx = ops.index(self.opid);
+            ops.chain[opidx].prepend_env(var.into(), value.into(),
+                                              sep.into());
+        }
+        self
+    }
+
+    /// Extends the operations environment for this operation in the chain by
+    /// appending a value to an environment variable.  If the environment
+    /// variable was not previously set, this becomes the new value for that
+    /// variable.  This can be used multiple times to extend the environment
+    /// variable with multiple values, including those from the overall chain.
+    ///
+    /// The first argument is the environment variable name and the second
+    /// argument is the value to append to that variable, and the third value is
+    /// the separator between the appended value and the existing value.
+    pub fn append_env<N,V,S>(&mut self, var: N, value: V, sep: S) -> &mut Self
+    where N: Into<String>,
+          V: Into<String>,
+          S: Into<String>
+    {
+        {
+            let mut ops: SharedRefMut<_> = self.chop.borrow_mut();
+            let opidx = ops.index(self.opid);
+            ops.chain[opidx].append_env(var.into(), value.into(),
+                                             sep.into());
+        }
+        self
+    }
+
+    /// Removes the specified environment variable from the environment in which
+    /// this chained operation executes.  Has no effect but does not fail if the
+    /// environment variable does not exist.
+    pub fn unset_env<N>(&mut self, var_name: N) -> &mut Self
+    where N: Into<String>
+    {
+        {
+            let mut ops: SharedRefMut<_> = self.chop.borrow_mut();
+            let opidx = ops.index(self.opid);
+            ops.chain[opidx].unset_env(var_name.into());
+        }
+        self
+    }
+
+
+    /// Specifies the maximum amount of time that this operation in the chain is
+    /// allowed to run, overriding the chain's default timeout (see
+    /// [SubProcOperation::set_timeout] and [ChainedOps::set_timeout]); does
+    /// nothing if this is a [FunctionOperation].
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self
+    {
+        {
+            let mut ops: SharedRefMut<_> = self.chop.borrow_mut();
+            let opidx = ops.index(self.opid);
+            ops.chain[opidx].set_timeout(timeout);
+        }
+        self
+    }
+
+    /// Specifies the output cache for this operation in the chain, overriding
+    /// the chain's default cache (see [SubProcOperation::set_cache] and
+    /// [ChainedOps::set_cache]); does nothing if this is a
+    /// [FunctionOperation].
+    pub fn set_cache(&mut self, cache: &OutputCache) -> &mut Self
+    {
+        {
+            let mut ops: SharedRefMut<_> = self.chop.borrow_mut();
+            let opidx = ops.index(self.opid);
+            ops.chain[opidx].set_cache(cache);
+        }
+        self
+    }
+
+    /// Specifies the retry policy for this operation in the chain (see
+    /// [SubProcOperation::set_retry] and [FunctionOperation::set_retry]); does
+    /// nothing if this is a nested [ChainedOps].
+    pub fn set_retry(&mut self, policy: &RetryPolicy) -> &mut Self
+    {
+        {
+            let mut ops: SharedRefMut<_> = self.chop.borrow_mut();
+            let opidx = ops.index(self.opid);
+            ops.chain[opidx].set_retry(policy);
+        }
+        self
+    }
+
+    /// Specifies a non-zero exit code that indicates success for this operation
+    /// in the chain (see [SubProcOperation::accept_exit_code]); does nothing if
+    /// this is a [FunctionOperation] or a nested [ChainedOps].
+    pub fn accept_exit_code(&mut self, code: i32) -> &mut Self
+    {
+        {
+            let mut ops: SharedRefMut<_> = self.chop.borrow_mut();
+            let opidx = ops.index(self.opid);
+            ops.chain[opidx].accept_exit_code(code);
+        }
+        self
+    }
+
+    /// Returns the exit code of the most recent execution of this operation in
+    /// the chain (see [SubProcOperation::exit_code]), for use once the chain
+    /// execution has completed.  This is always None for a [FunctionOperation]
+    /// or a nested [ChainedOps].
+    pub fn exit_code(&self) -> Option<i32>
+    {
+        self.chop.borrow().op(self.opid).exit_code()
+    }
+
+    /// Returns true if this operation has been removed from the chain (see
+    /// [ChainedOps::remove]), in which case this reference can no longer be
+    /// used: any other method of this reference will panic, and the
+    /// [ChainedOps] methods that edit the chain return an error for it.
+    pub fn is_removed(&self) -> bool
+    {
+        ! self.chop.borrow().ids.contains(&self.opid)
+    }
+
+    // Returns the output of this operation, as referenced by FileArg::output_of.
+    pub(crate) fn output_slot(&self) -> OpOutput
+    {
+        let mut ops: SharedRefMut<_> = self.chop.borrow_mut();
+        let opidx = ops.index(self.opid);
+        let label = ops.chain[opidx].label();
+        ops.outputs.entry(opidx)
+            .or_insert_with(|| HeldOutput { output : OpOutput::new(label), file : None })
+            .output.clone()
+    }
+
+    /// Returns the label of this operation in the chain.
+    pub fn label(&self) -> String
+    {
+        self.chop.borrow().op(self.opid).label()
+    }
+
+    /// Returns the arguments of this operation in the chain (not including the
+    /// arguments for the input and output files, which are determined when the
+    /// operation is executed).  This is always empty for a [FunctionOperation]
+    /// or a nested [ChainedOps].
+    pub fn args(&self) -> Vec<OsString>
+    {
+        self.chop.borrow().op(self.opid).args()
+    }
+
+    /// Returns the input file specifications of this operation in the chain.
+    /// Note that the execution of the chain updates the input files of each
+    /// operation (other than those set via [ChainedOpRef::set_input_file]) to
+    /// the output of the previous operation.
+    pub fn input_files(&self) -> Vec<FileArg>
+    {
+        self.chop.borrow().op(self.opid).files().inp_filenames
+    }
+
+    /// Returns the output file specification of this operation in the chain.
+    pub fn output_file(&self) -> FileArg
+    {
+        self.chop.borrow().op(self.opid).files().out_filename
+    }
+
+    /// Returns the directory in which this operation is performed, if it has
+    /// been set (see [ChainedOpRef::set_dir]).
+    pub fn dir(&self) -> Option<PathBuf>
+    {
+        self.chop.borrow().op(self.opid).files().in_dir
+    }
+
+    /// Returns the "active" status of this operation in the chain (see
+    /// [ChainedOpRef::active]).
+    pub fn activation(&self) -> Activation
+    {
+        let ops = self.chop.borrow();
+        ops.opstate.get(&ops.index(self.opid)).cloned()
+            .unwrap_or(Activation::Enabled)
+    }
+
+    /// Returns the environment settings of this operation in the chain (not
+    /// including the settings inherited from the chain).  This is always
+    /// [EnvSpec::StdEnv] for a [FunctionOperation].
+    pub fn env(&self) -> EnvSpec
+    {
+        self.chop.borrow().op(self.opid).get_full_env()
+    }
+
+    /// Add an argument to this operation in the chain; does nothing if this is a
+    /// [FunctionOperation] or a nested [ChainedOps].
+    #[inline]
+    pub fn push_arg<T>(&mut self, arg: T) -> &mut ChainedOpRef
+    where T: Into<OsString>
+    {
+        {
+            let mut ops: SharedRefMut<_> = self.chop.borrow_mut();
+            let opidx = ops.index(self.opid);
+            ops.chain[opidx].push_arg(arg.into());
+        }
+        self
+    }
+
+    /// Add an argument to this operation in the chain at the specified position
+    /// in the command line (see [SubProcOperation::push_arg_at]); does nothing
+    /// if this is a [FunctionOperation] or a nested [ChainedOps].
+    #[inline]
+    pub fn push_arg_at<T>(&mut self, pos: ArgPosition, arg: T) -> &mut ChainedOpRef
+    where T: Into<OsString>
+    {
+        {
+            let mut ops: SharedRefMut<_> = self.chop.borrow_mut();
+            let opidx = ops.index(self.opid);
+            ops.chain[opidx].push_arg_at(pos, arg.into());
+        }
+        self
+    }
+
+    /// Sets the "active" status of this operation in the chain.  An individual
+    /// operation in the chain can be skipped or executed normally based on the
+    /// [Activation] value set by this method, including conditionally via an
+    /// [Activation::When] predicate.  When initially added to the chain, all
+    /// operations are set to [Activation::Enabled] by default.
+    #[inline]
+    pub fn active(&mut self, state: &Activation) -> &mut ChainedOpRef
+    {
+        {
+            let mut ops: SharedRefMut<_> = self.chop.borrow_mut();
+            let opidx = ops.index(self.opid);
+            match state {
+                Activation::Enabled => ops.opstate.remove(&opidx),
+                Activation::Disabled | Activation::When(_) =>
+                    ops.opstate.insert(opidx, state.clone()),
+            };
+        }
+        self
+    }
+
+    /// Marks this operation as optional (or not): when the chain is executed with
+    /// the [ChainPolicy::ContinueOptional] policy, the execution continues past
+    /// the failure of an optional operation.  Operations are not optional by
+    /// default.
+    #[inline]
+    pub fn set_optional(&mut self, optional: bool) -> &mut ChainedOpRef
+    {
+        {
+            let mut ops: SharedRefMut<_> = self.chop.borrow_mut();
+            let opidx = ops.index(self.opid);
+            ops.optional.retain(|i| *i != opidx);
+            if optional {
+                ops.optional.push(opidx);
+            }
+        }
+        self
+    }
+
+    /// Marks this operation as incremental (or not).  An incremental operation
+    /// is not performed if its output file is an explicit [FileArg::Loc] file
+    /// that exists and is newer than all of its (resolved) input files; the
+    /// existing output file is then passed on to the next operation.  The
+    /// operation is always performed if it has no input files or if any of its
+    /// input files is a temporary file (including the temporary output of the
+    /// previous operation).  This is not applicable to a stage of a pipeline (see
+    /// [ChainLink::Pipe]) or to a finally operation.  Skipped operations are
+    /// reported via [OsRun::report_up_to_date] and as [OpStatus::UpToDate].
+    /// Operations are not incremental by default.
+    #[inline]
+    pub fn set_incremental(&mut self, incremental: bool) -> &mut ChainedOpRef
+    {
+        {
+            let mut ops: SharedRefMut<_> = self.chop.borrow_mut();
+            let opidx = ops.index(self.opid);
+            ops.incremental.retain(|i| *i != opidx);
+            if incremental {
+                ops.incremental.push(opidx);
+            }
+        }
+        self
+    }
+
+    /// Sets the manner in which this operation receives the output of the
+    /// previous operation in the chain (see [ChainLink]).  When initially added
+    /// to the chain, all operations are set to [ChainLink::File] by default.
+    #[inline]
+    pub fn link(&mut self, link: &ChainLink) -> &mut ChainedOpRef
+    {
+        {
+            let mut ops: SharedRefMut<_> = self.chop.borrow_mut();
+            let opidx = ops.index(self.opid);
+            match link {
+                ChainLink::File => ops.links.remove(&opidx),
+                ChainLink::Pipe => ops.links.insert(opidx, link.clone()),
+            };
+        }
+        self
+    }
+
+    /// Declares that this operation depends upon the specified (earlier)
+    /// operation in the same chain.  This is only used when the chain is
+    /// executed in [ChainMode::Dag] mode, where this operation will not be
+    /// started until the specified operation has completed, and the output of
+    /// that operation will be (one of) the inputs to this operation.  This can
+    /// be called multiple times to declare multiple dependencies.
+    ///
+    /// Depending upon an operation that is not earlier in the chain, or upon an
+    /// operation in another chain (including the corresponding operation in a
+    /// copy of this chain made by [ChainedOps::deep_clone]), will cause an
+    /// error when the chain is executed.
+    pub fn depends_on(&mut self, other: &ChainedOpRef) -> &mut ChainedOpRef
+    {
+        {
+            let mut ops: SharedRefMut<_> = self.chop.borrow_mut();
+            let opidx = ops.index(self.opid);
+            if ! Shared::ptr_eq(&self.chop, &other.chop) {
+                if ! ops.foreign_depends.contains(&opidx) {
+                    ops.foreign_depends.push(opidx);
+                }
+            } else {
+                let otheridx = ops.index(other.opid);
+                let deps = ops.depends.entry(opidx).or_default();
+                if ! deps.contains(&otheridx) {
+                    deps.push(otheridx);
+                }
+            }
+        }
+        self
+    }
+
+}
+
+
+impl FilesPrep for ChainedOpRef {
+
+    /// Sets the directory in which this operation is performed.  If this is a
+    /// relative directory, it will be relative to the directory of the entire
+    /// ChainedOps.  If not set, the ChainedOps directory is used, and if set to
+    /// an absolute directory, it will override the ChainedOps directory setting.
+    fn set_dir<T>(&mut self, tgtdir: T) -> &mut ChainedOpRef
+    where T: AsRef<Path>
+    {
+        {
+            let mut ops: SharedRefMut<_> = self.chop.borrow_mut();
+            let opidx = ops.index(self.opid);
+            ops.chain[opidx].set_dir(tgtdir);
+        }
+        self
+    }
+
+    /// Sets the input file for this operation in the chain.  This is normally
+    /// not used for chained operations, since the input file will default to the
+    /// output file of the previous operation in the chain (and the chain's input
+    /// file will become the input file for the first operation in the chain).
+    ///
+    /// * If this is the first operation in the chain, this will be ignored
+    ///   unless the chain itself has no input file specified.
+    ///
+    /// * If this is not the first operation in the chain, this will override the
+    ///   default chain behaviour of setting the input file to the output of the
+    ///   previous element.
+    fn set_input_file(&mut self, inp_fname : &FileArg) -> &mut ChainedOpRef
+    {
+        {
+            let mut ops: SharedRefMut<_> = self.chop.borrow_mut();
+            let opidx = ops.index(self.opid);
+            ops.chain[opidx].set_input_file(inp_fname);
+            if ! ops.preset_inputs.contains(&opidx) {
+                ops.preset_inputs.push(opidx);
+            }
+        }
+        self
+    }
+
+    /// Appends an additional input file for this operation.  Has the same
+    /// behavior characteristics as the [ChainedOpRef::set_input_file] method.
+    fn add_input_file(&mut self, inp_fname : &FileArg) -> &mut ChainedOpRef
+    {
+        {
+            let mut ops: SharedRefMut<_> = self.chop.borrow_mut();
+            let opidx = ops.index(self.opid);
+            ops.chain[opidx].add_input_file(inp_fname);
+            if ! ops.preset_inputs.contains(&opidx) {
+                ops.preset_inputs.push(opidx);
+            }
+        }
+        self
+    }
+
+    fn has_input_file(&self) -> bool
+    {
+        self.chop.borrow().op(self.opid).has_input_file()
+    }
+
+    /// Specifies the output file for this operation in the chain. This will also
+    /// inform the input file setting for the subsequent operation in the chain
+    /// unless that operation has an explicit input file setting.
+    ///
+    /// * If this is the last element of the chain, this is ignored if an output
+    ///   file for the chain itself is set.
+    fn set_output_file(&mut self, out_fname : &FileArg) -> &mut ChainedOpRef
+    {
+        {
+            let mut ops: SharedRefMut<_> = self.chop.borrow_mut();
+            let opidx = ops.index(self.opid);
+            ops.chain[opidx].set_output_file(out_fname);
+        }
+        self
+    }
+
+    fn has_explicit_output_file(&self) -> bool
+    {
+        self.chop.borrow().op(self.opid).has_explicit_output_file()
+    }
+
+}
+
+
+// ----------------------------------------------------------------------
+// TESTS
+// ----------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    // Tested functionality (summarized here for overview and completeness
+    // determination, then marked throughout where verified by a test):
+    //
+    // * [TC1] empty chain
+    // * [TC2] chain of one element
+    // * [TC3] multi-element chain (both SubProcOperation and FunctionOperation),
+    //         including setting input file of one op to the output file of the
+    //         previous op
+    // * [TC4] multi-element chain skips disabled individual ops
+    // * [TC5] multi-element chain does not override a chain op's explicit
+    //         input file
+    // * [TC6] Set chain output file *after* final operation output file
+    // * [TC7] Set chain output file *before* final operation output file
+    // * [TC8] Set chain input file *after* final operation output file
+    // * [TC9] Set chain input file *before* final operation output file
+    // * [TC10] first and last op set input and output, no chain-level spec.
+    // * [TC11] Default chain directory is current directory
+    // * [TC12] Chain operation without directory inherits chain directory
+    // * [TC13] Chain operation relative directory is relative to chain directory
+    // * [TC14] Chain operation absolute directory ignores chain directory
+    // * [TC15] Chain specification can be executed multiple times
+    // * [TC16] Verify Temp op output file
+    // * [TC17] Verify function ViaCall (function-specified) op output file
+    // * [TC18] Verify closure ViaCall (function-specified) op output file
+    // * [TC19] Verify Loc (explicitly specified) op output file
+    // * [TC20] Verify chained ops set_dir does not affect filenames
+    // * [TC21] Absolute chain directory overrides execute directory
+    // * [TC22] Absolute chain directory combines with relative op directory
+    // * [TC23] Specified env settings are applied
+    // * [TC24] Individual op env settings supplement chain env settings
+    // * [TC25] Nested chain receives previous output, provides next input, and
+    //          inherits the enclosing chain's env and directory
+    // * [TC85] A chain cannot be nested in itself, directly or indirectly
+    // * [TC86] The input files set on a nested chain after it was added to the
+    //          enclosing chain are used instead of the previous output
+    // * [TC87] The previous output provided to a nested chain is not retained
+    //          as the nested chain's input after the execution
+    // * [TC26] DAG mode inputs are the outputs of the dependencies, or the
+    //          chain input for operations without dependencies
+    // * [TC27] DAG mode dependency on a later operation is an error
+    // * [TC89] DAG mode dependency on an operation in another chain (including
+    //          a deep clone of the chain) is an error
+    // * [TC28] DAG mode runs independent operations concurrently, limited by
+    //          the maximum number of jobs, and dependent operations only after
+    //          their dependencies have completed
+    // * [TC29] With the "sync" feature, chains (including function operations
+    //          and temporary file results) can be executed on other threads
+    // * [TC30] Async execution of a chain (including nested chains, function
+    //          operations, and environment inheritance) performs the same
+    //          operations as synchronous execution
+    // * [TC31] Async execution of a DAG mode chain performs the operations in
+    //          chain order with the same inputs as synchronous execution
+    // * [TC32] Stdout output is written to the (temporary or explicit) output
+    //          file, which is the input of the next operation
+    // * [TC33] Stdin input is read from the input file(s) (concatenated if
+    //          there are multiple files)
+    // * [TC34] Piped operations are run as a single pipeline without
+    //          intermediate files, and the output of the pipeline is the input
+    //          of the next (non-piped) operation
+    // * [TC35] Piping an operation that does not read stdin (or from an
+    //          operation that does not write stdout) is an error
+    // * [TC36] Failure of a pipeline stage reports the status of every stage
+    // * [TC37] The chain default timeout applies to operations (including those
+    //          in nested chains) that do not have their own timeout
+    // * [TC38] An operation's own timeout overrides the chain default timeout
+    // * [TC39] Operations that fail in a retryable manner are retried (after
+    //          the backoff delay) in both sequential and DAG mode
+    // * [TC40] If all attempts fail, the error reports each attempt's failure
+    // * [TC41] Failures not identified by the retry policy are not retried
+    // * [TC42] An accepted non-zero exit code does not fail the chain (in both
+    //          sequential and DAG mode), and is available via the ChainedOpRef
+    // * [TC43] The execution report describes each element of the chain
+    //          (including disabled elements and nested chains) in chain order
+    // * [TC44] The execution report is available when the execution fails, and
+    //          identifies the failed operation and the operations not run
+    // * [TC45] With the ContinueOnError policy, all operations are performed
+    //          and the failures are reported together with their index and label
+    // * [TC46] With the ContinueOnError policy in DAG mode, the operations
+    //          depending on a failed operation are not performed
+    // * [TC47] With the ContinueOptional policy, only the failure of an
+    //          optional operation is continued past
+    // * [TC48] Finally operations run after the main chain in push order, even
+    //          if there are no other enabled operations
+    // * [TC49] Finally operations run after a chain failure, which remains the
+    //          error of the execution
+    // * [TC50] Finally operation failures are reported along with any chain
+    //          failure
+    // * [TC51] Finally operations can determine whether the main chain
+    //          succeeded via the ChainStatus or the environment
+    // * [TC52] Finally operations also run for an async execution
+    // * [TC53] An operation whose activation predicate is false is skipped and
+    //          the previous output is passed to the next operation
+    // * [TC54] The activation predicate is provided with the previous output
+    // * [TC55] A conditionally activated operation cannot be piped
+    // * [TC56] In DAG mode, a skipped operation passes its dependency outputs
+    //          on to its dependents
+    // * [TC57] An incremental operation whose output is newer than its inputs
+    //          is not performed, and its existing output is used
+    // * [TC58] An incremental operation is performed if it is no longer
+    //          incremental or its output is missing
+    // * [TC83] An incremental operation without any input files is always
+    //          performed
+    // * [TC84] An incremental operation whose input is the temporary output of
+    //          the previous operation is always performed
+    // * [TC59] An operation whose cache entry matches has its output restored
+    //          from the cache instead of being performed
+    // * [TC60] An operation is performed if its input contents have changed
+    // * [TC61] A checkpoint is written after each successful operation and
+    //          removed when the chain succeeds
+    // * [TC62] Resuming from a checkpoint performs only the incomplete
+    //          operations, starting with the recorded output
+    // * [TC63] A checkpoint cannot be used if the chain definition has changed
+    // * [TC88] A deep clone of a chain does not use the checkpoint of the
+    //          original chain
+    // * [TC64] The chain elements can be obtained (by iteration, index, or
+    //          label) as references that can be used to modify them
+    // * [TC65] The element references provide the settings of the elements
+    // * [TC66] Elements can be inserted, moved and removed, and the chain is
+    //          executed in the resulting order
+    // * [TC67] The settings of the elements follow them as the chain is edited
+    // * [TC68] The reference to a removed element is detected as removed and
+    //          cannot be used
+    // * [TC90] Editing the chain via the reference to a removed element is an
+    //          error
+    // * [TC91] Editing the chain via the reference to an element of another
+    //          chain is an error
+    // * [TC69] A deep clone of a chain (including its nested chains) can be
+    //          modified independently of the original
+    // * [TC70] The references to elements of the original chain (or its nested
+    //          chains) can be mapped to the corresponding elements of the clone
+    // * [TC71] A nested chain shared by multiple elements is shared in the same
+    //          manner by the copies in the clone
+    // * [TC72] An operation can use the outputs of any earlier operations as its
+    //          inputs, and temporary outputs remain until the chain completes
+    // * [TC73] Using the output of an operation that was not performed is an
+    //          error
+    // * [TC74] The output references in a deep clone refer to the operations of
+    //          the clone
+    // * [TC75] A ForEach chain performs its operations once for each input file,
+    //          with an output file derived from that input file
+    // * [TC76] The collected outputs of a ForEach chain are the input of the next
+    //          element
+    // * [TC77] The execution of a ForEach chain stops at the first failed
+    //          instance, reporting the input file of that instance
+    // * [TC78] The element references can add arguments to an operation at a
+    //          specified position in its command line
+    // * [TC79] Async execution of a DAG mode chain awaits independent
+    //          operations concurrently, limited by the maximum number of jobs
+    // * [TC80] When a sequential chain continues past a failure, the operations
+    //          that would receive the output of the failed operation are not
+    //          performed, but operations with pre-set inputs are
+    // * [TC81] The operations of a ForEach chain retain their own input and
+    //          output files after the execution
+    // * [TC82] A ForEach chain input that does not provide any file is an error
+    // * [TC92] The policy of a ForEach chain applies to each instance, and the
+    //          failures of the instances it continues past are reported
+    //          together, identifying the input file of each instance
+    // * [TC93] With the "sync" feature, the async execution of a chain can be
+    //          spawned as a separate task
+
+    use super::*;
+    use std::cell::RefCell;
+    use std::path::PathBuf;
+    use crate::executable::*;
+    use crate::execution::*;
+    use crate::retry::*;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct RunExec {
+        name: String,
+        exe: PathBuf,
+        args: Vec<OsString>,
+        env: EnvSpec,
+        dir: Option<PathBuf>
+    }
+    #[derive(Debug, PartialEq)]
+    struct RunFunc{
+        fname: String,
+        inpfiles: Vec<PathBuf>,
+        outfile: Option<PathBuf>,
+        dir: Option<PathBuf>
+    }
+    #[derive(Debug, PartialEq)]
+    enum TestOp {
+        SPO(RunExec),
+        FO(RunFunc)
+    }
+    struct TestCollector {
+        ops: RefCell<Vec<TestOp>>,
+        // The number of initial runs of each labelled operation that fail with
+        // the exit code.
+        failures: RefCell<HashMap<String, usize>>,
+        code: i32,
+        // The retry delays, which are recorded instead of waited for.
+        delays: RefCell<Vec<Duration>>,
+        // If set, each executable runs for this time in a separate thread (or
+        // task, when async), which are tracked by the jobs.
+        runtime: Option<Duration>,
+        jobs: std::sync::Arc<std::sync::Mutex<TestJobs>>,
+    }
+    impl TestCollector {
+        pub fn new() -> TestCollector {
+            TestCollector { ops: RefCell::new(vec![]),
+                            failures: RefCell::new(HashMap::new()),
+                            code: 0,
+                            delays: RefCell::new(vec![]),
+                            runtime: None,
+                            jobs: Default::default(),
+            }
+        }
+        fn failing(failures: &[(&str, usize)], code: i32) -> TestCollector {
+            let xor = TestCollector { code, ..TestCollector::new() };
+            xor.failures.borrow_mut()
+                .extend(failures.iter().map(|(l, n)| (l.to_string(), *n)));
+            xor
+        }
+        fn running_for(runtime: Duration) -> TestCollector {
+            TestCollector { runtime: Some(runtime), ..TestCollector::new() }
+        }
+        // The labels of the operations run, in the order they were run.
+        fn runs(&self) -> Vec<String> {
+            self.ops.borrow().iter().map(|op| match op {
+                TestOp::SPO(re) => re.name.clone(),
+                TestOp::FO(rf) => rf.fname.clone(),
+            }).collect()
+        }
+        fn outcome(&self, label: &str) -> OsRunResult {
+            match self.failures.borrow_mut().get_mut(label) {
+                Some(n) if *n > 0 => {
+                    *n -= 1;
+                    OsRunResult::ExecError(Some(self.code), "flaky".into())
+                }
+                _ => OsRunResult::Good,
+            }
+        }
+        fn exec_outcome(&self,
+                        label: &str,
+                        exe_file: &Path,
+                        args: &[OsString],
+                        exe_env: &EnvSpec,
+                        fromdir: &Option<PathBuf>) -> OsRunResult
+        {
+            self.ops.borrow_mut()
+                .push(TestOp::SPO(RunExec{ name: String::from(label),
+                                           exe: PathBuf::from(exe_file),
+                                           args: args.to_vec(),
+                                           env: exe_env.clone(),
+                                           dir: fromdir.clone()
+            }));
+            self.outcome(label)
+        }
+    }
+
+    // The executables run by a TestCollector with a runtime.
+    #[derive(Default)]
+    struct TestJobs {
+        running: Vec<String>,
+        completed: Vec<String>,
+        max_running: usize,
+        // The number of completed executables when each was started.
+        started: Vec<(String, usize)>,
+    }
+    impl TestJobs {
+        fn begin(&mut self, label: &str) {
+            self.started.push((label.to_string(), self.completed.len()));
+            self.running.push(label.to_string());
+            self.max_running = self.max_running.max(self.running.len());
+        }
+        fn end(&mut self, label: &str) {
+            self.running.retain(|l| *l != label);
+            self.completed.push(label.to_string());
+        }
+    }
+
+    impl OsRun for TestCollector {
+        fn run_executable(&self,
+                          label: &str,
+                          exe_file: &Path,
+                          args: &Vec<OsString>,
+                          exe_env: &EnvSpec,
+                          exe_opts: &ExecOptions,
+                          fromdir: &Option<PathBuf>) -> OsRunResult
+        {
+            self.start_executable(label, exe_file, args, exe_env, exe_opts,
+                                  fromdir).wait()
+        }
+        fn start_executable(&self,
+                            label: &str,
+                            exe_file: &Path,
+                            args: &Vec<OsString>,
+                            exe_env: &EnvSpec,
+                            _exe_opts: &ExecOptions,
+                            fromdir: &Option<PathBuf>) -> OsRunHandle
+        {
+            let result = self.exec_outcome(label, exe_file, args, exe_env, fromdir);
+            let Some(runtime) = self.runtime else {
+                return OsRunHandle::Completed(result);
+            };
+            self.jobs.lock().unwrap().begin(label);
+            let jobs = std::sync::Arc::clone(&self.jobs);
+            let label = label.to_string();
+            OsRunHandle::Running(thread::spawn(move || {
+                thread::sleep(runtime);
+                jobs.lock().unwrap().end(&label);
+                result
+            }))
+        }
+        fn run_function(&self,
+                        name : &str,
+                        _call : &OpFunction,
+                        inpfiles: &ActualFile,
+                        outfile: &ActualFile,
+                        fromdir: &Option<PathBuf>) -> OsRunResult
+        {
+            self.ops.borrow_mut()
+                .push(TestOp::FO(RunFunc{ fname: name.to_string(),
+                                          inpfiles: inpfiles.to_paths::<PathBuf>(&None).unwrap_or_default(),
+                                          outfile: outfile.to_path::<PathBuf>(&None).ok(),
+                                          dir: fromdir.clone()
+            }));
+            self.outcome(name)
+        }
+        fn glob_search(&self, _globpat: &String) -> anyhow::Result<Vec<PathBuf>>
+        {
+            Err(anyhow::anyhow!("glob_search not implemented for ArgCollector"))
+        }
+        fn mk_tempfile(&self, suffix: &String) -> anyhow::Result<tempfile::NamedTempFile>
+        {
+            Executor::DryRun.mk_tempfile(suffix)
+        }
+        fn delay(&self, delay: Duration)
+        {
+            self.delays.borrow_mut().push(delay);
+        }
+    }
+
+    #[cfg(feature = "async")]
+    impl AsyncOsRun for TestCollector {
+        async fn run_executable_async(&self,
+                                      label: &str,
+                                      exe_file: &Path,
+                                      args: &[OsString],
+                                      exe_env: &EnvSpec,
+                                      _exe_opts: &ExecOptions,
+                                      fromdir: &Option<PathBuf>) -> OsRunResult
+        {
+            let result = self.exec_outcome(label, exe_file, args, exe_env, fromdir);
+            if let Some(runtime) = self.runtime {
+                self.jobs.lock().unwrap().begin(label);
+                tokio::time::sleep(runtime).await;
+                self.jobs.lock().unwrap().end(label);
+            }
+            result
+        }
+    }
+
+    fn test_callee(_indir: &Path,
+                   _inpfiles: &ActualFile,
+                   _outfile: &ActualFile) -> anyhow::Result<()>
+    {
+        // n.b. the FunctionalOp entries in the chains below are disabled, so
+        // this is not actually needed.  If it is, a more complete body should be
+        // supplied.
+        todo!("test_callee not implemented")
+    }
+
+    fn add_finish_outarg(args: &mut Vec<OsString>,
+                         _cwd: &Option<PathBuf>,
+                         outf: &ActualFile)
+                         -> anyhow::Result<()>
+    {
+        for pth in outf.to_paths::<PathBuf>(&None)? {
+            args.push(format!("out:{:?}", pth).into());
+        }
+        Ok(())
+    }
+
+    #[test]
+    // n.b. this pretty much tests all the things as far as chainedops is
+    // concerned; more focused tests to identify individual failure modes may be
+    // helpful when tracking down issues.
+    fn test_chain() -> anyhow::Result<()> {
+        let mut ops = ChainedOps::new("test chain");
+        ops.set_input_file(&FileArg::loc("orig.inp"));   // [TC9]
+        ops.set_output_file(&FileArg::loc("final.out"));  // [TC7]
+        ops.set_env("GONE", "Long").append_env("SUB", "CLUB", ",")
+            .set_env("ZINC", "ALLOY");
+
+        let exe = Executable::new(&"test-cmd",
+                                  ExeFileSpec::Append,
+                                  ExeFileSpec::Append);
+        let op = SubProcOperation::new(&exe)
+            // set the input file, but the chain will override this
+            .set_input_file(&FileArg::loc("inpfile.txt"))  // [TC9]
+            .set_output_file(&FileArg::temp(".out")) // [TC16]
+            .prepend_env("SUB", "HUB", ":")
+            .unset_env("GONE")
+            .push_arg("-a")
+            .push_arg("a-arg-value")
+            .push_arg("-b")
+            .append_env("SUB", "DUB", ";")
+            .clone();
+
+        ops.push_op(&op);  // [TC12]
+        ops.add_input_file(&FileArg::loc("other_inpfile")); // [TC9]
+
+        let mut op2 = ops.push_op(SubProcOperation::new(
+            &Executable::new(&"cmd2",
+                             ExeFileSpec::Option("--input".into()),
+                             ExeFileSpec::Append))
+                              .set_output_file(&FileArg::temp(".o2")) // [TC16]
+                              .push_arg("-D")
+                              .push_arg("DVAL")
+        );
+        op2.push_arg("-a2");
+        op2.set_dir(".build");
+
+        let mut op3 = ops.push_op(&op);
+        op3.active(&Activation::Disabled);
+        op3.active(&Activation::Enabled).active(&Activation::Disabled); // [TC4]
+
+        ops.push_call(FunctionOperation::calling("a func", test_callee)
+                      .set_input_file(&FileArg::loc("not replaced")) // [TC5]
+                      .set_output_file(&FileArg::temp(".fnout"))); // [TC16]
+
+        // Verify ViaCall can take a closure
+        ops.push_op(SubProcOperation::new(
+            &Executable::new(&"finish",
+                             ExeFileSpec::option("-i"),
+                             ExeFileSpec::ViaCall(|args, cwd, outf| { // [TC18]
+                                 args.push(format!("+({:?}){:?}", cwd, outf).into());
+                                 Ok(())
+                             })))
+                    .set_output_file(&FileArg::TBD)
+                    .push_arg("--last")
+                    .set_dir(".other")
+        ).active(&Activation::Disabled); // [TC4]
+
+        let mut op5 = ops.push_op(SubProcOperation::new(
+            &Executable::new(&"finish",
+                             ExeFileSpec::option("-i"),
+                             ExeFileSpec::ViaCall(add_finish_outarg))) // [TC17]
+                                  // The output file setting here will be ignored
+                                  // because the output file for the chain itself
+                                  // is specified.
+                              .set_output_file(&FileArg::loc("ignored")) // [TC7]
+                              .push_arg("--last")
+                              .set_dir(".other")
+        );
+        op5.push_arg("op").set_dir(".build");  // [TC13]
+
+        let xor = TestCollector::new();
+        let result = ops.execute(&xor, &Some("target/loc"));
+        assert!(match result {
+            Ok(ActualFile::SingleFile(FileRef::StaticFile(sf))) =>
+                sf == PathBuf::from("final.out"),
+            _ => false,
+        });
+        let mut collected = xor.ops.into_inner();
+        assert_eq!(collected.len(), 4);
+
+        // The last arg of the first op is an assigned output tempfile
+        let output0_tmpfile = match &mut collected[0] {
+            TestOp::SPO(re) => {
+                let outf = PathBuf::from(&re.args.last().unwrap());
+                assert!(!outf.exists(), // [TC16]
+                        "intermediate temp file #0 {:?} did not get cleaned up!",
+                        outf);
+                assert_eq!(outf.extension(), Some(OsString::from("out")).as_deref()); // [TC16]
+                re.args.pop();
+                outf
+            }
+            TestOp::FO(_) => {
+                panic!("Expected first op to be a SubProcOperation");
+            }
+        };
+
+        // The penultimate arg of the second op should be the input tempfile,
+        // which should be the output tempfile of the previous op
+        match &mut collected[1] {
+            TestOp::SPO(re) => {
+                let inpf = PathBuf::from(&re.args[re.args.len()-2]);
+                assert_eq!(inpf, output0_tmpfile);
+            }
+            TestOp::FO(_) => {
+                panic!("Expected second op to be a SubProcOperation");
+            }
+        };
+        // The last arg of the second op is another assigned output tempfile
+        let output1_tmpfile = match &mut collected[1] {
+            TestOp::SPO(re) => {
+                let outf = PathBuf::from(&re.args.last().unwrap());
+                assert!(!outf.exists(), // [TC16]
+                        "intermediate temp file #1 {:?} did not get cleaned up!",
+                        outf);
+                assert_eq!(outf.extension(), Some(OsString::from("o2")).as_deref()); // [TC16]
+                re.args.pop();  // remove output file
+                re.args.pop();  // remove input file
+                outf
+            }
+            TestOp::FO(_) => {
+                panic!("Expected second op to be a SubProcOperation");
+            }
+        };
+
+        // The input file of the third op should be the tempfile output of the
+        // second op, and the output file of the third op should be an assigned
+        // tempfile.
+        let output2_tmpfile = match &mut collected[2] {
+            TestOp::SPO(_) => {
+                panic!("Expected third op to be a FunctionOperation");
+            }
+            TestOp::FO(rf) => {
+                // There was an explicit input file set for this operation, so it
+                // should *not* have been overwritten by the output of the
+                // previous operation.
+                assert_ne!(rf.inpfiles, vec![output1_tmpfile.clone()]);
+                assert_eq!(rf.inpfiles, vec![PathBuf::from("not replaced")]); // [TC5]
+                let outf = rf.outfile.clone().unwrap();
+                assert!(!outf.exists(), // [TC16]
+                        "intermediate temp file #2 {:?} did not get cleaned up!",
+                        outf);
+                assert_eq!(outf.extension(), Some(OsString::from("fnout")).as_deref()); // [TC16]
+                rf.inpfiles = vec![];
+                rf.outfile = None;
+                outf
+            }
+        };
+
+        // The penultimate arg of the third op should be the input tempfile,
+        // which should be the output tempfile of the previous op
+        match &mut collected[3] {
+            TestOp::SPO(re) => {
+                let inpf = PathBuf::from(&re.args[re.args.len()-2]);
+                assert_eq!(inpf, output2_tmpfile);
+                let outf = re.args.clone().last().unwrap().clone();
+                re.args.pop();  // remove output filespec
+                re.args.pop();  // remove input file
+                re.args.push(outf.clone()); // re-add output filespec
+                outf
+            }
+            TestOp::FO(_) => {
+                panic!("Expected second op to be a SubProcOperation");
+            }
+        };
+
+        assert_eq!(collected,
+                   vec![ TestOp::SPO(RunExec { name: "test-cmd".into(),
+                                               exe: "test-cmd".into(),
+                                               args: ["-a",
+                                                      "a-arg-value",
+                                                      "-b",
+                                                      "orig.inp", // [TC9]
+                                                      "other_inpfile", // [TC9]
+                                                      // output file removed above
+                                               ].map(Into::<OsString>::into).to_vec(),
+                                               env: EnvSpec::StdEnv
+                                               .append("SUB", "CLUB;DUB", ",") // [TC24]
+                                               .add("ZINC", "ALLOY")
+                                               .prepend("SUB", "HUB", ":")
+                                               .rmv("GONE"), // [TC24]
+                                               dir: Some(PathBuf::from("target/loc"))}), // [TC12]
+                         TestOp::SPO(RunExec { name: "cmd2".into(),
+                                               exe: "cmd2".into(),
+                                               args: ["-D",
+                                                      "DVAL",
+                                                      "-a2",
+                                                      "--input",
+                                                      // input file removed above
+                                                      // output file removed above
+                                               ].map(Into::<OsString>::into).to_vec(),
+                                               env: EnvSpec::StdEnv  // [TC23]
+                                               .add("GONE", "Long")
+                                               .append("SUB", "CLUB", ",")
+                                               .add("ZINC", "ALLOY"),
+                                               dir: Some(PathBuf::from("target/loc/.build"))}),
+                         // [TC4]
+                         TestOp::FO(RunFunc { fname: "a func".into(),
+                                              inpfiles: vec![], // input files removed above
+                                              outfile: None, // output file removed above
+                                              dir: Some(PathBuf::from("target/loc"))}),
+                         // [TC4]
+                         TestOp::SPO(RunExec { name: "finish".into(),
+                                               exe: "finish".into(),
+                                               args: ["--last",
+                                                      "op",
+                                                      "-i",
+                                                      // input file removed above
+                                                      "out:\"final.out\"", // [TC7], [TC17]
+                                               ].map(Into::<OsString>::into).to_vec(),
+                                               env: EnvSpec::StdEnv  // [TC23]
+                                               .add("GONE", "Long")
+                                               .append("SUB", "CLUB", ",")
+                                               .add("ZINC", "ALLOY"),
+                                               dir: Some(PathBuf::from("target/loc/.build"))}),
+                   ]);
+
+        // -------------------------------------------------------------------
+        // Execute the chainedops *again* to verify they can be re-used and are
+        // actually executed again.
+        let xor2 = TestCollector::new();
+        let result2 = ops.execute(&xor2, &Some("/other"));  // [TC15]
+
+        assert!(match result2 {
+            Ok(ActualFile::SingleFile(FileRef::StaticFile(sf))) =>
+                sf == PathBuf::from("final.out"),
+            _ => false,
+        });
+        let mut collected2 = xor2.ops.into_inner();
+        assert_eq!(collected2.len(), 4);
+
+        // The last arg of the first op is an assigned output tempfile
+        let output0_tmpfile2 = match &mut collected2[0] {
+            TestOp::SPO(re) => {
+                let outf = PathBuf::from(&re.args.last().unwrap());
+                assert!(!outf.exists(),
+                        "intermediate second temp file #0 {:?} did not get cleaned up!",
+                        outf);
+                re.args.pop();
+                outf
+            }
+            TestOp::FO(_) => {
+                panic!("Expected first op to be a SubProcOperation");
+            }
+        };
+        assert_ne!(output0_tmpfile, output0_tmpfile2);  // each run uses new tempfiles
+
+        // The penultimate arg of the second op should be the input tempfile,
+        // which should be the output tempfile of the previous op
+        match &mut collected2[1] {
+            TestOp::SPO(re) => {
+                let inpf = PathBuf::from(&re.args[re.args.len()-2]);
+                assert_eq!(inpf, output0_tmpfile2);
+            }
+            TestOp::FO(_) => {
+                panic!("Expected second op to be a SubProcOperation");
+            }
+        };
+        // The last arg of the second op is another assigned output tempfile
+        let output1_tmpfile2 = match &mut collected2[1] {
+            TestOp::SPO(re) => {
+                let outf = PathBuf::from(&re.args.last().unwrap());
+                assert!(!outf.exists(),
+                        "intermediate second temp file #1 {:?} did not get cleaned up!",
+                        outf);
+                re.args.pop();  // remove output file
+                re.args.pop();  // remove input file
+                outf
+            }
+            TestOp::FO(_) => {
+                panic!("Expected second op to be a SubProcOperation");
+            }
+        };
+        assert_ne!(output1_tmpfile, output1_tmpfile2);  // each run uses new tempfiles
+
+        // The input file of the third op should be the tempfile output of the
+        // second op, and the output file of the third op should be an assigned
+        // tempfile.
+        let output2_tmpfile2 = match &mut collected2[2] {
+            TestOp::SPO(_) => {
+                panic!("Expected third op to be a FunctionOperation");
+            }
+            TestOp::FO(rf) => {
+                // There was an explicit input file set for this operation, so it
+                // should *not* have been overwritten by the output of the
+                // previous operation.
+                assert_ne!(rf.inpfiles, vec![output1_tmpfile.clone()]);
+                assert_eq!(rf.inpfiles, vec![PathBuf::from("not replaced")]);
+                let outf = rf.outfile.clone().unwrap();
+                assert!(!outf.exists(),
+                        "intermediate second temp file #2 {:?} did not get cleaned up!",
+                        outf);
+                rf.inpfiles = vec![];
+                rf.outfile = None;
+                outf
+            }
+        };
+        assert_ne!(output2_tmpfile, output2_tmpfile2);  // each run uses new tempfiles
+
+        // The penultimate arg of the third op should be the input tempfile,
+        // which should be the output tempfile of the previous op
+        match &mut collected2[3] {
+            TestOp::SPO(re) => {
+                let inpf = PathBuf::from(&re.args[re.args.len()-2]);
+                assert_eq!(inpf, output2_tmpfile2);
+                let outf = re.args.clone().last().unwrap().clone();
+                re.args.pop();  // remove output filespec
+                re.args.pop();  // remove input file
+                re.args.push(outf.clone()); // re-add output filespec
+                outf
+            }
+            TestOp::FO(_) => {
+                panic!("Expected second op to be a SubProcOperation");
+            }
+        };
+
+        assert_eq!(collected2,  // almost `collected` but main directory changed
+                   vec![ TestOp::SPO(RunExec { name: "test-cmd".into(),
+                                               exe: "test-cmd".into(),
+                                               args: ["-a",
+                                                      "a-arg-value",
+                                                      "-b",
+                                                      "orig.inp",
+                                                      "other_inpfile",
+                                                      // output file removed above
+                                               ].map(Into::<OsString>::into).to_vec(),
+                                               env: EnvSpec::StdEnv
+                                               .append("SUB", "CLUB;DUB", ",") // [TC24]
+                                               .add("ZINC", "ALLOY")
+                                               .prepend("SUB", "HUB", ":")
+                                               .rmv("GONE"), // [TC24]
+                                               dir: Some(PathBuf::from("/other"))}),
+                         TestOp::SPO(RunExec { name: "cmd2".into(),
+                                               exe: "cmd2".into(),
+                                               args: ["-D",
+                                                      "DVAL",
+                                                      "-a2",
+                                                      "--input",
+                                                      // input file removed above
+                                                      // output file removed above
+                                               ].map(Into::<OsString>::into).to_vec(),
+                                               env: EnvSpec::StdEnv  // [TC23]
+                                               .add("GONE", "Long")
+                                               .append("SUB", "CLUB", ",")
+                                               .add("ZINC", "ALLOY"),
+                                               dir: Some(PathBuf::from("/other/.build"))}),
+                         TestOp::FO(RunFunc { fname: "a func".into(),
+                                              inpfiles: vec![], // input files removed above
+                                              outfile: None, // output file removed above
+                                              dir: Some(PathBuf::from("/other"))}),
+                         TestOp::SPO(RunExec { name: "finish".into(),
+                                               exe: "finish".into(),
+                                               args: ["--last",
+                                                      "op",
+                                                      "-i",
+                                                      // input file removed above
+                                                      "out:\"final.out\"",
+                                               ].map(Into::<OsString>::into).to_vec(),
+                                               env: EnvSpec::StdEnv  // [TC23]
+                                               .add("GONE", "Long")
+                                               .append("SUB", "CLUB", ",")
+                                               .add("ZINC", "ALLOY"),
+                                               dir: Some(PathBuf::from("/other/.build"))}),
+                   ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain_empty() -> anyhow::Result<()> {
+        let mut ops = ChainedOps::new("test empty chain");
+        // [TC1]
+        let ex = TestCollector::new();
+        let result = ops.execute(&ex, &Some("target/loc"));
+        match result {
+            Ok(ActualFile::NoActualFile) => (),
+            _ => assert!(false,
+                         "Expected single static file 'final.out' but got {:?}",
+                         result),
+        };
+        let collected = ex.ops.into_inner();
+        assert_eq!(collected.len(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain_single_op() -> anyhow::Result<()> {
+        let mut ops = ChainedOps::new("test chain single");
+
+        let exe = Executable::new(&"test-cmd",
+                                  ExeFileSpec::Append,
+                                  ExeFileSpec::Append);
+        ops.push_op(SubProcOperation::new(&exe)  // [TC2]
+                    // Set the input file.  The chain should *not* override this
+                    // because the chain itself is not given an input file.  The
+                    // usual expectation is that the chain *will* be given an
+                    // input file, but this verifies the behavior of the unusual
+                    // scenario.
+                    .set_input_file(&FileArg::loc("override-in"))
+                    .set_output_file(&FileArg::loc("override-out"))
+                    .push_arg("-b"));
+        ops.set_input_file(&FileArg::loc("real-in"));  // [TC8]
+        ops.set_output_file(&FileArg::loc("real-out")); // [TC6]
+
+        let ex = TestCollector::new();
+        let result = ops.execute_here(&ex);
+        match result {
+            Ok(ActualFile::SingleFile(FileRef::StaticFile(sf))) =>
+                assert_eq!(sf, PathBuf::from("real-out")), // [TC6]
+            _ => assert!(false,
+                         "Expected single static file 'final.out' but got {:?}",
+                         result),
+        };
+        let collected = ex.ops.into_inner();
+        assert_eq!(collected.len(), 1); // [TC2]
+
+        assert_eq!(collected,
+                   vec![ TestOp::SPO(RunExec { name: "test-cmd".into(),
+                                               exe: "test-cmd".into(),
+                                               args: ["-b",
+                                                      "real-in", // [TC8]
+                                                      "real-out", // [TC6]
+                                                      // output file removed above
+                                               ].map(Into::<OsString>::into).to_vec(),
+                                               env: EnvSpec::StdEnv,
+                                               dir: None}), // [TC11]
+                   ]);
+        Ok(())
+    }
+
+
+    #[test]
+    fn test_chain_op_settings() -> anyhow::Result<()> {
+        let mut ops = ChainedOps::new("test chain");
+        ops.set_dir("/ops/run/here");  // [TC20], [TC14]
+            // [TC10]
+        let exe = Executable::new(&"test-cmd",
+                                  ExeFileSpec::Append,
+                                  ExeFileSpec::Append);
+        ops.push_op(SubProcOperation::new(&exe)
+                    // Set the input file.  The chain should *not* override this
+                    // because the chain itself is not given an input file.  The
+                    // usual expectation is that the chain *will* be given an
+                    // input file, but this verifies the behavior of the unusual
+                    // scenario.
+                    .set_input_file(&FileArg::loc("inpfile.txt")) // [TC10]
+                    .set_output_file(&FileArg::temp(".out"))
+                    .push_arg("-a")
+                    .push_arg("a-arg-value")
+                    .push_arg("-b"));
+        ops.push_op(SubProcOperation::new(
+            &Executable::new(&"cmd2",
+                             ExeFileSpec::Option("--input".into()),
+                             ExeFileSpec::Append))
+                                  .set_output_file(&FileArg::temp(".o2"))
+                                  // Verify individual ops can change dirs
+                                  .set_dir("sub/dir") // [TC13]
+                                  .push_arg("-D")
+                                  .push_arg("DVAL")
+        );
+        ops.push_call(&FunctionOperation::calling(
+            "fop",
+            |_in_dir, _inpfiles, _outfile| todo!("not called during test")))
+            .set_output_file(&FileArg::loc("fop.done"));
+        ops.push_call(&FunctionOperation::calling(
+            "flop",
+            |_in_dir, _inpfiles, _outfile| todo!("not called during test")))
+            .set_output_file(&FileArg::loc("flop.done"));
+        ops.push_op(SubProcOperation::new(
+            &Executable::new(&"cmdexe3",
+                             ExeFileSpec::Option("--input".into()),
+                             ExeFileSpec::Append))
+                                  .set_output_file(&FileArg::loc("final.out")) // [TC19]
+                                  // Verify absolute dir override works
+                                  .set_dir("/abs/dir") // [TC14]
+        );
+
+        // No chain-level input or output files were set, so the individual
+        // operation's settings should apply.  [TC10]
+
+        let ex = TestCollector::new();
+        let result = ops.execute(&ex, &Some("target/loc"));
+        match result {
+            Ok(actual) => {
+                match actual {
+                    ActualFile::SingleFile(FileRef::StaticFile(ref sf)) =>
+                        assert_eq!(sf, &PathBuf::from("final.out")),
+                    _ => assert!(false,
+                                 "Expected single static file but got {:?}",
+                                 actual),
+                };
+                assert_eq!(PathBuf::from("/abs/dir/final.out"),
+                           actual.to_path(&Some("/abs/dir"))?)
+            },
+            Err(e) => assert!(false, "Err result: {:?}", e),
+        };
+
+        let mut collected = ex.ops.into_inner();
+
+        // The last arg of the first op is an assigned output tempfile
+        let output0_tmpfile = match &mut collected[0] {
+            TestOp::SPO(re) => {
+                let outf = PathBuf::from(&re.args.last().unwrap());
+                assert!(!outf.exists(),
+                        "intermediate temp file #0 {:?} did not get cleaned up!",
+                        outf);
+                re.args.pop();
+                outf
+            }
+            TestOp::FO(_) => {
+                panic!("Expected first op to be a SubProcOperation");
+            }
+        };
+
+
+        // The penultimate arg of the second op should be the input tempfile,
+        // which should be the output tempfile of the previous op
+        match &mut collected[1] {
+            TestOp::SPO(re) => {
+                let inpf = PathBuf::from(&re.args[re.args.len()-2]);
+                assert_eq!(inpf, output0_tmpfile);  // [TC3]
+            }
+            TestOp::FO(_) => {
+                panic!("Expected second op to be a SubProcOperation");
+            }
+        };
+        // The last arg of the second op is another assigned output tempfile
+        let output1_tmpfile = match &mut collected[1] {
+            TestOp::SPO(re) => {
+                let outf = PathBuf::from(&re.args.last().unwrap());
+                assert!(!outf.exists(),
+                        "intermediate temp file #1 {:?} did not get cleaned up!",
+                        outf);
+                re.args.pop();  // remove output file
+                re.args.pop();  // remove input file
+                outf
+            }
+            TestOp::FO(_) => {
+                panic!("Expected second op to be a SubProcOperation");
+            }
+        };
+
+        // The output file of the fifth (and last) op should be as specified by
+        // the individual op.
+        match &mut collected[4] {
+            TestOp::SPO(re) => {
+                let outf = PathBuf::from(&re.args.last().unwrap());
+                assert_eq!(outf, PathBuf::from("final.out"));  // [TC10]
+                assert!(!outf.exists(),
+                        "intermediate temp file #1 {:?} did not get cleaned up!",
+                        outf);
+                outf
+            }
+            TestOp::FO(_) => {
+                panic!("Expected fifth op to be a SubProcOperation");
+            }
+        };
+
+        assert_eq!(
+            collected, vec!
+                [
+                    TestOp::SPO(RunExec { name: "test-cmd".into(),
+                                          exe: "test-cmd".into(),
+                                          args: ["-a",
+                                                 "a-arg-value",
+                                                 "-b",
+                                                 "inpfile.txt",  // [TC10]
+                                                 // output file removed above
+                                          ].map(Into::<OsString>::into).to_vec(),
+                                          env: EnvSpec::StdEnv,
+                                          dir: Some(PathBuf::from("/ops/run/here"))}), // [TC20]
+                    TestOp::SPO(RunExec { name: "cmd2".into(),
+                                          exe: "cmd2".into(),
+                                          args: ["-D",
+                                                 "DVAL",
+                                                 "--input",
+                                                 // input file removed above
+                                                 // output file removed above
+                                          ].map(Into::<OsString>::into).to_vec(),
+                                          env: EnvSpec::StdEnv,
+                                          dir: Some(PathBuf::from("/ops/run/here/sub/dir"))}), // [TC22]
+                    TestOp::FO(RunFunc { fname: "fop".to_string(),
+                                         inpfiles: vec![
+                                             output1_tmpfile
+                                         ],
+                                         outfile: Some(PathBuf::from("fop.done")),
+                                         dir: Some("/ops/run/here".into()) }), // [TC21]
+                    TestOp::FO(RunFunc { fname: "flop".to_string(),
+                                         inpfiles: vec![
+                                             "fop.done".into(), // [TC20]
+                                         ],
+                                         outfile: Some(PathBuf::from("flop.done")),
+                                         dir: Some("/ops/run/here".into()) }), // [TC21]
+                    TestOp::SPO(RunExec { name: "cmdexe3".into(),
+                                          exe: "cmdexe3".into(),
+                                          args: ["--input",
+                                                 "flop.done",   // [TC20]
+                                                 "final.out",   // [TC10]
+                                          ].map(Into::<OsString>::into).to_vec(),
+                                          env: EnvSpec::StdEnv,
+                                          dir: Some(PathBuf::from("/abs/dir"))}),
+                ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain_reldirs() -> anyhow::Result<()> {
+        let mut ops = ChainedOps::new("test chain single");
+        ops.set_dir("here");
+
+        let exe = Executable::new(&"test-cmd",
+                                  ExeFileSpec::Append,
+                                  ExeFileSpec::Append);
+        ops.push_op(SubProcOperation::new(&exe)  // [TC2]
+                    // Set the input file.  The chain should *not* override this
+                    // because the chain itself is not given an input file.  The
+                    // usual expectation is that the chain *will* be given an
+                    // input file, but this verifies the behavior of the unusual
+                    // scenario.
+                    .set_input_file(&FileArg::loc("override-in"))
+                    .set_output_file(&FileArg::loc("override-out"))
+                    .push_arg("-b"));
+        ops.set_input_file(&FileArg::loc("real-in"));  // [TC8]
+        ops.set_output_file(&FileArg::loc("real-out")); // [TC6]
+
+        let ex = TestCollector::new();
+        let result = ops.execute_here(&ex);
+        match result {
+            Ok(ActualFile::SingleFile(FileRef::StaticFile(sf))) =>
+                assert_eq!(sf, PathBuf::from("real-out")), // [TC6]
+            _ => assert!(false,
+                         "Expected single static file 'final.out' but got {:?}",
+                         result),
+        };
+        let collected = ex.ops.into_inner();
+        assert_eq!(collected.len(), 1); // [TC2]
+
+        assert_eq!(collected,
+                   vec![ TestOp::SPO(RunExec { name: "test-cmd".into(),
+                                               exe: "test-cmd".into(),
+                                               args: ["-b",
+                                                      "real-in", // [TC8]
+                                                      "real-out", // [TC6]
+                                                      // output file removed above
+                                               ].map(Into::<OsString>::into).to_vec(),
+                                               env: EnvSpec::StdEnv,
+                                               dir: Some("here".into())}), // [TC13]
+                   ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain_nested() -> anyhow::Result<()> {
+        let mut sub = ChainedOps::new("sub chain");
+        sub.set_env("INNER", "in").set_dir("subdir");
+        let inner = Executable::new(&"inner-cmd",
+                                    ExeFileSpec::Append,
+                                    ExeFileSpec::Append);
+        sub.push_op(SubProcOperation::new(&inner)
+                    .set_output_file(&FileArg::temp(".s1"))
+                    .push_arg("-s1"));
+        sub.push_op(SubProcOperation::new(&inner)
+                    .set_output_file(&FileArg::temp(".s2"))
+                    .push_arg("-s2"));
+
+        let mut ops = ChainedOps::new("outer chain");
+        ops.set_input_file(&FileArg::loc("orig.inp"));
+        ops.set_output_file(&FileArg::loc("final.out"));
+        ops.set_env("OUTER", "out");
+        let outer = Executable::new(&"outer-cmd",
+                                    ExeFileSpec::Append,
+                                    ExeFileSpec::Append);
+        ops.push_op(SubProcOperation::new(&outer)
+                    .set_output_file(&FileArg::temp(".o1")));
+        ops.push_chain(&sub)?;  // [TC25]
+        ops.push_op(SubProcOperation::new(&outer).push_arg("-last"));
+
+        let xor = TestCollector::new();
+        match ops.execute(&xor, &Some("top"))? {
+            ActualFile::SingleFile(FileRef::StaticFile(sf)) =>
+                assert_eq!(sf, PathBuf::from("final.out")),
+            r => panic!("Expected single static file 'final.out' but got {:?}", r),
+        };
+        let mut collected = xor.ops.into_inner();
+        assert_eq!(collected.len(), 4);
+
+        // Each intermediate output is a tempfile that is the input to the
+        // subsequent operation, crossing into and out of the nested chain.
+        let mut prev_out : Option<OsString> = None;
+        for (n, op) in collected.iter_mut().enumerate() {
+            match op {
+                TestOp::SPO(re) => {
+                    if n < 3 {
+                        let outf = re.args.pop().unwrap();
+                        assert!(!PathBuf::from(&outf).exists(),
+                                "intermediate temp file #{} {:?} did not get cleaned up!",
+                                n, outf);
+                        if let Some(inpf) = prev_out {
+                            assert_eq!(re.args.pop().unwrap(), inpf);
+                        }
+                        prev_out = Some(outf);
+                    } else {
+                        let outf = re.args.pop().unwrap();
+                        assert_eq!(re.args.pop(), prev_out);
+                        re.args.push(outf);
+                    }
+                }
+                TestOp::FO(_) => panic!("Expected only SubProcOperations"),
+            }
+        }
+
+        let outer_env = EnvSpec::StdEnv.add("OUTER", "out");
+        let inner_env = outer_env.add("INNER", "in");
+        assert_eq!(collected,
+                   vec![ TestOp::SPO(RunExec { name: "outer-cmd".into(),
+                                               exe: "outer-cmd".into(),
+                                               args: ["orig.inp",
+                                               ].map(Into::<OsString>::into).to_vec(),
+                                               env: outer_env.clone(),
+                                               dir: Some(PathBuf::from("top"))}),
+                         TestOp::SPO(RunExec { name: "inner-cmd".into(),
+                                               exe: "inner-cmd".into(),
+                                               args: ["-s1",
+                                               ].map(Into::<OsString>::into).to_vec(),
+                                               env: inner_env.clone(),
+                                               dir: Some(PathBuf::from("top/subdir"))}),
+                         TestOp::SPO(RunExec { name: "inner-cmd".into(),
+                                               exe: "inner-cmd".into(),
+                                               args: ["-s2",
+                                               ].map(Into::<OsString>::into).to_vec(),
+                                               env: inner_env,
+                                               dir: Some(PathBuf::from("top/subdir"))}),
+                         TestOp::SPO(RunExec { name: "outer-cmd".into(),
+                                               exe: "outer-cmd".into(),
+                                               args: ["-last",
+                                                      "final.out",
+                                               ].map(Into::<OsString>::into).to_vec(),
+                                               env: outer_env,
+                                               dir: Some(PathBuf::from("top"))}),
+                   ]);
+
+        // The nested chain's own environment is not permanently modified by the
+        // enclosing chain.
+        assert_eq!(sub.get_full_env(), EnvSpec::StdEnv.add("INNER", "in"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain_nested_inputs() -> anyhow::Result<()> {
+        let inner = Executable::new(&"inner-cmd",
+                                    ExeFileSpec::Append,
+                                    ExeFileSpec::Append);
+        let mut sub = ChainedOps::new("sub chain");
+        sub.push_op(&SubProcOperation::new(&inner));
+
+        let mut ops = ChainedOps::new("outer chain");
+        ops.set_input_file(&FileArg::loc("orig.inp"));
+        ops.set_output_file(&FileArg::loc("final.out"));
+        let outer = Executable::new(&"outer-cmd",
+                                    ExeFileSpec::Append,
+                                    ExeFileSpec::Append);
+        ops.push_op(SubProcOperation::new(&outer)
+                    .set_output_file(&FileArg::loc("first.out")));
+        ops.push_chain(&sub)?;
+
+        let top = ChainedOps::new("top chain");
+        top.push_chain(&ops)?;
+        for (nested, label) in [(&sub, "sub chain"), (&top, "top chain")] {
+            match sub.push_chain(nested) {
+                Err(e) => match e.downcast_ref::<ChainsopError>() {
+                    Some(ChainsopError::ErrorChainCycle(o, n)) =>  // [TC85]
+                        assert_eq!((o.as_str(), n.as_str()), ("sub chain", label)),
+                    _ => panic!("Unexpected error: {:?}", e),
+                },
+                Ok(_) => panic!("Expected a cycle error nesting {:?}", label),
+            }
+        }
+        assert_eq!(sub.len(), 1);
+
+        let xor = TestCollector::new();
+        ops.execute_here(&xor)?;
+        assert_eq!(exec_args(xor)[1],
+                   ("inner-cmd".into(),
+                    ["first.out", "final.out"].map(Into::<OsString>::into).to_vec()));
+        assert!(! sub.has_input_file()); // [TC87]
+
+        sub.set_input_file(&FileArg::loc("sub.inp"));
+        let xor = TestCollector::new();
+        ops.execute_here(&xor)?;
+        assert_eq!(exec_args(xor)[1],
+                   ("inner-cmd".into(),
+                    ["sub.inp", "final.out"].map(Into::<OsString>::into).to_vec())); // [TC86]
+        assert!(sub.has_input_file());
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain_dag() -> anyhow::Result<()> {
+        let mut ops = ChainedOps::new("dag chain");
+        ops.set_mode(&ChainMode::Dag(2));
+        ops.set_input_file(&FileArg::loc("chain.inp"));
+        ops.set_output_file(&FileArg::loc("final.out"));
+        let exe = Executable::new(&"cmd",
+                                  ExeFileSpec::Append,
+                                  ExeFileSpec::option("-o"));
+
+        let compile_foo = ops.push_op(SubProcOperation::new(&exe)
+                                      .set_label("foo")
+                                      .set_input_file(&FileArg::loc("foo.c"))
+                                      .set_output_file(&FileArg::loc("foo.o")));
+        let gen = ops.push_op(SubProcOperation::new(&exe)
+                              .set_label("gen")
+                              .set_output_file(&FileArg::loc("gen.c")));
+        let compile_gen = ops.push_op(SubProcOperation::new(&exe)
+                                      .set_label("gen.o")
+                                      .set_output_file(&FileArg::loc("gen.o")))
+            .depends_on(&gen)
+            .clone();
+        ops.push_op(SubProcOperation::new(&exe)
+                    .set_label("link")
+                    .set_output_file(&FileArg::loc("ignored")))
+            .depends_on(&compile_foo)
+            .depends_on(&compile_gen);
+
+        let xor = TestCollector::new();
+        match ops.execute(&xor, &None::<PathBuf>)? {
+            ActualFile::SingleFile(FileRef::StaticFile(sf)) =>
+                assert_eq!(sf, PathBuf::from("final.out")),
+            r => panic!("Expected single static file 'final.out' but got {:?}", r),
+        };
+
+        let exec = |label: &str, args: &[&str]| TestOp::SPO(
+            RunExec { name: label.into(),
+                      exe: "cmd".into(),
+                      args: args.iter().map(Into::<OsString>::into).collect(),
+                      env: EnvSpec::StdEnv,
+                      dir: None,
+            });
+        assert_eq!(xor.ops.into_inner(),
+                   vec![ exec("foo", &["-o", "foo.o", "foo.c"]),
+                         exec("gen", &["-o", "gen.c", "chain.inp"]), // [TC26]
+                         exec("gen.o", &["-o", "gen.o", "gen.c"]), // [TC26]
+                         exec("link", &["-o", "final.out", // [TC26]
+                                        "foo.o", "gen.o"]),
+                   ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain_dag_bad_dependency() -> anyhow::Result<()> {
+        let mut ops = ChainedOps::new("bad dag chain");
+        ops.set_mode(&ChainMode::Dag(2));
+        let exe = Executable::new(&"cmd",
+                                  ExeFileSpec::Append,
+                                  ExeFileSpec::Append);
+        let mut first = ops.push_op(&SubProcOperation::new(&exe));
+        let second = ops.push_op(&SubProcOperation::new(&exe));
+        first.depends_on(&second);
+
+        let xor = TestCollector::new();
+        let result = ops.execute_here(&xor);
+        match result {
+            Err(e) => match e.downcast_ref::<ChainsopError>() {
+                Some(ChainsopError::ErrorInvalidDependency(_, 0, 1)) => (), // [TC27]
+                _ => panic!("Unexpected error: {:?}", e),
+            },
+            Ok(r) => panic!("Expected an error but got {:?}", r),
+        }
+        assert_eq!(xor.ops.into_inner().len(), 0);
+
+        let other = ChainedOps::new("other chain");
+        let foreign = other.push_op(&SubProcOperation::new(&exe));
+        for dep in [foreign, ops.deep_clone().get(0).unwrap()] {
+            let mut ops = ChainedOps::new("foreign dag chain");
+            ops.set_mode(&ChainMode::Dag(2));
+            ops.push_op(&SubProcOperation::new(&exe));
+            ops.push_op(&SubProcOperation::new(&exe)).depends_on(&dep);
+            let xor = TestCollector::new();
+            match ops.execute_here(&xor) {
+                Err(e) => match e.downcast_ref::<ChainsopError>() {
+                    Some(ChainsopError::ErrorForeignDependency(_, 1)) => (), // [TC89]
+                    _ => panic!("Unexpected error: {:?}", e),
+                },
+                Ok(r) => panic!("Expected an error but got {:?}", r),
+            }
+            assert_eq!(xor.ops.into_inner().len(), 0);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain_dag_concurrency() -> anyhow::Result<()> {
+        let mut ops = ChainedOps::new("concurrent dag chain");
+        ops.set_mode(&ChainMode::Dag(2));
+        let exe = Executable::new(&"cc",
+                                  ExeFileSpec::Append,
+                                  ExeFileSpec::NoFileUsed);
+        let compiles : Vec<ChainedOpRef> =
+            ["a", "b", "c", "d"].iter()
+            .map(|src| ops.push_op(SubProcOperation::new(&exe)
+                                   .set_label(src)
+                                   .set_input_file(&FileArg::loc(src))))
+            .collect();
+        let mut link = ops.push_op(SubProcOperation::new(&exe).set_label("link"));
+        for c in &compiles {
+            link.depends_on(c);
+        }
+
+        let xor = TestCollector::running_for(Duration::from_millis(100));
+        ops.execute_here(&xor)?;
+        let jobs = xor.jobs.lock().unwrap();
+        assert_eq!(jobs.max_running, 2); // [TC28]
+        assert_eq!(jobs.started.last(), Some(&("link".to_string(), 4))); // [TC28]
+        let mut completed = jobs.completed.clone();
+        assert_eq!(completed.pop(), Some("link".to_string())); // [TC28]
+        completed.sort();
+        assert_eq!(completed, vec!["a", "b", "c", "d"]);
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_chain_dag_concurrency_async() -> anyhow::Result<()> {
+        let mut ops = ChainedOps::new("concurrent dag chain");
+        ops.set_mode(&ChainMode::Dag(2));
+        let exe = Executable::new(&"cc",
+                                  ExeFileSpec::Append,
+                                  ExeFileSpec::NoFileUsed);
+        let compiles : Vec<ChainedOpRef> =
+            ["a", "b", "c", "d"].iter()
+            .map(|src| ops.push_op(SubProcOperation::new(&exe)
+                                   .set_label(src)
+                                   .set_input_file(&FileArg::loc(src))))
+            .collect();
+        let mut link = ops.push_op(SubProcOperation::new(&exe).set_label("link"));
+        for c in &compiles {
+            link.depends_on(c);
+        }
+
+        let xor = TestCollector::running_for(Duration::from_millis(100));
+        ops.execute_here_async(&xor).await?;
+        let jobs = xor.jobs.lock().unwrap();
+        assert_eq!(jobs.max_running, 2); // [TC79]
+        assert_eq!(jobs.started.last(), Some(&("link".to_string(), 4))); // [TC79]
+        let mut completed = jobs.completed.clone();
+        assert_eq!(completed.pop(), Some("link".to_string())); // [TC79]
+        completed.sort();
+        assert_eq!(completed, vec!["a", "b", "c", "d"]);
+        Ok(())
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn test_chain_send_sync() -> anyhow::Result<()> {
+        fn send_sync<T: Send + Sync>(v: T) -> T { v }
+
+        let exe = Executable::new(&"cmd", ExeFileSpec::Append, ExeFileSpec::Append);
+        let workers : Vec<_> = (0..3).map(|n| {
+            let mut ops = ChainedOps::new(format!("chain {}", n));
+            ops.set_input_file(&FileArg::loc(format!("inp{}.txt", n)));
+            ops.push_op(SubProcOperation::new(&exe)
+                        .set_output_file(&FileArg::temp(".mid")));
+            ops.push_call(FunctionOperation::calling("fn", |_, _, _| Ok(()))
+                          .set_output_file(&FileArg::temp(".out")));
+            let opref = send_sync(ops.push_op(SubProcOperation::new(&exe)
+                                              .set_output_file(&FileArg::temp(".fin"))));
+            thread::spawn(move || {  // [TC29]
+                let mut ops = send_sync(ops);
+                let xor = TestCollector::new();
+                let r = send_sync(ops.execute(&xor, &Some("threaded")));
+                (opref.opid, xor.ops.into_inner().len(), r)
+            })
+        }).collect();
+        for w in workers {
+            let (opidx, nops, r) = w.join().unwrap();
+            let out = r?;
+            assert_eq!(opidx, 2);
+            assert_eq!(nops, 3);
+            assert!(matches!(out, ActualFile::SingleFile(FileRef::TempFile(_))),
+                    "Expected a temporary file result but got {:?}", out);
+        }
+        Ok(())
+    }
+
+    // The chains executed asynchronously only use explicitly named files, so
+    // that the sync and async executions can be directly compared.
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_chain_async() -> anyhow::Result<()> {
+        let exe = Executable::new(&"cmd", ExeFileSpec::Append, ExeFileSpec::Append);
+        let mut sub = ChainedOps::new("sub chain");
+        sub.set_env("INNER", "in");
+        sub.push_op(SubProcOperation::new(&exe)
+                    .set_label("s1")
+                    .set_output_file(&FileArg::loc("s1.out")));
+        sub.push_op(SubProcOperation::new(&exe)
+                    .set_label("s2")
+                    .set_output_file(&FileArg::loc("s2.out")));
+        let mut ops = ChainedOps::new("async chain");
+        ops.set_input_file(&FileArg::loc("orig.inp"));
+        ops.set_output_file(&FileArg::loc("final.out"));
+        ops.set_env("OUTER", "out");
+        ops.push_op(SubProcOperation::new(&exe)
+                    .set_label("first")
+                    .set_output_file(&FileArg::loc("first.out")));
+        ops.push_call(FunctionOperation::calling("fn", test_callee)
+                      .set_output_file(&FileArg::loc("fn.out")));
+        ops.push_chain(&sub)?;
+        ops.push_op(SubProcOperation::new(&exe).set_label("last"));
+
+        let sync_xor = TestCollector::new();
+        ops.execute(&sync_xor, &Some("top"))?;
+        let async_xor = TestCollector::new();
+        match ops.execute_async(&async_xor, &Some("top")).await? {
+            ActualFile::SingleFile(FileRef::StaticFile(sf)) =>
+                assert_eq!(sf, PathBuf::from("final.out")),
+            r => panic!("Expected single static file 'final.out' but got {:?}", r),
+        };
+        let collected = async_xor.ops.into_inner();
+        assert_eq!(collected.len(), 5);
+        assert_eq!(collected, sync_xor.ops.into_inner()); // [TC30]
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_chain_dag_async() -> anyhow::Result<()> {
+        let exe = Executable::new(&"cmd", ExeFileSpec::Append, ExeFileSpec::Append);
+        let mut sub = ChainedOps::new("sub chain");
+        sub.set_env("INNER", "in");
+        sub.push_op(SubProcOperation::new(&exe)
+                    .set_label("s1")
+                    .set_output_file(&FileArg::loc("s1.out")));
+        sub.push_op(SubProcOperation::new(&exe)
+                    .set_label("s2")
+                    .set_output_file(&FileArg::loc("s2.out")));
+        let mut ops = ChainedOps::new("async chain");
+        ops.set_mode(&ChainMode::Dag(1));
+        ops.set_input_file(&FileArg::loc("orig.inp"));
+        ops.set_output_file(&FileArg::loc("final.out"));
+        ops.set_env("OUTER", "out");
+        let first = ops.push_op(SubProcOperation::new(&exe)
+                                .set_label("first")
+                                .set_output_file(&FileArg::loc("first.out")));
+        let mut call = ops.push_call(FunctionOperation::calling("fn", test_callee)
+                                     .set_output_file(&FileArg::loc("fn.out")));
+        let mut nested = ops.push_chain(&sub)?;
+        ops.push_op(SubProcOperation::new(&exe).set_label("last"))
+            .depends_on(&first)
+            .depends_on(&nested);
+        call.depends_on(&first);
+        nested.depends_on(&call);
+
+        let sync_xor = TestCollector::new();
+        ops.execute(&sync_xor, &Some("top"))?;
+        ops.set_mode(&ChainMode::Dag(3));
+        let async_xor = TestCollector::new();
+        ops.execute_here_async(&async_xor).await?;
+        let sync_ops = sync_xor.ops.into_inner();
+        let mut async_ops = async_xor.ops.into_inner();
+        assert_eq!(async_ops.len(), 5);
+        // The only difference is the directory
+        for op in async_ops.iter_mut() {
+            match op {
+                TestOp::SPO(re) => re.dir = Some("top".into()),
+                TestOp::FO(rf) => rf.dir = Some("top".into()),
+            }
+        }
+        assert_eq!(async_ops, sync_ops); // [TC31]
+        Ok(())
+    }
+
+    #[cfg(all(feature = "sync", feature = "async"))]
+    #[tokio::test]
+    async fn test_chain_async_spawn() -> anyhow::Result<()> {
+        let cc = Executable::new(&"cc", ExeFileSpec::Append,
+                                 ExeFileSpec::option("-o"));
+        let mut objs = ChainedOps::new("objects");
+        objs.set_mode(&ChainMode::Dag(2));
+        for name in ["a", "b"] {
+            objs.push_op(SubProcOperation::new(&cc)
+                         .set_label(name)
+                         .set_input_file(&FileArg::loc(format!("{}.c", name)))
+                         .set_output_file(&FileArg::loc(format!("{}.o", name))));
+        }
+        let mut ops = ChainedOps::new("spawned");
+        ops.push_chain(&objs)?;
+        ops.push_op(SubProcOperation::new(&cc).set_label("link"));
+        ops.set_output_file(&FileArg::loc("prog"));
+
+        let report = tokio::spawn(async move {
+            ops.execute_with_report_async(&Executor::DryRun, &None::<PathBuf>).await
+        }).await?;
+        assert!(matches!(report.result?,
+                         ActualFile::SingleFile(FileRef::StaticFile(f))
+                         if f == Path::new("prog"))); // [TC93]
+        assert!(report.ops.iter()
+                .all(|op| op.status == OpStatus::Succeeded)); // [TC93]
+        Ok(())
+    }
+
+    // Actually runs the commands to verify that stdout is captured.
+    #[cfg(unix)]
+    #[test]
+    fn test_chain_stdout() -> anyhow::Result<()> {
+        let workdir = tempfile::tempdir()?;
+        let mut ops = ChainedOps::new("stdout chain");
+        ops.push_op(SubProcOperation::new(&Executable::new(&"echo",
+                                                           ExeFileSpec::NoFileUsed,
+                                                           ExeFileSpec::Stdout))
+                    .push_arg("hello, world")
+                    .set_output_file(&FileArg::temp(".echo")));
+        ops.push_op(SubProcOperation::new(&Executable::new(&"sed",
+                                                           ExeFileSpec::Append,
+                                                           ExeFileSpec::Stdout))
+                    .push_arg("s/world/there/")
+                    .set_output_file(&FileArg::temp(".sed")));
+        ops.push_op(&SubProcOperation::new(&Executable::new(&"cat",
+                                                            ExeFileSpec::Append,
+                                                            ExeFileSpec::Stdout)));
+        ops.set_output_file(&FileArg::loc("final.out"));
+
+        let result = ops.execute(&Executor::NormalRun, &Some(workdir.path()))?;
+        assert_eq!(result.to_path(&None::<PathBuf>)?, PathBuf::from("final.out"));
+        assert_eq!(std::fs::read_to_string(workdir.path().join("final.out"))?,
+                   "hello, there\n"); // [TC32]
+        Ok(())
+    }
+
+    // Actually runs the commands to verify that stdin is provided.
+    #[cfg(unix)]
+    #[test]
+    fn test_chain_stdin() -> anyhow::Result<()> {
+        let workdir = tempfile::tempdir()?;
+        std::fs::write(workdir.path().join("one.txt"), "pear\napple\n")?;
+        std::fs::write(workdir.path().join("two.txt"), "fig\n")?;
+        let mut ops = ChainedOps::new("stdin chain");
+        ops.set_input_file(&FileArg::loc("one.txt"));
+        ops.add_input_file(&FileArg::loc("two.txt"));
+        ops.push_op(SubProcOperation::new(&Executable::new(&"sort",
+                                                           ExeFileSpec::Stdin,
+                                                           ExeFileSpec::Stdout))
+                    .set_output_file(&FileArg::temp(".sorted")));
+        ops.push_op(SubProcOperation::new(&Executable::new(&"tr",
+                                                           ExeFileSpec::Stdin,
+                                                           ExeFileSpec::Stdout))
+                    .push_arg("a-z").push_arg("A-Z"));
+        ops.set_output_file(&FileArg::loc("final.out"));
+
+        ops.execute(&Executor::NormalRun, &Some(workdir.path()))?;
+        assert_eq!(std::fs::read_to_string(workdir.path().join("final.out"))?,
+                   "APPLE\nFIG\nPEAR\n"); // [TC33]
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain_pipe() {
+        let mut ops = ChainedOps::new("piped chain");
+        ops.set_input_file(&FileArg::loc("logs.gz"));
+        ops.push_op(&SubProcOperation::new(&Executable::new(&"zcat",
+                                                            ExeFileSpec::Append,
+                                                            ExeFileSpec::Stdout)));
+        ops.push_op(SubProcOperation::new(&Executable::new(&"grep",
+                                                           ExeFileSpec::Stdin,
+                                                           ExeFileSpec::Stdout))
+                    .push_arg("ERROR")
+                    .set_output_file(&FileArg::loc("errors.txt")))
+            .link(&ChainLink::Pipe);
+        ops.push_op(&SubProcOperation::new(&Executable::new(&"sort",
+                                                            ExeFileSpec::Append,
+                                                            ExeFileSpec::Append)));
+        ops.set_output_file(&FileArg::loc("sorted.txt"));
+
+        let xor = TestCollector::new();
+        ops.execute_here(&xor).unwrap();
+        let args = xor.ops.into_inner().iter()
+            .map(|op| match op {
+                TestOp::SPO(re) => (re.name.clone(), re.args.clone()),
+                TestOp::FO(rf) => panic!("Unexpected function call {:?}", rf),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(args,
+                   vec![("zcat".to_string(), vec![OsString::from("logs.gz")]),
+                        ("grep".to_string(), vec![OsString::from("ERROR")]),
+                        ("sort".to_string(), vec![OsString::from("errors.txt"),
+                                                  OsString::from("sorted.txt")])
+                   ]); // [TC34]
+    }
+
+    #[test]
+    fn test_chain_pipe_invalid() {
+        let mut ops = ChainedOps::new("bad pipe chain");
+        ops.set_input_file(&FileArg::loc("logs.gz"));
+        ops.push_op(&SubProcOperation::new(&Executable::new(&"zcat",
+                                                            ExeFileSpec::Append,
+                                                            ExeFileSpec::Stdout)));
+        ops.push_op(&SubProcOperation::new(&Executable::new(&"grep",
+                                                            ExeFileSpec::Append,
+                                                            ExeFileSpec::Stdout)))
+            .link(&ChainLink::Pipe);
+        ops.set_output_file(&FileArg::loc("errors.txt"));
+
+        let xor = TestCollector::new();
+        match ops.execute_here(&xor) {
+            Err(e) => match e.downcast_ref::<ChainsopError>() {
+                Some(ChainsopError::ErrorInvalidPipe(label, _)) =>
+                    assert_eq!(label, "grep"), // [TC35]
+                _ => panic!("Unexpected error: {:?}", e),
+            },
+            Ok(r) => panic!("Expected an error but got {:?}", r),
+        }
+        assert_eq!(xor.ops.into_inner().len(), 0);
+    }
+
+    // Actually runs the commands to verify that the pipeline is connected.
+    #[cfg(unix)]
+    #[test]
+    fn test_chain_pipe_run() -> anyhow::Result<()> {
+        let workdir = tempfile::tempdir()?;
+        std::fs::write(workdir.path().join("fruit.txt"), "pear\napple\npear\nfig\n")?;
+        let mut ops = ChainedOps::new("pipeline");
+        ops.set_input_file(&FileArg::loc("fruit.txt"));
+        ops.push_op(&SubProcOperation::new(&Executable::new(&"cat",
+                                                            ExeFileSpec::Append,
+                                                            ExeFileSpec::Stdout)));
+        let mut middle = ops.push_op(
+            SubProcOperation::new(&Executable::new(&"sh",
+                                                   ExeFileSpec::Stdin,
+                                                   ExeFileSpec::Stdout))
+                .push_arg("-c").push_arg("sort; exit $SORT_STATUS")
+                .set_env("SORT_STATUS", "0"));
+        middle.link(&ChainLink::Pipe);
+        ops.push_op(&SubProcOperation::new(&Executable::new(&"uniq",
+                                                            ExeFileSpec::Stdin,
+                                                            ExeFileSpec::Stdout)))
+            .link(&ChainLink::Pipe);
+        ops.set_output_file(&FileArg::loc("final.out"));
+
+        ops.execute(&Executor::NormalRun, &Some(workdir.path()))?;
+        assert_eq!(std::fs::read_to_string(workdir.path().join("final.out"))?,
+                   "apple\nfig\npear\n"); // [TC34]
+        assert_eq!(std::fs::read_dir(workdir.path())?.count(), 2);
+
+        middle.set_env("SORT_STATUS", "3");
+        let err = ops.execute(&Executor::NormalRun, &Some(workdir.path()))
+            .unwrap_err();
+        assert_eq!(err.to_string(),
+                   "Pipeline failed: cat: ok, sh: exit code 3, uniq: ok"); // [TC36]
+        match err.downcast_ref::<ChainsopError>() {
+            Some(ChainsopError::ErrorRunningCmd(_, _, Some(3), _, _)) => (),
+            _ => panic!("Unexpected error: {:?}", err),
+        }
+        Ok(())
+    }
+
+    #[cfg(all(feature = "async", unix))]
+    #[tokio::test]
+    async fn test_chain_pipe_run_async() -> anyhow::Result<()> {
+        let workdir = tempfile::tempdir()?;
+        std::fs::write(workdir.path().join("fruit.txt"), "pear\napple\npear\nfig\n")?;
+        let mut ops = ChainedOps::new("pipeline");
+        ops.set_input_file(&FileArg::loc("fruit.txt"));
+        ops.push_op(&SubProcOperation::new(&Executable::new(&"cat",
+                                                            ExeFileSpec::Append,
+                                                            ExeFileSpec::Stdout)));
+        ops.push_op(&SubProcOperation::new(&Executable::new(&"sort",
+                                                            ExeFileSpec::Stdin,
+                                                            ExeFileSpec::Stdout)))
+            .link(&ChainLink::Pipe);
+        ops.push_op(&SubProcOperation::new(&Executable::new(&"uniq",
+                                                            ExeFileSpec::Stdin,
+                                                            ExeFileSpec::Stdout)))
+            .link(&ChainLink::Pipe);
+        ops.set_output_file(&FileArg::loc("final.out"));
+
+        ops.execute_async(&Executor::NormalRun, &Some(workdir.path())).await?;
+        assert_eq!(std::fs::read_to_string(workdir.path().join("final.out"))?,
+                   "apple\nfig\npear\n"); // [TC34]
+        Ok(())
+    }
+
+    // Actually runs the commands to verify the timeouts.
+    #[cfg(unix)]
+    #[test]
+    fn test_chain_timeout() -> anyhow::Result<()> {
+        let sleep = |secs: &str| SubProcOperation::new(
+            &Executable::new(&"sleep", ExeFileSpec::NoFileUsed,
+                             ExeFileSpec::NoFileUsed))
+            .push_arg(secs).clone();
+
+        let mut ops = ChainedOps::new("timeout chain");
+        ops.set_timeout(Duration::from_millis(200));
+        ops.push_op(&sleep("0.5")).set_timeout(Duration::from_secs(10));
+        ops.execute_here(&Executor::NormalRun)?; // [TC38]
+
+        let nested = ChainedOps::new("nested chain");
+        nested.push_op(&sleep("10"));
+        ops.push_chain(&nested)?;
+        let started = std::time::Instant::now();
+        let err = ops.execute_here(&Executor::NormalRun).unwrap_err();
+        match err.downcast_ref::<ChainsopError>() {
+            Some(ChainsopError::ErrorTimedOut(_, args, elapsed, _, _)) => {
+                assert_eq!(args, &vec![OsString::from("10")]);
+                assert!(*elapsed >= Duration::from_millis(200));
+            }
+            _ => panic!("Unexpected error: {:?}", err),
+        }
+        assert!(started.elapsed() < Duration::from_secs(5)); // [TC37]
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain_retry() -> anyhow::Result<()> {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            backoff: Backoff::Exponential { initial: Duration::from_millis(10),
+                                            max: Duration::from_millis(15) },
+            retry_on: vec![RetryOn::ExitCode(75)],
+        };
+        let mk_chain = |mode: &ChainMode| {
+            let mut ops = ChainedOps::new("retry chain");
+            ops.set_mode(mode);
+            ops.push_op(&SubProcOperation::new(
+                &Executable::new(&"flaky", ExeFileSpec::NoFileUsed,
+                                 ExeFileSpec::NoFileUsed)))
+                .set_retry(&policy);
+            ops.push_call(&FunctionOperation::calling("fn", test_callee))
+                .set_retry(&policy);
+            ops
+        };
+
+        for mode in [ChainMode::Sequential, ChainMode::Dag(2)] {
+            let xor = TestCollector::failing(&[("flaky", 2), ("fn", 1)], 75);
+            mk_chain(&mode).execute_here(&xor)?;
+            // n.b. the operations are independent, so they may be interleaved
+            // in DAG mode.
+            let mut runs = xor.runs();
+            runs.sort();
+            assert_eq!(runs, vec!["flaky", "flaky", "flaky", "fn", "fn"]); // [TC39]
+            let mut delays = xor.delays.into_inner();
+            delays.sort();
+            assert_eq!(delays,
+                       vec![Duration::from_millis(10), Duration::from_millis(10),
+                            Duration::from_millis(15)]); // [TC39]
+        }
+
+        let xor = TestCollector::failing(&[("flaky", 5)], 75);
+        let err = mk_chain(&ChainMode::Sequential).execute_here(&xor).unwrap_err();
+        match err.downcast_ref::<ChainsopError>() {
+            Some(ChainsopError::ErrorAttemptsFailed(label, failures)) => {
+                assert_eq!(label, "flaky");
+                assert_eq!(failures.len(), 3); // [TC40]
+                assert!(failures.iter().all(
+                    |f| matches!(f.downcast_ref::<ChainsopError>(),
+                                 Some(ChainsopError::ErrorRunningCmd(
+                                     _, _, Some(75), _, _)))));
+            }
+            _ => panic!("Unexpected error: {:?}", err),
+        }
+        assert_eq!(xor.runs().len(), 3);
+
+        let xor = TestCollector::failing(&[("flaky", 1)], 1);
+        let err = mk_chain(&ChainMode::Sequential).execute_here(&xor).unwrap_err();
+        match err.downcast_ref::<ChainsopError>() {
+            Some(ChainsopError::ErrorRunningCmd(_, _, Some(1), _, _)) => (),
+            _ => panic!("Unexpected error: {:?}", err),
+        }
+        assert_eq!(xor.runs(), vec!["flaky"]); // [TC41]
+        Ok(())
+    }
+
+    // Actually runs the commands to verify the exit code handling.
+    #[cfg(unix)]
+    #[test]
+    fn test_chain_exit_code() -> anyhow::Result<()> {
+        let workdir = tempfile::tempdir()?;
+        std::fs::write(workdir.path().join("results.txt"), "Failed\n")?;
+        for mode in [ChainMode::Sequential, ChainMode::Dag(2)] {
+            let mut ops = ChainedOps::new("exit code chain");
+            ops.set_mode(&mode);
+            ops.set_input_file(&FileArg::loc("results.txt"));
+            let sort = ops.push_op(SubProcOperation::new(
+                &Executable::new(&"sort", ExeFileSpec::Append,
+                                 ExeFileSpec::option("-o")))
+                                   .set_output_file(&FileArg::temp(".sorted")));
+            let grep = ops.push_op(SubProcOperation::new(
+                &Executable::new(&"grep", ExeFileSpec::Append,
+                                 ExeFileSpec::NoFileUsed))
+                                   .push_arg("Passed"))
+                .accept_exit_code(1)
+                .depends_on(&sort)
+                .clone();
+            assert_eq!(grep.exit_code(), None);
+            ops.execute(&Executor::NormalRun, &Some(workdir.path()))?;
+            assert_eq!(sort.exit_code(), Some(0));
+            assert_eq!(grep.exit_code(), Some(1)); // [TC42]
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain_report() -> anyhow::Result<()> {
+        let exe = Executable::new(&"cmd", ExeFileSpec::Append,
+                                  ExeFileSpec::option("-o"));
+        let mut ops = ChainedOps::new("report chain");
+        ops.set_input_file(&FileArg::loc("chain.inp"));
+        ops.set_output_file(&FileArg::loc("final.out"));
+        ops.push_op(SubProcOperation::new(&exe)
+                    .set_label("first")
+                    .set_output_file(&FileArg::loc("first.out")));
+        ops.push_call(&FunctionOperation::calling("skipped", test_callee))
+            .active(&Activation::Disabled);
+        let nested = ChainedOps::new("nested");
+        nested.push_op(SubProcOperation::new(&exe)
+                       .set_label("inner")
+                       .set_output_file(&FileArg::loc("inner.out")))
+            .push_arg("-v");
+        ops.push_chain(&nested)?;
+        ops.push_op(SubProcOperation::new(&exe).set_label("last"));
+
+        let report = ops.execute_with_report(&TestCollector::new(),
+                                             &None::<PathBuf>);
+        assert!(report.succeeded(), "{:?}", report);
+        assert_eq!(report.label, "report chain");
+        let summary = |ops: &[OpReport]| ops.iter()
+            .map(|r| (r.label.clone(), r.status.clone(), r.exit_code,
+                      r.command.iter().map(|a| a.to_string_lossy().into_owned())
+                      .collect::<Vec<_>>().join(" "),
+                      r.output_files.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(summary(&report.ops),
+                   vec![("first".into(), OpStatus::Succeeded, Some(0),
+                         "cmd -o first.out chain.inp".into(),
+                         vec![PathBuf::from("first.out")]),
+                        ("skipped".into(), OpStatus::Disabled, None,
+                         "".into(), vec![]),
+                        ("nested".into(), OpStatus::Succeeded, None,
+                         "".into(), vec![PathBuf::from("inner.out")]),
+                        ("last".into(), OpStatus::Succeeded, Some(0),
+                         "cmd -o final.out inner.out".into(),
+                         vec![PathBuf::from("final.out")]),
+                   ]); // [TC43]
+        assert!(report.ops[0].elapsed.is_some());
+        assert!(report.ops[1].elapsed.is_none());
+        assert_eq!(summary(&report.ops[2].nested),
+                   vec![("inner".into(), OpStatus::Succeeded, Some(0),
+                         "cmd -v -o inner.out first.out".into(),
+                         vec![PathBuf::from("inner.out")]),
+                   ]); // [TC43]
+
+        for mode in [ChainMode::Sequential, ChainMode::Dag(2)] {
+            ops.set_mode(&mode);
+            let xor = TestCollector::failing(&[("inner", 1)], 2);
+            let report = ops.execute_with_report(&xor, &None::<PathBuf>);
+            assert!(! report.succeeded());
+            let statuses = report.ops.iter()
+                .map(|r| (r.status.clone(), r.exit_code))
+                .collect::<Vec<_>>();
+            assert!(matches!(&statuses[..],
+                             [(OpStatus::Succeeded, Some(0)),
+                              (OpStatus::Disabled, None),
+                              (OpStatus::Failed(_), None),
+                              (OpStatus::NotRun, None),
+                             ]), "Unexpected statuses: {:?}", statuses); // [TC44]
+            assert_eq!(report.ops[2].nested[0].exit_code, Some(2)); // [TC44]
+            assert!(matches!(report.ops[2].nested[0].status,
+                             OpStatus::Failed(_))); // [TC44]
+        }
+        Ok(())
+    }
+
+    // Returns the (index, label) of each failed operation reported by the error.
+    fn failed_ops_of(err: &anyhow::Error) -> Vec<(usize, String)> {
+        match err.downcast_ref::<ChainsopError>() {
+            Some(ChainsopError::ErrorOpsFailed(_, failures)) =>
+                failures.iter().map(|f| (f.index, f.label.clone())).collect(),
+            _ => panic!("Unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn test_chain_continue_on_error() -> anyhow::Result<()> {
+        let lint = Executable::new(&"lint", ExeFileSpec::Append,
+                                   ExeFileSpec::NoFileUsed);
+        let mut ops = ChainedOps::new("policy chain");
+        ops.set_policy(&ChainPolicy::ContinueOnError);
+        let mut oprefs : Vec<ChainedOpRef> = ["a", "b", "c", "d"].iter().map(|label| {
+            ops.push_op(SubProcOperation::new(&lint)
+                        .set_label(label)
+                        .set_input_file(&FileArg::loc(format!("{}.src", label))))
+        }).collect();
+        let xor = TestCollector::failing(&[("a", 9), ("c", 9)], 1);
+        let err = ops.execute_here(&xor).unwrap_err();
+        assert_eq!(failed_ops_of(&err),
+                   vec![(0, "a".into()), (2, "c".into())]); // [TC45]
+        assert_eq!(xor.runs(), vec!["a", "b", "c", "d"]); // [TC45]
+        assert!(err.to_string().starts_with(
+            "2 operation(s) failed in chain \"policy chain\":\n  [0] a: "));
+
+        let xor = TestCollector::failing(&[("d", 9)], 1);
+        let err = ops.execute_here(&xor).unwrap_err();
+        assert_eq!(failed_ops_of(&err), vec![(3, "d".into())]); // [TC45]
+
+        ops.execute_here(&TestCollector::new())?;
+
+        ops.set_mode(&ChainMode::Dag(1));
+        let (a, c) = (oprefs[0].clone(), oprefs[2].clone());
+        oprefs[1].depends_on(&a);
+        oprefs[3].depends_on(&c);
+        let xor = TestCollector::failing(&[("a", 9)], 1);
+        let report = ops.execute_with_report(&xor, &None::<PathBuf>);
+        assert_eq!(failed_ops_of(report.result.as_ref().unwrap_err()),
+                   vec![(0, "a".into())]);
+        assert_eq!(xor.runs(), vec!["a", "c", "d"]); // [TC46]
+        assert_eq!(report.ops[1].status, OpStatus::NotRun); // [TC46]
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain_continue_optional() {
+        let lint = Executable::new(&"lint", ExeFileSpec::Append,
+                                   ExeFileSpec::NoFileUsed);
+        let mut ops = ChainedOps::new("policy chain");
+        ops.set_policy(&ChainPolicy::ContinueOptional);
+        let mut oprefs : Vec<ChainedOpRef> = ["a", "b", "c", "d"].iter().map(|label| {
+            ops.push_op(SubProcOperation::new(&lint)
+                        .set_label(label)
+                        .set_input_file(&FileArg::loc(format!("{}.src", label))))
+        }).collect();
+        oprefs[0].set_optional(true);
+        let xor = TestCollector::failing(&[("a", 9), ("c", 9)], 1);
+        let report = ops.execute_with_report(&xor, &None::<PathBuf>);
+        assert_eq!(failed_ops_of(report.result.as_ref().unwrap_err()),
+                   vec![(0, "a".into()), (2, "c".into())]); // [TC47]
+        assert_eq!(xor.runs(), vec!["a", "b", "c"]); // [TC47]
+        assert_eq!(report.ops[3].status, OpStatus::NotRun); // [TC47]
+    }
+
+    #[test]
+    fn test_chain_continue_chained() {
+        let exe = Executable::new(&"cc", ExeFileSpec::Append, ExeFileSpec::Append);
+        let mut ops = ChainedOps::new("chained policy chain");
+        ops.set_policy(&ChainPolicy::ContinueOnError);
+        ops.set_input_file(&FileArg::loc("a.src"));
+        ops.set_output_file(&FileArg::loc("final.out"));
+        for label in ["a", "b", "c"] {
+            ops.push_op(SubProcOperation::new(&exe)
+                        .set_label(label)
+                        .set_output_file(&FileArg::temp(".out")));
+        }
+        ops.push_op(SubProcOperation::new(&exe)
+                    .set_label("d")
+                    .set_input_file(&FileArg::loc("d.src")));
+
+        let xor = TestCollector::failing(&[("a", 9)], 1);
+        let report = ops.execute_with_report(&xor, &None::<PathBuf>);
+        assert_eq!(failed_ops_of(report.result.as_ref().unwrap_err()),
+                   vec![(0, "a".into())]);
+        assert_eq!(xor.runs(), vec!["a", "d"]); // [TC80]
+        assert_eq!(report.ops[1].status, OpStatus::NotRun); // [TC80]
+        assert_eq!(report.ops[2].status, OpStatus::NotRun); // [TC80]
+        assert_eq!(report.ops[3].status, OpStatus::Succeeded); // [TC80]
+
+        let xor = TestCollector::failing(&[("b", 9)], 1);
+        let report = ops.execute_with_report(&xor, &None::<PathBuf>);
+        assert_eq!(failed_ops_of(report.result.as_ref().unwrap_err()),
+                   vec![(1, "b".into())]);
+        assert_eq!(xor.runs(), vec!["a", "b", "d"]); // [TC80]
+        assert_eq!(report.ops[2].status, OpStatus::NotRun); // [TC80]
+    }
+
+    fn finally_failures_of(err: &anyhow::Error)
+                           -> (Option<String>, Vec<(usize, String)>) {
+        match err.downcast_ref::<ChainsopError>() {
+            Some(ChainsopError::ErrorFinallyFailed(_, chain_err, failures)) =>
+                (chain_err.as_ref().map(|e| e.to_string()),
+                 failures.iter().map(|f| (f.index, f.label.clone())).collect()),
+            _ => panic!("Unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn test_chain_finally() -> anyhow::Result<()> {
+        let lint = Executable::new(&"lint", ExeFileSpec::Append,
+                                   ExeFileSpec::NoFileUsed);
+        let mut ops = ChainedOps::new("policy chain");
+        let mut oprefs : Vec<ChainedOpRef> = ["a", "b", "c", "d"].iter().map(|label| {
+            ops.push_op(SubProcOperation::new(&lint)
+                        .set_label(label)
+                        .set_input_file(&FileArg::loc(format!("{}.src", label))))
+        }).collect();
+        let status = ops.status();
+        let stop = Executable::new(&"stop", ExeFileSpec::NoFileUsed,
+                                   ExeFileSpec::NoFileUsed);
+        ops.push_finally_op(SubProcOperation::new(&stop).set_label("cleanup"));
+        let collect = ops.push_finally_call(&FunctionOperation::calling("collect",
+                                                                        test_callee));
+        assert_eq!(status.succeeded(), None);
+
+        let xor = TestCollector::new();
+        ops.execute_here(&xor)?;
+        assert_eq!(xor.runs(),
+                   vec!["a", "b", "c", "d", "cleanup", "collect"]); // [TC48]
+        assert_eq!(status.succeeded(), Some(true)); // [TC48]
+
+        let xor = TestCollector::failing(&[("b", 9)], 1);
+        let report = ops.execute_with_report(&xor, &None::<PathBuf>);
+        assert_eq!(xor.runs(),
+                   vec!["a", "b", "cleanup", "collect"]); // [TC49]
+        assert_eq!(status.succeeded(), Some(false)); // [TC49]
+        assert_eq!(report.ops[4].status, OpStatus::Succeeded); // [TC49]
+        match report.result.unwrap_err().downcast_ref::<ChainsopError>() {
+            Some(ChainsopError::ErrorRunningCmd(..)) => {} // [TC49]
+            e => panic!("Unexpected error: {:?}", e),
+        }
+
+        let xor = TestCollector::failing(&[("b", 9), ("cleanup", 9)], 1);
+        let err = ops.execute_here(&xor).unwrap_err();
+        assert_eq!(xor.runs(),
+                   vec!["a", "b", "cleanup", "collect"]); // [TC50]
+        let (chain_err, failures) = finally_failures_of(&err);
+        assert!(chain_err.unwrap().contains("b.src")); // [TC50]
+        assert_eq!(failures, vec![(4, "cleanup".into())]); // [TC50]
+
+        let xor = TestCollector::failing(&[("cleanup", 9)], 1);
+        let err = ops.execute_here(&xor).unwrap_err();
+        assert_eq!(finally_failures_of(&err),
+                   (None, vec![(4, "cleanup".into())])); // [TC50]
+        assert!(err.to_string().starts_with(
+            "1 finally operation(s) failed in chain \"policy chain\":\n  [4] cleanup: "));
+
+        for opref in oprefs.iter_mut() {
+            opref.active(&Activation::Disabled);
+        }
+        let xor = TestCollector::new();
+        ops.execute_here(&xor)?;
+        assert_eq!(xor.runs(), vec!["cleanup", "collect"]); // [TC48]
+
+        // Moving a finally operation within the chain does not change the order
+        // in which the finally operations are performed.
+        ops.move_to(&collect, 0)?;
+        let xor = TestCollector::new();
+        ops.execute_here(&xor)?;
+        assert_eq!(xor.runs(), vec!["cleanup", "collect"]); // [TC48]
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain_finally_status() -> anyhow::Result<()> {
+        let workdir = tempfile::tempdir()?;
+        let mut ops = ChainedOps::new("finally chain");
+        let mut fail = ops.push_op(&SubProcOperation::new(
+            &Executable::new(&"false", ExeFileSpec::NoFileUsed,
+                             ExeFileSpec::NoFileUsed)));
+        ops.push_finally_op(&SubProcOperation::new(
+            &Executable::new(&"sh", ExeFileSpec::NoFileUsed,
+                             ExeFileSpec::NoFileUsed)
+                .push_arg("-c")
+                .push_arg("echo $CHAINSOP_CHAIN_STATUS >> status.txt")));
+        let status = ops.status();
+        ops.push_finally_call(&FunctionOperation::calling(
+            "collect",
+            move |dir, _inp, _out| {
+                std::fs::write(dir.join("called.txt"),
+                               format!("{:?}", status.succeeded()))?;
+                Ok(())
+            }));
+
+        assert!(ops.execute(&Executor::NormalRun, &Some(workdir.path())).is_err());
+        assert_eq!(std::fs::read_to_string(workdir.path().join("called.txt"))?,
+                   "Some(false)"); // [TC51]
+        fail.active(&Activation::Disabled);
+        ops.execute(&Executor::NormalRun, &Some(workdir.path()))?;
+        assert_eq!(std::fs::read_to_string(workdir.path().join("status.txt"))?,
+                   "failure\nsuccess\n"); // [TC51]
+        assert_eq!(std::fs::read_to_string(workdir.path().join("called.txt"))?,
+                   "Some(true)"); // [TC51]
+        Ok(())
+    }
+
+    fn exec_args(xor: TestCollector) -> Vec<(String, Vec<OsString>)> {
+        xor.ops.into_inner().into_iter().map(|op| match op {
+            TestOp::SPO(RunExec { name, args, .. }) => (name, args),
+            TestOp::FO(RunFunc { fname, .. }) => (fname, vec![]),
+        }).collect()
+    }
+
+    #[test]
+    fn test_chain_when() -> anyhow::Result<()> {
+        let exe = Executable::new(&"cmd", ExeFileSpec::Append,
+                                  ExeFileSpec::option("-o"));
+        let seen : Shared<SharedCell<Vec<Vec<PathBuf>>>> =
+            Shared::new(SharedCell::new(vec![]));
+        let seen_by = Shared::clone(&seen);
+        let mut ops = ChainedOps::new("when chain");
+        ops.set_input_file(&FileArg::loc("chain.inp"));
+        let mut first = ops.push_op(SubProcOperation::new(&exe)
+                                    .set_label("first")
+                                    .set_output_file(&FileArg::loc("first.out")));
+        ops.push_op(SubProcOperation::new(&exe)
+                    .set_label("second")
+                    .set_output_file(&FileArg::loc("second.out")))
+            .active(&Activation::when(move |ctx| {
+                seen_by.borrow_mut().push(
+                    ctx.previous.to_paths::<PathBuf>(&None).unwrap_or_default());
+                false
+            }));
+        let mut last = ops.push_op(SubProcOperation::new(&exe)
+                                   .set_label("last")
+                                   .set_output_file(&FileArg::loc("last.out")));
+
+        let xor = TestCollector::new();
+        let report = ops.execute_with_report(&xor, &None::<PathBuf>);
+        assert!(report.succeeded());
+        assert_eq!(report.ops[1].status, OpStatus::Skipped); // [TC53]
+        assert_eq!(exec_args(xor),
+                   vec![("first".into(), vec!["-o".into(), "first.out".into(),
+                                              "chain.inp".into()]),
+                        ("last".into(), vec!["-o".into(), "last.out".into(),
+                                             "first.out".into()])]); // [TC53]
+        assert_eq!(seen.borrow().clone(), vec![vec![PathBuf::from("first.out")]]); // [TC54]
+
+        first.active(&Activation::Disabled);
+        let xor = TestCollector::new();
+        ops.execute_here(&xor)?;
+        assert_eq!(exec_args(xor),
+                   vec![("last".into(), vec!["-o".into(), "last.out".into(),
+                                             "chain.inp".into()])]); // [TC53]
+        assert_eq!(seen.borrow().last(), Some(&vec![])); // [TC54]
+
+        first.active(&Activation::Enabled);
+        last.active(&Activation::when(|ctx| ctx.path("nonexistent.file").exists()));
+        match ops.execute_here(&TestCollector::new())? {
+            ActualFile::SingleFile(FileRef::StaticFile(sf)) =>
+                assert_eq!(sf, PathBuf::from("first.out")), // [TC53]
+            r => panic!("Expected single static file 'first.out' but got {:?}", r),
+        }
+
+        last.link(&ChainLink::Pipe);
+        match ops.execute_here(&TestCollector::new()).unwrap_err()
+            .downcast_ref::<ChainsopError>()
+        {
+            Some(ChainsopError::ErrorInvalidPipe(label, _)) =>
+                assert_eq!(label, "second"), // [TC55]
+            e => panic!("Unexpected error: {:?}", e),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain_when_dag() -> anyhow::Result<()> {
+        let exe = Executable::new(&"cmd", ExeFileSpec::Append,
+                                  ExeFileSpec::option("-o"));
+        let mut ops = ChainedOps::new("when dag");
+        ops.set_mode(&ChainMode::Dag(1));
+        let a = ops.push_op(SubProcOperation::new(&exe)
+                            .set_label("a")
+                            .set_output_file(&FileArg::loc("a.out")));
+        let b = ops.push_op(SubProcOperation::new(&exe)
+                            .set_label("b")
+                            .set_output_file(&FileArg::loc("b.out")))
+            .depends_on(&a)
+            .active(&Activation::when(|ctx| matches!(ctx.previous,
+                                                     ActualFile::NoActualFile)))
+            .clone();
+        ops.push_op(SubProcOperation::new(&exe)
+                    .set_label("c")
+                    .set_output_file(&FileArg::loc("c.out")))
+            .depends_on(&b);
+        ops.push_finally_op(SubProcOperation::new(&exe).set_label("cleanup"))
+            .active(&Activation::when(|ctx| ctx.previous.to_path::<PathBuf>(&None)
+                                      .map(|p| p != Path::new("c.out"))
+                                      .unwrap_or(true)));
+        let xor = TestCollector::new();
+        let report = ops.execute_with_report(&xor, &None::<PathBuf>);
+        assert!(report.succeeded());
+        assert_eq!(report.ops.iter().map(|r| r.status.clone()).collect::<Vec<_>>(),
+                   vec![OpStatus::Succeeded, OpStatus::Skipped,
+                        OpStatus::Succeeded, OpStatus::Skipped]); // [TC56]
+        assert_eq!(exec_args(xor)[1],
+                   ("c".into(), vec!["-o".into(), "c.out".into(),
+                                     "a.out".into()])); // [TC56]
+        Ok(())
+    }
+
+    fn set_mtime(path: &Path, secs: u64) -> anyhow::Result<()> {
+        std::fs::write(path, "")?;
+        std::fs::File::options().write(true).open(path)?
+            .set_modified(std::time::UNIX_EPOCH + Duration::from_secs(secs))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain_incremental() -> anyhow::Result<()> {
+        let workdir = tempfile::tempdir()?;
+        let wd = Some(workdir.path());
+        let exe = Executable::new(&"cc", ExeFileSpec::Append,
+                                  ExeFileSpec::option("-o"));
+        for mode in [ChainMode::Sequential, ChainMode::Dag(1)] {
+            let mut ops = ChainedOps::new("incremental chain");
+            ops.set_mode(&mode);
+            let mut compile = ops.push_op(SubProcOperation::new(&exe)
+                                          .set_label("compile")
+                                          .set_input_file(&FileArg::loc("foo.c"))
+                                          .set_output_file(&FileArg::loc("foo.o")))
+                .set_incremental(true)
+                .clone();
+            ops.push_op(SubProcOperation::new(&exe)
+                        .set_label("link")
+                        .set_output_file(&FileArg::loc("foo")))
+                .depends_on(&compile);
+
+            set_mtime(&workdir.path().join("foo.c"), 2000)?;
+            set_mtime(&workdir.path().join("foo.o"), 1000)?;
+            let xor = TestCollector::new();
+            ops.execute(&xor, &wd)?;
+            assert_eq!(exec_args(xor).len(), 2); // [TC57]
+
+            set_mtime(&workdir.path().join("foo.o"), 3000)?;
+            let xor = TestCollector::new();
+            let report = ops.execute_with_report(&xor, &wd);
+            assert!(report.succeeded());
+            assert_eq!(report.ops[0].status, OpStatus::UpToDate); // [TC57]
+            assert_eq!(report.ops[0].output_files, vec![PathBuf::from("foo.o")]);
+            assert_eq!(exec_args(xor),
+                       vec![("link".into(), vec!["-o".into(), "foo".into(),
+                                                 "foo.o".into()])]); // [TC57]
+
+            compile.set_incremental(false);
+            let xor = TestCollector::new();
+            ops.execute(&xor, &wd)?;
+            assert_eq!(exec_args(xor).len(), 2); // [TC58]
+            compile.set_incremental(true);
+
+            std::fs::remove_file(workdir.path().join("foo.o"))?;
+            let xor = TestCollector::new();
+            ops.execute(&xor, &wd)?;
+            assert_eq!(exec_args(xor).len(), 2); // [TC58]
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain_incremental_inputs() -> anyhow::Result<()> {
+        let workdir = tempfile::tempdir()?;
+        let wd = Some(workdir.path());
+        let exe = Executable::new(&"cc", ExeFileSpec::Append,
+                                  ExeFileSpec::option("-o"));
+        set_mtime(&workdir.path().join("gen.out"), 4_000_000_000)?;
+        set_mtime(&workdir.path().join("foo"), 4_000_000_000)?;
+        for mode in [ChainMode::Sequential, ChainMode::Dag(1)] {
+            let mut ops = ChainedOps::new("incremental inputs chain");
+            ops.set_mode(&mode);
+            ops.push_op(SubProcOperation::new(&exe)
+                        .set_label("gen")
+                        .set_output_file(&FileArg::loc("gen.out")))
+                .set_incremental(true);
+            let xor = TestCollector::new();
+            let report = ops.execute_with_report(&xor, &wd);
+            assert!(report.succeeded());
+            assert_eq!(report.ops[0].status, OpStatus::Succeeded); // [TC83]
+
+            let mut ops = ChainedOps::new("incremental temp chain");
+            ops.set_mode(&mode);
+            let compile = ops.push_op(SubProcOperation::new(&exe)
+                                      .set_label("compile")
+                                      .set_input_file(&FileArg::loc("gen.out"))
+                                      .set_output_file(&FileArg::temp(".o")));
+            ops.push_op(SubProcOperation::new(&exe)
+                        .set_label("link")
+                        .set_output_file(&FileArg::loc("foo")))
+                .depends_on(&compile)
+                .set_incremental(true);
+            let xor = TestCollector::new();
+            let report = ops.execute_with_report(&xor, &wd);
+            assert!(report.succeeded());
+            assert_eq!(report.ops[1].status, OpStatus::Succeeded); // [TC84]
+            assert_eq!(exec_args(xor).len(), 2); // [TC84]
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain_cache() -> anyhow::Result<()> {
+        let workdir = tempfile::tempdir()?;
+        let wd = Some(workdir.path());
+        let src = workdir.path().join("foo.c");
+        let obj = workdir.path().join("foo.o");
+        let exe = Executable::new(&"cc", ExeFileSpec::Append,
+                                  ExeFileSpec::option("-o"));
+        for (n, mode) in [ChainMode::Sequential, ChainMode::Dag(1)].iter().enumerate() {
+            let mut ops = ChainedOps::new("cached chain");
+            ops.set_mode(mode);
+            ops.set_cache(&OutputCache::new(workdir.path().join(format!("cache{}", n))));
+            let compile = ops.push_op(SubProcOperation::new(&exe)
+                                      .set_label("compile")
+                                      .set_input_file(&FileArg::loc("foo.c"))
+                                      .set_output_file(&FileArg::loc("foo.o")))
+                .clone();
+            ops.push_op(SubProcOperation::new(&exe)
+                        .set_label("link")
+                        .set_output_file(&FileArg::loc("foo")))
+                .depends_on(&compile);
+
+            // The TestCollector does not write the output file, so it is written
+            // here as the output of the first execution.
+            std::fs::write(&src, "int main;")?;
+            std::fs::write(&obj, "compiled")?;
+            let xor = TestCollector::new();
+            ops.execute(&xor, &wd)?;
+            assert_eq!(exec_args(xor).len(), 2);
+
+            std::fs::remove_file(&obj)?;
+            let xor = TestCollector::new();
+            ops.execute(&xor, &wd)?;
+            assert_eq!(std::fs::read_to_string(&obj)?, "compiled"); // [TC59]
+            assert_eq!(exec_args(xor),
+                       vec![("link".into(), vec!["-o".into(), "foo".into(),
+                                                 "foo.o".into()])]); // [TC59]
+
+            std::fs::write(&src, "int main();")?;
+            let xor = TestCollector::new();
+            ops.execute(&xor, &wd)?;
+            assert_eq!(exec_args(xor).len(), 2); // [TC60]
+        }
+        Ok(())
+    }
 
-        // The penultimate arg of the third op should be the input tempfile,
-        // which should be the output tempfile of the previous op
-        match &mut collected[3] {
-            TestOp::SPO(re) => {
-                let inpf = PathBuf::from(&re.args[re.args.len()-2]);
-                assert_eq!(inpf, output2_tmpfile);
-                let outf = re.args.clone().last().unwrap().clone();
-                re.args.pop();  // remove output filespec
-                re.args.pop();  // remove input file
-                re.args.push(outf.clone()); // re-add output filespec
-                outf
-            }
-            TestOp::FO(_) => {
-                panic!("Expected second op to be a SubProcOperation");
-            }
-        };
+    #[test]
+    fn test_chain_lookup() {
+        let exe = Executable::new(&"cc", ExeFileSpec::Append,
+                                  ExeFileSpec::option("-o"));
+        let ops = ChainedOps::new("lookup chain");
+        assert!(ops.is_empty());
+        ops.push_op(SubProcOperation::new(&exe)
+                    .set_label("compile")
+                    .push_arg("-c")
+                    .set_input_file(&FileArg::loc("foo.c")))
+            .set_env("CC_OPTS", "-g");
+        ops.push_call(&FunctionOperation::calling("check", test_callee))
+            .active(&Activation::Disabled)
+            .set_dir("sub");
+        ops.push_op(SubProcOperation::new(&exe).set_label("compile"))
+            .set_output_file(&FileArg::loc("foo"));
 
-        assert_eq!(collected,
-                   vec![ TestOp::SPO(RunExec { name: "test-cmd".into(),
-                                               exe: "test-cmd".into(),
-                                               args: ["-a",
-                                                      "a-arg-value",
-                                                      "-b",
-                                                      "orig.inp", // [TC9]
-                                                      "other_inpfile", // [TC9]
-                                                      // output file removed above
-                                               ].map(Into::<OsString>::into).to_vec(),
-                                               env: EnvSpec::StdEnv
-                                               .append("SUB", "CLUB;DUB", ",") // [TC24]
-                                               .add("ZINC", "ALLOY")
-                                               .prepend("SUB", "HUB", ":")
-                                               .rmv("GONE"), // [TC24]
-                                               dir: Some(PathBuf::from("target/loc"))}), // [TC12]
-                         TestOp::SPO(RunExec { name: "cmd2".into(),
-                                               exe: "cmd2".into(),
-                                               args: ["-D",
-                                                      "DVAL",
-                                                      "-a2",
-                                                      "--input",
-                                                      // input file removed above
-                                                      // output file removed above
-                                               ].map(Into::<OsString>::into).to_vec(),
-                                               env: EnvSpec::StdEnv  // [TC23]
-                                               .add("GONE", "Long")
-                                               .append("SUB", "CLUB", ",")
-                                               .add("ZINC", "ALLOY"),
-                                               dir: Some(PathBuf::from("target/loc/.build"))}),
-                         // [TC4]
-                         TestOp::FO(RunFunc { fname: "a func".into(),
-                                              inpfiles: vec![], // input files removed above
-                                              outfile: None, // output file removed above
-                                              dir: Some(PathBuf::from("target/loc"))}),
-                         // [TC4]
-                         TestOp::SPO(RunExec { name: "finish".into(),
-                                               exe: "finish".into(),
-                                               args: ["--last",
-                                                      "op",
-                                                      "-i",
-                                                      // input file removed above
-                                                      "out:\"final.out\"", // [TC7], [TC17]
-                                               ].map(Into::<OsString>::into).to_vec(),
-                                               env: EnvSpec::StdEnv  // [TC23]
-                                               .add("GONE", "Long")
-                                               .append("SUB", "CLUB", ",")
-                                               .add("ZINC", "ALLOY"),
-                                               dir: Some(PathBuf::from("target/loc/.build"))}),
-                   ]);
+        assert_eq!(ops.len(), 3);
+        assert_eq!(ops.iter().map(|op| op.label()).collect::<Vec<_>>(),
+                   vec!["compile", "check", "compile"]); // [TC64]
+        assert_eq!(ops.get(1).map(|op| op.label()), Some("check".into())); // [TC64]
+        assert!(ops.get(3).is_none()); // [TC64]
 
-        // -------------------------------------------------------------------
-        // Execute the chainedops *again* to verify they can be re-used and are
-        // actually executed again.
-        let xor2 = TestCollector::new();
-        let result2 = ops.execute(&xor2, &Some("/other"));  // [TC15]
+        let mut first = ops.find_by_label("compile").unwrap();
+        assert_eq!(first.args(), vec![OsString::from("-c")]); // [TC65]
+        assert_eq!(first.input_files(), vec![FileArg::loc("foo.c")]); // [TC65]
+        assert_eq!(first.env(), EnvSpec::StdEnv.add("CC_OPTS", "-g")); // [TC65]
+        assert_eq!(first.activation(), Activation::Enabled); // [TC65]
+        let check = ops.find_by_label("check").unwrap();
+        assert_eq!(check.activation(), Activation::Disabled); // [TC65]
+        assert_eq!(check.dir(), Some(PathBuf::from("sub"))); // [TC65]
+        assert!(check.args().is_empty()); // [TC65]
+        assert_eq!(ops.get(2).unwrap().output_file(), FileArg::loc("foo")); // [TC65]
+        assert!(ops.find_by_label("link").is_none()); // [TC64]
 
-        assert!(match result2 {
-            Ok(ActualFile::SingleFile(FileRef::StaticFile(sf))) =>
-                sf == PathBuf::from("final.out"),
-            _ => false,
-        });
-        let mut collected2 = xor2.0.into_inner();
-        assert_eq!(collected2.len(), 4);
+        // The references can be used to modify the chain.
+        first.push_arg("-O2");
+        assert_eq!(ops.get(0).unwrap().args().len(), 2); // [TC64]
+        first.push_arg_at(ArgPosition::AfterInputs, "-lm")
+            .push_arg_at(ArgPosition::Leading, "-x");
+        assert_eq!(ops.get(0).unwrap().args(),
+                   ["-x", "-c", "-O2", "-lm"]
+                   .map(Into::<OsString>::into).to_vec()); // [TC78]
+    }
 
-        // The last arg of the first op is an assigned output tempfile
-        let output0_tmpfile2 = match &mut collected2[0] {
-            TestOp::SPO(re) => {
-                let outf = PathBuf::from(&re.args.last().unwrap());
-                assert!(!outf.exists(),
-                        "intermediate second temp file #0 {:?} did not get cleaned up!",
-                        outf);
-                re.args.pop();
-                outf
-            }
-            TestOp::FO(_) => {
-                panic!("Expected first op to be a SubProcOperation");
-            }
-        };
-        assert_ne!(output0_tmpfile, output0_tmpfile2);  // each run uses new tempfiles
+    #[test]
+    fn test_chain_edit() -> anyhow::Result<()> {
+        let exe = Executable::new(&"tool", ExeFileSpec::Append,
+                                  ExeFileSpec::option("-o"));
+        let op = |label: &str| SubProcOperation::new(&exe)
+            .set_label(label)
+            .set_output_file(&FileArg::temp(".tmp"))
+            .clone();
+        let mut ops = ChainedOps::new("edit chain");
+        ops.set_input_file(&FileArg::loc("in"));
+        let compile = ops.push_op(&op("compile"));
+        let mut check = ops.push_op(&op("check"));
+        let mut package = ops.push_op(&op("package"));
+        package.set_output_file(&FileArg::loc("out.pkg"));
+        check.active(&Activation::Disabled);
 
-        // The penultimate arg of the second op should be the input tempfile,
-        // which should be the output tempfile of the previous op
-        match &mut collected2[1] {
-            TestOp::SPO(re) => {
-                let inpf = PathBuf::from(&re.args[re.args.len()-2]);
-                assert_eq!(inpf, output0_tmpfile2);
-            }
-            TestOp::FO(_) => {
-                panic!("Expected second op to be a SubProcOperation");
-            }
-        };
-        // The last arg of the second op is another assigned output tempfile
-        let output1_tmpfile2 = match &mut collected2[1] {
-            TestOp::SPO(re) => {
-                let outf = PathBuf::from(&re.args.last().unwrap());
-                assert!(!outf.exists(),
-                        "intermediate second temp file #1 {:?} did not get cleaned up!",
-                        outf);
-                re.args.pop();  // remove output file
-                re.args.pop();  // remove input file
-                outf
-            }
-            TestOp::FO(_) => {
-                panic!("Expected second op to be a SubProcOperation");
-            }
-        };
-        assert_ne!(output1_tmpfile, output1_tmpfile2);  // each run uses new tempfiles
+        let labels = |ops: &ChainedOps| ops.iter().map(|op| op.label())
+            .collect::<Vec<_>>();
+        let mut strip = ops.insert_op_before(&package, &op("strip"))?;
+        strip.push_arg("-s");
+        ops.insert_op_after(&compile, &op("link"))?;
+        assert_eq!(labels(&ops),
+                   vec!["compile", "link", "check", "strip", "package"]); // [TC66]
+        assert_eq!(check.activation(), Activation::Disabled); // [TC67]
+        assert_eq!(strip.args(), vec![OsString::from("-s")]); // [TC67]
 
-        // The input file of the third op should be the tempfile output of the
-        // second op, and the output file of the third op should be an assigned
-        // tempfile.
-        let output2_tmpfile2 = match &mut collected2[2] {
-            TestOp::SPO(_) => {
-                panic!("Expected third op to be a FunctionOperation");
-            }
-            TestOp::FO(rf) => {
-                // There was an explicit input file set for this operation, so it
-                // should *not* have been overwritten by the output of the
-                // previous operation.
-                assert_ne!(rf.inpfiles, vec![output1_tmpfile.clone()]);
-                assert_eq!(rf.inpfiles, vec![PathBuf::from("not replaced")]);
-                let outf = rf.outfile.clone().unwrap();
-                assert!(!outf.exists(),
-                        "intermediate second temp file #2 {:?} did not get cleaned up!",
-                        outf);
-                rf.inpfiles = vec![];
-                rf.outfile = None;
-                outf
-            }
-        };
-        assert_ne!(output2_tmpfile, output2_tmpfile2);  // each run uses new tempfiles
+        ops.move_to(&package, 0)?;
+        assert_eq!(labels(&ops),
+                   vec!["package", "compile", "link", "check", "strip"]); // [TC66]
+        ops.move_to(&package, 4)?;
+        assert_eq!(check.activation(), Activation::Disabled); // [TC67]
 
-        // The penultimate arg of the third op should be the input tempfile,
-        // which should be the output tempfile of the previous op
-        match &mut collected2[3] {
-            TestOp::SPO(re) => {
-                let inpf = PathBuf::from(&re.args[re.args.len()-2]);
-                assert_eq!(inpf, output2_tmpfile2);
-                let outf = re.args.clone().last().unwrap().clone();
-                re.args.pop();  // remove output filespec
-                re.args.pop();  // remove input file
-                re.args.push(outf.clone()); // re-add output filespec
-                outf
-            }
-            TestOp::FO(_) => {
-                panic!("Expected second op to be a SubProcOperation");
-            }
-        };
+        ops.remove(&check)?;
+        assert!(check.is_removed()); // [TC68]
+        assert!(! strip.is_removed()); // [TC68]
+        assert_eq!(labels(&ops), vec!["compile", "link", "strip", "package"]); // [TC66]
+        let xor = TestCollector::new();
+        ops.execute_here(&xor)?;
+        let args = exec_args(xor);
+        assert_eq!(args.iter().map(|(l, _)| l.clone()).collect::<Vec<_>>(),
+                   labels(&ops)); // [TC66]
+        assert_eq!(args[2].1[0], OsString::from("-s")); // [TC67]
+        assert_eq!(args[3].1[..2], [OsString::from("-o"), OsString::from("out.pkg")]);
 
-        assert_eq!(collected2,  // almost `collected` but main directory changed
-                   vec![ TestOp::SPO(RunExec { name: "test-cmd".into(),
-                                               exe: "test-cmd".into(),
-                                               args: ["-a",
-                                                      "a-arg-value",
-                                                      "-b",
-                                                      "orig.inp",
-                                                      "other_inpfile",
-                                                      // output file removed above
-                                               ].map(Into::<OsString>::into).to_vec(),
-                                               env: EnvSpec::StdEnv
-                                               .append("SUB", "CLUB;DUB", ",") // [TC24]
-                                               .add("ZINC", "ALLOY")
-                                               .prepend("SUB", "HUB", ":")
-                                               .rmv("GONE"), // [TC24]
-                                               dir: Some(PathBuf::from("/other"))}),
-                         TestOp::SPO(RunExec { name: "cmd2".into(),
-                                               exe: "cmd2".into(),
-                                               args: ["-D",
-                                                      "DVAL",
-                                                      "-a2",
-                                                      "--input",
-                                                      // input file removed above
-                                                      // output file removed above
-                                               ].map(Into::<OsString>::into).to_vec(),
-                                               env: EnvSpec::StdEnv  // [TC23]
-                                               .add("GONE", "Long")
-                                               .append("SUB", "CLUB", ",")
-                                               .add("ZINC", "ALLOY"),
-                                               dir: Some(PathBuf::from("/other/.build"))}),
-                         TestOp::FO(RunFunc { fname: "a func".into(),
-                                              inpfiles: vec![], // input files removed above
-                                              outfile: None, // output file removed above
-                                              dir: Some(PathBuf::from("/other"))}),
-                         TestOp::SPO(RunExec { name: "finish".into(),
-                                               exe: "finish".into(),
-                                               args: ["--last",
-                                                      "op",
-                                                      "-i",
-                                                      // input file removed above
-                                                      "out:\"final.out\"",
-                                               ].map(Into::<OsString>::into).to_vec(),
-                                               env: EnvSpec::StdEnv  // [TC23]
-                                               .add("GONE", "Long")
-                                               .append("SUB", "CLUB", ",")
-                                               .add("ZINC", "ALLOY"),
-                                               dir: Some(PathBuf::from("/other/.build"))}),
-                   ]);
+        // Dependencies are updated for the moved and removed elements.
+        ops.set_mode(&ChainMode::Dag(1));
+        let link = ops.find_by_label("link").unwrap();
+        package.depends_on(&link).depends_on(&strip);
+        ops.remove(&strip)?;
+        ops.move_to(&link, 0)?;
+        assert_eq!(ops.chops.borrow().depends.get(&2), Some(&vec![0])); // [TC67]
+
+        // Function operations and nested chains can also be inserted.
+        let sub = ChainedOps::new("sub chain");
+        sub.push_op(&op("sign"));
+        ops.insert_chain_after(&link, &sub)?;
+        ops.insert_call_before(&link, &FunctionOperation::calling("prep", test_callee))?;
+        assert_eq!(labels(&ops),
+                   vec!["prep", "link", "sub chain", "compile", "package"]); // [TC66]
+        match ops.insert_chain_before(&link, &ops) {
+            Err(e) => assert!(matches!(e.downcast_ref::<ChainsopError>(),
+                                       Some(ChainsopError::ErrorChainCycle(..)))),
+            Ok(_) => panic!("Expected a cycle error"),
+        }
+        assert_eq!(ops.len(), 5);
         Ok(())
     }
 
     #[test]
-    fn test_chain_empty() -> anyhow::Result<()> {
-        let mut ops = ChainedOps::new("test empty chain");
-        // [TC1]
-        let ex = TestCollector::new();
-        let result = ops.execute(&ex, &Some("target/loc"));
-        match result {
-            Ok(ActualFile::NoActualFile) => (),
-            _ => assert!(false,
-                         "Expected single static file 'final.out' but got {:?}",
-                         result),
+    fn test_chain_edit_bad_ref() -> anyhow::Result<()> {
+        let exe = Executable::new(&"tool", ExeFileSpec::Append,
+                                  ExeFileSpec::option("-o"));
+        let ops = ChainedOps::new("edit chain");
+        let first = ops.push_op(&SubProcOperation::new(&exe));
+        let removed = ops.push_op(&SubProcOperation::new(&exe));
+        ops.remove(&removed)?;
+        let other = ChainedOps::new("other chain");
+        let foreign = other.push_op(&SubProcOperation::new(&exe));
+
+        let removed_err = |r: anyhow::Result<()>| match r {
+            Err(e) => assert!(matches!(e.downcast_ref::<ChainsopError>(),
+                                       Some(ChainsopError::ErrorRemovedOperation(_)))),
+            Ok(_) => panic!("Expected a removed operation error"),
         };
-        let collected = ex.0.into_inner();
-        assert_eq!(collected.len(), 0);
+        let foreign_err = |r: anyhow::Result<()>| match r {
+            Err(e) => assert!(matches!(e.downcast_ref::<ChainsopError>(),
+                                       Some(ChainsopError::ErrorForeignOperation(_)))),
+            Ok(_) => panic!("Expected a foreign operation error"),
+        };
+        removed_err(ops.remove(&removed)); // [TC90]
+        removed_err(ops.move_to(&removed, 0)); // [TC90]
+        removed_err(ops.insert_op_after(&removed, &SubProcOperation::new(&exe))
+                    .map(|_| ())); // [TC90]
+        foreign_err(ops.remove(&foreign)); // [TC91]
+        foreign_err(ops.move_to(&foreign, 0)); // [TC91]
+        foreign_err(ops.insert_op_before(&foreign, &SubProcOperation::new(&exe))
+                    .map(|_| ())); // [TC91]
+        assert_eq!(ops.len(), 1);
+        assert_eq!(other.len(), 1);
+        assert!(! first.is_removed());
         Ok(())
     }
 
     #[test]
-    fn test_chain_single_op() -> anyhow::Result<()> {
-        let mut ops = ChainedOps::new("test chain single");
+    #[should_panic(expected = "removed from chain")]
+    fn test_chain_removed_ref() {
+        let exe = Executable::new(&"tool", ExeFileSpec::Append,
+                                  ExeFileSpec::option("-o"));
+        let ops = ChainedOps::new("edit chain");
+        let mut first = ops.push_op(&SubProcOperation::new(&exe));
+        ops.remove(&first.clone()).unwrap();
+        first.push_arg("-x"); // [TC68]
+    }
 
-        let exe = Executable::new(&"test-cmd",
-                                  ExeFileSpec::Append,
-                                  ExeFileSpec::Append);
-        ops.push_op(SubProcOperation::new(&exe)  // [TC2]
-                    // Set the input file.  The chain should *not* override this
-                    // because the chain itself is not given an input file.  The
-                    // usual expectation is that the chain *will* be given an
-                    // input file, but this verifies the behavior of the unusual
-                    // scenario.
-                    .set_input_file(&FileArg::loc("override-in"))
-                    .set_output_file(&FileArg::loc("override-out"))
-                    .push_arg("-b"));
-        ops.set_input_file(&FileArg::loc("real-in"));  // [TC8]
-        ops.set_output_file(&FileArg::loc("real-out")); // [TC6]
+    #[test]
+    fn test_chain_deep_clone() -> anyhow::Result<()> {
+        let exe = Executable::new(&"tool", ExeFileSpec::Append,
+                                  ExeFileSpec::option("-o"));
+        let op = |label: &str| SubProcOperation::new(&exe)
+            .set_label(label)
+            .set_output_file(&FileArg::temp(".tmp"))
+            .clone();
+        let sub = ChainedOps::new("sub chain");
+        let strip = sub.push_op(&op("strip"));
+        let mut release = ChainedOps::new("release");
+        release.set_input_file(&FileArg::loc("in.c"));
+        release.set_output_file(&FileArg::loc("out"));
+        let compile = release.push_op(&op("compile"));
+        release.push_chain(&sub)?;
+        release.push_chain(&sub)?;
+
+        let mut debug = release.deep_clone();
+        let mut debug_compile = debug.corresponding(&compile).unwrap(); // [TC70]
+        debug_compile.push_arg("-g");
+        debug.corresponding(&strip).unwrap().push_arg("-x"); // [TC70]
+        debug.set_output_file(&FileArg::loc("out.debug"));
+        debug.push_op(&op("check"));
+        assert!(compile.args().is_empty()); // [TC69]
+        assert!(strip.args().is_empty()); // [TC69]
+        assert_eq!(release.len(), 3); // [TC69]
+        assert_eq!(release.corresponding(&debug_compile).map(|op| op.label()),
+                   Some("compile".into())); // [TC70]
+        assert!(release.corresponding(&debug.get(3).unwrap()).is_none()); // [TC70]
+        assert!(ChainedOps::new("other").corresponding(&compile).is_none()); // [TC70]
+
+        let xor = TestCollector::new();
+        release.execute_here(&xor)?;
+        let args = exec_args(xor);
+        assert_eq!(args.len(), 3);
+        assert!(args.iter().all(|(_, a)| a[0] != "-g" && a[0] != "-x")); // [TC69]
+        assert_eq!(args[2].1[..2], [OsString::from("-o"), OsString::from("out")]);
+        let xor = TestCollector::new();
+        debug.execute_here(&xor)?;
+        let args = exec_args(xor);
+        assert_eq!(args.iter().map(|(l, _)| l.clone()).collect::<Vec<_>>(),
+                   vec!["compile", "strip", "strip", "check"]); // [TC69]
+        assert_eq!(args[0].1[0], OsString::from("-g")); // [TC69]
+        assert_eq!(args[1].1[0], OsString::from("-x")); // [TC71]
+        assert_eq!(args[2].1[0], OsString::from("-x")); // [TC71]
+        assert_eq!(args[3].1[..2], [OsString::from("-o"), OsString::from("out.debug")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain_output_of() -> anyhow::Result<()> {
+        let cc = Executable::new(&"cc", ExeFileSpec::Append,
+                                 ExeFileSpec::option("-o"));
+        let mut ops = ChainedOps::new("output chain");
+        ops.set_input_file(&FileArg::loc("a.c"));
+        let compile_a = ops.push_op(SubProcOperation::new(&cc)
+                                    .set_label("compile a")
+                                    .push_arg("-c")
+                                    .set_output_file(&FileArg::loc("a.o")));
+        let compile_b = ops.push_op(SubProcOperation::new(&cc)
+                                    .set_label("compile b")
+                                    .push_arg("-c")
+                                    .set_input_file(&FileArg::loc("b.c"))
+                                    .set_output_file(&FileArg::loc("b.o")));
+        ops.push_op(SubProcOperation::new(&cc).set_label("link"))
+            .set_input_file(&FileArg::output_of(&compile_a))
+            .add_input_file(&FileArg::output_of(&compile_b))
+            .set_output_file(&FileArg::loc("prog"));
+
+        let xor = TestCollector::new();
+        ops.execute_here(&xor)?;
+        assert_eq!(exec_args(xor)[2].1,
+                   vec![OsString::from("-o"), "prog".into(), "a.o".into(),
+                        "b.o".into()]); // [TC72]
 
-        let ex = TestCollector::new();
-        let result = ops.execute_here(&ex);
-        match result {
-            Ok(ActualFile::SingleFile(FileRef::StaticFile(sf))) =>
-                assert_eq!(sf, PathBuf::from("real-out")), // [TC6]
-            _ => assert!(false,
-                         "Expected single static file 'final.out' but got {:?}",
-                         result),
-        };
-        let collected = ex.0.into_inner();
-        assert_eq!(collected.len(), 1); // [TC2]
+        let mut debug = ops.deep_clone();
+        debug.corresponding(&compile_a).unwrap()
+            .set_output_file(&FileArg::loc("a-debug.o"));
+        let xor = TestCollector::new();
+        debug.execute_here(&xor)?;
+        assert_eq!(exec_args(xor)[2].1[2..], ["a-debug.o", "b.o"]); // [TC74]
+        let xor = TestCollector::new();
+        ops.execute_here(&xor)?;
+        assert_eq!(exec_args(xor)[2].1[2..], ["a.o", "b.o"]); // [TC74]
 
-        assert_eq!(collected,
-                   vec![ TestOp::SPO(RunExec { name: "test-cmd".into(),
-                                               exe: "test-cmd".into(),
-                                               args: ["-b",
-                                                      "real-in", // [TC8]
-                                                      "real-out", // [TC6]
-                                                      // output file removed above
-                                               ].map(Into::<OsString>::into).to_vec(),
-                                               env: EnvSpec::StdEnv,
-                                               dir: None}), // [TC11]
-                   ]);
+        compile_b.clone().active(&Activation::Disabled);
+        let err = ops.execute_here(&TestCollector::new()).unwrap_err();
+        assert!(matches!(err.downcast_ref::<ChainsopError>(),
+                         Some(ChainsopError::ErrorMissingOutput(l))
+                         if l == "compile b")); // [TC73]
         Ok(())
     }
 
+    // Actually runs the commands to verify that a temporary output remains
+    // available to a later operation.
+    #[cfg(unix)]
+    #[test]
+    fn test_chain_output_of_temp() -> anyhow::Result<()> {
+        let workdir = tempfile::tempdir()?;
+        let mut ops = ChainedOps::new("output chain");
+        let echo = ops.push_op(SubProcOperation::new(&Executable::new(&"echo",
+                                                                      ExeFileSpec::NoFileUsed,
+                                                                      ExeFileSpec::Stdout))
+                               .push_arg("alpha")
+                               .set_output_file(&FileArg::temp(".echo")));
+        ops.push_op(SubProcOperation::new(&Executable::new(&"sed",
+                                                           ExeFileSpec::Append,
+                                                           ExeFileSpec::Stdout))
+                    .push_arg("s/alpha/beta/")
+                    .set_output_file(&FileArg::temp(".sed")));
+        let upcase = ops.push_op(SubProcOperation::new(&Executable::new(&"tr",
+                                                                        ExeFileSpec::Stdin,
+                                                                        ExeFileSpec::Stdout))
+                                 .push_arg("a-z").push_arg("A-Z")
+                                 .set_output_file(&FileArg::temp(".tr")));
+        ops.push_op(&SubProcOperation::new(&Executable::new(&"cat",
+                                                            ExeFileSpec::Append,
+                                                            ExeFileSpec::Stdout)))
+            .set_input_file(&FileArg::output_of(&echo))
+            .add_input_file(&FileArg::output_of(&upcase));
+        ops.set_output_file(&FileArg::loc("final.out"));
+
+        let report = ops.execute_with_report(&Executor::NormalRun, &Some(workdir.path()));
+        assert!(report.succeeded());
+        assert_eq!(std::fs::read_to_string(workdir.path().join("final.out"))?,
+                   "alpha\nBETA\n"); // [TC72]
+        assert!(! report.ops[0].output_files[0].exists()); // [TC72]
+        Ok(())
+    }
 
     #[test]
-    fn test_chain_op_settings() -> anyhow::Result<()> {
-        let mut ops = ChainedOps::new("test chain");
-        ops.set_dir("/ops/run/here");  // [TC20], [TC14]
-            // [TC10]
-        let exe = Executable::new(&"test-cmd",
-                                  ExeFileSpec::Append,
-                                  ExeFileSpec::Append);
-        ops.push_op(SubProcOperation::new(&exe)
-                    // Set the input file.  The chain should *not* override this
-                    // because the chain itself is not given an input file.  The
-                    // usual expectation is that the chain *will* be given an
-                    // input file, but this verifies the behavior of the unusual
-                    // scenario.
-                    .set_input_file(&FileArg::loc("inpfile.txt")) // [TC10]
-                    .set_output_file(&FileArg::temp(".out"))
-                    .push_arg("-a")
-                    .push_arg("a-arg-value")
-                    .push_arg("-b"));
-        ops.push_op(SubProcOperation::new(
-            &Executable::new(&"cmd2",
-                             ExeFileSpec::Option("--input".into()),
-                             ExeFileSpec::Append))
-                                  .set_output_file(&FileArg::temp(".o2"))
-                                  // Verify individual ops can change dirs
-                                  .set_dir("sub/dir") // [TC13]
-                                  .push_arg("-D")
-                                  .push_arg("DVAL")
-        );
-        ops.push_call(&FunctionOperation::calling(
-            "fop",
-            |_in_dir, _inpfiles, _outfile| todo!("not called during test")))
-            .set_output_file(&FileArg::loc("fop.done"));
-        ops.push_call(&FunctionOperation::calling(
-            "flop",
-            |_in_dir, _inpfiles, _outfile| todo!("not called during test")))
-            .set_output_file(&FileArg::loc("flop.done"));
-        ops.push_op(SubProcOperation::new(
-            &Executable::new(&"cmdexe3",
-                             ExeFileSpec::Option("--input".into()),
-                             ExeFileSpec::Append))
-                                  .set_output_file(&FileArg::loc("final.out")) // [TC19]
-                                  // Verify absolute dir override works
-                                  .set_dir("/abs/dir") // [TC14]
-        );
+    fn test_chain_for_each() -> anyhow::Result<()> {
+        let cc = Executable::new(&"cc", ExeFileSpec::Append,
+                                 ExeFileSpec::option("-o"));
+        let mut compile = ChainedOps::new("compile each");
+        compile.set_mode(&ChainMode::ForEach(EachOutput::Extension("o".into())));
+        compile.set_input_file(&FileArg::loc("src/a.c"));
+        compile.add_input_file(&FileArg::loc("src/b.c"));
+        compile.push_op(SubProcOperation::new(&cc).set_label("compile").push_arg("-c"));
+        let mut ops = ChainedOps::new("build");
+        ops.push_chain(&compile)?;
+        ops.push_op(SubProcOperation::new(&cc).set_label("link"));
+        ops.set_output_file(&FileArg::loc("prog"));
+        let xor = TestCollector::new();
+        ops.execute_here(&xor)?;
+        let argstrs = |args: &Vec<OsString>| args.iter()
+            .map(|a| a.to_string_lossy().to_string()).collect::<Vec<_>>();
+        let args = exec_args(xor);
+        assert_eq!(argstrs(&args[0].1), ["-c", "-o", "src/a.o", "src/a.c"]); // [TC75]
+        assert_eq!(argstrs(&args[1].1), ["-c", "-o", "src/b.o", "src/b.c"]); // [TC75]
+        assert_eq!(argstrs(&args[2].1), ["-o", "prog", "src/a.o", "src/b.o"]); // [TC76]
+        assert!(compile.get(0).unwrap().input_files().is_empty()); // [TC81]
+        assert_eq!(compile.get(0).unwrap().output_file(), FileArg::TBD); // [TC81]
 
-        // No chain-level input or output files were set, so the individual
-        // operation's settings should apply.  [TC10]
+        // Each instance can be a sequence of operations, with separate temporary
+        // files for each instance.
+        compile.set_mode(&ChainMode::ForEach(EachOutput::ExtensionIn("obj".into(),
+                                                                     "o".into())));
+        compile.get(0).unwrap().set_output_file(&FileArg::temp(".s"));
+        compile.push_op(SubProcOperation::new(&cc).set_label("assemble"));
+        let xor = TestCollector::new();
+        ops.execute_here(&xor)?;
+        let args = exec_args(xor);
+        assert_eq!(args.iter().map(|(l, _)| l.as_str()).collect::<Vec<_>>(),
+                   ["compile", "assemble", "compile", "assemble", "link"]); // [TC75]
+        assert_ne!(args[0].1[2], args[2].1[2]); // [TC75]
+        assert_eq!(argstrs(&args[1].1), vec!["-o".to_string(), "obj/a.o".into(),
+                                             args[0].1[2].to_string_lossy().into()]); // [TC75]
+        assert_eq!(argstrs(&args[4].1), ["-o", "prog", "obj/a.o", "obj/b.o"]); // [TC76]
 
-        let ex = TestCollector::new();
-        let result = ops.execute(&ex, &Some("target/loc"));
-        match result {
-            Ok(actual) => {
-                match actual {
-                    ActualFile::SingleFile(FileRef::StaticFile(ref sf)) =>
-                        assert_eq!(sf, &PathBuf::from("final.out")),
-                    _ => assert!(false,
-                                 "Expected single static file but got {:?}",
-                                 actual),
-                };
-                assert_eq!(PathBuf::from("/abs/dir/final.out"),
-                           actual.to_path(&Some("/abs/dir"))?)
-            },
-            Err(e) => assert!(false, "Err result: {:?}", e),
-        };
+        // The mode applies at the start of each execution.
+        compile.set_mode(&ChainMode::ForEach(EachOutput::AsSpecified));
+        compile.get(1).unwrap().set_output_file(&FileArg::temp(".o"));
+        let xor = TestCollector::new();
+        ops.execute_here(&xor)?;
+        let args = exec_args(xor);
+        assert!(args[4].1[2].to_string_lossy().ends_with(".o")); // [TC75]
+        assert_ne!(args[4].1[2], args[4].1[3]); // [TC76]
 
-        let mut collected = ex.0.into_inner();
+        compile.add_input_file(&FileArg::TBD);
+        let err = ops.execute_here(&TestCollector::new()).unwrap_err();
+        assert!(matches!(err.root_cause().downcast_ref::<ChainsopError>(),
+                         Some(ChainsopError::ErrorMissingFile))); // [TC82]
+        Ok(())
+    }
 
-        // The last arg of the first op is an assigned output tempfile
-        let output0_tmpfile = match &mut collected[0] {
-            TestOp::SPO(re) => {
-                let outf = PathBuf::from(&re.args.last().unwrap());
-                assert!(!outf.exists(),
-                        "intermediate temp file #0 {:?} did not get cleaned up!",
-                        outf);
-                re.args.pop();
-                outf
-            }
-            TestOp::FO(_) => {
-                panic!("Expected first op to be a SubProcOperation");
-            }
-        };
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_chain_for_each_async() -> anyhow::Result<()> {
+        let cc = Executable::new(&"cc", ExeFileSpec::Append,
+                                 ExeFileSpec::option("-o"));
+        let mut compile = ChainedOps::new("compile each");
+        compile.set_mode(&ChainMode::ForEach(EachOutput::Extension("o".into())));
+        compile.set_input_file(&FileArg::loc("src/a.c"));
+        compile.add_input_file(&FileArg::loc("src/b.c"));
+        compile.push_op(SubProcOperation::new(&cc).set_label("compile").push_arg("-c"));
+        let mut ops = ChainedOps::new("build");
+        ops.push_chain(&compile)?;
+        ops.push_op(SubProcOperation::new(&cc).set_label("link"));
+        ops.set_output_file(&FileArg::loc("prog"));
 
+        let sync_xor = TestCollector::new();
+        ops.execute_here(&sync_xor)?;
+        let async_xor = TestCollector::new();
+        ops.execute_here_async(&async_xor).await?;
+        let collected = async_xor.ops.into_inner();
+        assert_eq!(collected.len(), 3);
+        assert_eq!(collected, sync_xor.ops.into_inner()); // [TC75] [TC76]
+        Ok(())
+    }
 
-        // The penultimate arg of the second op should be the input tempfile,
-        // which should be the output tempfile of the previous op
-        match &mut collected[1] {
-            TestOp::SPO(re) => {
-                let inpf = PathBuf::from(&re.args[re.args.len()-2]);
-                assert_eq!(inpf, output0_tmpfile);  // [TC3]
-            }
-            TestOp::FO(_) => {
-                panic!("Expected second op to be a SubProcOperation");
-            }
-        };
-        // The last arg of the second op is another assigned output tempfile
-        let output1_tmpfile = match &mut collected[1] {
-            TestOp::SPO(re) => {
-                let outf = PathBuf::from(&re.args.last().unwrap());
-                assert!(!outf.exists(),
-                        "intermediate temp file #1 {:?} did not get cleaned up!",
-                        outf);
-                re.args.pop();  // remove output file
-                re.args.pop();  // remove input file
-                outf
-            }
-            TestOp::FO(_) => {
-                panic!("Expected second op to be a SubProcOperation");
-            }
-        };
+    #[test]
+    fn test_chain_for_each_policy() -> anyhow::Result<()> {
+        let cc = Executable::new(&"cc", ExeFileSpec::Append,
+                                 ExeFileSpec::option("-o"));
+        let mut compile = ChainedOps::new("compile each");
+        compile.set_mode(&ChainMode::ForEach(EachOutput::Extension("o".into())));
+        compile.set_input_file(&FileArg::loc("src/a.c"));
+        compile.add_input_file(&FileArg::loc("src/b.c"));
+        compile.add_input_file(&FileArg::loc("src/c.c"));
+        compile.push_op(SubProcOperation::new(&cc).set_label("compile").push_arg("-c"));
+        compile.set_policy(&ChainPolicy::ContinueOnError);
+        let xor = TestCollector::failing(&[("compile", 2)], 1);
+        let err = compile.execute_here(&xor).unwrap_err();
+        assert_eq!(failed_ops_of(&err),
+                   vec![(0, "compile".into()), (0, "compile".into())]); // [TC92]
+        assert_eq!(xor.runs(),
+                   vec!["compile", "compile", "compile"]); // [TC92]
+        let msg = err.to_string();
+        assert!(msg.contains("src/a.c") && msg.contains("src/b.c")); // [TC92]
+        assert!(!msg.contains("src/c.c")); // [TC92]
 
-        // The output file of the fifth (and last) op should be as specified by
-        // the individual op.
-        match &mut collected[4] {
-            TestOp::SPO(re) => {
-                let outf = PathBuf::from(&re.args.last().unwrap());
-                assert_eq!(outf, PathBuf::from("final.out"));  // [TC10]
-                assert!(!outf.exists(),
-                        "intermediate temp file #1 {:?} did not get cleaned up!",
-                        outf);
-                outf
-            }
-            TestOp::FO(_) => {
-                panic!("Expected fifth op to be a SubProcOperation");
-            }
-        };
+        compile.set_policy(&ChainPolicy::ContinueOptional);
+        let xor = TestCollector::failing(&[("compile", 1)], 1);
+        let err = compile.execute_here(&xor).unwrap_err();
+        assert_eq!(failed_ops_of(&err), vec![(0, "compile".into())]); // [TC92]
+        assert_eq!(xor.runs(), vec!["compile"]); // [TC92]
 
-        assert_eq!(
-            collected, vec!
-                [
-                    TestOp::SPO(RunExec { name: "test-cmd".into(),
-                                          exe: "test-cmd".into(),
-                                          args: ["-a",
-                                                 "a-arg-value",
-                                                 "-b",
-                                                 "inpfile.txt",  // [TC10]
-                                                 // output file removed above
-                                          ].map(Into::<OsString>::into).to_vec(),
-                                          env: EnvSpec::StdEnv,
-                                          dir: Some(PathBuf::from("/ops/run/here"))}), // [TC20]
-                    TestOp::SPO(RunExec { name: "cmd2".into(),
-                                          exe: "cmd2".into(),
-                                          args: ["-D",
-                                                 "DVAL",
-                                                 "--input",
-                                                 // input file removed above
-                                                 // output file removed above
-                                          ].map(Into::<OsString>::into).to_vec(),
-                                          env: EnvSpec::StdEnv,
-                                          dir: Some(PathBuf::from("/ops/run/here/sub/dir"))}), // [TC22]
-                    TestOp::FO(RunFunc { fname: "fop".to_string(),
-                                         inpfiles: vec![
-                                             output1_tmpfile
-                                         ],
-                                         outfile: Some(PathBuf::from("fop.done")),
-                                         dir: Some("/ops/run/here".into()) }), // [TC21]
-                    TestOp::FO(RunFunc { fname: "flop".to_string(),
-                                         inpfiles: vec![
-                                             "fop.done".into(), // [TC20]
-                                         ],
-                                         outfile: Some(PathBuf::from("flop.done")),
-                                         dir: Some("/ops/run/here".into()) }), // [TC21]
-                    TestOp::SPO(RunExec { name: "cmdexe3".into(),
-                                          exe: "cmdexe3".into(),
-                                          args: ["--input",
-                                                 "flop.done",   // [TC20]
-                                                 "final.out",   // [TC10]
-                                          ].map(Into::<OsString>::into).to_vec(),
-                                          env: EnvSpec::StdEnv,
-                                          dir: Some(PathBuf::from("/abs/dir"))}),
-                ]);
+        compile.get(0).unwrap().set_optional(true);
+        let xor = TestCollector::failing(&[("compile", 1)], 1);
+        let err = compile.execute_here(&xor).unwrap_err();
+        assert_eq!(failed_ops_of(&err), vec![(0, "compile".into())]); // [TC92]
+        assert_eq!(xor.runs(),
+                   vec!["compile", "compile", "compile"]); // [TC92]
+
+        compile.set_policy(&ChainPolicy::FailFast);
+        let xor = TestCollector::failing(&[("compile", 1)], 1);
+        let err = compile.execute_here(&xor).unwrap_err();
+        assert!(format!("{:#}", err).contains("src/a.c")); // [TC77]
+        assert_eq!(xor.runs(), vec!["compile"]); // [TC77]
         Ok(())
     }
 
+    // Actually runs the commands for each file matched by a glob.
+    #[cfg(unix)]
     #[test]
-    fn test_chain_reldirs() -> anyhow::Result<()> {
-        let mut ops = ChainedOps::new("test chain single");
-        ops.set_dir("here");
+    fn test_chain_for_each_glob() -> anyhow::Result<()> {
+        let workdir = tempfile::tempdir()?;
+        std::fs::write(workdir.path().join("one.txt"), "pear\n")?;
+        std::fs::write(workdir.path().join("two.txt"), "fig\n")?;
+        let mut upcase = ChainedOps::new("upcase each");
+        upcase.set_mode(&ChainMode::ForEach(EachOutput::Extension("up".into())));
+        upcase.set_input_file(&FileArg::glob_in(workdir.path(), "*.txt"));
+        upcase.push_op(SubProcOperation::new(&Executable::new(&"tr",
+                                                              ExeFileSpec::Stdin,
+                                                              ExeFileSpec::Stdout))
+                       .push_arg("a-z").push_arg("A-Z"));
+        let mut ops = ChainedOps::new("glob chain");
+        ops.push_chain(&upcase)?;
+        ops.push_op(&SubProcOperation::new(&Executable::new(&"cat",
+                                                            ExeFileSpec::Append,
+                                                            ExeFileSpec::Stdout)));
+        ops.set_output_file(&FileArg::loc("final.out"));
 
-        let exe = Executable::new(&"test-cmd",
-                                  ExeFileSpec::Append,
-                                  ExeFileSpec::Append);
-        ops.push_op(SubProcOperation::new(&exe)  // [TC2]
-                    // Set the input file.  The chain should *not* override this
-                    // because the chain itself is not given an input file.  The
-                    // usual expectation is that the chain *will* be given an
-                    // input file, but this verifies the behavior of the unusual
-                    // scenario.
-                    .set_input_file(&FileArg::loc("override-in"))
-                    .set_output_file(&FileArg::loc("override-out"))
-                    .push_arg("-b"));
-        ops.set_input_file(&FileArg::loc("real-in"));  // [TC8]
-        ops.set_output_file(&FileArg::loc("real-out")); // [TC6]
+        ops.execute(&Executor::NormalRun, &Some(workdir.path()))?;
+        assert_eq!(std::fs::read_to_string(workdir.path().join("one.up"))?,
+                   "PEAR\n"); // [TC75]
+        assert_eq!(std::fs::read_to_string(workdir.path().join("final.out"))?,
+                   "PEAR\nFIG\n"); // [TC76]
 
-        let ex = TestCollector::new();
-        let result = ops.execute_here(&ex);
-        match result {
-            Ok(ActualFile::SingleFile(FileRef::StaticFile(sf))) =>
-                assert_eq!(sf, PathBuf::from("real-out")), // [TC6]
-            _ => assert!(false,
-                         "Expected single static file 'final.out' but got {:?}",
-                         result),
+        upcase.add_input_file(&FileArg::loc(workdir.path().join("missing.txt")));
+        let err = ops.execute(&Executor::NormalRun, &Some(workdir.path())).unwrap_err();
+        assert!(format!("{:#}", err).contains("missing.txt")); // [TC77]
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain_checkpoint() -> anyhow::Result<()> {
+        let workdir = tempfile::tempdir()?;
+        let ckpt = workdir.path().join("chain.ckpt");
+        let exe = Executable::new(&"cc", ExeFileSpec::Append,
+                                  ExeFileSpec::option("-o"));
+        let mk_chain = || {
+            let mut ops = ChainedOps::new("checkpoint chain");
+            ops.set_checkpoint(&ckpt);
+            let compile = ops.push_op(SubProcOperation::new(&exe)
+                                      .set_label("compile")
+                                      .set_input_file(&FileArg::loc("foo.c"))
+                                      .set_output_file(&FileArg::temp(".o")));
+            ops.push_op(SubProcOperation::new(&exe)
+                        .set_label("link")
+                        .set_output_file(&FileArg::temp(".exe")));
+            ops.push_op(SubProcOperation::new(&exe)
+                        .set_label("package")
+                        .set_output_file(&FileArg::loc("foo.pkg")));
+            (ops, compile)
         };
-        let collected = ex.0.into_inner();
-        assert_eq!(collected.len(), 1); // [TC2]
+        let (mut ops, _) = mk_chain();
 
-        assert_eq!(collected,
-                   vec![ TestOp::SPO(RunExec { name: "test-cmd".into(),
-                                               exe: "test-cmd".into(),
-                                               args: ["-b",
-                                                      "real-in", // [TC8]
-                                                      "real-out", // [TC6]
-                                                      // output file removed above
-                                               ].map(Into::<OsString>::into).to_vec(),
-                                               env: EnvSpec::StdEnv,
-                                               dir: Some("here".into())}), // [TC13]
-                   ]);
+        let xor = TestCollector::failing(&[("package", 1)], 1);
+        assert!(ops.execute_here(&xor).is_err());
+        assert!(ckpt.exists()); // [TC61]
+
+        // A deep clone does not share (and therefore does not resume from or
+        // remove) the checkpoint of the original.
+        let mut variant = ops.deep_clone();
+        let xor = TestCollector::new();
+        variant.execute_resume(&xor, &None::<PathBuf>)?;
+        assert_eq!(exec_args(xor).len(), 3); // [TC88]
+        assert!(ckpt.exists()); // [TC88]
+
+        // A new definition of the same chain (e.g. in a later process) resumes
+        // after the last successful operation, using its persisted output.
+        let (mut ops, _) = mk_chain();
+        let xor = TestCollector::new();
+        ops.execute_resume(&xor, &None::<PathBuf>)?;
+        let args = exec_args(xor);
+        assert_eq!(args.len(), 1); // [TC62]
+        assert_eq!(args[0].0, "package"); // [TC62]
+        assert_eq!(args[0].1[..2], [OsString::from("-o"), OsString::from("foo.pkg")]);
+        assert!(Path::new(&args[0].1[2])
+                .starts_with(workdir.path().join("chain.ckpt.files"))); // [TC62]
+        assert!(! ckpt.exists()); // [TC61]
+
+        // Without a checkpoint, the entire chain is performed.
+        let xor = TestCollector::new();
+        ops.execute_resume(&xor, &None::<PathBuf>)?;
+        assert_eq!(exec_args(xor).len(), 3);
+
+        let xor = TestCollector::failing(&[("link", 1)], 1);
+        assert!(ops.execute_here(&xor).is_err());
+        let (mut ops, mut compile) = mk_chain();
+        compile.push_arg("-O2");
+        let xor = TestCollector::new();
+        match ops.execute_resume(&xor, &None::<PathBuf>) {
+            Err(e) => match e.downcast_ref::<ChainsopError>() {
+                Some(ChainsopError::ErrorCheckpoint(_, p, reason)) => {
+                    assert_eq!(p, &ckpt); // [TC63]
+                    assert_eq!(reason, "the chain definition has changed"); // [TC63]
+                }
+                _ => panic!("Unexpected error: {:?}", e),
+            },
+            Ok(_) => panic!("Expected failure"),
+        }
+        assert!(exec_args(xor).is_empty()); // [TC63]
+
+        // A normal execution replaces the checkpoint.
+        let xor = TestCollector::new();
+        ops.execute_here(&xor)?;
+        assert_eq!(exec_args(xor).len(), 3);
+        assert!(! ckpt.exists());
         Ok(())
     }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_chain_finally_async() {
+        let lint = Executable::new(&"lint", ExeFileSpec::Append,
+                                   ExeFileSpec::NoFileUsed);
+        let mut ops = ChainedOps::new("policy chain");
+        for label in ["a", "b", "c", "d"] {
+            ops.push_op(SubProcOperation::new(&lint)
+                        .set_label(label)
+                        .set_input_file(&FileArg::loc(format!("{}.src", label))));
+        }
+        ops.push_finally_call(&FunctionOperation::calling("collect", test_callee));
+        for mode in [ChainMode::Sequential, ChainMode::Dag(1)] {
+            ops.set_mode(&mode);
+            let xor = TestCollector::failing(&[("c", 9), ("collect", 9)], 1);
+            let err = ops.execute_here_async(&xor).await.unwrap_err();
+            assert_eq!(xor.runs(),
+                       vec!["a", "b", "c", "collect"]); // [TC52]
+            let (chain_err, failures) = finally_failures_of(&err);
+            assert!(chain_err.is_some()); // [TC52]
+            assert_eq!(failures, vec![(4, "collect".into())]); // [TC52]
+        }
+    }
 }
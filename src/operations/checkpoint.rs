@@ -0,0 +1,202 @@
+// Checkpoint files for resuming a failed chain execution.
+//
+// When a checkpoint is specified for a ChainedOps (see
+// ChainedOps::set_checkpoint), a checkpoint file is written after each
+// successful operation of a sequential chain, recording the index of the
+// operation and its output file(s).  Temporary output files are copied to a
+// directory alongside the checkpoint file (the checkpoint path with a ".files"
+// suffix) so that they remain available to a later execution.  The checkpoint
+// also records a fingerprint of the chain definition, which must match for the
+// checkpoint to be used to resume the chain.
+//
+// The file is a simple line-oriented text file:
+//
+//     chainsop checkpoint
+//     fingerprint <hex>
+//     completed <index>
+//     output <path>
+//     ...
+
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::filehandling::defs::{ActualFile, FileRef};
+use crate::shared::SharedRef;
+#[cfg(feature = "sync")]
+use crate::shared::SharedBorrow;
+
+
+const HEADER : &str = "chainsop checkpoint";
+
+/// The state recorded by a checkpoint file.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Checkpoint {
+    pub(crate) fingerprint : String,
+    pub(crate) completed : usize,
+    pub(crate) outputs : Vec<PathBuf>,
+}
+
+impl Checkpoint {
+
+    /// Reads the checkpoint file, returning None if it does not exist.
+    pub(crate) fn load(path: &Path) -> anyhow::Result<Option<Checkpoint>>
+    {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let bad = || anyhow::anyhow!("invalid checkpoint file");
+        let mut lines = text.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(bad());
+        }
+        let fingerprint = lines.next()
+            .and_then(|l| l.strip_prefix("fingerprint "))
+            .ok_or_else(bad)?
+            .to_string();
+        let completed = lines.next()
+            .and_then(|l| l.strip_prefix("completed "))
+            .and_then(|n| n.parse().ok())
+            .ok_or_else(bad)?;
+        let outputs = lines
+            .map(|l| l.strip_prefix("output ").map(PathBuf::from).ok_or_else(bad))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Some(Checkpoint { fingerprint, completed, outputs }))
+    }
+
+    /// Writes the checkpoint file for the completed operation with the
+    /// specified output, copying any temporary output files to the checkpoint's
+    /// files directory.  Checkpoints are an aid for recovering from failures, so
+    /// this is best-effort: returns false (leaving any previous checkpoint in
+    /// place) if the checkpoint could not be written.
+    pub(crate) fn save(path: &Path, fingerprint: &str, completed: usize,
+                       outfile: &ActualFile) -> bool
+    {
+        let filesdir = files_dir(path);
+        let frefs : Vec<&FileRef> = match outfile {
+            ActualFile::NoActualFile => Vec::new(),
+            ActualFile::SingleFile(fref) => vec![fref],
+            ActualFile::MultiFile(frefs) => frefs.iter().collect(),
+        };
+        let mut outputs = Vec::new();
+        for (n, fref) in frefs.into_iter().enumerate() {
+            match fref {
+                FileRef::StaticFile(p) => outputs.push(p.clone()),
+                FileRef::TempFile(tf) => {
+                    let tf : SharedRef<_> = tf.borrow();
+                    let src = tf.path();
+                    let mut name = OsString::from(format!("{}-{}-", completed, n));
+                    name.push(src.file_name().unwrap_or_default());
+                    let dst = filesdir.join(name);
+                    if fs::create_dir_all(&filesdir).is_err()
+                        || fs::copy(src, &dst).is_err() {
+                            return false;
+                        }
+                    match std::path::absolute(&dst) {
+                        Ok(dst) => outputs.push(dst),
+                        Err(_) => return false,
+                    }
+                }
+            }
+        }
+
+        let mut text = format!("{}\nfingerprint {}\ncompleted {}\n",
+                               HEADER, fingerprint, completed);
+        for p in &outputs {
+            match p.to_str() {
+                Some(p) if ! p.contains('\n') => {
+                    text.push_str("output ");
+                    text.push_str(p);
+                    text.push('\n');
+                }
+                _ => return false,
+            }
+        }
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        if fs::write(&partial, text).is_err() || fs::rename(&partial, path).is_err() {
+            let _ = fs::remove_file(&partial);
+            return false;
+        }
+
+        // Remove the files persisted for any earlier checkpoint.
+        if let Ok(entries) = fs::read_dir(&filesdir) {
+            for entry in entries.filter_map(|e| e.ok()) {
+                if ! outputs.iter().any(|o| o.file_name() == Some(&entry.file_name())) {
+                    let _ = fs::remove_file(entry.path());
+                }
+            }
+        }
+        true
+    }
+
+    /// Removes the checkpoint file and any persisted files.
+    pub(crate) fn remove(path: &Path)
+    {
+        let _ = fs::remove_file(path);
+        let _ = fs::remove_dir_all(files_dir(path));
+    }
+
+    /// Returns the output of the completed operation.
+    pub(crate) fn output(&self) -> ActualFile
+    {
+        let mut frefs : Vec<FileRef> = self.outputs.iter()
+            .map(|p| FileRef::StaticFile(p.clone()))
+            .collect();
+        match frefs.len() {
+            0 => ActualFile::NoActualFile,
+            1 => ActualFile::SingleFile(frefs.pop().unwrap()),
+            _ => ActualFile::MultiFile(frefs),
+        }
+    }
+}
+
+fn files_dir(path: &Path) -> PathBuf
+{
+    let mut dir = path.as_os_str().to_owned();
+    dir.push(".files");
+    PathBuf::from(dir)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::shared::{Shared, SharedCell};
+
+    #[test]
+    fn test_checkpoint_save_load() -> anyhow::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let path = tmp.path().join("chain.ckpt");
+        assert_eq!(Checkpoint::load(&path)?, None);
+
+        let temp = tempfile::NamedTempFile::new()?;
+        fs::write(temp.path(), "intermediate")?;
+        let outfile = ActualFile::MultiFile(vec![
+            FileRef::StaticFile("a b.o".into()),
+            FileRef::TempFile(Shared::new(SharedCell::new(temp))),
+        ]);
+        assert!(Checkpoint::save(&path, "f00d", 3, &outfile));
+        let ckpt = Checkpoint::load(&path)?.unwrap();
+        assert_eq!(ckpt.fingerprint, "f00d");
+        assert_eq!(ckpt.completed, 3);
+        assert_eq!(ckpt.outputs[0], PathBuf::from("a b.o"));
+        // The temporary file is persisted and outlives the original.
+        drop(outfile);
+        assert!(ckpt.outputs[1].starts_with(files_dir(&path)));
+        assert_eq!(fs::read_to_string(&ckpt.outputs[1])?, "intermediate");
+
+        assert!(Checkpoint::save(&path, "f00d", 4, &ActualFile::NoActualFile));
+        assert_eq!(Checkpoint::load(&path)?.unwrap().outputs, Vec::<PathBuf>::new());
+        assert!(! ckpt.outputs[1].exists());
+
+        Checkpoint::remove(&path);
+        assert_eq!(Checkpoint::load(&path)?, None);
+        fs::write(&path, "something else")?;
+        assert!(Checkpoint::load(&path).is_err());
+        Ok(())
+    }
+}
@@ -1,11 +1,14 @@
 use std::path::{Path,PathBuf};
-use std::rc::Rc;
 use filesprep_derive::*;
 
 use crate::filehandling::*;
 use crate::errors::*;
 use crate::operations::generic::*;
-use crate::execution::{OsRun,OsRunResult::*};
+use crate::execution::{OsRun,OsRunResult,OsRunResult::*,OpFunction};
+#[cfg(feature = "async")]
+use crate::execution::AsyncOsRun;
+use crate::retry::{Attempts,RetryPolicy};
+use crate::shared::{Shared,Shareable};
 
 
 /// This structure represents a single command that is performed via a local code
@@ -28,10 +31,11 @@ use crate::execution::{OsRun,OsRunResult::*};
 #[derive(Clone,FilesTransformationPrep)]
 pub struct FunctionOperation {
     name : String,  // for informational purposes only
-    call : Rc<dyn Fn(&Path, &ActualFile, &ActualFile) -> anyhow::Result<()>>,
+    call : OpFunction,
                // n.b. Would prefer this to be an FnOnce, but that breaks move
                // semantics when trying to call it while it's a part of an
                // enclosing Enum.
+    retry : RetryPolicy,
     files : FileTransformation,
 }
 
@@ -58,34 +62,81 @@ impl FunctionOperation {
     /// the next stage. This might initially seem awkward, but makes sense when
     /// you consider that most operations are executions in subprocesses that are
     /// in a separate address space already.
+    ///
+    /// When the "sync" feature is enabled, the function must be `Send + Sync`.
+    /// When used in a [crate::ChainedOps], the function must not access that
+    /// chain: this panics, or deadlocks when the "sync" feature is enabled.
     pub fn calling<T>(n: &str, f: T) -> FunctionOperation
-    where T: Fn(&Path, &ActualFile, &ActualFile) -> anyhow::Result<()> + 'static
+    where T: Fn(&Path, &ActualFile, &ActualFile) -> anyhow::Result<()> + Shareable + 'static
     {
         FunctionOperation {
             name : n.to_string(),
-            call : Rc::new(f),
+            call : Shared::new(f),
+            retry : RetryPolicy::default(),
             files : FileTransformation::new(),
         }
     }
 
-    fn run_with_files<Exec, P>(&self,
-                               executor: &Exec,
-                               cwd: &Option<P>,
-                               inpfiles: ActualFile,
-                               outfile: ActualFile)
-                               -> anyhow::Result<ActualFile>
-    where P: AsRef<Path>, Exec: OsRun
+    /// Specifies the retry policy for this operation: if the function call
+    /// fails in one of the manners identified by the policy, it is called again
+    /// (up to the maximum number of attempts).  If all of the attempts fail, the
+    /// error reports the failure of each attempt.
+    pub fn set_retry(&mut self, policy: &RetryPolicy) -> &mut Self
+    {
+        self.retry = policy.clone();
+        self
+    }
+
+    /// Returns the file specifications for this operation.
+    pub(crate) fn files(&self) -> &FileTransformation
+    {
+        &self.files
+    }
+
+    /// Replaces the input file specifications for this operation (which may
+    /// leave it with no input files).
+    pub(crate) fn set_input_files(&mut self, fnames: &[FileArg])
+    {
+        self.files.inp_filenames = fnames.to_vec();
+    }
+
+    fn setup_files<Exec>(&self, executor: &Exec)
+                         -> anyhow::Result<(ActualFile, ActualFile)>
+    where Exec: OsRun
+    {
+        let inpfiles =
+            self.files.inp_filenames.iter().try_fold(
+                ActualFile::NoActualFile,
+                |dfs, inpf|
+                setup_file(executor, inpf,
+                           || Ok(ActualFile::NoActualFile)
+                ).and_then(|df| Ok(dfs.extend(df)))
+        )?;
+        let outfile = setup_file(executor, &self.files.out_filename,
+                                 || Ok(ActualFile::NoActualFile),
+        )?;
+        Ok((inpfiles, outfile))
+    }
+
+    fn run_dir<P>(&self, cwd: &Option<P>) -> Option<PathBuf>
+    where P: AsRef<Path>
     {
-        let fromdir: Option<PathBuf> =
-            match cwd {
-                Some(root) => match &self.files.in_dir {
-                    Some(sub) => Some(root.as_ref().to_path_buf().join(sub)),
-                    None => Some(root.as_ref().to_path_buf()),
-                },
-                None => self.files.in_dir.clone(),
-            };
-        match executor.run_function(self.name.as_str(), &self.call,
-                                    &inpfiles, &outfile, &fromdir) {
+        match cwd {
+            Some(root) => match &self.files.in_dir {
+                Some(sub) => Some(root.as_ref().to_path_buf().join(sub)),
+                None => Some(root.as_ref().to_path_buf()),
+            },
+            None => self.files.in_dir.clone(),
+        }
+    }
+
+    fn call_result(&self,
+                   result: OsRunResult,
+                   outfile: ActualFile,
+                   fromdir: Option<PathBuf>)
+                   -> anyhow::Result<ActualFile>
+    {
+        match result {
             Good => Ok(outfile),
             ExecFailed(e) =>
                 Err(anyhow::Error::new(
@@ -106,6 +157,11 @@ impl FunctionOperation {
                 Err(anyhow::Error::new(
                     ChainsopError::ErrorBadDirectory(
                         format!("{:?}", self), p, e))),
+            TimedOut(t,s) =>
+                Err(anyhow::Error::new(
+                    ChainsopError::ErrorTimedOut(
+                        format!("{:?}", self), Vec::new(),
+                        t, fromdir, s))),
         }
     }
 }
@@ -123,18 +179,54 @@ impl OpInterface for FunctionOperation {
                         -> anyhow::Result<ActualFile>
     where P: AsRef<Path>, Exec: OsRun
     {
-        let inpfiles =
-            self.files.inp_filenames.iter().try_fold(
-                ActualFile::NoActualFile,
-                |dfs, inpf|
-                setup_file(executor, inpf,
-                           || Ok(ActualFile::NoActualFile)
-                ).and_then(|df| Ok(dfs.extend(df)))
-        )?;
-        let outfile = setup_file(executor, &self.files.out_filename,
-                                 || Ok(ActualFile::NoActualFile),
-        )?;
-        self.run_with_files(executor, cwd, inpfiles, outfile)
+        let (inpfiles, outfile) = self.setup_files(executor)?;
+        let fromdir = self.run_dir(cwd);
+        let mut attempts = Attempts::new(&self.retry);
+        loop {
+            let result = executor.run_function(self.name.as_str(), &self.call,
+                                               &inpfiles, &outfile, &fromdir);
+            match attempts.retry_delay(&result) {
+                Some(delay) => {
+                    attempts.failed(self.call_result(result,
+                                                     ActualFile::NoActualFile,
+                                                     fromdir.clone()));
+                    executor.delay(delay);
+                }
+                None => return attempts.finish(
+                    &self.name,
+                    self.call_result(result, outfile, fromdir)),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl AsyncOpInterface for FunctionOperation {
+
+    // The local function is called synchronously: there is no executable to
+    // await, but the delay between retry attempts is awaited.
+    async fn execute_async<Exec, P>(&mut self, executor: &Exec, cwd: &Option<P>)
+                                    -> anyhow::Result<ActualFile>
+    where P: AsRef<Path>, Exec: AsyncOsRun
+    {
+        let (inpfiles, outfile) = self.setup_files(executor)?;
+        let fromdir = self.run_dir(cwd);
+        let mut attempts = Attempts::new(&self.retry);
+        loop {
+            let result = executor.run_function(self.name.as_str(), &self.call,
+                                               &inpfiles, &outfile, &fromdir);
+            match attempts.retry_delay(&result) {
+                Some(delay) => {
+                    attempts.failed(self.call_result(result,
+                                                     ActualFile::NoActualFile,
+                                                     fromdir.clone()));
+                    executor.delay_async(delay).await;
+                }
+                None => return attempts.finish(
+                    &self.name,
+                    self.call_result(result, outfile, fromdir)),
+            }
+        }
     }
 }
 
@@ -148,8 +240,9 @@ mod tests {
 
     use super::*;
     use crate::execution::*;
+    #[cfg(feature = "sync")]
+    use crate::shared::SharedBorrow;
     use std::cell::RefCell;
-    use std::rc::Rc;
     use std::ffi::OsString;
 
     #[derive(Clone, Debug, PartialEq)]
@@ -173,6 +266,7 @@ mod tests {
                           exe_file: &Path,
                           _args: &Vec<OsString>,
                           _exe_env: &EnvSpec,
+                          _exe_opts: &ExecOptions,
                           _fromdir: &Option<PathBuf>) -> OsRunResult
         {
             RunError(anyhow::anyhow!("run_executable {:?}: {:?} not implemented for CallCollector",
@@ -180,7 +274,7 @@ mod tests {
         }
         fn run_function(&self,
                         name : &str,
-                        _call : &Rc<dyn Fn(&Path, &ActualFile, &ActualFile) -> anyhow::Result<()>>,
+                        _call : &OpFunction,
                         inpfiles: &ActualFile,
                         outfile: &ActualFile,
                         fromdir: &Option<PathBuf>) -> OsRunResult
@@ -1,7 +1,11 @@
+#[cfg(feature = "async")]
+use std::future::Future;
 use std::path::{Path, PathBuf};
 
 use crate::filehandling::defs::{ActualFile};
 use crate::execution::OsRun;
+#[cfg(feature = "async")]
+use crate::execution::AsyncOsRun;
 
 
 /// Defines the interface for an Operation that can be performed (where an
@@ -87,3 +91,33 @@ pub trait OpInterface {
         self.execute(executor, &None::<PathBuf>)
     }
 }
+
+
+/// Defines the asynchronous execution interface for an Operation.  This is the
+/// async counterpart of the [OpInterface::execute] method, where any
+/// executables are run via the [AsyncOsRun] trait instead of blocking the
+/// current thread.  This allows many operations (or chains of operations) to be
+/// performed concurrently within a single async runtime.
+///
+/// Note that local function operations ([crate::FunctionOperation]) are still
+/// called synchronously.  The operations in a [crate::ChainedOps] in
+/// [crate::ChainMode::Dag] mode are awaited concurrently, as described for
+/// the synchronous execution of that mode.
+#[cfg(feature = "async")]
+pub trait AsyncOpInterface: OpInterface {
+
+    /// Executes this operation in the specified directory, completing when the
+    /// operation has completed.  The handling of the cwd and the result are the
+    /// same as for [OpInterface::execute].
+    fn execute_async<Exec, P>(&mut self, executor: &Exec, cwd: &Option<P>)
+                              -> impl Future<Output = anyhow::Result<ActualFile>>
+        where P: AsRef<Path>, Exec: AsyncOsRun;
+
+    /// Convenience method to asynchronously execute an operation with a given
+    /// [crate::Executor] in the current directory.
+    fn execute_here_async(&mut self, executor: &impl AsyncOsRun)
+                          -> impl Future<Output = anyhow::Result<ActualFile>>
+    {
+        self.execute_async(executor, &None::<PathBuf>)
+    }
+}
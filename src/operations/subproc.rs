@@ -1,13 +1,19 @@
 use anyhow::Context;
 use std::ffi::{OsString};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use filesprep_derive::*;
 
 use crate::filehandling::*;
 use crate::executable::*;
 use crate::errors::*;
 use crate::operations::generic::*;
-use crate::execution::{OsRun, OsRunResult::*, EnvSpec};
+use crate::execution::{OsRun, OsRunResult, OsRunResult::*, OsRunHandle, EnvSpec,
+                       ExecOptions, PipeStage};
+use crate::retry::{Attempts, RetryPolicy};
+use crate::cache::{CacheEntry, OutputCache};
+#[cfg(feature = "async")]
+use crate::execution::AsyncOsRun;
 
 
 
@@ -21,10 +27,51 @@ pub struct SubProcOperation {
     name : String,
     exec : Executable,
     args : Vec<OsString>,
+    slot_args : Vec<(ArgPosition, OsString)>,
     env : EnvSpec,
+    timeout : Option<Duration>,
+    retry : RetryPolicy,
+    cache : Option<OutputCache>,
+    ok_exit_codes : Vec<i32>,
+    exit_code : Option<i32>,
+    command : Vec<OsString>,
     files : FileTransformation,
 }
 
+/// Specifies where an argument added by [SubProcOperation::push_arg_at] is
+/// placed in the command line relative to the base arguments of the
+/// [Executable] and the input and output file arguments.  The command line is
+/// assembled in the order:
+///
+///   1. [ArgPosition::Leading] arguments
+///   2. the base arguments of the [Executable], followed by the
+///      [ArgPosition::Standard] arguments
+///   3. the input file argument(s), followed by the [ArgPosition::AfterInputs]
+///      arguments
+///   4. the output file argument(s), followed by the
+///      [ArgPosition::AfterOutputs] arguments
+///
+/// Note that the output file is specified before the input files when the
+/// output file is provided via an option and the input files are appended (see
+/// [ExeFileSpec::Option]), in which case steps 3 and 4 are swapped.  An
+/// [ExeFileSpec::Pattern] is substituted in the arguments at any position.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArgPosition {
+    /// Before the base arguments of the [Executable] (e.g. a subcommand).
+    Leading,
+
+    /// After the base arguments of the [Executable]; this is where
+    /// [SubProcOperation::push_arg] places arguments.
+    Standard,
+
+    /// After the input file arguments (e.g. libraries following the object
+    /// files for a linker).
+    AfterInputs,
+
+    /// After the output file arguments.
+    AfterOutputs,
+}
+
 
 impl SubProcOperation {
 
@@ -39,7 +86,14 @@ impl SubProcOperation {
                     .unwrap_or("{an-exe}".to_string())),
             exec : executing.clone(),
             args : get_base_args(&executing).iter().map(|x| x.into()).collect(),
+            slot_args : Vec::new(),
             env : EnvSpec::StdEnv,
+            timeout : None,
+            retry : RetryPolicy::default(),
+            cache : None,
+            ok_exit_codes : get_ok_exit_codes(executing).clone(),
+            exit_code : None,
+            command : Vec::new(),
             files : FileTransformation::new(),
         }
     }
@@ -97,6 +151,163 @@ impl SubProcOperation {
         self
     }
 
+    /// Specifies the maximum amount of time that the command is allowed to run.
+    /// If the command has not completed within this time, it is killed (along
+    /// with any other processes in its process group) and the execution fails
+    /// with [ChainsopError::ErrorTimedOut].
+    ///
+    /// By default, there is no timeout (unless one is inherited from an
+    /// enclosing [crate::ChainedOps]).
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self
+    {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Specifies the retry policy for this operation: if the command fails in
+    /// one of the manners identified by the policy, it is run again (up to the
+    /// maximum number of attempts).  If all of the attempts fail, the error
+    /// reports the failure of each attempt.  The input and output files are
+    /// determined once for all of the attempts.
+    ///
+    /// Note that retries are not performed when the operation is part of a
+    /// pipeline (see [crate::ChainLink::Pipe]).
+    pub fn set_retry(&mut self, policy: &RetryPolicy) -> &mut Self
+    {
+        self.retry = policy.clone();
+        self
+    }
+
+    /// Specifies a cache for the output file of this operation: if the cache
+    /// has an entry for the same command, environment and input file contents,
+    /// the output file is restored from the cache instead of running the
+    /// command, otherwise the output file is stored in the cache after a
+    /// successful run.  See [OutputCache] for details.
+    ///
+    /// By default, there is no cache (unless one is inherited from an enclosing
+    /// [crate::ChainedOps]).  Note that operations that are part of a pipeline
+    /// (see [crate::ChainLink::Pipe]) are not cached.
+    pub fn set_cache(&mut self, cache: &OutputCache) -> &mut Self
+    {
+        self.cache = Some(cache.clone());
+        self
+    }
+
+    /// Specifies a non-zero exit code that indicates success for this operation,
+    /// in addition to any specified by the [Executable] (see
+    /// [Executable::accept_exit_code]).
+    pub fn accept_exit_code(&mut self, code: i32) -> &mut Self
+    {
+        self.ok_exit_codes.push(code);
+        self
+    }
+
+    /// Returns the exit code of the most recent execution of this operation.
+    /// This is None if the operation has not been executed, or if the
+    /// executable did not exit normally (e.g. it could not be started, it was
+    /// killed by a signal, or it timed out).  Note that an [Executor::DryRun]
+    /// execution, or an execution whose output was restored from the
+    /// [OutputCache], is reported as an exit code of 0.
+    ///
+    /// [Executor::DryRun]: crate::Executor::DryRun
+    pub fn exit_code(&self) -> Option<i32>
+    {
+        self.exit_code
+    }
+
+    /// Returns the command line (the executable followed by the arguments) of the
+    /// most recent execution of this operation, or an empty list if the
+    /// operation has not been executed.
+    pub(crate) fn command_line(&self) -> Vec<OsString>
+    {
+        self.command.clone()
+    }
+
+    /// Returns the arguments for this operation (not including the input and
+    /// output file arguments), in the order of their [ArgPosition].
+    pub(crate) fn args(&self) -> Vec<OsString>
+    {
+        let mut args = self.slot_args_at(ArgPosition::Leading);
+        args.extend(self.args.iter().cloned());
+        args.extend(self.slot_args_at(ArgPosition::AfterInputs));
+        args.extend(self.slot_args_at(ArgPosition::AfterOutputs));
+        args
+    }
+
+    fn slot_args_at(&self, pos: ArgPosition) -> Vec<OsString>
+    {
+        self.slot_args.iter()
+            .filter(|(p, _)| *p == pos)
+            .map(|(_, arg)| arg.clone())
+            .collect()
+    }
+
+    /// Returns the file specifications for this operation.
+    pub(crate) fn files(&self) -> &FileTransformation
+    {
+        &self.files
+    }
+
+    /// Replaces the input file specifications for this operation (which may
+    /// leave it with no input files).
+    pub(crate) fn set_input_files(&mut self, fnames: &[FileArg])
+    {
+        self.files.inp_filenames = fnames.to_vec();
+    }
+
+    /// Returns a description of the command performed by this operation (the
+    /// executable, arguments and environment, but not the files), which
+    /// identifies the definition of the operation for a chain checkpoint.
+    pub(crate) fn definition(&self) -> String
+    {
+        format!("{:?} {:?} {:?} {:?} {:?}", self.exec, self.args, self.slot_args,
+                self.env, self.ok_exit_codes)
+    }
+
+    /// Returns the current timeout setting for this operation.
+    pub(crate) fn get_full_timeout(&self) -> Option<Duration>
+    {
+        self.timeout
+    }
+
+    /// Sets (or clears) the timeout setting for this operation.
+    pub(crate) fn set_full_timeout(&mut self, timeout: &Option<Duration>) -> &mut Self
+    {
+        self.timeout = *timeout;
+        self
+    }
+
+    /// Uses the specified timeout as the default timeout for executing the
+    /// operation; this is only used if this operation does not have its own
+    /// timeout setting.  This is used to inherit the timeout of a chain.
+    pub(crate) fn set_base_timeout(&mut self, base_timeout: &Option<Duration>) -> &mut Self
+    {
+        self.timeout = self.timeout.or(*base_timeout);
+        self
+    }
+
+    /// Returns the current cache setting for this operation.
+    pub(crate) fn get_full_cache(&self) -> Option<OutputCache>
+    {
+        self.cache.clone()
+    }
+
+    /// Sets (or clears) the cache setting for this operation.
+    pub(crate) fn set_full_cache(&mut self, cache: &Option<OutputCache>) -> &mut Self
+    {
+        self.cache = cache.clone();
+        self
+    }
+
+    /// Uses the specified cache as the default cache for the operation; this is
+    /// only used if this operation does not have its own cache setting.  This is
+    /// used to inherit the cache of a chain.
+    pub(crate) fn set_base_cache(&mut self, base_cache: &Option<OutputCache>) -> &mut Self
+    {
+        self.cache = self.cache.clone().or(base_cache.clone());
+        self
+    }
+
     /// Specifies an environment variable value to be set in the environment for
     /// executing this operation.  This can be used multiple times to set
     /// multiple environment variables; subsequent settings of the same variable
@@ -169,6 +380,19 @@ impl SubProcOperation {
         self
     }
 
+    /// Adds an argument to use when executing the operation at the specified
+    /// position in the command line (see [ArgPosition]).  Arguments added at
+    /// the same position are presented in the order they were added.
+    pub fn push_arg_at<T>(&mut self, pos: ArgPosition, arg: T) -> &mut Self
+    where T: Into<OsString>
+    {
+        match pos {
+            ArgPosition::Standard => self.args.push(arg.into()),
+            _ => self.slot_args.push((pos, arg.into())),
+        }
+        self
+    }
+
     /// Prepares the final/actual argument list that is to be presented to the
     /// command, including lookup and preparation of files that are referenced by
     /// the command.  This function is normally only used internally by the
@@ -180,11 +404,36 @@ impl SubProcOperation {
                                                  (ActualFile, ActualFile))>
     where Exec: OsRun, P: AsRef<Path>
     {
-        let mut args = self.args.clone();
+        let mut args = self.slot_args_at(ArgPosition::Leading);
+        args.extend(self.args.iter().cloned());
         let files = self.cmd_file_setup(executor, &mut args, cwd)?;
         Ok((args, files))
     }
 
+    /// Starts a new execution of this operation: resets the record of the most
+    /// recent execution and then determines the arguments and files as with
+    /// [SubProcOperation::finalize_args], recording the resulting command line.
+    fn begin_run<Exec, P>(&mut self,
+                          executor: &Exec,
+                          cwd: &Option<P>)
+                          -> anyhow::Result<(Vec<OsString>,
+                                             (ActualFile, ActualFile))>
+    where Exec: OsRun, P: AsRef<Path>
+    {
+        self.exit_code = None;
+        self.command = Vec::new();
+        let (args, files) = self.finalize_args(executor, cwd)?;
+        self.record_command(&args);
+        Ok((args, files))
+    }
+
+    fn record_command(&mut self, args: &[OsString])
+    {
+        self.command = std::iter::once(self.exec.exe_file.clone().into_os_string())
+            .chain(args.iter().cloned())
+            .collect();
+    }
+
     // Sets up file references for running a command.  Note that these are
     // relative to the cwd specified for this operation, which might not yet be
     // the current working directory.
@@ -200,8 +449,34 @@ impl SubProcOperation {
             Err(anyhow::Error::new(ChainsopError::ErrorMissingFile));
         let errctxt = |w| move || format!("Setting {} file for {:?}", w, self.exec);
 
+        if let ExeFileSpec::Stdout = get_inpfile(&self.exec) {
+            return Err(anyhow::Error::new(
+                ChainsopError::ErrorInvalidFileSpec(format!("{:?}", self.exec),
+                                                    "input".into())));
+        }
+        if let ExeFileSpec::Stdin = get_outfile(&self.exec) {
+            return Err(anyhow::Error::new(
+                ChainsopError::ErrorInvalidFileSpec(format!("{:?}", self.exec),
+                                                    "output".into())));
+        }
+        for (spec, role) in [(get_inpfile(&self.exec), "input"),
+                             (get_outfile(&self.exec), "output")] {
+            if let ExeFileSpec::Pattern(pattern, _) = spec {
+                if pattern.is_empty() {
+                    return Err(anyhow::Error::new(
+                        ChainsopError::ErrorInvalidFileSpec(format!("{:?}", self.exec),
+                                                            role.into())));
+                }
+            }
+        }
+
         // Note: order of file specification is important below because
-        // setup_file has side-effects of modifying the args.
+        // setup_file has side-effects of modifying the args.  Each file
+        // specification is followed by the arguments for its ArgPosition.
+        // The arguments provided by the user (as opposed to the file arguments
+        // added here) are tracked because only those are subject to pattern
+        // substitution.
+        let mut user_args = vec![true; args.len()];
         if self.emit_output_file_first() {
             outfile = self.setup_exe_file(executor,
                                           args,
@@ -210,29 +485,20 @@ impl SubProcOperation {
                                           &self.files.out_filename,
                                           missing_file_err)
                 .with_context(errctxt("output (first)"))?;
-            inpfiles = self.files.inp_filenames.iter()
-                .try_fold(ActualFile::NoActualFile,
-                          |dfs, inpf|
-                          self.setup_exe_file(executor,
-                                              args,
-                                              &cwd,
-                                              &get_inpfile(&self.exec),
-                                              &inpf,
-                                              missing_file_err)
-                          .and_then(|df| Ok(dfs.extend(df))))
+            user_args.resize(args.len(), false);
+            args.extend(self.slot_args_at(ArgPosition::AfterOutputs));
+            user_args.resize(args.len(), true);
+            inpfiles = self.setup_exe_inputs(executor, args, &cwd, missing_file_err)
                 .with_context(errctxt("output (append)"))?;
+            user_args.resize(args.len(), false);
+            args.extend(self.slot_args_at(ArgPosition::AfterInputs));
+            user_args.resize(args.len(), true);
         } else {
-            inpfiles = self.files.inp_filenames.iter()
-                .try_fold(ActualFile::NoActualFile,
-                          |dfs, inpf|
-                          self.setup_exe_file(executor,
-                                              args,
-                                              &cwd,
-                                              &get_inpfile(&self.exec),
-                                              &inpf,
-                                              missing_file_err)
-                          .and_then(|df| Ok(dfs.extend(df))))
+            inpfiles = self.setup_exe_inputs(executor, args, &cwd, missing_file_err)
                 .with_context(errctxt("output (append)"))?;
+            user_args.resize(args.len(), false);
+            args.extend(self.slot_args_at(ArgPosition::AfterInputs));
+            user_args.resize(args.len(), true);
             outfile = self.setup_exe_file(executor,
                                           args,
                                           &cwd,
@@ -240,7 +506,23 @@ impl SubProcOperation {
                                           &self.files.out_filename,
                                           missing_file_err)
                 .with_context(errctxt("output (append)"))?;
+            user_args.resize(args.len(), false);
+            args.extend(self.slot_args_at(ArgPosition::AfterOutputs));
+            user_args.resize(args.len(), true);
+        }
+
+        // Patterns are substituted once all of the arguments are present.
+        let mut substs = Vec::new();
+        if let ExeFileSpec::Pattern(pattern, join) = get_inpfile(&self.exec) {
+            substs.push(PatternSubst::new(pattern, &join, &inpfiles, "input")
+                        .with_context(errctxt("input (pattern)"))?);
         }
+        if let ExeFileSpec::Pattern(pattern, join) = get_outfile(&self.exec) {
+            substs.push(PatternSubst::new(pattern, &join, &outfile, "output")
+                        .with_context(errctxt("output (pattern)"))?);
+        }
+        self.substitute_patterns(args, &user_args, &mut substs)
+            .with_context(errctxt("pattern"))?;
         Ok((inpfiles, outfile))
     }
 
@@ -250,7 +532,7 @@ impl SubProcOperation {
     /// order is input file and then output file (e.g. "cp inpfile outfile").
     fn emit_output_file_first(&self) -> bool
     {
-        if let ExeFileSpec::Option(_) = get_outfile(&self.exec) {
+        if let ExeFileSpec::Option(_) | ExeFileSpec::OptionJoin(..) = get_outfile(&self.exec) {
             if let ExeFileSpec::Append = get_inpfile(&self.exec) {
                 true
             } else {
@@ -261,6 +543,115 @@ impl SubProcOperation {
         }
     }
 
+    /// Resolves the input files and inserts them into the argument list (see
+    /// setup_exe_file).  For an [ExeFileSpec::Pattern] or
+    /// [ExeFileSpec::OptionJoin], all of the input files are resolved together so
+    /// that they are combined as specified by the [FileJoin] (a Pattern is
+    /// substituted later by cmd_file_setup); otherwise each input file
+    /// specification is inserted in turn.
+    fn setup_exe_inputs<E, Exec, P>(&self,
+                                    executor: &Exec,
+                                    args: &mut Vec<OsString>,
+                                    cwd: &Option<P>,
+                                    on_missing: E)
+                                    -> anyhow::Result<ActualFile>
+    where E: Fn() -> anyhow::Result<ActualFile>,
+          Exec: OsRun,
+          P: AsRef<Path>
+    {
+        match get_inpfile(&self.exec) {
+            spec @ (ExeFileSpec::Pattern(..) | ExeFileSpec::OptionJoin(..)) => {
+                let sf = self.files.inp_filenames.iter()
+                    .try_fold(ActualFile::NoActualFile,
+                              |dfs, inpf| setup_file(executor, inpf, &on_missing)
+                              .map(|df| dfs.extend(df)))?;
+                // Patterns are substituted by cmd_file_setup.
+                if let ExeFileSpec::OptionJoin(optflag, join) = spec {
+                    push_option(args, &optflag, &join, &sf)?;
+                }
+                Ok(sf)
+            }
+            spec => self.files.inp_filenames.iter()
+                .try_fold(ActualFile::NoActualFile,
+                          |dfs, inpf|
+                          self.setup_exe_file(executor,
+                                              args,
+                                              cwd,
+                                              &spec,
+                                              inpf,
+                                              &on_missing)
+                          .map(|df| dfs.extend(df))),
+        }
+    }
+
+    /// Replaces each occurrence of the patterns in the user-provided arguments
+    /// with the corresponding file(s).  The arguments are substituted in a
+    /// single pass, so the pattern of one file specification is not replaced
+    /// within the name of a file (whether that file was substituted for another
+    /// pattern or added to the arguments by another file specification).  It is
+    /// an error if a pattern does not occur in any argument, or if it occurs in
+    /// an argument that is not valid UTF-8.
+    fn substitute_patterns(&self,
+                           args: &mut Vec<OsString>,
+                           user_args: &[bool],
+                           substs: &mut [PatternSubst]) -> anyhow::Result<()>
+    {
+        if substs.is_empty() {
+            return Ok(());
+        }
+        let invalid_spec = |subst: &PatternSubst| anyhow::Error::new(
+            ChainsopError::ErrorInvalidFileSpec(format!("{:?}", self.exec),
+                                                subst.role.into()));
+        let mut substituted = Vec::with_capacity(args.len());
+        for (arg, user) in args.drain(..).zip(user_args.iter()) {
+            let bytes = arg.as_encoded_bytes();
+            let contained = substs.iter().find(|s| *user && contains(bytes, &s.pattern));
+            let Some(subst) = contained else {
+                substituted.push(arg);
+                continue;
+            };
+            let Some(text) = arg.to_str() else {
+                return Err(invalid_spec(subst).context(
+                    format!("Pattern {:?} in argument {:?} which is not valid UTF-8",
+                            subst.pattern, arg)));
+            };
+            let pieces = split_patterns(text, substs);
+            let mut used : Vec<usize> = pieces.iter()
+                .filter_map(|p| match p { ArgPiece::File(n) => Some(*n), _ => None })
+                .collect();
+            used.sort();
+            used.dedup();
+            // Each combination of the replacement texts of the patterns in this
+            // argument produces a copy of the argument (with the first pattern
+            // varying the slowest).
+            let copies : usize = used.iter().map(|n| substs[*n].texts.len()).product();
+            let mut choice = vec![0; substs.len()];
+            for copy in 0..copies {
+                let mut rem = copy;
+                for n in used.iter().rev() {
+                    choice[*n] = rem % substs[*n].texts.len();
+                    rem /= substs[*n].texts.len();
+                }
+                let mut out = OsString::new();
+                for piece in &pieces {
+                    match piece {
+                        ArgPiece::Text(t) => out.push(t),
+                        ArgPiece::File(n) => out.push(&substs[*n].texts[choice[*n]]),
+                    }
+                }
+                substituted.push(out);
+            }
+            for n in used {
+                substs[n].found = true;
+            }
+        }
+        *args = substituted;
+        match substs.iter().find(|s| ! s.found) {
+            Some(missing) => Err(invalid_spec(missing)),
+            None => Ok(()),
+        }
+    }
+
     /// Resolves a FileSpec and inserts the actual named file into the argument
     /// list.  This also returns the file; the file may be a temporary file
     /// object which will delete the file at the end of its lifetime, so the
@@ -289,18 +680,20 @@ impl SubProcOperation {
             }
             ExeFileSpec::Option(optflag) => {
                 let sf = setup_file(executor, candidate, on_missing)?;
-                let pths = sf.to_paths::<PathBuf>(&None)?;
-                let fnames = pths.iter()
-                    .map(|x| x.to_str().unwrap()).collect::<Vec<_>>();
-                if optflag.ends_with("=") {
-                    args.push(OsString::from(optflag.to_owned() +
-                                             &fnames.join(",")));
-                } else {
-                    args.push(OsString::from(optflag));
-                    args.push(OsString::from(fnames.join(",")));
-                };
+                push_option(args, optflag, &FileJoin::Separator(",".into()), &sf)?;
+                Ok(sf)
+            }
+            // An input OptionJoin is handled by setup_exe_inputs.
+            ExeFileSpec::OptionJoin(optflag, join) => {
+                let sf = setup_file(executor, candidate, on_missing)?;
+                push_option(args, optflag, join, &sf)?;
                 Ok(sf)
             }
+            ExeFileSpec::Stdin |
+            ExeFileSpec::Stdout => setup_file(executor, candidate, on_missing),
+            // The Pattern is substituted by cmd_file_setup when all of the
+            // arguments are present.
+            ExeFileSpec::Pattern(..) => setup_file(executor, candidate, on_missing),
             ExeFileSpec::ViaCall(userfun) => {
                 let sf = setup_file(executor, candidate, on_missing)?;
                 userfun(args,
@@ -311,30 +704,150 @@ impl SubProcOperation {
         }
     }
 
-    /// After the files are setup, this performs the actual run.  See the
-    /// documentation for `OpInterface::execute()` above for a description of the
-    /// handling of the `cwd` parameter.
-    fn run_cmd<Exec, P>(&self,
-                        executor: &Exec,
-                        cwd: &Option<P>,
-                        outfile : ActualFile,
-                        args : Vec<OsString>)
-                        -> anyhow::Result<ActualFile>
-    where P: AsRef<Path>, // T: Clone,
+    /// Prepares to run the command: determines the arguments and files (as with
+    /// [SubProcOperation::begin_run]) along with the manner in which the command
+    /// is run.  The command does not need to be run if the output file is
+    /// restored from the cache.  See the documentation for
+    /// `OpInterface::execute()` above for a description of the handling of the
+    /// `cwd` parameter.
+    fn begin_cmd<Exec, P>(&mut self, executor: &Exec, cwd: &Option<P>)
+                          -> anyhow::Result<CmdStart>
+    where P: AsRef<Path>,
           Exec: OsRun
     {
-        let fromdir: Option<PathBuf> =
-            match cwd {
-                Some(root) => match &self.files.in_dir {
-                    Some(sub) => Some(root.as_ref().to_path_buf().join(sub)),
-                    None => Some(root.as_ref().to_path_buf()),
-                },
-                None => self.files.in_dir.clone(),
-            };
-        match executor.run_executable(&self.label(),
-                                      &self.exec.exe_file, &args,
-                                      &self.env,
-                                      &fromdir) {
+        let (args, (inpfiles, outfile)) = self.begin_run(executor, cwd)?;
+        let fromdir = self.run_dir(cwd);
+        let exe_opts = self.exec_options(&inpfiles, &outfile)?;
+        let cached = self.cache_entry(&args, &inpfiles, &outfile, &fromdir);
+        if self.restore_cached(executor, &cached) {
+            return Ok(CmdStart::Cached(outfile));
+        }
+        Ok(CmdStart::Run(Box::new(CmdRun { label: self.label(),
+                                           exe_file: self.exec.exe_file.clone(),
+                                           args,
+                                           env: self.env.clone(),
+                                           exe_opts,
+                                           fromdir,
+                                           outfile,
+                                           attempts: Attempts::new(&self.retry),
+                                           cached })))
+    }
+
+    /// Handles the result of an attempt to run the command prepared by
+    /// [SubProcOperation::begin_cmd].  If the attempt failed and should be
+    /// retried (per the retry policy), returns the delay before the retry;
+    /// otherwise returns the final result of the execution.  This is common to
+    /// all the ways the command is run (waiting for it, starting it in the
+    /// background, or asynchronously).
+    fn attempted<Exec>(&mut self, executor: &Exec, mut run: Box<CmdRun>,
+                       result: OsRunResult) -> Attempted
+    where Exec: OsRun
+    {
+        let result = self.completed(result);
+        match run.attempts.retry_delay(&result) {
+            Some(delay) => {
+                let failure = self.run_result(result, ActualFile::NoActualFile,
+                                              run.args.clone(),
+                                              run.fromdir.clone());
+                run.attempts.failed(failure);
+                Attempted::Retry(delay, run)
+            }
+            None => {
+                store_cached(executor, &run.cached, &result);
+                let CmdRun { attempts, outfile, args, fromdir, .. } = *run;
+                Attempted::Done(
+                    attempts.finish(&self.label(),
+                                    self.run_result(result, outfile, args,
+                                                    fromdir)))
+            }
+        }
+    }
+
+    /// Returns the cache entry for running the command with the specified
+    /// arguments and files, if the operation has a cache.
+    fn cache_entry(&self,
+                   args: &[OsString],
+                   inpfiles: &ActualFile,
+                   outfile: &ActualFile,
+                   fromdir: &Option<PathBuf>) -> Option<CacheEntry>
+    {
+        self.cache.as_ref().and_then(
+            |c| c.entry(&self.exec.exe_file, args, &self.env,
+                        inpfiles, outfile, fromdir))
+    }
+
+    /// Attempts to restore the output file from the cache entry, returning true
+    /// (and recording the successful completion of the command) if it was
+    /// restored.
+    fn restore_cached<Exec>(&mut self, executor: &Exec, cached: &Option<CacheEntry>)
+                            -> bool
+    where Exec: OsRun
+    {
+        match cached {
+            Some(entry) if entry.restore(executor, &self.label()) => {
+                self.completed(Good);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Determines the additional options for running the command, based on the
+    /// (already setup) input and output files.
+    fn exec_options(&self, inpfiles: &ActualFile, outfile: &ActualFile)
+                    -> anyhow::Result<ExecOptions>
+    {
+        Ok(ExecOptions {
+            stdout: match get_outfile(&self.exec) {
+                ExeFileSpec::Stdout => Some(outfile.to_path(&None::<PathBuf>)?),
+                _ => None,
+            },
+            stdin: match get_inpfile(&self.exec) {
+                ExeFileSpec::Stdin => inpfiles.to_paths(&None::<PathBuf>)?,
+                _ => Vec::new(),
+            },
+            timeout: self.timeout,
+        })
+    }
+
+    /// Determines the directory from which the command should be run.
+    fn run_dir<P>(&self, cwd: &Option<P>) -> Option<PathBuf>
+    where P: AsRef<Path>
+    {
+        match cwd {
+            Some(root) => match &self.files.in_dir {
+                Some(sub) => Some(root.as_ref().to_path_buf().join(sub)),
+                None => Some(root.as_ref().to_path_buf()),
+            },
+            None => self.files.in_dir.clone(),
+        }
+    }
+
+    /// Records the exit code of a completed run of the command, and converts a
+    /// failure with an accepted exit code into a successful result.
+    fn completed(&mut self, result: OsRunResult) -> OsRunResult
+    {
+        self.exit_code = match &result {
+            Good => Some(0),
+            ExecError(c, _) => *c,
+            _ => None,
+        };
+        match result {
+            ExecError(Some(c), _) if self.ok_exit_codes.contains(&c) => Good,
+            _ => result,
+        }
+    }
+
+    /// Converts the result of running the command into the output file or an
+    /// error describing the failure.
+    fn run_result(&self,
+                  result: OsRunResult,
+                  outfile: ActualFile,
+                  args: Vec<OsString>,
+                  fromdir: Option<PathBuf>)
+                  -> anyhow::Result<ActualFile>
+    {
+        match result {
             Good => Ok(outfile),
             RunError(e) =>
                 Err(anyhow::Error::new(
@@ -353,6 +866,399 @@ impl SubProcOperation {
                 Err(anyhow::Error::new(
                     ChainsopError::ErrorBadDirectory(
                         format!("{:?}", self.exec), p, e))),
+            TimedOut(t,s) =>
+                Err(anyhow::Error::new(
+                    ChainsopError::ErrorTimedOut(
+                        format!("{:?}", self.exec), args,
+                        t, fromdir, s))),
+        }
+    }
+
+    /// Starts executing this operation without waiting for it to complete.  The
+    /// returned [PendingExec] should be passed to [SubProcOperation::finish] to
+    /// obtain the result of the execution, which is the same as the result that
+    /// would have been returned by `OpInterface::execute()`.
+    pub(crate) fn start<Exec, P>(&mut self, executor: &Exec, cwd: &Option<P>)
+                                 -> anyhow::Result<PendingExec>
+    where P: AsRef<Path>,
+          Exec: OsRun
+    {
+        Ok(match self.begin_cmd(executor, cwd)? {
+            CmdStart::Cached(outfile) => PendingExec::Cached(outfile),
+            CmdStart::Run(run) => PendingExec::Running(run.start(executor), run),
+        })
+    }
+
+    /// Waits for the completion of an execution started by
+    /// [SubProcOperation::start] and returns the result.  If the execution
+    /// failed and should be retried (per the retry policy), the retry is started
+    /// (after the retry delay) and the new [PendingExec] is returned instead.
+    pub(crate) fn finish<Exec>(&mut self, executor: &Exec, pending: PendingExec)
+                               -> FinishedExec
+    where Exec: OsRun
+    {
+        let (handle, run) = match pending {
+            PendingExec::Cached(outfile) =>
+                return FinishedExec::Complete(Ok(outfile)),
+            PendingExec::Running(handle, run) => (handle, run),
+        };
+        match self.attempted(executor, run, handle.wait()) {
+            Attempted::Retry(delay, run) => {
+                executor.delay(delay);
+                FinishedExec::Retrying(
+                    PendingExec::Running(run.start(executor), run))
+            }
+            Attempted::Done(result) => FinishedExec::Complete(result),
+        }
+    }
+
+    /// Starts executing this operation asynchronously.  The command (and any
+    /// retries) is run by awaiting [AsyncExec::attempt] for the returned
+    /// [AsyncExec], so several of these may be awaited concurrently; the result
+    /// is obtained by passing the [AsyncAttempt] to
+    /// [SubProcOperation::finish_async].
+    #[cfg(feature = "async")]
+    pub(crate) fn start_async<Exec, P>(&mut self, executor: &Exec, cwd: &Option<P>)
+                                       -> anyhow::Result<AsyncExec>
+    where P: AsRef<Path>,
+          Exec: AsyncOsRun
+    {
+        Ok(match self.begin_cmd(executor, cwd)? {
+            CmdStart::Cached(outfile) => AsyncExec::Cached(outfile),
+            CmdStart::Run(run) => AsyncExec::Run(run, None),
+        })
+    }
+
+    /// Handles the outcome of an [AsyncExec] returned by
+    /// [SubProcOperation::start_async] (or by this function), returning either
+    /// the final result or the [AsyncExec] for the retry of the command.
+    #[cfg(feature = "async")]
+    pub(crate) fn finish_async<Exec>(&mut self, executor: &Exec,
+                                     attempt: AsyncAttempt)
+                                     -> Result<anyhow::Result<ActualFile>, AsyncExec>
+    where Exec: AsyncOsRun
+    {
+        match attempt {
+            AsyncAttempt::Cached(outfile) => Ok(Ok(outfile)),
+            AsyncAttempt::Ran(result, run) =>
+                match self.attempted(executor, run, result) {
+                    Attempted::Retry(delay, run) =>
+                        Err(AsyncExec::Run(run, Some(delay))),
+                    Attempted::Done(result) => Ok(result),
+                },
+        }
+    }
+
+    /// Prepares this operation to be run as a stage of a pipeline (see
+    /// [OsRun::run_pipeline]).  If pipe_in is true, the stdin is provided by the
+    /// previous stage instead of the input file(s), and if pipe_out is true, the
+    /// stdout is provided to the next stage instead of being written to the
+    /// output file; the corresponding [ExeFileSpec] must therefore be `Stdin` or
+    /// `Stdout`, respectively.  Also returns the output file, which should be
+    /// passed to [SubProcOperation::pipe_result] with the stage's result.
+    pub(crate) fn pipe_stage<Exec, P>(&mut self,
+                                      executor: &Exec,
+                                      cwd: &Option<P>,
+                                      pipe_in: bool,
+                                      pipe_out: bool)
+                                      -> anyhow::Result<(PipeStage, ActualFile)>
+    where P: AsRef<Path>,
+          Exec: OsRun
+    {
+        self.exit_code = None;
+        self.command = Vec::new();
+        let mut inp_spec = get_inpfile(&self.exec);
+        let mut out_spec = get_outfile(&self.exec);
+        if pipe_in {
+            let ExeFileSpec::Stdin = inp_spec else {
+                return Err(anyhow::Error::new(
+                    ChainsopError::ErrorInvalidPipe(
+                        self.label(), "the input is not provided via stdin".into())));
+            };
+            inp_spec = ExeFileSpec::NoFileUsed;
+        }
+        if pipe_out {
+            let ExeFileSpec::Stdout = out_spec else {
+                return Err(anyhow::Error::new(
+                    ChainsopError::ErrorInvalidPipe(
+                        self.label(), "the output is not provided via stdout".into())));
+            };
+            out_spec = ExeFileSpec::NoFileUsed;
+        }
+        let stage_op = SubProcOperation {
+            exec: with_file_specs(&self.exec, inp_spec, out_spec),
+            ..self.clone()
+        };
+        let (args, (inpfiles, outfile)) = stage_op.finalize_args(executor, cwd)?;
+        let exe_opts = stage_op.exec_options(&inpfiles, &outfile)?;
+        self.record_command(&args);
+        Ok((PipeStage { label: self.label(),
+                        exe_file: self.exec.exe_file.clone(),
+                        args,
+                        exe_env: self.env.clone(),
+                        exe_opts,
+                        fromdir: self.run_dir(cwd) },
+            outfile))
+    }
+
+    /// Converts the result of running a stage prepared by
+    /// [SubProcOperation::pipe_stage] into the output file or an error
+    /// describing the failure.
+    pub(crate) fn pipe_result(&mut self,
+                              result: OsRunResult,
+                              stage: PipeStage,
+                              outfile: ActualFile)
+                              -> anyhow::Result<ActualFile>
+    {
+        let result = self.completed(result);
+        self.run_result(result, outfile, stage.args, stage.fromdir)
+    }
+}
+
+
+/// A run of a [SubProcOperation] command that has been prepared by
+/// [SubProcOperation::begin_cmd].  This holds everything needed to run (or
+/// retry) the command, so a retry uses the environment and options of the
+/// original attempt, which may have been inherited from an enclosing chain.
+pub(crate) struct CmdRun {
+    label : String,
+    exe_file : PathBuf,
+    args : Vec<OsString>,
+    env : EnvSpec,
+    exe_opts : ExecOptions,
+    fromdir : Option<PathBuf>,
+    outfile : ActualFile,
+    attempts : Attempts,
+    cached : Option<CacheEntry>,
+}
+
+impl CmdRun {
+    /// Runs the command, waiting for it to complete.
+    fn run<Exec>(&self, executor: &Exec) -> OsRunResult
+    where Exec: OsRun
+    {
+        executor.run_executable(&self.label, &self.exe_file, &self.args,
+                                &self.env, &self.exe_opts, &self.fromdir)
+    }
+
+    /// Starts running the command without waiting for it to complete.
+    fn start<Exec>(&self, executor: &Exec) -> OsRunHandle
+    where Exec: OsRun
+    {
+        executor.start_executable(&self.label, &self.exe_file, &self.args,
+                                  &self.env, &self.exe_opts, &self.fromdir)
+    }
+
+}
+
+/// The first step of running a [SubProcOperation] command, as determined by
+/// [SubProcOperation::begin_cmd].
+enum CmdStart {
+    /// The output file was restored from the cache, so the command is not run.
+    Cached(ActualFile),
+
+    /// The command should be run, passing the result of each attempt to
+    /// [SubProcOperation::attempted].
+    Run(Box<CmdRun>),
+}
+
+/// The outcome of an attempt to run a [SubProcOperation] command, as
+/// determined by [SubProcOperation::attempted].
+enum Attempted {
+    /// The attempt failed and the command should be run again after the delay.
+    Retry(Duration, Box<CmdRun>),
+
+    /// The execution has completed with the specified result.
+    Done(anyhow::Result<ActualFile>),
+}
+
+/// Tracks a [SubProcOperation] execution that has been started but whose result
+/// has not yet been obtained.
+pub(crate) enum PendingExec {
+    /// The output file was restored from the cache.
+    Cached(ActualFile),
+
+    /// The command is running.
+    Running(OsRunHandle, Box<CmdRun>),
+}
+
+/// The result of [SubProcOperation::finish].
+pub(crate) enum FinishedExec {
+    /// The execution has completed with the specified result.
+    Complete(anyhow::Result<ActualFile>),
+
+    /// The execution failed and a retry has been started.
+    Retrying(PendingExec),
+}
+
+/// An attempt to run a [SubProcOperation] command asynchronously, as started by
+/// [SubProcOperation::start_async].  The attempt owns everything needed to run
+/// the command, so the future returned by [AsyncExec::attempt] borrows only the
+/// executor (and is `Send` if the executor is `Sync` and its futures are
+/// `Send`).
+#[cfg(feature = "async")]
+pub(crate) enum AsyncExec {
+    /// The output file was restored from the cache, so the command is not run.
+    Cached(ActualFile),
+
+    /// The command is run after the delay (if any).
+    Run(Box<CmdRun>, Option<Duration>),
+}
+
+#[cfg(feature = "async")]
+impl AsyncExec {
+    /// Performs the attempt, completing when the command has completed.
+    pub(crate) async fn attempt<Exec>(self, executor: &Exec) -> AsyncAttempt
+    where Exec: AsyncOsRun
+    {
+        match self {
+            AsyncExec::Cached(outfile) => AsyncAttempt::Cached(outfile),
+            AsyncExec::Run(run, delay) => {
+                if let Some(delay) = delay {
+                    executor.delay_async(delay).await;
+                }
+                let result = executor.run_executable_async(&run.label,
+                                                           &run.exe_file,
+                                                           &run.args, &run.env,
+                                                           &run.exe_opts,
+                                                           &run.fromdir).await;
+                AsyncAttempt::Ran(result, run)
+            }
+        }
+    }
+}
+
+/// The output of an [AsyncExec].
+#[cfg(feature = "async")]
+pub(crate) enum AsyncAttempt {
+    /// The output file was restored from the cache.
+    Cached(ActualFile),
+
+    /// The command was run, with the specified result.
+    Ran(OsRunResult, Box<CmdRun>),
+}
+
+/// Stores the output file in the cache entry (if any) when the (final) result of
+/// running the command is successful.
+fn store_cached<Exec>(executor: &Exec, cached: &Option<CacheEntry>,
+                      result: &OsRunResult)
+where Exec: OsRun
+{
+    if let (Some(entry), Good) = (cached, result) {
+        entry.store(executor);
+    }
+}
+
+// An ExeFileSpec::Pattern to be substituted in the arguments of a command: the
+// pattern, the replacement text for the file(s) (one text for each copy of the
+// argument when the files are repeated, otherwise a single text), the role of
+// the file (input or output), and whether the pattern was found.
+struct PatternSubst {
+    pattern : String,
+    texts : Vec<OsString>,
+    role : &'static str,
+    found : bool,
+}
+
+impl PatternSubst {
+    fn new(pattern: String, join: &FileJoin, sf: &ActualFile, role: &'static str)
+           -> anyhow::Result<PatternSubst>
+    {
+        let texts = match join {
+            FileJoin::Single => vec![sf.to_path::<PathBuf>(&None)?.into_os_string()],
+            FileJoin::Repeat => sf.to_paths::<PathBuf>(&None)?.into_iter()
+                .map(PathBuf::into_os_string)
+                .collect(),
+            FileJoin::Separator(sep) => {
+                let mut fnames = OsString::new();
+                for (n, pth) in sf.to_paths::<PathBuf>(&None)?.iter().enumerate() {
+                    if n > 0 {
+                        fnames.push(sep);
+                    }
+                    fnames.push(pth);
+                }
+                vec![fnames]
+            }
+        };
+        Ok(PatternSubst { pattern, texts, role, found: false })
+    }
+}
+
+// A portion of an argument containing patterns: either literal text or the
+// occurrence of the pattern of the indexed PatternSubst.
+enum ArgPiece<'a> {
+    Text(&'a str),
+    File(usize),
+}
+
+// Splits the argument text into the literal text and the occurrences of the
+// patterns.  Where the occurrences of patterns overlap, the earliest (and then
+// the longest) is used.
+fn split_patterns<'a>(mut text: &'a str, substs: &[PatternSubst]) -> Vec<ArgPiece<'a>>
+{
+    let mut pieces = Vec::new();
+    while let Some((pos, n)) = substs.iter().enumerate()
+        .filter_map(|(n, s)| text.find(s.pattern.as_str()).map(|pos| (pos, n)))
+        .min_by_key(|(pos, n)| (*pos, std::cmp::Reverse(substs[*n].pattern.len())))
+    {
+        pieces.push(ArgPiece::Text(&text[..pos]));
+        pieces.push(ArgPiece::File(n));
+        text = &text[pos + substs[n].pattern.len()..];
+    }
+    pieces.push(ArgPiece::Text(text));
+    pieces
+}
+
+// Returns true if the (encoded) argument contains the (non-empty) pattern.
+fn contains(arg: &[u8], pattern: &str) -> bool
+{
+    arg.windows(pattern.len()).any(|w| w == pattern.as_bytes())
+}
+
+/// Adds the option and the file(s) to the arguments (see [ExeFileSpec::Option]),
+/// with multiple files combined as specified by the join.  An option ending in
+/// '=' is presented as a single argument along with its file(s).
+fn push_option(args: &mut Vec<OsString>, optflag: &str, join: &FileJoin,
+               sf: &ActualFile) -> anyhow::Result<()>
+{
+    let with_opt = |args: &mut Vec<OsString>, fname: OsString| {
+        if optflag.ends_with('=') {
+            let mut arg = OsString::from(optflag);
+            arg.push(fname);
+            args.push(arg);
+        } else {
+            args.push(OsString::from(optflag));
+            args.push(fname);
+        }
+    };
+    match join {
+        FileJoin::Separator(sep) => {
+            let mut fnames = OsString::new();
+            for (n, pth) in sf.to_paths::<PathBuf>(&None)?.iter().enumerate() {
+                if n > 0 {
+                    fnames.push(sep);
+                }
+                fnames.push(pth);
+            }
+            with_opt(args, fnames);
+        }
+        FileJoin::Repeat =>
+            for pth in sf.to_paths::<PathBuf>(&None)? {
+                with_opt(args, pth.into_os_string());
+            },
+        FileJoin::Single => with_opt(args, sf.to_path::<PathBuf>(&None)?.into_os_string()),
+    }
+    Ok(())
+}
+
+impl PendingExec {
+    /// Returns true if the execution has completed (and therefore
+    /// [SubProcOperation::finish] will not block).
+    pub(crate) fn is_complete(&self) -> bool
+    {
+        match self {
+            PendingExec::Cached(_) => true,
+            PendingExec::Running(handle, _) => handle.is_complete(),
         }
     }
 }
@@ -372,12 +1278,42 @@ impl OpInterface for SubProcOperation {
     where P: AsRef<Path>,
           Exec: OsRun
     {
-        let (args, (_inpfiles, outfile)) = self.finalize_args(executor, cwd)?;
-        self.run_cmd(executor, cwd, outfile, args)
+        let mut run = match self.begin_cmd(executor, cwd)? {
+            CmdStart::Cached(outfile) => return Ok(outfile),
+            CmdStart::Run(run) => run,
+        };
+        loop {
+            let result = run.run(executor);
+            match self.attempted(executor, run, result) {
+                Attempted::Retry(delay, retry) => {
+                    executor.delay(delay);
+                    run = retry;
+                }
+                Attempted::Done(result) => return result,
+            }
+        }
     }
 
 }
 
+#[cfg(feature = "async")]
+impl AsyncOpInterface for SubProcOperation {
+
+    async fn execute_async<Exec, P>(&mut self, executor: &Exec, cwd: &Option<P>)
+                                    -> anyhow::Result<ActualFile>
+    where P: AsRef<Path>,
+          Exec: AsyncOsRun
+    {
+        let mut running = self.start_async(executor, cwd)?;
+        loop {
+            let attempt = running.attempt(executor).await;
+            match self.finish_async(executor, attempt) {
+                Ok(result) => return result,
+                Err(retry) => running = retry,
+            }
+        }
+    }
+}
 
 // ----------------------------------------------------------------------
 // TESTS
@@ -388,8 +1324,9 @@ mod tests {
 
     use super::*;
     use crate::execution::*;
+    #[cfg(feature = "sync")]
+    use crate::shared::SharedBorrow;
     use std::cell::RefCell;
-    use std::rc::Rc;
 
     #[derive(Debug, PartialEq)]
     struct RunExec {
@@ -397,6 +1334,7 @@ mod tests {
         exe: PathBuf,
         args: Vec<OsString>,
         env: EnvSpec,
+        opts: ExecOptions,
         dir: Option<PathBuf>
     }
     struct ArgCollector(RefCell<Vec<RunExec>>);
@@ -412,6 +1350,7 @@ mod tests {
                           exe_file: &Path,
                           args: &Vec<OsString>,
                           exe_env: &EnvSpec,
+                          exe_opts: &ExecOptions,
                           fromdir: &Option<PathBuf>) -> OsRunResult
         {
             self.0.borrow_mut()
@@ -419,13 +1358,14 @@ mod tests {
                                exe: PathBuf::from(exe_file),
                                args: args.clone(),
                                env: exe_env.clone(),
+                               opts: exe_opts.clone(),
                                dir: fromdir.clone()
             });
             Good
         }
         fn run_function(&self,
                         name : &str,
-                        _call : &Rc<dyn Fn(&Path, &ActualFile, &ActualFile) -> anyhow::Result<()>>,
+                        _call : &OpFunction,
                         _inpfiles: &ActualFile,
                         _outfile: &ActualFile,
                         _fromdir: &Option<PathBuf>) -> OsRunResult
@@ -493,6 +1433,7 @@ mod tests {
                                    .append("env2", "env2last", ":")
                                    .rmv("wild")
                                    .rmv("env1"),
+                                   opts: ExecOptions::default(),
                                    dir: None,
                    },
                    ]);
@@ -533,6 +1474,7 @@ mod tests {
                                           "inp2.foo",
                                    ].map(Into::<OsString>::into).to_vec(),
                                    env: EnvSpec::StdEnv,
+                                   opts: ExecOptions::default(),
                                    dir: Some(PathBuf::from("/other/location/sub/dir")),
                    }]);
 
@@ -557,6 +1499,7 @@ mod tests {
                                           "inp2.foo",
                                    ].map(Into::<OsString>::into).to_vec(),
                                    env: EnvSpec::StdEnv,
+                                   opts: ExecOptions::default(),
                                    dir: Some(PathBuf::from("loc/sub/dir")),
                    }]);
     }
@@ -584,9 +1527,377 @@ mod tests {
                                    args: ["-a",
                                    ].map(Into::<OsString>::into).to_vec(),
                                    env: EnvSpec::StdEnv,
+                                   opts: ExecOptions::default(),
                                    dir: Some(PathBuf::from("sub/dir")),
                    }]);
     }
 
 
+    #[test]
+    fn test_stdout() {
+        let exe = Executable::new(&"grep",
+                                  ExeFileSpec::Append,
+                                  ExeFileSpec::Stdout);
+        let mut op = SubProcOperation::new(&exe)
+            .push_arg("pattern")
+            .set_input_file(&FileArg::loc("inpfile.txt"))
+            .set_output_file(&FileArg::loc("matches.out"))
+            .set_timeout(Duration::from_secs(30))
+            .clone();
+
+        let executor = ArgCollector::new();
+        let result = op.execute(&executor, &Some("loc"));
+        assert!(match result {
+            Ok(ActualFile::SingleFile(FileRef::StaticFile(p))) =>
+                p == Path::new("matches.out"),
+            _ => false
+        });
+        let collected = executor.0.into_inner();
+        assert_eq!(collected,
+                   vec![ RunExec { name: "grep".into(),
+                                   exe: "grep".into(),
+                                   args: ["pattern",
+                                          "inpfile.txt",
+                                   ].map(Into::<OsString>::into).to_vec(),
+                                   env: EnvSpec::StdEnv,
+                                   opts: ExecOptions {
+                                       stdout: Some(PathBuf::from("matches.out")),
+                                       timeout: Some(Duration::from_secs(30)),
+                                       ..ExecOptions::default()
+                                   },
+                                   dir: Some(PathBuf::from("loc")),
+                   }]);
+
+        let mut badop = SubProcOperation::new(&Executable::new(&"grep",
+                                                               ExeFileSpec::Stdout,
+                                                               ExeFileSpec::Append))
+            .set_input_file(&FileArg::loc("inpfile.txt"))
+            .set_output_file(&FileArg::loc("matches.out"))
+            .clone();
+        let exec2 = ArgCollector::new();
+        match badop.execute(&exec2, &Some("loc")) {
+            Err(e) => assert!(matches!(e.downcast_ref::<ChainsopError>(),
+                                       Some(ChainsopError::ErrorInvalidFileSpec(_, _))),
+                              "Unexpected error: {:?}", e),
+            Ok(r) => panic!("Expected an error for a stdout input but got {:?}", r),
+        }
+        assert_eq!(exec2.0.into_inner(), vec![]);
+    }
+
+    #[test]
+    fn test_stdin() {
+        let exe = Executable::new(&"sort",
+                                  ExeFileSpec::Stdin,
+                                  ExeFileSpec::option("-o"));
+        let mut op = SubProcOperation::new(&exe)
+            .set_input_file(&FileArg::loc("first.txt"))
+            .add_input_file(&FileArg::loc("second.txt"))
+            .set_output_file(&FileArg::loc("sorted.out"))
+            .clone();
+
+        let executor = ArgCollector::new();
+        let result = op.execute(&executor, &None::<PathBuf>);
+        assert!(match result {
+            Ok(ActualFile::SingleFile(FileRef::StaticFile(p))) =>
+                p == Path::new("sorted.out"),
+            _ => false
+        });
+        let collected = executor.0.into_inner();
+        assert_eq!(collected,
+                   vec![ RunExec { name: "sort".into(),
+                                   exe: "sort".into(),
+                                   args: ["-o",
+                                          "sorted.out",
+                                   ].map(Into::<OsString>::into).to_vec(),
+                                   env: EnvSpec::StdEnv,
+                                   opts: ExecOptions {
+                                       stdin: vec![PathBuf::from("first.txt"),
+                                                   PathBuf::from("second.txt")],
+                                       ..ExecOptions::default()
+                                   },
+                                   dir: None,
+                   }]);
+
+        let mut badop = SubProcOperation::new(&Executable::new(&"sort",
+                                                               ExeFileSpec::Append,
+                                                               ExeFileSpec::Stdin))
+            .set_input_file(&FileArg::loc("first.txt"))
+            .set_output_file(&FileArg::loc("sorted.out"))
+            .clone();
+        let exec2 = ArgCollector::new();
+        match badop.execute(&exec2, &None::<PathBuf>) {
+            Err(e) => assert!(matches!(e.downcast_ref::<ChainsopError>(),
+                                       Some(ChainsopError::ErrorInvalidFileSpec(_, _))),
+                              "Unexpected error: {:?}", e),
+            Ok(r) => panic!("Expected an error for a stdin output but got {:?}", r),
+        }
+        assert_eq!(exec2.0.into_inner(), vec![]);
+    }
+
+    #[test]
+    fn test_pattern() {
+        let exe = Executable::new(&"objcopy",
+                                  ExeFileSpec::Pattern("{in}".into(),
+                                                       FileJoin::Separator(",".into())),
+                                  ExeFileSpec::Append)
+            .push_arg("--add-section")
+            .push_arg(".data={in}");
+        let mut op = SubProcOperation::new(&exe)
+            .set_input_file(&FileArg::loc("a.bin"))
+            .add_input_file(&FileArg::loc("b.bin"))
+            .set_output_file(&FileArg::loc("out.elf"))
+            .clone();
+        let executor = ArgCollector::new();
+        op.execute_here(&executor).unwrap();
+        assert_eq!(executor.0.into_inner()[0].args,
+                   ["--add-section", ".data=a.bin,b.bin", "out.elf"]
+                   .map(Into::<OsString>::into).to_vec());
+
+        let exe = Executable::new(&"sh", ExeFileSpec::pattern("{in}"),
+                                  ExeFileSpec::pattern("{out}"))
+            .push_arg("-c")
+            .push_arg("tool < {in} > {out}; echo {out}");
+        let mut op = SubProcOperation::new(&exe)
+            .set_input_file(&FileArg::loc("in.txt"))
+            .set_output_file(&FileArg::loc("out.txt"))
+            .clone();
+        let executor = ArgCollector::new();
+        op.execute_here(&executor).unwrap();
+        assert_eq!(executor.0.into_inner()[0].args,
+                   ["-c", "tool < in.txt > out.txt; echo out.txt"]
+                   .map(Into::<OsString>::into).to_vec());
+
+        let exe = Executable::new(&"tool",
+                                  ExeFileSpec::Pattern("{in}".into(), FileJoin::Repeat),
+                                  ExeFileSpec::NoFileUsed)
+            .push_arg("-i{in}")
+            .push_arg("-v");
+        let mut op = SubProcOperation::new(&exe)
+            .set_input_file(&FileArg::loc("a"))
+            .add_input_file(&FileArg::loc("b"))
+            .clone();
+        let executor = ArgCollector::new();
+        op.execute_here(&executor).unwrap();
+        assert_eq!(executor.0.into_inner()[0].args,
+                   ["-ia", "-ib", "-v"].map(Into::<OsString>::into).to_vec());
+
+        // Multiple files are not allowed for FileJoin::Single
+        let single = Executable::new(&"tool",
+                                     ExeFileSpec::Pattern("{in}".into(), FileJoin::Single),
+                                     ExeFileSpec::NoFileUsed)
+            .push_arg("-i{in}");
+        let executor = ArgCollector::new();
+        assert!(SubProcOperation::new(&single)
+                .set_input_file(&FileArg::loc("a"))
+                .add_input_file(&FileArg::loc("b"))
+                .execute_here(&executor).is_err());
+        assert!(SubProcOperation::new(&single)
+                .set_input_file(&FileArg::loc("a"))
+                .execute_here(&executor).is_ok());
+
+        // The pattern must be present in the arguments
+        let nopattern = Executable::new(&"tool", ExeFileSpec::pattern("{in}"),
+                                        ExeFileSpec::NoFileUsed);
+        match SubProcOperation::new(&nopattern)
+            .set_input_file(&FileArg::loc("a"))
+            .execute_here(&executor)
+        {
+            Err(e) => assert!(matches!(e.downcast_ref::<ChainsopError>(),
+                                       Some(ChainsopError::ErrorInvalidFileSpec(_, _))),
+                              "Unexpected error: {:?}", e),
+            Ok(r) => panic!("Expected an error for a missing pattern but got {:?}", r),
+        }
+        assert_eq!(executor.0.into_inner().len(), 1);
+
+        // Patterns are only substituted in the provided arguments, and not in
+        // the names of files (whether substituted or added to the arguments).
+        let exe = Executable::new(&"sh", ExeFileSpec::pattern("{in}"),
+                                  ExeFileSpec::pattern("{out}"))
+            .push_arg("-c")
+            .push_arg("tool < {in} > {out}");
+        let mut op = SubProcOperation::new(&exe)
+            .set_input_file(&FileArg::loc("x{out}.txt"))
+            .set_output_file(&FileArg::loc("y{in}.txt"))
+            .clone();
+        let executor = ArgCollector::new();
+        op.execute_here(&executor).unwrap();
+        assert_eq!(executor.0.into_inner()[0].args,
+                   ["-c", "tool < x{out}.txt > y{in}.txt"]
+                   .map(Into::<OsString>::into).to_vec());
+        let exe = Executable::new(&"cc", ExeFileSpec::Append,
+                                  ExeFileSpec::pattern("{out}"))
+            .push_arg("-o{out}");
+        let mut op = SubProcOperation::new(&exe)
+            .set_input_file(&FileArg::loc("x{out}.c"))
+            .set_output_file(&FileArg::loc("app"))
+            .clone();
+        let executor = ArgCollector::new();
+        op.execute_here(&executor).unwrap();
+        assert_eq!(executor.0.into_inner()[0].args,
+                   ["-oapp", "x{out}.c"].map(Into::<OsString>::into).to_vec());
+
+        // An empty pattern is not valid.
+        let empty = Executable::new(&"tool", ExeFileSpec::NoFileUsed,
+                                    ExeFileSpec::pattern(""))
+            .push_arg("-o");
+        let executor = ArgCollector::new();
+        match SubProcOperation::new(&empty)
+            .set_output_file(&FileArg::loc("a"))
+            .execute_here(&executor)
+        {
+            Err(e) => assert!(matches!(e.downcast_ref::<ChainsopError>(),
+                                       Some(ChainsopError::ErrorInvalidFileSpec(_, role))
+                                       if role == "output"),
+                              "Unexpected error: {:?}", e),
+            Ok(r) => panic!("Expected an error for an empty pattern but got {:?}", r),
+        }
+        assert!(executor.0.into_inner().is_empty());
+    }
+
+    // A pattern in an argument that is not valid UTF-8 cannot be substituted.
+    #[cfg(unix)]
+    #[test]
+    fn test_pattern_not_utf8() {
+        use std::os::unix::ffi::OsStringExt;
+        let exe = Executable::new(&"tool", ExeFileSpec::pattern("{in}"),
+                                  ExeFileSpec::NoFileUsed);
+        let executor = ArgCollector::new();
+        let mut op = SubProcOperation::new(&exe);
+        op.push_arg(OsString::from_vec(b"-\xff".to_vec()))
+            .push_arg("-i{in}")
+            .set_input_file(&FileArg::loc("a"));
+        op.execute_here(&executor).unwrap();
+        assert_eq!(executor.0.into_inner()[0].args,
+                   vec![OsString::from_vec(b"-\xff".to_vec()), "-ia".into()]);
+
+        let executor = ArgCollector::new();
+        let mut op = SubProcOperation::new(&exe);
+        op.push_arg(OsString::from_vec(b"-\xff{in}".to_vec()))
+            .set_input_file(&FileArg::loc("a"));
+        match op.execute_here(&executor) {
+            Err(e) => assert!(matches!(e.downcast_ref::<ChainsopError>(),
+                                       Some(ChainsopError::ErrorInvalidFileSpec(_, role))
+                                       if role == "input"),
+                              "Unexpected error: {:?}", e),
+            Ok(r) => panic!("Expected an error for a non-UTF-8 pattern but got {:?}", r),
+        }
+        assert!(executor.0.into_inner().is_empty());
+    }
+
+    #[test]
+    fn test_option_join() {
+        let run = |inp: ExeFileSpec, out: ExeFileSpec| {
+            let exe = Executable::new(&"cc", inp, out).push_arg("-c");
+            let executor = ArgCollector::new();
+            SubProcOperation::new(&exe)
+                .set_input_file(&FileArg::loc("a.h"))
+                .add_input_file(&FileArg::loc("b.h"))
+                .set_output_file(&FileArg::loc("out.o"))
+                .execute_here(&executor)
+                .map(|_| executor.0.into_inner()[0].args.clone())
+        };
+        let args = |a: &[&str]| a.iter().map(Into::<OsString>::into).collect::<Vec<_>>();
+
+        // The default Option joins multiple files with commas, separately for
+        // each input file specification.
+        assert_eq!(run(ExeFileSpec::option("-I"), ExeFileSpec::option("-o")).unwrap(),
+                   args(&["-c", "-I", "a.h", "-I", "b.h", "-o", "out.o"]));
+        assert_eq!(run(ExeFileSpec::option_join("-I", FileJoin::Separator(",".into())),
+                       ExeFileSpec::option("-o")).unwrap(),
+                   args(&["-c", "-I", "a.h,b.h", "-o", "out.o"]));
+        assert_eq!(run(ExeFileSpec::option_join("--inc=", FileJoin::Separator(" ".into())),
+                       ExeFileSpec::Append).unwrap(),
+                   args(&["-c", "--inc=a.h b.h", "out.o"]));
+        assert_eq!(run(ExeFileSpec::option_join("-I", FileJoin::Repeat),
+                       ExeFileSpec::option_join("--out=", FileJoin::Repeat)).unwrap(),
+                   args(&["-c", "-I", "a.h", "-I", "b.h", "--out=out.o"]));
+        // The output option is emitted first for appended inputs.
+        assert_eq!(run(ExeFileSpec::Append,
+                       ExeFileSpec::option_join("-o", FileJoin::Single)).unwrap(),
+                   args(&["-c", "-o", "out.o", "a.h", "b.h"]));
+        assert!(run(ExeFileSpec::option_join("-I", FileJoin::Single),
+                    ExeFileSpec::Append).is_err());
+    }
+
+    #[test]
+    fn test_arg_positions() {
+        let run = |exe: &Executable| {
+            let mut op = SubProcOperation::new(exe);
+            op.push_arg_at(ArgPosition::AfterOutputs, "-v")
+                .push_arg_at(ArgPosition::AfterInputs, "-lm")
+                .push_arg("-O2")
+                .push_arg_at(ArgPosition::Leading, "link")
+                .push_arg_at(ArgPosition::AfterInputs, "-lc")
+                .push_arg_at(ArgPosition::Standard, "-g")
+                .set_input_file(&FileArg::loc("foo.o"))
+                .add_input_file(&FileArg::loc("bar.o"))
+                .set_output_file(&FileArg::loc("app"));
+            assert_eq!(op.args(),
+                       ["link", "-s", "-O2", "-g", "-lm", "-lc", "-v"]
+                       .map(Into::<OsString>::into).to_vec());
+            let executor = ArgCollector::new();
+            op.execute_here(&executor).unwrap();
+            executor.0.into_inner()[0].args.clone()
+        };
+        let args = |a: &[&str]| a.iter().map(Into::<OsString>::into).collect::<Vec<_>>();
+
+        let exe = Executable::new(&"cc", ExeFileSpec::Append, ExeFileSpec::Append)
+            .push_arg("-s");
+        assert_eq!(run(&exe),
+                   args(&["link", "-s", "-O2", "-g", "foo.o", "bar.o", "-lm", "-lc",
+                          "app", "-v"]));
+
+        // The output option precedes the appended input files.
+        let exe = Executable::new(&"cc", ExeFileSpec::Append, ExeFileSpec::option("-o"))
+            .push_arg("-s");
+        assert_eq!(run(&exe),
+                   args(&["link", "-s", "-O2", "-g", "-o", "app", "-v", "foo.o", "bar.o",
+                          "-lm", "-lc"]));
+
+        // A pattern is substituted in the arguments at any position.
+        let exe = Executable::new(&"cc", ExeFileSpec::NoFileUsed,
+                                  ExeFileSpec::pattern("{out}"))
+            .push_arg("-s");
+        let mut op = SubProcOperation::new(&exe);
+        op.push_arg_at(ArgPosition::AfterInputs, "--map={out}.map")
+            .push_arg_at(ArgPosition::Leading, "-o{out}")
+            .set_output_file(&FileArg::loc("app"));
+        let executor = ArgCollector::new();
+        op.execute_here(&executor).unwrap();
+        assert_eq!(executor.0.into_inner()[0].args,
+                   args(&["-oapp", "-s", "--map=app.map"]));
+    }
+
+    // Actually runs the command to verify the exit code handling.
+    #[cfg(unix)]
+    #[test]
+    fn test_accept_exit_code() {
+        let exe = Executable::new(&"sh", ExeFileSpec::NoFileUsed,
+                                  ExeFileSpec::NoFileUsed)
+            .push_arg("-c")
+            .accept_exit_code(1);
+        let mut op = SubProcOperation::new(&exe);
+        assert_eq!(op.exit_code(), None);
+
+        op.push_arg("exit 1");
+        assert!(op.execute_here(&Executor::NormalRun).is_ok());
+        assert_eq!(op.exit_code(), Some(1));
+
+        let mut op2 = SubProcOperation::new(&exe)
+            .push_arg("exit 2")
+            .accept_exit_code(2)
+            .clone();
+        assert!(op2.execute_here(&Executor::NormalRun).is_ok());
+        assert_eq!(op2.exit_code(), Some(2));
+
+        let mut op3 = SubProcOperation::new(&exe).push_arg("exit 3").clone();
+        match op3.execute_here(&Executor::NormalRun) {
+            Err(e) => assert!(matches!(e.downcast_ref::<ChainsopError>(),
+                                       Some(ChainsopError::ErrorRunningCmd(
+                                           _, _, Some(3), _, _))),
+                              "Unexpected error: {:?}", e),
+            Ok(r) => panic!("Expected an error for exit code 3 but got {:?}", r),
+        }
+        assert_eq!(op3.exit_code(), Some(3));
+    }
 }
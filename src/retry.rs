@@ -0,0 +1,247 @@
+// Retry handling for operations that may fail intermittently.
+//
+// A RetryPolicy is specified for a SubProcOperation or FunctionOperation (or
+// via the ChainedOpRef for such an operation in a chain).  The operations use
+// the Attempts object to track the failed attempts during an execution and
+// determine whether another attempt should be made.
+
+use std::time::Duration;
+
+use crate::errors::ChainsopError;
+use crate::execution::OsRunResult;
+
+
+/// Specifies whether (and how) an operation is retried when it fails.  The
+/// default policy performs a single attempt (i.e. no retries).
+///
+/// ```
+/// # use chainsop::*;
+/// # use std::time::Duration;
+/// let policy = RetryPolicy {
+///     max_attempts: 3,
+///     backoff: Backoff::Exponential { initial: Duration::from_millis(100),
+///                                     max: Duration::from_secs(5) },
+///     retry_on: vec![RetryOn::ExitCode(75), RetryOn::TimedOut],
+/// };
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// The maximum number of times the operation is attempted, including the
+    /// initial attempt.  Values of 0 and 1 both specify a single attempt.
+    pub max_attempts: u32,
+
+    /// The delay before each retry attempt.
+    pub backoff: Backoff,
+
+    /// The kinds of failure for which the operation is retried; any other
+    /// failure is returned immediately.
+    pub retry_on: Vec<RetryOn>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy
+    {
+        RetryPolicy { max_attempts: 1,
+                      backoff: Backoff::NoDelay,
+                      retry_on: vec![RetryOn::AnyFailure],
+        }
+    }
+}
+
+impl RetryPolicy {
+
+    /// Creates a policy that attempts the operation up to the specified number
+    /// of times, retrying immediately on any failure.
+    pub fn new(max_attempts: u32) -> RetryPolicy
+    {
+        RetryPolicy { max_attempts, ..RetryPolicy::default() }
+    }
+
+    /// Returns true if the (failed) result of an attempt is one of the
+    /// retryable kinds of failure for this policy.
+    pub fn is_retryable(&self, result: &OsRunResult) -> bool
+    {
+        self.retry_on.iter().any(|r| r.matches(result))
+    }
+}
+
+/// Specifies the delay before each retry of a failed operation (see
+/// [RetryPolicy]).
+#[derive(Clone, Debug, PartialEq)]
+pub enum Backoff {
+    /// The operation is retried immediately.
+    NoDelay,
+
+    /// Each retry is performed after the specified delay.
+    Fixed(Duration),
+
+    /// The first retry is performed after the initial delay, and the delay is
+    /// doubled for each subsequent retry, up to the maximum delay.
+    Exponential { initial: Duration, max: Duration },
+}
+
+impl Backoff {
+
+    /// Returns the delay before the specified retry, where 1 is the first retry
+    /// (i.e. the second attempt).
+    pub fn delay(&self, retry: u32) -> Duration
+    {
+        match self {
+            Backoff::NoDelay => Duration::ZERO,
+            Backoff::Fixed(d) => *d,
+            Backoff::Exponential { initial, max } =>
+                initial.saturating_mul(
+                    2u32.saturating_pow(retry.saturating_sub(1)))
+                .min(*max),
+        }
+    }
+}
+
+/// Identifies a kind of operation failure that should be retried (see
+/// [RetryPolicy]).
+#[derive(Clone, Debug, PartialEq)]
+pub enum RetryOn {
+    /// Any failure of the operation.
+    AnyFailure,
+
+    /// The executable exited with the specified exit code.
+    ExitCode(i32),
+
+    /// The executable exited with any failing exit code or was terminated by a
+    /// signal ([OsRunResult::ExecError]).
+    ExecError,
+
+    /// The executable could not be started ([OsRunResult::ExecFailed]).
+    ExecFailed,
+
+    /// The function (or the executor) reported an error
+    /// ([OsRunResult::RunError]).
+    RunError,
+
+    /// The executable did not complete before its timeout
+    /// ([OsRunResult::TimedOut]).
+    TimedOut,
+}
+
+impl RetryOn {
+    fn matches(&self, result: &OsRunResult) -> bool
+    {
+        match (self, result) {
+            (_, OsRunResult::Good) => false,
+            (RetryOn::AnyFailure, _) => true,
+            (RetryOn::ExitCode(c), OsRunResult::ExecError(Some(code), _)) => c == code,
+            (RetryOn::ExecError, OsRunResult::ExecError(_, _)) => true,
+            (RetryOn::ExecFailed, OsRunResult::ExecFailed(_)) => true,
+            (RetryOn::RunError, OsRunResult::RunError(_)) => true,
+            (RetryOn::TimedOut, OsRunResult::TimedOut(_, _)) => true,
+            _ => false,
+        }
+    }
+}
+
+
+/// Tracks the failed attempts of an operation that is performed according to a
+/// [RetryPolicy].
+pub(crate) struct Attempts {
+    policy : RetryPolicy,
+    failures : Vec<anyhow::Error>,
+}
+
+impl Attempts {
+
+    pub(crate) fn new(policy: &RetryPolicy) -> Attempts
+    {
+        Attempts { policy: policy.clone(), failures: Vec::new() }
+    }
+
+    /// If the result of the latest attempt is a failure that should be retried,
+    /// returns the delay before the next attempt.  The failure should then be
+    /// recorded via [Attempts::failed].
+    pub(crate) fn retry_delay(&self, result: &OsRunResult) -> Option<Duration>
+    {
+        let attempt = self.failures.len() as u32 + 1;
+        if attempt < self.policy.max_attempts && self.policy.is_retryable(result) {
+            Some(self.policy.backoff.delay(attempt))
+        } else {
+            None
+        }
+    }
+
+    /// Records the failure of an attempt that will be retried.
+    pub(crate) fn failed<T>(&mut self, result: anyhow::Result<T>)
+    {
+        if let Err(e) = result {
+            self.failures.push(e);
+        }
+    }
+
+    /// Returns the final result of the operation.  If the final attempt failed
+    /// after previous attempts have also failed, the returned error reports all
+    /// of the failures.
+    pub(crate) fn finish<T>(mut self, label: &str, result: anyhow::Result<T>)
+                            -> anyhow::Result<T>
+    {
+        match result {
+            Err(e) if ! self.failures.is_empty() => {
+                self.failures.push(e);
+                Err(anyhow::Error::new(
+                    ChainsopError::ErrorAttemptsFailed(label.to_string(),
+                                                       self.failures)))
+            }
+            _ => result,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_backoff_delay() {
+        let exp = Backoff::Exponential { initial: Duration::from_millis(100),
+                                         max: Duration::from_millis(500) };
+        assert_eq!((1..=5).map(|r| exp.delay(r).as_millis()).collect::<Vec<_>>(),
+                   vec![100, 200, 400, 500, 500]);
+        assert_eq!(Backoff::Fixed(Duration::from_secs(2)).delay(7),
+                   Duration::from_secs(2));
+        assert_eq!(Backoff::NoDelay.delay(3), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let mut attempts = Attempts::new(&RetryPolicy {
+            max_attempts: 3,
+            backoff: Backoff::Fixed(Duration::from_millis(10)),
+            retry_on: vec![RetryOn::ExitCode(75), RetryOn::TimedOut],
+        });
+        let tempfail = || OsRunResult::ExecError(Some(75), String::new());
+        assert_eq!(attempts.retry_delay(&OsRunResult::Good), None);
+        assert_eq!(attempts.retry_delay(&OsRunResult::ExecError(Some(1),
+                                                                String::new())),
+                   None);
+        assert_eq!(attempts.retry_delay(&tempfail()),
+                   Some(Duration::from_millis(10)));
+        attempts.failed::<()>(Err(anyhow::anyhow!("first")));
+        assert_eq!(attempts.retry_delay(&OsRunResult::TimedOut(Duration::ZERO,
+                                                               String::new())),
+                   Some(Duration::from_millis(10)));
+        attempts.failed::<()>(Err(anyhow::anyhow!("second")));
+        assert_eq!(attempts.retry_delay(&tempfail()), None);
+
+        match attempts.finish::<()>("op", Err(anyhow::anyhow!("third"))) {
+            Err(e) => match e.downcast_ref::<ChainsopError>() {
+                Some(ChainsopError::ErrorAttemptsFailed(label, failures)) => {
+                    assert_eq!(label, "op");
+                    assert_eq!(failures.iter().map(|f| f.to_string())
+                               .collect::<Vec<_>>(),
+                               vec!["first", "second", "third"]);
+                }
+                _ => panic!("Unexpected error: {:?}", e),
+            },
+            Ok(_) => panic!("Expected failure"),
+        }
+    }
+}
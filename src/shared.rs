@@ -0,0 +1,94 @@
+// Shared-ownership types used internally by chainsop.
+//
+// By default, the operation chains, function operations, and temporary file
+// references use single-threaded reference counting (Rc) and interior
+// mutability (RefCell) for their shared portions.  When the "sync" cargo
+// feature is enabled, these are replaced by the thread-safe Arc and RwLock
+// equivalents, which makes those types Send + Sync so that they can be moved to
+// (and executed on) worker threads.  The RwLock is used instead of a Mutex
+// because it retains the RefCell semantics of allowing multiple concurrent
+// immutable borrows.
+//
+// The rest of the crate is written in terms of the aliases defined here so that
+// it is agnostic to the feature selection.
+
+#[cfg(not(feature = "sync"))]
+use std::cell::{Ref, RefCell, RefMut};
+#[cfg(not(feature = "sync"))]
+use std::rc::Rc;
+#[cfg(feature = "sync")]
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard, PoisonError};
+
+
+// The shared (reference-counted) pointer type.
+#[cfg(not(feature = "sync"))]
+pub(crate) type Shared<T> = Rc<T>;
+#[cfg(feature = "sync")]
+pub(crate) type Shared<T> = Arc<T>;
+
+// The interior mutability type for a value held in a Shared pointer.  In both
+// cases, the value is accessed via the `borrow()` and `borrow_mut()` methods
+// (which are provided by the SharedBorrow trait for the RwLock).
+//
+// Note that a conflicting borrow of a RefCell is a runtime panic, whereas a
+// conflicting borrow of an RwLock waits for the other borrow to be released.
+// When that other borrow is held by the same thread (e.g. a ChainedOpRef
+// accessor called from an Activation::when predicate or a FunctionOperation
+// while the chain is being executed), the wait is a deadlock.
+#[cfg(not(feature = "sync"))]
+pub(crate) type SharedCell<T> = RefCell<T>;
+#[cfg(feature = "sync")]
+pub(crate) type SharedCell<T> = RwLock<T>;
+
+// The immutable borrow returned by SharedCell::borrow.
+#[cfg(not(feature = "sync"))]
+pub(crate) type SharedRef<'a, T> = Ref<'a, T>;
+#[cfg(feature = "sync")]
+pub(crate) type SharedRef<'a, T> = RwLockReadGuard<'a, T>;
+
+// The mutable borrow returned by SharedCell::borrow_mut.
+#[cfg(not(feature = "sync"))]
+pub(crate) type SharedRefMut<'a, T> = RefMut<'a, T>;
+#[cfg(feature = "sync")]
+pub(crate) type SharedRefMut<'a, T> = RwLockWriteGuard<'a, T>;
+
+
+// Provides the RefCell borrow methods for the RwLock used as the SharedCell
+// when the "sync" feature is enabled.  A poisoned lock is still used: the
+// operations do not leave the chain internals in an inconsistent state when
+// they panic.
+#[cfg(feature = "sync")]
+pub(crate) trait SharedBorrow<T> {
+    fn borrow(&self) -> SharedRef<'_, T>;
+    fn borrow_mut(&self) -> SharedRefMut<'_, T>;
+}
+
+#[cfg(feature = "sync")]
+impl<T> SharedBorrow<T> for RwLock<T> {
+    fn borrow(&self) -> SharedRef<'_, T> {
+        self.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn borrow_mut(&self) -> SharedRefMut<'_, T> {
+        self.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+
+// A marker trait for values that are held by the shared types: when the "sync"
+// feature is enabled this requires `Send + Sync`, otherwise it is satisfied by
+// all types.  This is the bound on the functions provided to
+// FunctionOperation::calling and Activation::when.
+//
+// This is declared pub (rather than pub(crate)) only because it appears in the
+// bounds of those public functions; it is not exported from chainsop, so it
+// cannot be named (or otherwise used) outside of this crate.
+#[cfg(not(feature = "sync"))]
+pub trait Shareable {}
+#[cfg(not(feature = "sync"))]
+impl<T: ?Sized> Shareable for T {}
+
+#[cfg(feature = "sync")]
+pub trait Shareable: Send + Sync {}
+#[cfg(feature = "sync")]
+impl<T: ?Sized + Send + Sync> Shareable for T {}